@@ -0,0 +1,8 @@
+CREATE TABLE flight_details_cache (
+    url             TEXT PRIMARY KEY NOT NULL,
+    thumbnail_large BLOB             NOT NULL,
+    thumbnail_small BLOB             NOT NULL,
+    format          TEXT             NOT NULL,
+    animated        BOOLEAN          NOT NULL,
+    fetched_at      DATETIME         NOT NULL
+);
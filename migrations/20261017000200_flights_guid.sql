@@ -0,0 +1,3 @@
+ALTER TABLE xcontest_flights ADD COLUMN guid TEXT;
+UPDATE xcontest_flights SET guid = url WHERE guid IS NULL;
+CREATE UNIQUE INDEX xcontest_flights_guid ON xcontest_flights(guid);
@@ -0,0 +1,12 @@
+CREATE TABLE parse_failures (
+    id          INTEGER PRIMARY KEY NOT NULL,
+    kind        TEXT                NOT NULL,
+    source      TEXT                NOT NULL,
+    payload     TEXT                NOT NULL,
+    error       TEXT                NOT NULL,
+    occurrences INTEGER             NOT NULL DEFAULT 1,
+    first_seen  DATETIME            NOT NULL,
+    last_seen   DATETIME            NOT NULL,
+
+    UNIQUE(kind, source)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE notification_counters (
+    user_id  INTEGER NOT NULL,
+    month    TEXT    NOT NULL,
+    messages INTEGER NOT NULL DEFAULT 0,
+    images   INTEGER NOT NULL DEFAULT 0,
+    capped   BOOLEAN NOT NULL DEFAULT 0,
+
+    PRIMARY KEY(user_id, month),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
@@ -0,0 +1,18 @@
+ALTER TABLE xcontest_flights ADD COLUMN seen_at DATETIME;
+ALTER TABLE users ADD COLUMN digest BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE scheduler_runs (
+    task     TEXT PRIMARY KEY NOT NULL,
+    last_run DATETIME         NOT NULL
+);
+
+CREATE TABLE leaderboard (
+    month          TEXT    NOT NULL,
+    pilot_username TEXT    NOT NULL COLLATE NOCASE,
+    flights        INTEGER NOT NULL,
+    distance_km    REAL    NOT NULL,
+    max_km         REAL    NOT NULL,
+    computed_at    DATETIME NOT NULL,
+
+    PRIMARY KEY(month, pilot_username)
+);
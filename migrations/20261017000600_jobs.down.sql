@@ -0,0 +1,2 @@
+DROP INDEX jobs_run_at;
+DROP TABLE jobs;
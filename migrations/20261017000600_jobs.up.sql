@@ -0,0 +1,10 @@
+CREATE TABLE jobs (
+    id         INTEGER PRIMARY KEY NOT NULL,
+    payload    TEXT                NOT NULL,
+    run_at     DATETIME            NOT NULL,
+    attempts   INTEGER             NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at DATETIME            NOT NULL
+);
+
+CREATE INDEX jobs_run_at ON jobs(run_at);
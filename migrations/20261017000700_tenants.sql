@@ -0,0 +1,84 @@
+-- Scope users, flights and leaderboards by tenant. Existing rows belong to
+-- the default tenant.
+--
+-- SQLite cannot change table constraints, so the tables are rebuilt. The
+-- tables referencing users are rebuilt as well, so that their foreign keys
+-- never point to a missing table.
+
+CREATE TABLE users_new (
+    id                 INTEGER PRIMARY KEY NOT NULL,
+    tenant             TEXT                NOT NULL DEFAULT 'default',
+    username           TEXT                NOT NULL,
+    usertype           TEXT                NOT NULL,
+    since              DATETIME,
+    threema_public_key BLOB,
+    digest             BOOLEAN             NOT NULL DEFAULT 0,
+
+    UNIQUE(tenant, username, usertype)
+);
+INSERT INTO users_new (id, username, usertype, since, threema_public_key, digest)
+    SELECT id, username, usertype, since, threema_public_key, digest FROM users;
+
+CREATE TABLE subscriptions_new (
+    id             INTEGER PRIMARY KEY NOT NULL,
+    user_id        INTEGER             NOT NULL,
+    pilot_username TEXT                NOT NULL,
+
+    UNIQUE(user_id, pilot_username),
+    FOREIGN KEY(user_id) REFERENCES users_new(id)
+);
+INSERT INTO subscriptions_new (id, user_id, pilot_username)
+    SELECT id, user_id, pilot_username FROM subscriptions;
+
+CREATE TABLE notification_counters_new (
+    user_id  INTEGER NOT NULL,
+    month    TEXT    NOT NULL,
+    messages INTEGER NOT NULL DEFAULT 0,
+    images   INTEGER NOT NULL DEFAULT 0,
+    capped   BOOLEAN NOT NULL DEFAULT 0,
+
+    PRIMARY KEY(user_id, month),
+    FOREIGN KEY(user_id) REFERENCES users_new(id)
+);
+INSERT INTO notification_counters_new (user_id, month, messages, images, capped)
+    SELECT user_id, month, messages, images, capped FROM notification_counters;
+
+DROP TABLE notification_counters;
+DROP TABLE subscriptions;
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
+ALTER TABLE subscriptions_new RENAME TO subscriptions;
+ALTER TABLE notification_counters_new RENAME TO notification_counters;
+
+-- Flights keep their row IDs, which are used in card URLs
+CREATE TABLE xcontest_flights_new (
+    tenant         TEXT NOT NULL DEFAULT 'default',
+    url            TEXT NOT NULL,
+    title          TEXT NOT NULL,
+    pilot_username TEXT NOT NULL,
+    guid           TEXT,
+    seen_at        DATETIME,
+
+    UNIQUE(tenant, url),
+    UNIQUE(tenant, guid)
+);
+INSERT INTO xcontest_flights_new (rowid, url, title, pilot_username, guid, seen_at)
+    SELECT rowid, url, title, pilot_username, guid, seen_at FROM xcontest_flights;
+DROP TABLE xcontest_flights;
+ALTER TABLE xcontest_flights_new RENAME TO xcontest_flights;
+
+CREATE TABLE leaderboard_new (
+    tenant         TEXT     NOT NULL DEFAULT 'default',
+    month          TEXT     NOT NULL,
+    pilot_username TEXT     NOT NULL COLLATE NOCASE,
+    flights        INTEGER  NOT NULL,
+    distance_km    REAL     NOT NULL,
+    max_km         REAL     NOT NULL,
+    computed_at    DATETIME NOT NULL,
+
+    PRIMARY KEY(tenant, month, pilot_username)
+);
+INSERT INTO leaderboard_new (month, pilot_username, flights, distance_km, max_km, computed_at)
+    SELECT month, pilot_username, flights, distance_km, max_km, computed_at FROM leaderboard;
+DROP TABLE leaderboard;
+ALTER TABLE leaderboard_new RENAME TO leaderboard;
@@ -0,0 +1,4 @@
+-- Flights that are not yet notified (because notifications of the pilot are
+-- grouped) have no notification timestamp.
+ALTER TABLE xcontest_flights ADD COLUMN notified_at DATETIME;
+UPDATE xcontest_flights SET notified_at = coalesce(seen_at, CURRENT_TIMESTAMP);
@@ -0,0 +1,8 @@
+-- What the bot expects as the next message of a user (e.g. picking one of
+-- several pilots), stored as JSON.
+CREATE TABLE conversation_states (
+    user_id    INTEGER PRIMARY KEY NOT NULL,
+    state      TEXT                NOT NULL,
+    expires_at DATETIME            NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
@@ -0,0 +1,7 @@
+-- The creation time of existing subscriptions is unknown. Use the
+-- registration time of the user as approximation.
+ALTER TABLE subscriptions ADD COLUMN created_at DATETIME;
+UPDATE subscriptions SET created_at = coalesce(
+    (SELECT since FROM users WHERE users.id = subscriptions.user_id),
+    CURRENT_TIMESTAMP
+);
@@ -0,0 +1,9 @@
+-- Tokens for the HTTP API. Only the SHA-256 hash of a token is stored.
+CREATE TABLE api_tokens (
+    id           INTEGER  PRIMARY KEY NOT NULL,
+    name         TEXT     NOT NULL,
+    token_hash   TEXT     NOT NULL UNIQUE,
+    scope        TEXT     NOT NULL,
+    created_at   DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at DATETIME
+);
@@ -0,0 +1,23 @@
+-- Surveys sent to users as Threema polls. The options are stored as JSON
+-- array, the ballot ID (hex) identifies the poll in incoming votes.
+CREATE TABLE surveys (
+    id         INTEGER  PRIMARY KEY NOT NULL,
+    tenant     TEXT     NOT NULL,
+    question   TEXT     NOT NULL,
+    options    TEXT     NOT NULL,
+    ballot_id  TEXT     NOT NULL UNIQUE,
+    recipients INTEGER  NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- The latest vote of every user (a changed vote replaces the previous one)
+CREATE TABLE survey_responses (
+    survey_id  INTEGER  NOT NULL,
+    user_id    INTEGER  NOT NULL,
+    choice     INTEGER  NOT NULL,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(survey_id, user_id),
+    FOREIGN KEY(survey_id) REFERENCES surveys(id),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
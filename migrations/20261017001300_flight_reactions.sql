@@ -0,0 +1,20 @@
+-- The flight of every notification message, to attribute reactions to it
+CREATE TABLE notification_messages (
+    message_id TEXT     PRIMARY KEY NOT NULL,
+    user_id    INTEGER  NOT NULL,
+    flight_url TEXT     NOT NULL,
+    sent_at    DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+-- Reactions of users to notified flights (1: agree/like, -1: disagree)
+CREATE TABLE flight_reactions (
+    user_id    INTEGER  NOT NULL,
+    flight_url TEXT     NOT NULL,
+    reaction   INTEGER  NOT NULL,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(user_id, flight_url),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
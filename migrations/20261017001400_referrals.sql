@@ -0,0 +1,2 @@
+-- The referral code the user signed up with (e.g. of a club or campaign)
+ALTER TABLE users ADD COLUMN referral TEXT;
@@ -0,0 +1,3 @@
+-- When the title of a flight last changed in the feed (e.g. distance corrected
+-- after optimization)
+ALTER TABLE xcontest_flights ADD COLUMN title_updated_at DATETIME;
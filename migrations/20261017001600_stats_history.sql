@@ -0,0 +1,12 @@
+-- Daily snapshots of the stats of every tenant, for long-term trends
+CREATE TABLE stats_history (
+    tenant        TEXT     NOT NULL,
+    date          DATE     NOT NULL,
+    users         INTEGER  NOT NULL,
+    subscriptions INTEGER  NOT NULL,
+    flights       INTEGER  NOT NULL,
+    notifications INTEGER  NOT NULL,
+    failures      INTEGER  NOT NULL,
+
+    PRIMARY KEY(tenant, date)
+);
@@ -0,0 +1,7 @@
+-- When the user removed their last subscription (cleared when following a
+-- pilot again)
+ALTER TABLE users ADD COLUMN churned_at DATETIME;
+
+-- Notifications that failed on the first attempt
+ALTER TABLE notification_counters ADD COLUMN failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE stats_history ADD COLUMN notification_failures INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- An optional label of a subscription (e.g. `team`), to group pilots
+ALTER TABLE subscriptions ADD COLUMN label TEXT;
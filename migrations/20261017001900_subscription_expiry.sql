@@ -0,0 +1,2 @@
+-- When a temporary subscription (e.g. for a competition) ends
+ALTER TABLE subscriptions ADD COLUMN expires_at DATETIME;
@@ -0,0 +1,9 @@
+-- Users subscribed to the monthly leaderboard of a configured club
+CREATE TABLE club_subscriptions (
+    user_id    INTEGER  NOT NULL,
+    club       TEXT     NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY(user_id, club),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
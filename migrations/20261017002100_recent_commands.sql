@@ -0,0 +1,8 @@
+-- The last command of every user, to detect commands resent by flaky clients
+CREATE TABLE recent_commands (
+    user_id     INTEGER  PRIMARY KEY NOT NULL,
+    command     TEXT     NOT NULL,
+    received_at DATETIME NOT NULL,
+
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
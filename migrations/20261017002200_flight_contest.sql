@@ -0,0 +1,2 @@
+-- The contest (e.g. `switzerland`) inferred from the flight URL
+ALTER TABLE xcontest_flights ADD COLUMN contest TEXT;
@@ -0,0 +1,5 @@
+-- Start time and airtime of a flight, parsed from the detail page
+ALTER TABLE xcontest_flights ADD COLUMN start_time TEXT;
+ALTER TABLE xcontest_flights ADD COLUMN airtime_minutes INTEGER;
+ALTER TABLE flight_details_cache ADD COLUMN start_time TEXT;
+ALTER TABLE flight_details_cache ADD COLUMN airtime_minutes INTEGER;
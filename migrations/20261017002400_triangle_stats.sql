@@ -0,0 +1,3 @@
+-- Closing and speed of triangles, parsed from the detail page
+ALTER TABLE flight_details_cache ADD COLUMN closing_percent REAL;
+ALTER TABLE flight_details_cache ADD COLUMN speed_kmh REAL;
@@ -0,0 +1,2 @@
+-- The ntfy topic of ntfy users (NULL: the configured default topic)
+ALTER TABLE users ADD COLUMN ntfy_topic TEXT;
@@ -0,0 +1,2 @@
+-- The Pushover user (or group) key of Pushover users
+ALTER TABLE users ADD COLUMN pushover_user_key TEXT;
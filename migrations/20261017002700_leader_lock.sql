@@ -0,0 +1,6 @@
+-- The lock held by the leader of several instances sharing the database
+CREATE TABLE leader_lock (
+    name       TEXT PRIMARY KEY NOT NULL,
+    holder     TEXT     NOT NULL,
+    expires_at DATETIME NOT NULL
+);
@@ -0,0 +1,19 @@
+-- Every outbound message with its gateway message ID (replaces the
+-- notification messages), to reconcile billing and delivery disputes
+CREATE TABLE sent_messages (
+    message_id   TEXT     PRIMARY KEY NOT NULL,
+    user_id      INTEGER  NOT NULL,
+    kind         TEXT     NOT NULL,
+    flight_url   TEXT,
+    sent_at      DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered_at DATETIME,
+    read_at      DATETIME,
+
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+INSERT INTO sent_messages (message_id, user_id, kind, flight_url, sent_at)
+SELECT message_id, user_id, 'notification', flight_url, sent_at
+FROM notification_messages;
+
+DROP TABLE notification_messages;
@@ -0,0 +1,2 @@
+-- The application token of Gotify users
+ALTER TABLE users ADD COLUMN gotify_app_token TEXT;
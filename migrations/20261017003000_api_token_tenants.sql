@@ -0,0 +1,3 @@
+-- The tenant an API token is restricted to (NULL: all tenants, for tokens
+-- created with the CLI)
+ALTER TABLE api_tokens ADD COLUMN tenant TEXT;
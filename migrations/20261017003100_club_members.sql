@@ -0,0 +1,8 @@
+-- Club members seen in the feed, to note new members to the club subscribers
+CREATE TABLE club_members (
+    tenant TEXT NOT NULL,
+    club TEXT NOT NULL,
+    pilot_username TEXT NOT NULL COLLATE NOCASE,
+    first_seen TEXT NOT NULL,
+    PRIMARY KEY (tenant, club, pilot_username)
+);
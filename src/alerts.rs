@@ -0,0 +1,246 @@
+//! Alerts sent to the bot administrator.
+//!
+//! Alerts are sent through the channel configured in the `[alerts]` config
+//! section (by default to the admin's Threema ID), separate from user traffic.
+//! If enabled, the same channel is used to inform the admin about new users
+//! and milestones.
+
+use anyhow::{bail, Context, Result};
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde_json::json;
+use sqlx::{Executor, Pool, Sqlite};
+use threema_gateway::E2eApi;
+
+use crate::{
+    config::Config,
+    db,
+    tenants::DEFAULT_TENANT,
+    threema::{self, MessageKind},
+};
+
+#[derive(Clone)]
+enum Channel {
+    /// Send alerts as Threema message to the admin
+    Threema {
+        api: E2eApi,
+        pool: Pool<Sqlite>,
+        admin_id: Option<String>,
+    },
+    /// POST alerts as JSON (`{"text": "..."}`) to a webhook
+    Webhook { client: Client, url: String },
+}
+
+/// The first milestone of the user count (followed by 200, 300, ...).
+const FIRST_USER_MILESTONE: u64 = 100;
+
+/// The first milestone of the flight count (followed by 20'000, 30'000, ...).
+const FIRST_FLIGHT_MILESTONE: u64 = 10_000;
+
+/// Sends operational alerts to the admin.
+#[derive(Clone)]
+pub struct Alerter {
+    channel: Channel,
+    /// Whether the admin is informed about new users
+    new_users: bool,
+    /// Whether the admin is informed about milestones
+    milestones: bool,
+}
+
+impl Alerter {
+    /// Create an alerter for the channel configured in the `[alerts]` config
+    /// section.
+    pub fn from_config(
+        config: &Config,
+        api: E2eApi,
+        pool: Pool<Sqlite>,
+        client: Client,
+    ) -> Result<Self> {
+        let alerts_config = config.alerts.as_ref();
+        let channel = match alerts_config.and_then(|alerts| alerts.channel.as_deref()) {
+            None | Some("threema") => Channel::Threema {
+                api,
+                pool,
+                admin_id: alerts_config
+                    .and_then(|alerts| alerts.threema_id.clone())
+                    .or_else(|| config.threema.admin_id.clone()),
+            },
+            Some("webhook") => Channel::Webhook {
+                client,
+                url: alerts_config
+                    .and_then(|alerts| alerts.webhook_url.clone())
+                    .context("The webhook alert channel requires a webhook_url")?,
+            },
+            Some(other) => bail!("Unknown alert channel: {}", other),
+        };
+        Ok(Self {
+            channel,
+            new_users: alerts_config
+                .and_then(|alerts| alerts.new_users)
+                .unwrap_or(false),
+            milestones: alerts_config
+                .and_then(|alerts| alerts.milestones)
+                .unwrap_or(false),
+        })
+    }
+
+    /// Send an alert to the admin.
+    ///
+    /// Errors are logged, but not returned, since there's nobody else to
+    /// notify anyways.
+    pub async fn alert(&self, text: &str) {
+        tracing::warn!("Admin alert: {}", text);
+        self.send(&format!("🚨 {}", text)).await;
+    }
+
+    /// Send a report (e.g. the weekly report) to the admin.
+    pub async fn report(&self, text: &str) {
+        self.send(text).await;
+    }
+
+    /// Inform the admin about a new user of the tenant (if enabled), and about
+    /// the user count milestone reached with it (if enabled).
+    pub async fn new_user(
+        &self,
+        pool: &Pool<Sqlite>,
+        tenant: &str,
+        identity: &str,
+        nickname: Option<&str>,
+    ) {
+        if self.new_users {
+            let text = match nickname {
+                Some(nickname) => format!(
+                    "👋 New user {} ({}){}",
+                    identity,
+                    nickname,
+                    in_tenant(tenant)
+                ),
+                None => format!("👋 New user {}{}", identity, in_tenant(tenant)),
+            };
+            self.send(&text).await;
+        }
+        if self.milestones {
+            match db::count_users(pool, tenant).await {
+                Ok(users) => {
+                    if let Some(milestone) =
+                        reached_milestone(users.saturating_sub(1), users, FIRST_USER_MILESTONE)
+                    {
+                        self.send(&format!("🎉 {} users{}!", milestone, in_tenant(tenant)))
+                            .await;
+                    }
+                }
+                Err(e) => tracing::warn!("Could not count users: {}", e),
+            }
+        }
+    }
+
+    /// Inform the admin about the flight count milestone reached with the new
+    /// flights of the tenant (if enabled).
+    pub async fn new_flights(
+        &self,
+        executor: impl Executor<'_, Database = Sqlite>,
+        tenant: &str,
+        new_flights: u64,
+    ) {
+        if !self.milestones || new_flights == 0 {
+            return;
+        }
+        match db::count_flights(executor, tenant).await {
+            Ok(flights) => {
+                if let Some(milestone) = reached_milestone(
+                    flights.saturating_sub(new_flights),
+                    flights,
+                    FIRST_FLIGHT_MILESTONE,
+                ) {
+                    self.send(&format!("🎉 {} flights{}!", milestone, in_tenant(tenant)))
+                        .await;
+                }
+            }
+            Err(e) => tracing::warn!("Could not count flights: {}", e),
+        }
+    }
+
+    /// Send a message to the admin, logging errors.
+    async fn send(&self, text: &str) {
+        let result = match &self.channel {
+            Channel::Threema {
+                api,
+                pool,
+                admin_id,
+            } => {
+                let admin_id = match admin_id.as_deref() {
+                    Some(admin_id) => admin_id,
+                    None => {
+                        tracing::debug!("No admin ID configured, not sending alert");
+                        return;
+                    }
+                };
+                async {
+                    let admin =
+                        db::get_or_create_user(pool, DEFAULT_TENANT, admin_id, "threema").await?;
+                    threema::send_text_message(&admin, text, MessageKind::Alert, api, pool, false)
+                        .await?;
+                    anyhow::Ok(())
+                }
+                .await
+            }
+            Channel::Webhook { client, url } => send_webhook(client, url, text).await,
+        };
+        if let Err(e) = result {
+            tracing::error!("Could not send alert to admin: {}", e);
+        }
+    }
+}
+
+/// Return the tenant suffix of admin messages (empty for the default tenant).
+fn in_tenant(tenant: &str) -> String {
+    match tenant {
+        DEFAULT_TENANT => String::new(),
+        tenant => format!(" in tenant {}", tenant),
+    }
+}
+
+/// Return the highest milestone in `before+1..=after`, if any.
+///
+/// Milestones are the multiples of the highest power of ten below them (e.g.
+/// 100, 200, ..., 900, 1000, 2000, ...), starting at `first`.
+fn reached_milestone(before: u64, after: u64, first: u64) -> Option<u64> {
+    if after == 0 {
+        return None;
+    }
+    let magnitude = 10u64.pow(after.ilog10());
+    let milestone = after - after % magnitude;
+    (milestone > before && milestone >= first).then_some(milestone)
+}
+
+/// POST the alert text to a webhook.
+async fn send_webhook(client: &Client, url: &str, text: &str) -> Result<()> {
+    client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json!({ "text": text }).to_string())
+        .send()
+        .await
+        .context("Could not send webhook request")?
+        .error_for_status()
+        .context("Webhook returned an error")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn milestones() {
+        assert_eq!(reached_milestone(99, 100, 100), Some(100));
+        assert_eq!(reached_milestone(100, 101, 100), None);
+        assert_eq!(reached_milestone(199, 200, 100), Some(200));
+        assert_eq!(reached_milestone(9, 10, 100), None);
+        assert_eq!(reached_milestone(0, 0, 100), None);
+
+        // Several flights can be added at once
+        assert_eq!(reached_milestone(9_990, 10_020, 10_000), Some(10_000));
+        assert_eq!(reached_milestone(10_020, 10_040, 10_000), None);
+        assert_eq!(reached_milestone(98_000, 120_000, 10_000), Some(100_000));
+    }
+}
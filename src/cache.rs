@@ -0,0 +1,76 @@
+//! Database-backed cache for flight details.
+
+use sqlx::{Pool, Sqlite};
+use xcontest_client::{self as xcontest, Flight, FlightDetails, XContest};
+
+use crate::db;
+
+/// Default TTL for cached flight details: One day.
+pub const DEFAULT_TTL_SECONDS: u64 = 24 * 3600;
+
+/// Caches parsed flight details by flight URL, so that the same detail page
+/// isn't fetched repeatedly.
+#[derive(Clone)]
+pub struct DetailsCache {
+    pool: Pool<Sqlite>,
+    ttl_seconds: u64,
+}
+
+impl DetailsCache {
+    /// Create a new cache. A TTL of 0 disables caching.
+    pub fn new(pool: Pool<Sqlite>, ttl_seconds: u64) -> Self {
+        Self { pool, ttl_seconds }
+    }
+
+    /// Return the details for this flight, either from the cache or by
+    /// fetching them from XContest.
+    ///
+    /// The start time and airtime from the details are stored with the
+    /// flight.
+    pub async fn get_or_fetch(
+        &self,
+        xc: &XContest,
+        flight: &Flight,
+    ) -> xcontest::Result<FlightDetails> {
+        let details = self.get_or_fetch_details(xc, flight).await?;
+        if !details.times.is_empty() && flight.times.is_empty() {
+            if let Err(e) = db::set_flight_times(&self.pool, &flight.url, &details.times).await {
+                tracing::warn!("Could not store flight times: {}", e);
+            }
+        }
+        Ok(details)
+    }
+
+    async fn get_or_fetch_details(
+        &self,
+        xc: &XContest,
+        flight: &Flight,
+    ) -> xcontest::Result<FlightDetails> {
+        if self.ttl_seconds == 0 {
+            return xc.fetch_details(flight).await;
+        }
+
+        // Look up cache
+        match db::get_cached_flight_details(&self.pool, &flight.url, self.ttl_seconds).await {
+            Ok(Some(details)) => {
+                tracing::debug!("Using cached details for flight {}", flight.url);
+                return Ok(details);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Could not look up cached flight details: {}", e),
+        }
+
+        // Fetch details and store them in the cache
+        let details = xc.fetch_details(flight).await?;
+        if let Err(e) = db::cache_flight_details(&self.pool, &flight.url, &details).await {
+            tracing::warn!("Could not cache flight details: {}", e);
+        }
+        Ok(details)
+    }
+
+    /// Evict expired entries from the cache, return the number of evicted
+    /// entries.
+    pub async fn evict_expired(&self) -> db::Result<u64> {
+        db::evict_flight_details(&self.pool, self.ttl_seconds).await
+    }
+}
@@ -0,0 +1,233 @@
+//! Rendering of shareable flight cards (OpenGraph preview images).
+//!
+//! Text is rendered with an embedded 5x8 bitmap font, so no font files need
+//! to be shipped with the bot.
+
+use std::io::Cursor;
+
+use anyhow::{Context, Result};
+use image::{imageops::FilterType, ImageFormat, ImageReader, Rgb, RgbImage};
+
+use xcontest_client::{Flight, FlightDetails};
+
+/// Card width in pixels (recommended OpenGraph image size)
+pub const CARD_WIDTH: u32 = 1200;
+
+/// Card height in pixels (recommended OpenGraph image size)
+pub const CARD_HEIGHT: u32 = 630;
+
+const BACKGROUND: Rgb<u8> = Rgb([29, 53, 87]);
+const MAP_BACKGROUND: Rgb<u8> = Rgb([69, 123, 157]);
+const TEXT: Rgb<u8> = Rgb([241, 250, 238]);
+const TEXT_DIM: Rgb<u8> = Rgb([168, 218, 220]);
+
+const MARGIN: u32 = 50;
+const MAP_SIZE: u32 = CARD_HEIGHT - 2 * MARGIN;
+const TEXT_WIDTH: u32 = CARD_WIDTH - 3 * MARGIN - MAP_SIZE;
+
+/// Render a card for the specified flight as PNG.
+///
+/// If flight details are available, the preview image is shown as mini map.
+pub fn render_card(flight: &Flight, details: Option<&FlightDetails>) -> Result<Vec<u8>> {
+    let mut card = RgbImage::from_pixel(CARD_WIDTH, CARD_HEIGHT, BACKGROUND);
+
+    // Text
+    let (pilot, facts) = match &flight.parsed_title {
+        Some(parsed) => {
+            let mut facts = vec![];
+            if let Some(distance) = parsed.distance_km {
+                facts.push((format!("{:.1} km", distance), 6, TEXT));
+            }
+            if let Some(flight_type) = &parsed.flight_type {
+                facts.push((flight_type.to_string(), 4, TEXT_DIM));
+            }
+            if let Some(date) = parsed.date {
+                facts.push((date.format("%d.%m.%Y").to_string(), 4, TEXT_DIM));
+            }
+            (parsed.pilot_name.clone(), facts)
+        }
+        None => (flight.title.clone(), vec![]),
+    };
+    let mut y = MARGIN + 20;
+    y += draw_text(&mut card, &pilot, MARGIN, y, 6, TEXT) + 40;
+    y += draw_text(&mut card, &flight.pilot_username, MARGIN, y, 3, TEXT_DIM) + 60;
+    for (text, scale, color) in facts {
+        y += draw_text(&mut card, &text, MARGIN, y, scale, color) + 30;
+    }
+    draw_text(
+        &mut card,
+        "xcontest.org",
+        MARGIN,
+        CARD_HEIGHT - MARGIN - 8 * 3,
+        3,
+        TEXT_DIM,
+    );
+
+    // Mini map
+    let map_x = CARD_WIDTH - MARGIN - MAP_SIZE;
+    fill_rect(&mut card, map_x, MARGIN, MAP_SIZE, MAP_SIZE, MAP_BACKGROUND);
+    if let Some(details) = details {
+        let map =
+            ImageReader::with_format(Cursor::new(&details.thumbnail_large), details.format.into())
+                .decode()
+                .context("Could not decode flight preview")?
+                .resize(MAP_SIZE, MAP_SIZE, FilterType::CatmullRom)
+                .to_rgb8();
+        let offset_x = (MAP_SIZE - map.width()) / 2;
+        let offset_y = (MAP_SIZE - map.height()) / 2;
+        image::imageops::replace(
+            &mut card,
+            &map,
+            (map_x + offset_x).into(),
+            (MARGIN + offset_y).into(),
+        );
+    }
+
+    // Encode
+    let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    card.write_to(&mut bytes, ImageFormat::Png)
+        .context("Could not encode card")?;
+    Ok(bytes.into_inner())
+}
+
+/// Fill a rectangle with the specified color.
+fn fill_rect(image: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, color);
+        }
+    }
+}
+
+/// Draw a single line of text, truncated to the text column width.
+///
+/// Return the height of the drawn line in pixels.
+fn draw_text(image: &mut RgbImage, text: &str, x: u32, y: u32, scale: u32, color: Rgb<u8>) -> u32 {
+    let advance = 6 * scale;
+    let max_chars = (TEXT_WIDTH / advance) as usize;
+    let mut chars: Vec<char> = text.chars().map(transliterate).collect();
+    if chars.len() > max_chars {
+        chars.truncate(max_chars.saturating_sub(2));
+        chars.extend(['.', '.']);
+    }
+    for (i, c) in chars.into_iter().enumerate() {
+        let glyph = glyph(c);
+        let glyph_x = x + i as u32 * advance;
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..8 {
+                if bits & (1 << row) != 0 {
+                    fill_rect(
+                        image,
+                        glyph_x + column as u32 * scale,
+                        y + row * scale,
+                        scale,
+                        scale,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+    8 * scale
+}
+
+/// Map characters outside of the font to a printable ASCII replacement.
+fn transliterate(c: char) -> char {
+    match c {
+        ' '..='~' => c,
+        'ä' | 'à' | 'á' | 'â' | 'å' => 'a',
+        'Ä' | 'À' | 'Á' | 'Â' | 'Å' => 'A',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'É' | 'È' | 'Ê' | 'Ë' => 'E',
+        'ï' | 'î' | 'í' | 'ì' => 'i',
+        'ö' | 'ô' | 'ó' | 'ò' | 'ø' => 'o',
+        'Ö' | 'Ô' | 'Ó' | 'Ò' | 'Ø' => 'O',
+        'ü' | 'û' | 'ú' | 'ù' => 'u',
+        'Ü' | 'Û' | 'Ú' | 'Ù' => 'U',
+        'ç' => 'c',
+        'Ç' => 'C',
+        'ñ' => 'n',
+        'ß' => 's',
+        '·' | '–' | '—' => '-',
+        _ => '?',
+    }
+}
+
+/// Return the column bitmaps of the glyph for a printable ASCII character
+/// (bit 0 is the top row).
+fn glyph(c: char) -> &'static [u8; 5] {
+    let index = (c as usize).wrapping_sub(0x20);
+    FONT.get(index).unwrap_or(&FONT[('?' as usize) - 0x20])
+}
+
+/// Classic 5x8 bitmap font for the printable ASCII range (0x20-0x7e).
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], // ' ' '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7f, 0x14, 0x7f, 0x14], // '"' '#'
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], // '$' '%'
+    [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x08, 0x07, 0x03, 0x00], // '&' '''
+    [0x00, 0x1c, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1c, 0x00], // '(' ')'
+    [0x2a, 0x1c, 0x7f, 0x1c, 0x2a], [0x08, 0x08, 0x3e, 0x08, 0x08], // '*' '+'
+    [0x00, 0x80, 0x70, 0x30, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], // ',' '-'
+    [0x00, 0x00, 0x60, 0x60, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02], // '.' '/'
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00], // '0' '1'
+    [0x72, 0x49, 0x49, 0x49, 0x46], [0x21, 0x41, 0x49, 0x4d, 0x33], // '2' '3'
+    [0x18, 0x14, 0x12, 0x7f, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], // '4' '5'
+    [0x3c, 0x4a, 0x49, 0x49, 0x31], [0x41, 0x21, 0x11, 0x09, 0x07], // '6' '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x46, 0x49, 0x49, 0x29, 0x1e], // '8' '9'
+    [0x00, 0x00, 0x14, 0x00, 0x00], [0x00, 0x40, 0x34, 0x00, 0x00], // ':' ';'
+    [0x00, 0x08, 0x14, 0x22, 0x41], [0x14, 0x14, 0x14, 0x14, 0x14], // '<' '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x59, 0x09, 0x06], // '>' '?'
+    [0x3e, 0x41, 0x5d, 0x59, 0x4e], [0x7c, 0x12, 0x11, 0x12, 0x7c], // '@' 'A'
+    [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22], // 'B' 'C'
+    [0x7f, 0x41, 0x41, 0x41, 0x3e], [0x7f, 0x49, 0x49, 0x49, 0x41], // 'D' 'E'
+    [0x7f, 0x09, 0x09, 0x09, 0x01], [0x3e, 0x41, 0x41, 0x51, 0x73], // 'F' 'G'
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00], // 'H' 'I'
+    [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41], // 'J' 'K'
+    [0x7f, 0x40, 0x40, 0x40, 0x40], [0x7f, 0x02, 0x1c, 0x02, 0x7f], // 'L' 'M'
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e], // 'N' 'O'
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], // 'P' 'Q'
+    [0x7f, 0x09, 0x19, 0x29, 0x46], [0x26, 0x49, 0x49, 0x49, 0x32], // 'R' 'S'
+    [0x03, 0x01, 0x7f, 0x01, 0x03], [0x3f, 0x40, 0x40, 0x40, 0x3f], // 'T' 'U'
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x3f, 0x40, 0x38, 0x40, 0x3f], // 'V' 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x03, 0x04, 0x78, 0x04, 0x03], // 'X' 'Y'
+    [0x61, 0x59, 0x49, 0x4d, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x41], // 'Z' '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x41, 0x7f], // '\' ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40], // '^' '_'
+    [0x00, 0x03, 0x07, 0x08, 0x00], [0x20, 0x54, 0x54, 0x78, 0x40], // '`' 'a'
+    [0x7f, 0x28, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x28], // 'b' 'c'
+    [0x38, 0x44, 0x44, 0x28, 0x7f], [0x38, 0x54, 0x54, 0x54, 0x18], // 'd' 'e'
+    [0x00, 0x08, 0x7e, 0x09, 0x02], [0x18, 0xa4, 0xa4, 0x9c, 0x78], // 'f' 'g'
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], // 'h' 'i'
+    [0x20, 0x40, 0x40, 0x3d, 0x00], [0x7f, 0x10, 0x28, 0x44, 0x00], // 'j' 'k'
+    [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x78, 0x04, 0x78], // 'l' 'm'
+    [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], // 'n' 'o'
+    [0xfc, 0x18, 0x24, 0x24, 0x18], [0x18, 0x24, 0x24, 0x18, 0xfc], // 'p' 'q'
+    [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x24], // 'r' 's'
+    [0x04, 0x04, 0x3f, 0x44, 0x24], [0x3c, 0x40, 0x40, 0x20, 0x7c], // 't' 'u'
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], [0x3c, 0x40, 0x30, 0x40, 0x3c], // 'v' 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x4c, 0x90, 0x90, 0x90, 0x7c], // 'x' 'y'
+    [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], // 'z' '{'
+    [0x00, 0x00, 0x77, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], // '|' '}'
+    [0x02, 0x01, 0x02, 0x04, 0x02],                                 // '~'
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_without_details() {
+        let flight = Flight::new(
+            "09.08.20 [21.98 km :: free_flight] Danilo Bärgen".to_string(),
+            "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+                .to_string(),
+        )
+        .unwrap();
+        let png = render_card(&flight, None).unwrap();
+        let card = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+        assert_eq!(card.width(), CARD_WIDTH);
+        assert_eq!(card.height(), CARD_HEIGHT);
+    }
+}
@@ -1,10 +1,192 @@
 //! Ultra-simple CLI argument parsing.
 //!
-//! The CLI only supports passing a configfile path. It also prints usage text
-//! with --help or if invalid arguments are passed in.
+//! The CLI supports passing a configfile path, followed by an optional
+//! subcommand. It also prints usage text with --help or if invalid arguments
+//! are passed in.
 
 use std::path::PathBuf;
 
+/// The subcommand to run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Run the bot (default), optionally in read-only mode
+    Run { read_only: bool },
+    /// Check config, database, credentials and XContest access
+    SelfTest,
+    /// Send a canned flight notification to a Threema ID
+    SendTest {
+        to: String,
+        with_image: bool,
+        tenant: Option<String>,
+    },
+    /// Generate a Threema E2E keypair, optionally write a config skeleton
+    Keygen { write_config: Option<PathBuf> },
+    /// Write a commented config template (default: the config file path)
+    Init { path: Option<PathBuf> },
+    /// Show, apply or revert database migrations
+    Migrate { action: MigrateAction },
+    /// Create, list or revoke API tokens
+    Token { action: TokenAction },
+    /// Merge a duplicate user into another user
+    MergeUsers {
+        from: String,
+        into: String,
+        tenant: Option<String>,
+    },
+}
+
+/// What the `migrate` command should do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MigrateAction {
+    /// Print applied and pending migrations (default)
+    Status,
+    /// Apply all pending migrations
+    Run,
+    /// Revert the most recently applied migration
+    Revert,
+}
+
+/// What the `token` command should do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenAction {
+    /// Create a token with the specified name and scope
+    Create { name: String, scope: String },
+    /// Print all tokens
+    List,
+    /// Revoke the token with the specified ID
+    Revoke { id: i64 },
+}
+
+impl Command {
+    /// Parse a subcommand and its arguments.
+    fn parse(name: &str, args: &[String]) -> Result<Self, String> {
+        let mut args = args.iter();
+        let command = match name {
+            "run" => match args.next().map(String::as_str) {
+                Some("--read-only") => Command::Run { read_only: true },
+                Some(other) => return Err(format!("Unexpected argument for {}: {}", name, other)),
+                None => Command::Run { read_only: false },
+            },
+            "selftest" => Command::SelfTest,
+            "send-test" => {
+                let mut to = None;
+                let mut with_image = false;
+                let mut tenant = None;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--to" => to = args.next().cloned(),
+                        "--with-image" => with_image = true,
+                        "--tenant" => {
+                            tenant = Some(
+                                args.next()
+                                    .cloned()
+                                    .ok_or("Missing argument for send-test: --tenant <ID>")?,
+                            )
+                        }
+                        other => {
+                            return Err(format!("Unexpected argument for {}: {}", name, other))
+                        }
+                    }
+                }
+                Command::SendTest {
+                    to: to.ok_or("Missing argument for send-test: --to <THREEMA_ID>")?,
+                    with_image,
+                    tenant,
+                }
+            }
+            "keygen" => match args.next().map(String::as_str) {
+                Some("--write-config") => Command::Keygen {
+                    write_config: Some(PathBuf::from(
+                        args.next()
+                            .ok_or("Missing argument for keygen: --write-config <PATH>")?,
+                    )),
+                },
+                Some(other) => return Err(format!("Unexpected argument for {}: {}", name, other)),
+                None => Command::Keygen { write_config: None },
+            },
+            "init" => Command::Init {
+                path: args.next().map(PathBuf::from),
+            },
+            "migrate" => Command::Migrate {
+                action: match args.next().map(String::as_str) {
+                    None | Some("--status") => MigrateAction::Status,
+                    Some("--run") => MigrateAction::Run,
+                    Some("--revert") => MigrateAction::Revert,
+                    Some(other) => {
+                        return Err(format!("Unexpected argument for {}: {}", name, other))
+                    }
+                },
+            },
+            "token" => Command::Token {
+                action: match args.next().map(String::as_str) {
+                    Some("create") => {
+                        let name = args
+                            .next()
+                            .cloned()
+                            .ok_or("Missing argument for token create: <NAME>")?;
+                        let scope = match args.next().map(String::as_str) {
+                            Some("--scope") => args
+                                .next()
+                                .cloned()
+                                .ok_or("Missing argument for token create: --scope <SCOPE>")?,
+                            Some(other) => {
+                                return Err(format!("Unexpected argument for {}: {}", name, other))
+                            }
+                            None => "read".into(),
+                        };
+                        TokenAction::Create { name, scope }
+                    }
+                    None | Some("list") => TokenAction::List,
+                    Some("revoke") => TokenAction::Revoke {
+                        id: args
+                            .next()
+                            .and_then(|id| id.parse().ok())
+                            .ok_or("Missing or invalid argument for token revoke: <ID>")?,
+                    },
+                    Some(other) => {
+                        return Err(format!("Unexpected argument for {}: {}", name, other))
+                    }
+                },
+            },
+            "merge-users" => {
+                let mut usernames = vec![];
+                let mut tenant = None;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--tenant" => {
+                            tenant = Some(
+                                args.next()
+                                    .cloned()
+                                    .ok_or("Missing argument for merge-users: --tenant <ID>")?,
+                            )
+                        }
+                        other if usernames.len() < 2 => usernames.push(other.to_string()),
+                        other => {
+                            return Err(format!("Unexpected argument for {}: {}", name, other))
+                        }
+                    }
+                }
+                let mut usernames = usernames.into_iter();
+                match (usernames.next(), usernames.next()) {
+                    (Some(from), Some(into)) => Command::MergeUsers { from, into, tenant },
+                    _ => return Err("Missing argument for merge-users: <FROM> <INTO>".into()),
+                }
+            }
+            other => return Err(format!("Unknown command: {}", other)),
+        };
+        match args.next() {
+            Some(arg) => Err(format!("Unexpected argument for {}: {}", name, arg)),
+            None => Ok(command),
+        }
+    }
+}
+
+/// The parsed command line arguments.
+pub struct Args {
+    pub configfile: PathBuf,
+    pub command: Command,
+}
+
 pub struct App<'a> {
     name: &'a str,
     version: &'a str,
@@ -34,16 +216,34 @@ impl<'a> App<'a> {
         eprintln!("{} {}", self.name, self.version);
         eprintln!("\n{}", self.description);
         eprintln!("Author: {}", self.author);
-        eprintln!("\nUsage:");
+        eprintln!("\nUsage: xc-bot [OPTIONS] [COMMAND]");
+        eprintln!("\nOptions:");
         eprintln!(
             "  -c, --config <PATH>  Path to config file (default: '{}')",
             self.default_config_path
         );
         eprintln!("  -v, --version        Return the version");
         eprintln!("  -h, --help           Print this information");
+        eprintln!("\nCommands:");
+        eprintln!("  run [--read-only]    Run the bot (default), read-only: send no notifications");
+        eprintln!("                       and refuse state-changing commands");
+        eprintln!("  selftest             Check config, database, credentials and XContest access");
+        eprintln!("  send-test --to <THREEMA_ID> [--with-image] [--tenant <ID>]");
+        eprintln!("                       Send a canned flight notification");
+        eprintln!("  keygen [--write-config <PATH>]");
+        eprintln!("                       Generate a Threema E2E keypair (and a config template)");
+        eprintln!(
+            "  init [PATH]          Write a commented config template (default: config path)"
+        );
+        eprintln!("  migrate [--status|--run|--revert]");
+        eprintln!("                       Show, apply or revert the last database migration");
+        eprintln!("  token [list|create <NAME> [--scope read|admin]|revoke <ID>]");
+        eprintln!("                       Manage the tokens of the HTTP API");
+        eprintln!("  merge-users <FROM> <INTO> [--tenant <ID>]");
+        eprintln!("                       Merge a duplicate user into another user");
     }
 
-    pub fn get_configfile(self) -> PathBuf {
+    pub fn parse(self) -> Args {
         let args: Vec<String> = std::env::args().collect();
 
         // Handle -h / --help
@@ -58,14 +258,131 @@ impl<'a> App<'a> {
             std::process::exit(0);
         }
 
-        // Parse other args
-        match args.len() {
-            1 => PathBuf::from(self.default_config_path),
-            3 if args[1] == "-c" || args[1] == "--config" => PathBuf::from(&args[2]),
-            _ => {
+        // Parse config path
+        let (configfile, rest) = match args.get(1).map(String::as_str) {
+            Some("-c") | Some("--config") if args.len() >= 3 => {
+                (PathBuf::from(&args[2]), &args[3..])
+            }
+            Some("-c") | Some("--config") => {
                 self.print_help();
                 std::process::exit(1);
             }
+            _ => (
+                PathBuf::from(self.default_config_path),
+                &args[1.min(args.len())..],
+            ),
+        };
+
+        // Parse subcommand
+        let command = match rest.split_first() {
+            None => Command::Run { read_only: false },
+            Some((name, args)) => Command::parse(name, args).unwrap_or_else(|e| {
+                eprintln!("{}\n", e);
+                self.print_help();
+                std::process::exit(1);
+            }),
+        };
+
+        Args {
+            configfile,
+            command,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_send_test() {
+        assert_eq!(
+            Command::parse("send-test", &args(&["--to", "ECHOECHO", "--with-image"])),
+            Ok(Command::SendTest {
+                to: "ECHOECHO".into(),
+                with_image: true,
+                tenant: None,
+            })
+        );
+        assert!(Command::parse("send-test", &args(&["--with-image"])).is_err());
+        assert!(Command::parse("selftest", &args(&["--to"])).is_err());
+    }
+
+    #[test]
+    fn parse_run() {
+        assert_eq!(
+            Command::parse("run", &[]),
+            Ok(Command::Run { read_only: false })
+        );
+        assert_eq!(
+            Command::parse("run", &args(&["--read-only"])),
+            Ok(Command::Run { read_only: true })
+        );
+        assert!(Command::parse("run", &args(&["--read-only", "--read-only"])).is_err());
+    }
+
+    #[test]
+    fn parse_migrate() {
+        assert_eq!(
+            Command::parse("migrate", &[]),
+            Ok(Command::Migrate {
+                action: MigrateAction::Status
+            })
+        );
+        assert_eq!(
+            Command::parse("migrate", &args(&["--revert"])),
+            Ok(Command::Migrate {
+                action: MigrateAction::Revert
+            })
+        );
+        assert!(Command::parse("migrate", &args(&["--run", "--revert"])).is_err());
+        assert!(Command::parse("migrate", &args(&["--all"])).is_err());
+    }
+
+    #[test]
+    fn parse_token() {
+        assert_eq!(
+            Command::parse("token", &args(&["create", "club", "--scope", "admin"])),
+            Ok(Command::Token {
+                action: TokenAction::Create {
+                    name: "club".into(),
+                    scope: "admin".into(),
+                }
+            })
+        );
+        assert_eq!(
+            Command::parse("token", &args(&["revoke", "3"])),
+            Ok(Command::Token {
+                action: TokenAction::Revoke { id: 3 }
+            })
+        );
+        assert!(Command::parse("token", &args(&["create"])).is_err());
+        assert!(Command::parse("token", &args(&["revoke", "x"])).is_err());
+    }
+
+    #[test]
+    fn parse_merge_users() {
+        assert_eq!(
+            Command::parse("merge-users", &args(&["echoecho", "ECHOECHO"])),
+            Ok(Command::MergeUsers {
+                from: "echoecho".into(),
+                into: "ECHOECHO".into(),
+                tenant: None,
+            })
+        );
+        assert_eq!(
+            Command::parse("merge-users", &args(&["A", "--tenant", "club", "B"])),
+            Ok(Command::MergeUsers {
+                from: "A".into(),
+                into: "B".into(),
+                tenant: Some("club".into()),
+            })
+        );
+        assert!(Command::parse("merge-users", &args(&["A"])).is_err());
+        assert!(Command::parse("merge-users", &args(&["A", "B", "C"])).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Clubs whose monthly kilometre leaderboard users can subscribe to (`club
+//! <code>`).
+//!
+//! The leaderboard of a club is derived from the monthly leaderboard of the
+//! tenant, restricted to the configured members. When the first flight of a
+//! member appears in the feed, the subscribers get a "new member spotted"
+//! note.
+
+use anyhow::{Context, Result};
+use sqlx::{Pool, Sqlite};
+
+use crate::{
+    competitions,
+    config::ClubConfig,
+    db::{self, LeaderboardEntry},
+    messages::{self, Messages},
+    tenants::Tenant,
+    threema::{self, MessageKind},
+};
+
+/// Maximum number of pilots listed in a club leaderboard message
+pub const MAX_LEADERBOARD_ENTRIES: usize = 10;
+
+/// Find the club with the specified code.
+pub fn find<'a>(clubs: &'a [ClubConfig], code: &str) -> Option<&'a ClubConfig> {
+    clubs
+        .iter()
+        .find(|club| club.code.eq_ignore_ascii_case(code))
+}
+
+/// Return the display name of the club.
+pub fn name(club: &ClubConfig) -> &str {
+    club.name.as_deref().unwrap_or(&club.code)
+}
+
+/// Return the clubs that list the pilot as a member.
+pub fn member_clubs<'a>(clubs: &'a [ClubConfig], pilot: &str) -> Result<Vec<&'a ClubConfig>> {
+    let mut found = vec![];
+    for club in clubs {
+        let members =
+            competitions::read_roster(club.pilots.as_deref(), club.roster_file.as_deref())?;
+        if members
+            .iter()
+            .any(|member| member.eq_ignore_ascii_case(pilot))
+        {
+            found.push(club);
+        }
+    }
+    Ok(found)
+}
+
+/// Send the note that the pilot was spotted as new member of the club to the
+/// subscribers of the club.
+pub async fn note_new_member(
+    pool: &Pool<Sqlite>,
+    tenant: &Tenant,
+    code: &str,
+    pilot: &str,
+    flight_url: &str,
+) -> Result<()> {
+    let club = find(&tenant.config.clubs, code)
+        .with_context(|| format!("Club {} does not exist", code))?;
+    let text = messages::fill(
+        tenant.config.messages().club_new_member,
+        &[("name", name(club)), ("pilot", pilot), ("url", flight_url)],
+    );
+    for user in db::get_club_subscribers(pool, tenant.id(), &club.code).await? {
+        match &*user.usertype {
+            "threema" => {
+                threema::send_text_message(
+                    &user,
+                    &text,
+                    MessageKind::ClubMember,
+                    &tenant.api,
+                    pool,
+                    tenant.config.threema.request_delivery_receipts(),
+                )
+                .await?;
+            }
+            other => tracing::warn!("Unsupported channel: {}", other),
+        }
+    }
+    Ok(())
+}
+
+/// Return the leaderboard of the club members in the month (`YYYY-MM`).
+pub async fn leaderboard(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    club: &ClubConfig,
+    month: &str,
+) -> Result<Vec<LeaderboardEntry>> {
+    let members = competitions::read_roster(club.pilots.as_deref(), club.roster_file.as_deref())?;
+    Ok(db::get_club_leaderboard(pool, tenant, month, &members).await?)
+}
+
+/// Format the leaderboard of the club: The total of all members, followed by
+/// the best pilots.
+pub fn format_leaderboard(
+    club: &ClubConfig,
+    month: &str,
+    entries: &[LeaderboardEntry],
+    messages: &Messages,
+) -> String {
+    let month = format_month(month);
+    let name = name(club);
+    if entries.is_empty() {
+        return messages::fill(
+            messages.club_leaderboard_empty,
+            &[("name", name), ("month", &month)],
+        );
+    }
+    let flights = |count: u32| {
+        messages::fill(
+            if count == 1 {
+                messages.flights_one
+            } else {
+                messages.flights_other
+            },
+            &[("count", &count.to_string())],
+        )
+    };
+    let mut text = messages::fill(
+        messages.club_leaderboard_header,
+        &[("name", name), ("month", &month)],
+    );
+    text.push('\n');
+    text.push_str(&messages::fill(
+        messages.club_leaderboard_total,
+        &[
+            (
+                "distance",
+                &format!(
+                    "{:.1}",
+                    entries.iter().map(|entry| entry.distance_km).sum::<f64>()
+                ),
+            ),
+            (
+                "flights",
+                &flights(entries.iter().map(|entry| entry.flights).sum()),
+            ),
+            ("pilots", &entries.len().to_string()),
+        ],
+    ));
+    text.push('\n');
+    for (i, entry) in entries.iter().take(MAX_LEADERBOARD_ENTRIES).enumerate() {
+        text.push('\n');
+        text.push_str(&messages::fill(
+            messages.leaderboard_entry,
+            &[
+                ("rank", &(i + 1).to_string()),
+                ("pilot", &entry.pilot_username),
+                ("distance", &format!("{:.1}", entry.distance_km)),
+                ("flights", &flights(entry.flights)),
+                ("max", &format!("{:.1}", entry.max_km)),
+            ],
+        ));
+    }
+    text
+}
+
+/// Format a month (`YYYY-MM`) as `MM/YYYY`.
+fn format_month(month: &str) -> String {
+    match month.split_once('-') {
+        Some((year, month)) => format!("{}/{}", month, year),
+        None => month.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pilot: &str, flights: u32, distance_km: f64) -> LeaderboardEntry {
+        LeaderboardEntry {
+            pilot_username: pilot.into(),
+            flights,
+            distance_km,
+            max_km: distance_km / f64::from(flights),
+        }
+    }
+
+    #[test]
+    fn leaderboard_text() {
+        let club = ClubConfig {
+            code: "alpin".into(),
+            name: Some("Club Alpin".into()),
+            pilots: None,
+            roster_file: None,
+        };
+        let messages = messages::Language::German.messages();
+        assert_eq!(
+            format_leaderboard(&club, "2026-09", &[], messages),
+            "Die Mitglieder von Club Alpin haben im 09/2026 keine Flüge hochgeladen."
+        );
+        assert_eq!(
+            format_leaderboard(
+                &club,
+                "2026-09",
+                &[entry("chrigel", 2, 120.0), entry("dbrgn", 1, 30.5)],
+                messages
+            ),
+            "*Rangliste Club Alpin 09/2026* 🏆\n\
+             Total: 150.5 km (3 Flüge, 2 Piloten)\n\n\
+             1. chrigel: 120.0 km (2 Flüge, max. 60.0 km)\n\
+             2. dbrgn: 30.5 km (1 Flug, max. 30.5 km)"
+        );
+    }
+
+    #[test]
+    fn member_clubs_of_pilot() {
+        let club = |code: &str, pilots: &[&str]| ClubConfig {
+            code: code.into(),
+            name: None,
+            pilots: Some(pilots.iter().map(|pilot| pilot.to_string()).collect()),
+            roster_file: None,
+        };
+        let clubs = [
+            club("alpin", &["chrigel", "dbrgn"]),
+            club("jura", &["DBRGN"]),
+            club("ticino", &["chrigel"]),
+        ];
+        let codes = |pilot: &str| -> Vec<String> {
+            member_clubs(&clubs, pilot)
+                .unwrap()
+                .into_iter()
+                .map(|club| club.code.clone())
+                .collect()
+        };
+        assert_eq!(codes("dbrgn"), vec!["alpin", "jura"]);
+        assert_eq!(codes("nobody"), Vec::<String>::new());
+    }
+}
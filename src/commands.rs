@@ -0,0 +1,3690 @@
+//! Command handling, independent of the messaging channel.
+//!
+//! Every channel (currently only Threema) converts incoming text messages into
+//! an [`IncomingCommand`] and sends the resulting [`OutgoingReply`] back to the
+//! sender.
+
+use std::{borrow::Cow, collections::HashSet, time::Duration};
+
+use chrono::{Datelike, Local};
+use lazy_static::lazy_static;
+use regex::{Match, Regex};
+use sqlx::{Pool, Sqlite};
+use xcontest_client::{self as xcontest, Flight, ParsedTitle};
+
+use crate::{
+    clubs, competitions,
+    config::TenantConfig,
+    conversation::{self, ConversationState},
+    db::{self, User},
+    jobs::{self, Job},
+    logging,
+    messages::{self, Messages},
+    middleware::{Chain, CommandInfo},
+    scheduler,
+    status::BotStatus,
+    surveys,
+    tenants::DEFAULT_TENANT,
+    tokens,
+};
+
+/// Maximum number of payload characters shown when inspecting a parse failure
+const MAX_PAYLOAD_CHARS: usize = 2000;
+
+/// Maximum number of pilots offered when following by name
+const MAX_PILOT_CHOICES: usize = 9;
+
+/// Maximum length of a subscription label (without `#`)
+const MAX_LABEL_LENGTH: usize = 32;
+
+/// Maximum duration of a temporary subscription in days
+const MAX_FOLLOW_DAYS: u32 = 365;
+
+/// The commands available to all users
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Follow,
+    Unfollow,
+    Move,
+    List,
+    Digest,
+    Leaderboard,
+    Club,
+    Github,
+    Version,
+    Start,
+}
+
+/// All command aliases. If a mistyped command is equally close to several
+/// aliases, the first one is suggested, so German aliases come first.
+const ALIASES: &[(&str, Command)] = &[
+    ("folge", Command::Follow),
+    ("stopp", Command::Unfollow),
+    ("umziehen", Command::Move),
+    ("liste", Command::List),
+    ("zusammenfassung", Command::Digest),
+    ("rangliste", Command::Leaderboard),
+    ("club", Command::Club),
+    ("github", Command::Github),
+    ("version", Command::Version),
+    ("start", Command::Start),
+    ("follow", Command::Follow),
+    ("add", Command::Follow),
+    ("stop", Command::Unfollow),
+    ("remove", Command::Unfollow),
+    ("move", Command::Move),
+    ("list", Command::List),
+    ("digest", Command::Digest),
+    ("leaderboard", Command::Leaderboard),
+];
+
+impl Command {
+    /// Look up the command with the specified alias.
+    fn from_alias(alias: &str) -> Option<Self> {
+        ALIASES
+            .iter()
+            .find(|(name, _)| *name == alias)
+            .map(|(_, command)| *command)
+    }
+
+    /// Return the canonical name of the command.
+    fn name(self) -> &'static str {
+        match self {
+            Command::Follow => "follow",
+            Command::Unfollow => "stop",
+            Command::Move => "move",
+            Command::List => "list",
+            Command::Digest => "digest",
+            Command::Leaderboard => "leaderboard",
+            Command::Club => "club",
+            Command::Github => "github",
+            Command::Version => "version",
+            Command::Start => "start",
+        }
+    }
+
+    /// Return the usage text of the command, if it has one.
+    fn usage(self, messages: &Messages) -> Option<&'static str> {
+        match self {
+            Command::Follow => Some(messages.follow_usage),
+            Command::Unfollow => Some(messages.unfollow_usage),
+            Command::Move => Some(messages.move_usage),
+            Command::List => Some(messages.list_usage),
+            Command::Digest => Some(messages.digest_usage),
+            Command::Leaderboard => Some(messages.leaderboard_usage),
+            Command::Club => Some(messages.club_usage),
+            Command::Github => Some(messages.github_usage),
+            Command::Version | Command::Start => None,
+        }
+    }
+}
+
+/// The commands available to the admin only
+const ADMIN_COMMANDS: &[&str] = &[
+    "stats",
+    "trend",
+    "export",
+    "features",
+    "tokens",
+    "token",
+    "survey",
+    "surveys",
+    "referrals",
+    "loglevel",
+    "failures",
+    "failure",
+    "retry",
+    "prune",
+    "diagnose",
+    "simulate",
+    "msginfo",
+];
+
+/// The admin commands that affect the whole process instead of a tenant (the
+/// log filter and the parse failures of the shared feed), available to the
+/// admin of the default tenant only
+const GLOBAL_ADMIN_COMMANDS: &[&str] = &["loglevel", "failures", "failure", "retry"];
+
+/// Number of recent flights of a pilot checked by the `diagnose` command
+const DIAGNOSE_FLIGHTS: u32 = 5;
+
+/// Maximum length of a referral code
+const MAX_REFERRAL_LENGTH: usize = 32;
+
+/// Return the canonical name of the command: The admin command, `choice` for
+/// numbers (replies to a choice), the name of a user command or `unknown`.
+fn command_name(command: &str, text: &str, is_admin: bool) -> &'static str {
+    if let Some(name) = ADMIN_COMMANDS.iter().find(|name| **name == command) {
+        if is_admin {
+            return name;
+        }
+    }
+    if command.is_empty() && text.trim().parse::<usize>().is_ok() {
+        return "choice";
+    }
+    Command::from_alias(command).map_or("unknown", Command::name)
+}
+
+/// Return the alias closest to the mistyped command, if it's close enough.
+///
+/// Up to one typo is tolerated in commands with less than five characters, up
+/// to two typos in longer commands.
+fn suggest_alias(command: &str) -> Option<(&'static str, Command)> {
+    let max_distance = match command.chars().count() {
+        0..=2 => return None,
+        3..=4 => 1,
+        _ => 2,
+    };
+    ALIASES
+        .iter()
+        // The start command is only used in links, not typed by users
+        .filter(|(_, aliased)| *aliased != Command::Start)
+        .map(|(name, aliased)| (edit_distance(command, name), *name, *aliased))
+        .filter(|(distance, _, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _, _)| *distance)
+        .map(|(_, name, command)| (name, command))
+}
+
+/// Return the edit distance between two strings (optimal string alignment,
+/// i.e. Levenshtein distance where swapping two adjacent characters counts as
+/// one edit).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    d[0] = (0..=b.len()).collect();
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// A text message sent to the bot.
+#[derive(Debug, Clone, Copy)]
+pub struct IncomingCommand<'a> {
+    /// The message text
+    pub text: &'a str,
+    /// The identity of the sender in the channel (e.g. the Threema ID)
+    pub sender: &'a str,
+    /// The nickname of the sender, if known
+    pub sender_nickname: Option<&'a str>,
+    /// Whether the sender is the admin of the bot
+    pub is_admin: bool,
+}
+
+impl IncomingCommand<'_> {
+    /// Return the nickname of the sender, or their identity if unknown.
+    fn nickname_or_sender(&self) -> &str {
+        self.sender_nickname.unwrap_or(self.sender).trim()
+    }
+}
+
+/// The result of handling a command.
+pub enum OutgoingReply {
+    /// Send a reply containing the enclosed text to the sender of the command
+    Text(Cow<'static, str>),
+    /// Do nothing, processing is done
+    Nothing,
+    /// Processing failed (e.g. the Threema channel returns HTTP 500, so that
+    /// the message is delivered again)
+    Error,
+}
+
+/// Handle a command sent to the bot.
+pub async fn handle_command(
+    incoming: &IncomingCommand<'_>,
+    tenant: &TenantConfig,
+    user: &User,
+    pool: &Pool<Sqlite>,
+    status: &BotStatus,
+    middleware: &Chain,
+) -> OutgoingReply {
+    let text = incoming.text;
+
+    // Parse command and data
+    tracing::debug!("Incoming request from {}: {:?}", incoming.sender, text);
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?x)
+                    (?P<command>[a-zA-Z]*)
+                    \s*(?P<data>.*)"
+        )
+        .unwrap();
+    }
+    let caps = match RE.captures(text) {
+        Some(caps) => caps,
+        None => {
+            tracing::error!("Regex did not match incoming text {:?}", &text);
+            return OutgoingReply::Error;
+        }
+    };
+    let command = caps.name("command").unwrap().as_str().to_ascii_lowercase();
+
+    let name = command_name(&command, text, incoming.is_admin);
+
+    // Ignore commands resent by flaky clients (the same choice may be the
+    // answer to another prompt)
+    let window = tenant.duplicate_window_seconds();
+    let deduplicate = window > 0 && name != "choice";
+    if deduplicate {
+        match db::is_duplicate_command(pool, user.id, text.trim(), window).await {
+            Ok(false) => {}
+            Ok(true) => {
+                tracing::info!("Ignoring duplicate command from {}", incoming.sender);
+                return OutgoingReply::Nothing;
+            }
+            Err(e) => tracing::warn!("Could not check for duplicate command: {}", e),
+        }
+    }
+
+    // Process command, wrapped in the middleware
+    let info = CommandInfo {
+        name,
+        tenant: &tenant.id,
+        sender: incoming.sender,
+        is_admin: incoming.is_admin,
+    };
+    let messages = tenant.messages();
+    let handler = async {
+        match name {
+            name if GLOBAL_ADMIN_COMMANDS.contains(&name) && tenant.id != DEFAULT_TENANT => {
+                OutgoingReply::Text(Cow::Borrowed(
+                    "This command is only available to the admin of the default tenant.",
+                ))
+            }
+            "stats" => handle_admin_stats(incoming.sender, tenant, pool, status).await,
+            "trend" => handle_admin_trend(tenant, pool).await,
+            "prune" => handle_admin_prune(tenant, pool).await,
+            "diagnose" => handle_admin_diagnose(caps.name("data"), tenant, pool).await,
+            "simulate" => handle_admin_simulate(caps.name("data"), tenant, pool).await,
+            "export" => handle_admin_export(caps.name("data"), tenant, pool).await,
+            "tokens" => handle_admin_tokens(tenant, pool).await,
+            "token" => handle_admin_token(caps.name("data"), tenant, pool).await,
+            "features" => {
+                OutgoingReply::Text(format!("Features: {}", tenant.features.summary()).into())
+            }
+            "survey" | "surveys" if !tenant.features.surveys() => {
+                OutgoingReply::Text(Cow::Borrowed("Surveys are disabled."))
+            }
+            "survey" => handle_admin_survey(caps.name("data"), tenant, pool).await,
+            "surveys" => handle_admin_surveys(tenant, pool).await,
+            "referrals" => handle_admin_referrals(tenant, pool).await,
+            "loglevel" => handle_admin_loglevel(caps.name("data")).await,
+            "failures" => handle_admin_failures(pool).await,
+            "failure" => handle_admin_failure(caps.name("data"), pool).await,
+            "retry" => handle_admin_retry(caps.name("data"), pool).await,
+            "msginfo" => handle_admin_msginfo(caps.name("data"), tenant, pool).await,
+            "choice" => handle_choice(text.trim(), incoming, tenant, user, pool).await,
+            _ => match Command::from_alias(&command) {
+                Some(Command::Follow) => handle_follow(caps.name("data"), tenant, user, pool).await,
+                Some(Command::Unfollow) => {
+                    handle_unfollow(caps.name("data"), messages, user, pool).await
+                }
+                Some(Command::Move) => handle_move(caps.name("data"), messages, user, pool).await,
+                Some(Command::List) => handle_list(caps.name("data"), tenant, user, pool).await,
+                Some(Command::Digest) if !tenant.features.digests() => {
+                    OutgoingReply::Text(Cow::Borrowed(messages.feature_disabled))
+                }
+                Some(Command::Digest) => {
+                    handle_digest(caps.name("data"), messages, user, pool).await
+                }
+                Some(Command::Leaderboard) => handle_leaderboard(messages, user, pool).await,
+                Some(Command::Club) => handle_club(caps.name("data"), tenant, user, pool).await,
+                Some(Command::Github) => handle_github(messages).await,
+                Some(Command::Version) => handle_version(tenant, status).await,
+                Some(Command::Start) => {
+                    handle_start(caps.name("data"), incoming, tenant, user, pool).await
+                }
+                None => handle_unknown_command(&command, incoming, tenant).await,
+            },
+        }
+    };
+    let reply = middleware.run(&info, handler).await;
+
+    // Only handled commands are recorded, so that a message delivered again
+    // after a failure is processed
+    if deduplicate && !matches!(reply, OutgoingReply::Error) {
+        if let Err(e) = db::record_command(pool, user.id, text.trim()).await {
+            tracing::warn!("Could not record command: {}", e);
+        }
+    }
+    reply
+}
+
+/// Prune the data older than configured (usually done by the maintenance task).
+async fn handle_admin_prune(tenant: &TenantConfig, pool: &Pool<Sqlite>) -> OutgoingReply {
+    match scheduler::prune_data(pool, &tenant.retention).await {
+        Ok(pruned) => OutgoingReply::Text(
+            format!(
+                "Pruned {} flights (older than {} days), {} sent messages \
+                 (older than {} days) and {} parse failures (older than {} days).",
+                pruned.flights,
+                tenant.retention.flights_days(),
+                pruned.notifications,
+                tenant.retention.notifications_days(),
+                pruned.parse_failures,
+                tenant.retention.parse_failures_days(),
+            )
+            .into(),
+        ),
+        Err(e) => {
+            tracing::error!("Could not prune data: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to show the trend of the daily stats snapshots
+async fn handle_admin_trend(tenant: &TenantConfig, pool: &Pool<Sqlite>) -> OutgoingReply {
+    let history = match db::get_stats_history(pool, &tenant.id, db::STATS_TREND_DAYS).await {
+        Ok(history) => history,
+        Err(e) => {
+            tracing::error!("Could not fetch stats history: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    let (first, last) = match (history.first(), history.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return OutgoingReply::Text(Cow::Borrowed("No stats snapshots yet.")),
+    };
+    let mut reply = format!(
+        "Trend of the last {} days ({} to {}, {} snapshots):\n",
+        db::STATS_TREND_DAYS,
+        first.date,
+        last.date,
+        history.len()
+    );
+    for (name, first, last) in [
+        ("Users", first.users, last.users),
+        ("Subscriptions", first.subscriptions, last.subscriptions),
+        ("Flights", first.flights, last.flights),
+        ("Notifications", first.notifications, last.notifications),
+        ("Parse failures", first.failures, last.failures),
+    ] {
+        reply.push_str(&format!(
+            "\n- {}: {} → {} ({:+})",
+            name,
+            first,
+            last,
+            i64::from(last) - i64::from(first)
+        ));
+    }
+    OutgoingReply::Text(reply.into())
+}
+
+/// Handle command to show admin stats
+async fn handle_admin_stats(
+    sender_identity: &str,
+    tenant: &TenantConfig,
+    pool: &Pool<Sqlite>,
+    status: &BotStatus,
+) -> OutgoingReply {
+    tracing::info!("Received stats request from admin {}", sender_identity);
+    match db::get_stats(pool, &tenant.id).await {
+        Ok(stats) => {
+            let mut reply = format!(
+                "Database stats:\n\n- Users: {}\n- Subscriptions: {} ({} added in the last 30 days)\n- Flights: {}",
+                stats.user_count,
+                stats.subscription_count,
+                stats.new_subscription_count,
+                stats.flight_count
+            );
+
+            // Notifications and estimated gateway costs (1 credit per message,
+            // plus 2 credits for the blob uploads of an image message)
+            reply.push_str(&format!(
+                "\n\nNotifications this month: {} ({} with image, ~{} credits)",
+                stats.notifications_this_month,
+                stats.images_this_month,
+                stats.notifications_this_month + 2 * stats.images_this_month,
+            ));
+            match db::get_top_notification_counters(pool, &tenant.id, &db::current_month(), 5).await
+            {
+                Ok(counters) => {
+                    for counter in counters {
+                        reply.push_str(&format!(
+                            "\n- {}/{}: {} ({} with image)",
+                            counter.usertype, counter.username, counter.messages, counter.images
+                        ));
+                    }
+                }
+                Err(e) => tracing::error!("Could not fetch notification counters: {}", e),
+            }
+
+            let commands = status.command_counts();
+            if !commands.is_empty() {
+                reply.push_str("\n\nCommands since start:");
+                for (name, handled, failures) in commands {
+                    reply.push_str(&format!("\n- {}: {}", name, handled));
+                    if failures > 0 {
+                        reply.push_str(&format!(" ({} failed)", failures));
+                    }
+                }
+            }
+
+            if let Some(last) = status.last_parse_coverage() {
+                let total = status.total_parse_coverage();
+                reply.push_str(&format!(
+                    "\n\nFeed items in the last fetch: {} ({} not parseable)\n\
+                     Feed items since start: {} ({} not parseable, {:.1}%)",
+                    last.parsed + last.failed,
+                    last.failed,
+                    total.parsed + total.failed,
+                    total.failed,
+                    total.failure_percent(),
+                ));
+            }
+
+            match db::get_queue_depths(pool).await {
+                Ok(depths) if !depths.is_empty() => {
+                    reply.push_str("\n\nJob queue:");
+                    for depth in depths {
+                        reply.push_str(&format!("\n- {}", depth.kind));
+                        if let Some(channel) = &depth.channel {
+                            reply.push_str(&format!(" ({})", channel));
+                        }
+                        reply.push_str(&format!(
+                            ": {} pending, oldest {}",
+                            depth.pending,
+                            format_uptime(chrono::Duration::seconds(depth.oldest_age_seconds))
+                        ));
+                        if depth.retrying > 0 {
+                            reply.push_str(&format!(
+                                ", ⚠️ {} retrying ({} failed attempts)",
+                                depth.retrying, depth.failed_attempts
+                            ));
+                        }
+                    }
+                }
+                Ok(_) => reply.push_str("\n\nJob queue: empty"),
+                Err(e) => tracing::error!("Could not fetch queue depths: {}", e),
+            }
+
+            if let Some(latency) = status.notification_latency() {
+                reply.push_str(&format!(
+                    "\n\nNotification latency (last {} flights): median {}s, 90th percentile {}s, \
+                     99th percentile {}s",
+                    latency.samples, latency.p50, latency.p90, latency.p99,
+                ));
+            }
+
+            let busy_retries = db::busy_retries();
+            if busy_retries > 0 {
+                reply.push_str(&format!(
+                    "\n\n⚠️ Database writes retried because the database was busy: {}",
+                    busy_retries
+                ));
+            }
+
+            let panics = status.panics();
+            if panics > 0 {
+                reply.push_str(&format!("\n\n⚠️ Panics since start: {}", panics));
+            }
+
+            if let Some(throttling) = status.throttling() {
+                reply.push_str(&format!(
+                    "\n\n⚠️ XContest is throttling requests (HTTP {}) since {}, next attempt at {}",
+                    throttling.status.as_u16(),
+                    throttling.since.format("%Y-%m-%d %H:%M"),
+                    throttling.until.format("%Y-%m-%d %H:%M"),
+                ));
+            }
+            OutgoingReply::Text(reply.into())
+        }
+        Err(e) => {
+            tracing::error!("Could not fetch stats: {}", e);
+            OutgoingReply::Nothing
+        }
+    }
+}
+
+/// Handle command to find out why a user wasn't notified about a pilot's
+/// flight: Whether the flights were seen in the feed, whether the user follows
+/// the pilot (or a similar username), and how the notifications were delivered.
+async fn handle_admin_diagnose(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let mut args = command_data
+        .map_or("", |data| data.as_str())
+        .split_whitespace();
+    let (pilot, username) = match (args.next(), args.next(), args.next()) {
+        (Some(pilot), username, None) => (pilot, username),
+        _ => return OutgoingReply::Text(Cow::Borrowed("Usage: diagnose <pilot> [user]")),
+    };
+    let mut reply = format!("Diagnosis for pilot {}:\n", pilot);
+
+    // Flights seen in the feed
+    let flights = match db::get_pilot_flights(pool, &tenant.id, pilot, DIAGNOSE_FLIGHTS).await {
+        Ok(flights) => flights,
+        Err(e) => {
+            tracing::error!("Could not fetch flights of pilot: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    if flights.is_empty() {
+        reply.push_str(&format!(
+            "\n⚠️ No flights of {} seen in the feed {}. Check the username on XContest.",
+            pilot,
+            tenant.feed_url()
+        ));
+    } else {
+        reply.push_str("\nRecent flights in the feed:");
+        for flight in &flights {
+            reply.push_str(&format!(
+                "\n- {} (seen {}, {})",
+                flight.title,
+                flight.seen_at.as_deref().unwrap_or("?"),
+                match &flight.notified_at {
+                    Some(notified_at) => format!("notified {}", notified_at),
+                    None => "not notified yet, notifications are grouped".to_string(),
+                },
+            ));
+        }
+    }
+    match db::get_pilot_subscribers(pool, &tenant.id, pilot).await {
+        Ok(subscribers) => reply.push_str(&format!("\nSubscribers: {}", subscribers.len())),
+        Err(e) => tracing::error!("Could not fetch subscribers: {}", e),
+    }
+
+    let username = match username {
+        Some(username) => username,
+        None => return OutgoingReply::Text(reply.into()),
+    };
+    let user = match db::find_user(pool, &tenant.id, username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            reply.push_str(&format!("\n\n⚠️ User {} not found.", username));
+            return OutgoingReply::Text(reply.into());
+        }
+        Err(e) => {
+            tracing::error!("Could not fetch user: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    reply.push_str(&format!("\n\nUser {}/{}:", user.usertype, user.username));
+
+    // Subscription, including usernames that only almost match
+    let subscriptions = match db::get_subscription_list(pool, user.id, None, false).await {
+        Ok(subscriptions) => subscriptions,
+        Err(e) => {
+            tracing::error!("Could not fetch subscriptions for uid {}: {}", user.id, e);
+            return OutgoingReply::Error;
+        }
+    };
+    let normalize = |username: &str| username.trim().trim_start_matches('@').to_lowercase();
+    match subscriptions
+        .iter()
+        .find(|subscription| subscription.pilot_username.eq_ignore_ascii_case(pilot))
+    {
+        Some(subscription) => {
+            reply.push_str(&format!("\n- ✅ Follows {}", subscription.pilot_username));
+            if let Some(expires_at) = &subscription.expires_at {
+                reply.push_str(&format!(" (until {})", expires_at));
+            }
+        }
+        None => {
+            reply.push_str(&format!("\n- ⚠️ Does not follow {}", pilot));
+            let similar: Vec<&str> = subscriptions
+                .iter()
+                .map(|subscription| subscription.pilot_username.as_str())
+                .filter(|followed| {
+                    normalize(followed) == normalize(pilot)
+                        || edit_distance(&normalize(followed), &normalize(pilot)) <= 2
+                })
+                .collect();
+            if !similar.is_empty() {
+                reply.push_str(&format!(
+                    " (similar subscriptions: {})",
+                    similar
+                        .iter()
+                        .map(|followed| format!("{:?}", followed))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+    }
+
+    // Digest and monthly cap
+    match db::get_digest(pool, user.id).await {
+        Ok(true) => reply.push_str("\n- Receives a daily digest instead of instant notifications"),
+        Ok(false) => {}
+        Err(e) => tracing::error!("Could not fetch digest setting: {}", e),
+    }
+    match db::get_notification_counter(pool, user.id, &db::current_month()).await {
+        Ok(counter) => {
+            reply.push_str(&format!(
+                "\n- Notifications this month: {}",
+                counter.messages
+            ));
+            if counter.capped {
+                reply.push_str(" (⚠️ monthly limit reached)");
+            }
+        }
+        Err(e) => tracing::error!("Could not fetch notification counter: {}", e),
+    }
+
+    // Delivery of the recent flights
+    if !flights.is_empty() {
+        match db::get_notified_pilot_flights(pool, user.id, pilot).await {
+            Ok(notified) => reply.push_str(&format!(
+                "\n- Delivered: {} of the {} recent flights",
+                flights
+                    .iter()
+                    .filter(|flight| notified.contains(&flight.url))
+                    .count(),
+                flights.len()
+            )),
+            Err(e) => tracing::error!("Could not fetch notification messages: {}", e),
+        }
+    }
+    match db::get_pending_notifications(pool, user.id).await {
+        Ok(pending) => {
+            if let Some((attempts, last_error)) = pending.last() {
+                reply.push_str(&format!(
+                    "\n- ⚠️ Notification retries queued: {} (last one after {} attempts: {})",
+                    pending.len(),
+                    attempts,
+                    last_error.as_deref().unwrap_or("no error"),
+                ));
+            }
+        }
+        Err(e) => tracing::error!("Could not fetch pending notifications: {}", e),
+    }
+    OutgoingReply::Text(reply.into())
+}
+
+/// Handle command to send a simulated flight to a user through the normal
+/// notification pipeline (marked as test message). The preview image is
+/// fetched if the URL is a real flight.
+async fn handle_admin_simulate(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let usage = "Usage: simulate <user> <flight-url> [title]";
+    let data = command_data.map_or("", |data| data.as_str()).trim();
+    let (username, rest) = match data.split_once(char::is_whitespace) {
+        Some((username, rest)) => (username, rest.trim()),
+        None => return OutgoingReply::Text(Cow::Borrowed(usage)),
+    };
+    let (flight_url, title) = match rest.split_once(char::is_whitespace) {
+        Some((flight_url, title)) => (flight_url, Some(title.trim())),
+        None => (rest, None),
+    };
+    let pilot = match Flight::new(String::new(), flight_url.to_string()) {
+        Ok(flight) => flight.pilot_username,
+        Err(e) => return OutgoingReply::Text(format!("{:#}\n\n{}", e, usage).into()),
+    };
+    let title = title.map_or_else(
+        || {
+            format!(
+                "{} [42.00 km :: free_flight] {}",
+                Local::now().format("%d.%m.%y"),
+                pilot
+            )
+        },
+        str::to_string,
+    );
+    let user = match db::find_user(pool, &tenant.id, username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return OutgoingReply::Text(format!("User {} not found.", username).into()),
+        Err(e) => {
+            tracing::error!("Could not fetch user: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    let job = Job::SimulateFlight {
+        user_id: user.id,
+        title: title.clone(),
+        flight_url: flight_url.to_string(),
+    };
+    if let Err(e) = jobs::enqueue(pool, &job, Duration::ZERO).await {
+        tracing::error!("Could not enqueue simulated flight: {}", e);
+        return OutgoingReply::Error;
+    }
+    tracing::info!(
+        "Simulated flight {} queued for user {}",
+        flight_url,
+        user.id
+    );
+    OutgoingReply::Text(
+        format!(
+            "Simulated flight \"{}\" queued for {}/{}. Failures are retried and alerted like real notifications.",
+            title, user.usertype, user.username
+        )
+        .into(),
+    )
+}
+
+/// Handle command to export anonymized usage statistics (e.g. for a yearly
+/// blog post)
+async fn handle_admin_export(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let year = match command_data.map(|data| data.as_str().trim()) {
+        None | Some("") => Local::now().year(),
+        Some(year) => match year.parse() {
+            Ok(year) => year,
+            Err(_) => return OutgoingReply::Text(Cow::Borrowed("Usage: export [year]")),
+        },
+    };
+    let stats = match db::get_usage_stats(pool, &tenant.id, year).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            tracing::error!("Could not fetch usage stats: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+
+    // Counts based on too few users are only shown as upper bound
+    let count = |count: Option<u32>| match count {
+        Some(count) => count.to_string(),
+        None => format!("<{}", db::USAGE_STATS_MIN_USERS),
+    };
+    let mut reply = format!(
+        "Usage statistics {} (anonymized):\n\n- Users: {}\n- Users with digest: {}\n- Subscriptions: {}\n- Followed pilots: {}\n- Flights: {}",
+        year,
+        count(stats.users),
+        count(stats.digest_users),
+        count(stats.subscriptions),
+        count(stats.followed_pilots),
+        stats.flights,
+    );
+    reply.push_str("\n\nUsers by number of subscriptions:");
+    let buckets = db::USAGE_STATS_BUCKETS;
+    for (i, (bucket, users)) in stats.subscriptions_per_user.iter().enumerate() {
+        let label = match buckets.get(i + 1) {
+            Some(next) if next - bucket > 1 => format!("{}-{}", bucket, next - 1),
+            Some(_) => bucket.to_string(),
+            None => format!("{}+", bucket),
+        };
+        reply.push_str(&format!("\n- {}: {}", label, count(*users)));
+    }
+    reply.push_str("\n\nNew users / notifications per month:");
+    for month in &stats.months {
+        reply.push_str(&format!(
+            "\n- {}: {} / {}",
+            month.month,
+            count(month.new_users),
+            count(month.notifications)
+        ));
+    }
+    OutgoingReply::Text(reply.into())
+}
+
+/// Handle command to list the API tokens of the tenant
+async fn handle_admin_tokens(tenant: &TenantConfig, pool: &Pool<Sqlite>) -> OutgoingReply {
+    match db::get_api_tokens(pool, Some(&tenant.id)).await {
+        Ok(tokens) if tokens.is_empty() => OutgoingReply::Text(Cow::Borrowed("No API tokens.")),
+        Ok(tokens) => {
+            let mut reply = String::from("API tokens:\n");
+            for token in &tokens {
+                reply.push_str(&format!("\n- {}", tokens::format_token(token)));
+            }
+            OutgoingReply::Text(reply.into())
+        }
+        Err(e) => {
+            tracing::error!("Could not fetch API tokens: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to create or revoke an API token of the tenant
+async fn handle_admin_token(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let usage = "Usage: token create <name> [read|admin] / token revoke <id>";
+    let args: Vec<&str> = command_data
+        .map(|data| data.as_str().split_whitespace().collect())
+        .unwrap_or_default();
+    match &args[..] {
+        ["create", name, scope @ ..] => {
+            let scope = match scope {
+                [] => tokens::Scope::Read,
+                [scope] => match scope.parse::<tokens::Scope>() {
+                    Ok(scope) => scope,
+                    Err(e) => return OutgoingReply::Text(e.to_string().into()),
+                },
+                _ => return OutgoingReply::Text(Cow::Borrowed(usage)),
+            };
+            match tokens::create(pool, Some(&tenant.id), name, scope).await {
+                Ok((id, token)) => OutgoingReply::Text(
+                    format!(
+                        "Created token {} ({}, {}):\n\n{}\n\nStore the token now, it can't be shown again.",
+                        id, name, scope, token
+                    )
+                    .into(),
+                ),
+                Err(e) => {
+                    tracing::error!("Could not create API token: {}", e);
+                    OutgoingReply::Error
+                }
+            }
+        }
+        ["revoke", id] => match id.parse() {
+            Ok(id) => match db::delete_api_token(pool, Some(&tenant.id), id).await {
+                Ok(true) => OutgoingReply::Text(format!("Revoked token {}.", id).into()),
+                Ok(false) => OutgoingReply::Text(format!("Token {} does not exist.", id).into()),
+                Err(e) => {
+                    tracing::error!("Could not revoke API token: {}", e);
+                    OutgoingReply::Error
+                }
+            },
+            Err(_) => OutgoingReply::Text(Cow::Borrowed(usage)),
+        },
+        _ => OutgoingReply::Text(Cow::Borrowed(usage)),
+    }
+}
+
+/// Handle command to create a survey or to show its results
+async fn handle_admin_survey(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let usage =
+        "Usage: survey [sample <n>] <question> | <option1> | <option2> [| ...] / survey <id>";
+    let data = command_data.map_or("", |data| data.as_str().trim());
+
+    // Show results
+    if let Ok(id) = data.parse::<i64>() {
+        let survey = match db::get_survey(pool, id).await {
+            Ok(Some(survey)) if survey.tenant == tenant.id => survey,
+            Ok(_) => return OutgoingReply::Text(format!("Survey {} does not exist.", id).into()),
+            Err(e) => {
+                tracing::error!("Could not fetch survey: {}", e);
+                return OutgoingReply::Error;
+            }
+        };
+        return match surveys::format_results(pool, &survey).await {
+            Ok(results) => OutgoingReply::Text(results.into()),
+            Err(e) => {
+                tracing::error!("Could not fetch survey results: {}", e);
+                OutgoingReply::Error
+            }
+        };
+    }
+
+    // Create survey
+    let (sample, data) = match data.strip_prefix("sample ") {
+        Some(rest) => {
+            let (size, rest) = rest.trim_start().split_once(' ').unwrap_or((rest, ""));
+            match size.parse::<u32>() {
+                Ok(size) if size > 0 => (Some(size), rest),
+                _ => return OutgoingReply::Text(Cow::Borrowed(usage)),
+            }
+        }
+        None => (None, data),
+    };
+    let mut parts = data.split('|').map(str::trim);
+    let question = parts.next().unwrap_or_default();
+    let options: Vec<String> = parts.map(ToOwned::to_owned).collect();
+    if question.is_empty() || options.len() < 2 || options.iter().any(|option| option.is_empty()) {
+        return OutgoingReply::Text(Cow::Borrowed(usage));
+    }
+    match surveys::create(pool, &tenant.id, question, &options, sample).await {
+        Ok((id, recipients)) => OutgoingReply::Text(
+            format!(
+                "Created survey {}, sending the poll to {} users. Show the results with \"survey {}\".",
+                id, recipients, id
+            )
+            .into(),
+        ),
+        Err(e) => {
+            tracing::error!("Could not create survey: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to list the surveys
+async fn handle_admin_surveys(tenant: &TenantConfig, pool: &Pool<Sqlite>) -> OutgoingReply {
+    match db::get_surveys(pool, &tenant.id, 20).await {
+        Ok(surveys) if surveys.is_empty() => OutgoingReply::Text(Cow::Borrowed("No surveys.")),
+        Ok(surveys) => {
+            let mut reply = String::from("Surveys:\n");
+            for survey in &surveys {
+                reply.push_str(&format!(
+                    "\n- {} ({}): {} (sent to {} users)",
+                    survey.id, survey.created_at, survey.question, survey.recipients
+                ));
+            }
+            OutgoingReply::Text(reply.into())
+        }
+        Err(e) => {
+            tracing::error!("Could not fetch surveys: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to show the signups per referral code
+async fn handle_admin_referrals(tenant: &TenantConfig, pool: &Pool<Sqlite>) -> OutgoingReply {
+    match db::get_referral_counts(pool, &tenant.id).await {
+        Ok(counts) => {
+            let mut reply = String::from("Users by referral code:\n");
+            for count in &counts {
+                reply.push_str(&format!(
+                    "\n- {}: {} ({} in the last 30 days)",
+                    count.referral.as_deref().unwrap_or("(none)"),
+                    count.users,
+                    count.new_users
+                ));
+            }
+            OutgoingReply::Text(reply.into())
+        }
+        Err(e) => {
+            tracing::error!("Could not fetch referral counts: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to show or replace the log filter
+async fn handle_admin_loglevel(data: Option<Match<'_>>) -> OutgoingReply {
+    let result = match data.map_or("", |data| data.as_str().trim()) {
+        "" => logging::current_filter().map(|filter| format!("Log filter: {}", filter)),
+        "reset" => logging::reset_filter().map(|filter| format!("Log filter reset to {}", filter)),
+        filter => logging::set_filter(filter).map(|()| format!("Log filter set to {}", filter)),
+    };
+    match result {
+        Ok(reply) => OutgoingReply::Text(reply.into()),
+        Err(e) => OutgoingReply::Text(format!("Error: {:#}", e).into()),
+    }
+}
+
+/// Handle command to list quarantined parse failures
+async fn handle_admin_failures(pool: &Pool<Sqlite>) -> OutgoingReply {
+    match db::get_parse_failures(pool, 20).await {
+        Ok(failures) if failures.is_empty() => {
+            OutgoingReply::Text(Cow::Borrowed("No parse failures."))
+        }
+        Ok(failures) => {
+            let mut reply = String::from("Parse failures (most recent first):\n");
+            for failure in failures {
+                reply.push_str(&format!(
+                    "\n- #{} [{}] {} ({}x, last seen {})",
+                    failure.id,
+                    failure.kind,
+                    failure.source,
+                    failure.occurrences,
+                    failure.last_seen
+                ));
+            }
+            reply.push_str("\n\nUse \"failure <id>\" to inspect or \"retry <id>\" to re-parse.");
+            OutgoingReply::Text(reply.into())
+        }
+        Err(e) => {
+            tracing::error!("Could not fetch parse failures: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Parse the ID of a parse failure from the command data
+fn parse_failure_id(command_data: Option<Match<'_>>) -> Option<i64> {
+    command_data.and_then(|data| data.as_str().trim().trim_start_matches('#').parse().ok())
+}
+
+/// Handle command to inspect a quarantined parse failure
+async fn handle_admin_failure(
+    command_data: Option<Match<'_>>,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let id = match parse_failure_id(command_data) {
+        Some(id) => id,
+        None => return OutgoingReply::Text(Cow::Borrowed("Usage: failure <id>")),
+    };
+    match db::get_parse_failure(pool, id).await {
+        Ok(Some(failure)) => {
+            let payload: String = failure.payload.chars().take(MAX_PAYLOAD_CHARS).collect();
+            let ellipsis = if payload.len() < failure.payload.len() {
+                "…"
+            } else {
+                ""
+            };
+            OutgoingReply::Text(
+                format!(
+                    "Parse failure #{}\n\nKind: {}\nSource: {}\nError: {}\nOccurrences: {}\nFirst seen: {}\nLast seen: {}\n\nPayload:\n{}{}",
+                    failure.id,
+                    failure.kind,
+                    failure.source,
+                    failure.error,
+                    failure.occurrences,
+                    failure.first_seen,
+                    failure.last_seen,
+                    payload,
+                    ellipsis,
+                )
+                .into(),
+            )
+        }
+        Ok(None) => OutgoingReply::Text(format!("Parse failure #{} not found.", id).into()),
+        Err(e) => {
+            tracing::error!("Could not fetch parse failure: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to look up a sent message of the tenant by its gateway
+/// message ID
+async fn handle_admin_msginfo(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let message_id = match command_data {
+        Some(data) => data.as_str().trim().to_lowercase(),
+        None => return OutgoingReply::Text(Cow::Borrowed("Usage: msginfo <message-id>")),
+    };
+    match db::get_sent_message(pool, &tenant.id, &message_id).await {
+        Ok(Some(message)) => {
+            let flight = match (&message.flight_url, &message.flight_title) {
+                (Some(url), Some(title)) => format!("{}\n{}", title, url),
+                (Some(url), None) => url.clone(),
+                (None, _) => "-".to_string(),
+            };
+            let unconfirmed = || "not confirmed".to_string();
+            OutgoingReply::Text(
+                format!(
+                    "Message {}\n\nKind: {}\nUser: {}/{} (#{})\nFlight: {}\nSent: {}\nDelivered: {}\nRead: {}",
+                    message.message_id,
+                    message.kind,
+                    message.usertype,
+                    message.username,
+                    message.user_id,
+                    flight,
+                    message.sent_at,
+                    message.delivered_at.unwrap_or_else(unconfirmed),
+                    message.read_at.unwrap_or_else(unconfirmed),
+                )
+                .into(),
+            )
+        }
+        Ok(None) => OutgoingReply::Text(format!("Message {} not found.", message_id).into()),
+        Err(e) => {
+            tracing::error!("Could not fetch sent message: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to re-parse a quarantined payload
+async fn handle_admin_retry(command_data: Option<Match<'_>>, pool: &Pool<Sqlite>) -> OutgoingReply {
+    let id = match parse_failure_id(command_data) {
+        Some(id) => id,
+        None => return OutgoingReply::Text(Cow::Borrowed("Usage: retry <id>")),
+    };
+    let failure = match db::get_parse_failure(pool, id).await {
+        Ok(Some(failure)) => failure,
+        Ok(None) => return OutgoingReply::Text(format!("Parse failure #{} not found.", id).into()),
+        Err(e) => {
+            tracing::error!("Could not fetch parse failure: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    let kind = match failure.payload_kind() {
+        Some(kind) => kind,
+        None => {
+            return OutgoingReply::Text(format!("Unknown payload kind: {}", failure.kind).into())
+        }
+    };
+    match xcontest::reparse(kind, &failure.payload) {
+        Ok(result) => {
+            if let Err(e) = db::delete_parse_failure(pool, id).await {
+                tracing::error!("Could not delete parse failure: {}", e);
+                return OutgoingReply::Error;
+            }
+            OutgoingReply::Text(
+                format!(
+                    "✅ Parse failure #{} parses now: {}\n\n\
+                    The entry was removed. If the item is still in the feed, \
+                    it will be processed during the next update.",
+                    id, result
+                )
+                .into(),
+            )
+        }
+        Err(e) => {
+            OutgoingReply::Text(format!("❌ Parse failure #{} still fails: {}", id, e).into())
+        }
+    }
+}
+
+/// Parse a subscription label (`#team`), return it in lowercase without `#`.
+fn parse_label(word: &str) -> Option<String> {
+    let label = word.strip_prefix('#')?;
+    let valid = !label.is_empty()
+        && label.chars().count() <= MAX_LABEL_LENGTH
+        && label
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| label.to_lowercase())
+}
+
+/// Parse the duration of a temporary subscription (`3d`/`3t` for days, `2w`
+/// for weeks), return it in days.
+///
+/// Return `Some(None)` for words that look like a duration but are out of
+/// range, and `None` for other words.
+fn parse_follow_duration(word: &str) -> Option<Option<u32>> {
+    let (index, unit) = word.char_indices().last()?;
+    let number = word[..index].parse::<u32>().ok()?;
+    let days = match unit.to_ascii_lowercase() {
+        'd' | 't' => Some(number),
+        'w' => number.checked_mul(7),
+        _ => return None,
+    };
+    Some(days.filter(|days| (1..=MAX_FOLLOW_DAYS).contains(days)))
+}
+
+/// Handle command to follow a pilot (optionally with a label and for a
+/// limited time) or a competition roster (`folge comp <code>`)
+async fn handle_follow(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let messages = tenant.messages();
+    let usage = messages.follow_usage;
+
+    let mut pilot = match command_data {
+        Some(data) => data.as_str().trim(),
+        None => return OutgoingReply::Text(Cow::Borrowed(usage)),
+    };
+
+    // Split off label and duration (in any order)
+    let mut label = None;
+    let mut days = None;
+    while let Some((rest, word)) = pilot.rsplit_once(char::is_whitespace) {
+        if word.starts_with('#') && label.is_none() {
+            match parse_label(word) {
+                Some(parsed) => label = Some(parsed),
+                None => return OutgoingReply::Text(Cow::Borrowed(messages.label_invalid)),
+            }
+        } else if let (Some(parsed), None) = (parse_follow_duration(word), days) {
+            match parsed {
+                Some(parsed) => days = Some(parsed),
+                None => {
+                    return OutgoingReply::Text(Cow::Borrowed(messages.follow_duration_invalid))
+                }
+            }
+        } else {
+            break;
+        }
+        pilot = rest.trim();
+    }
+
+    // Follow a competition roster (a bare `comp` shows the usage)
+    let (first, roster) = pilot.split_once(char::is_whitespace).unwrap_or((pilot, ""));
+    if first.eq_ignore_ascii_case("comp") {
+        return handle_follow_competition(roster.trim(), label, days, tenant, user, pool).await;
+    }
+
+    // Validate pilot name
+    if pilot.is_empty() || pilot.starts_with('#') {
+        return OutgoingReply::Text(Cow::Borrowed(usage));
+    }
+    if pilot.contains(char::is_whitespace) {
+        return handle_follow_by_name(pilot, label, days, messages, user, pool).await;
+    }
+
+    // Add subscription
+    follow(pilot, pilot, label.as_deref(), days, messages, user, pool).await
+}
+
+/// Follow all pilots of a configured competition (by code or URL) until the
+/// day after the competition, or of a pasted list of usernames.
+///
+/// Pilots the user already follows are skipped, so that their subscriptions
+/// don't end with the competition.
+async fn handle_follow_competition(
+    roster: &str,
+    label: Option<String>,
+    days: Option<u32>,
+    tenant: &TenantConfig,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let messages = tenant.messages();
+
+    // Look up roster
+    let (usernames, label, days) = match competitions::find(&tenant.competitions, roster) {
+        Some(competition) => {
+            let days = match competitions::days_left(competition, Local::now().date_naive()) {
+                Ok(Some(days)) => days,
+                Ok(None) => {
+                    return OutgoingReply::Text(
+                        messages::fill(
+                            messages.comp_over,
+                            &[("name", competitions::name(competition))],
+                        )
+                        .into(),
+                    )
+                }
+                Err(e) => {
+                    tracing::error!("{:#}", e);
+                    return OutgoingReply::Error;
+                }
+            };
+            let usernames = match competitions::roster(competition) {
+                Ok(usernames) => usernames,
+                Err(e) => {
+                    tracing::error!("Could not load roster: {:#}", e);
+                    return OutgoingReply::Error;
+                }
+            };
+            let label = label.unwrap_or_else(|| {
+                parse_label(&format!("#{}", competition.code))
+                    .unwrap_or_else(|| competitions::PASTED_ROSTER_LABEL.to_string())
+            });
+            (usernames, label, days)
+        }
+        None => {
+            let usernames = competitions::parse_usernames(roster);
+            if usernames.len() < 2 {
+                let mut reply = match usernames.first() {
+                    Some(code) => messages::fill(messages.comp_unknown, &[("code", code)]) + "\n\n",
+                    None => String::new(),
+                };
+                reply.push_str(messages.comp_usage);
+                if !tenant.competitions.is_empty() {
+                    let codes = tenant
+                        .competitions
+                        .iter()
+                        .map(|competition| competition.code.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    reply.push('\n');
+                    reply.push_str(&messages::fill(messages.comp_list, &[("codes", &codes)]));
+                }
+                return OutgoingReply::Text(reply.into());
+            }
+            (
+                usernames,
+                label.unwrap_or_else(|| competitions::PASTED_ROSTER_LABEL.to_string()),
+                days.unwrap_or(competitions::PASTED_ROSTER_DAYS),
+            )
+        }
+    };
+    if usernames.len() > competitions::MAX_ROSTER_SIZE {
+        return OutgoingReply::Text(
+            messages::fill(
+                messages.comp_too_many,
+                &[("max", &competitions::MAX_ROSTER_SIZE.to_string())],
+            )
+            .into(),
+        );
+    }
+
+    // Skip the pilots the user already follows
+    let followed: HashSet<String> =
+        match db::get_subscription_list(pool, user.id, None, false).await {
+            Ok(subscriptions) => subscriptions
+                .into_iter()
+                .map(|subscription| subscription.pilot_username.to_lowercase())
+                .collect(),
+            Err(e) => {
+                tracing::error!("Could not fetch subscriptions for uid {}: {}", user.id, e);
+                return OutgoingReply::Error;
+            }
+        };
+    let (skipped, new): (Vec<_>, Vec<_>) = usernames
+        .into_iter()
+        .partition(|username| followed.contains(&username.to_lowercase()));
+
+    // Add subscriptions
+    if let Err(e) = db::add_subscriptions(pool, user.id, &new, Some(&label), Some(days)).await {
+        tracing::error!("Could not add subscriptions: {}", e);
+        return OutgoingReply::Error;
+    }
+    let mut reply = messages::fill(
+        messages.comp_success,
+        &[("count", &new.len().to_string()), ("label", &label)],
+    );
+    if !skipped.is_empty() {
+        reply.push(' ');
+        reply.push_str(&messages::fill(
+            messages.comp_skipped,
+            &[("count", &skipped.len().to_string())],
+        ));
+    }
+    let end = Local::now() + chrono::Duration::days(days.into());
+    reply.push_str("\n\n");
+    reply.push_str(&messages::fill(
+        messages.follow_expires,
+        &[("date", &end.format("%d.%m.%Y").to_string())],
+    ));
+    OutgoingReply::Text(reply.into())
+}
+
+/// Follow a pilot by their full name.
+///
+/// If several pilots match, the user is asked to pick one of them.
+async fn handle_follow_by_name(
+    name: &str,
+    label: Option<String>,
+    days: Option<u32>,
+    messages: &Messages,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let pilots = match find_pilots_by_name(pool, &user.tenant, name).await {
+        Ok(pilots) => pilots,
+        Err(e) => {
+            tracing::error!("Could not search pilots: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    match &pilots[..] {
+        [] => OutgoingReply::Text(
+            format!(
+                "{}\n\n{}",
+                messages::fill(messages.follow_name_not_found, &[("name", name)]),
+                messages.follow_usage
+            )
+            .into(),
+        ),
+        [(username, pilot_name)] => {
+            follow(
+                username,
+                &format!("{} ({})", pilot_name, username),
+                label.as_deref(),
+                days,
+                messages,
+                user,
+                pool,
+            )
+            .await
+        }
+        pilots => {
+            let state = ConversationState::ChoosePilot {
+                pilots: pilots
+                    .iter()
+                    .map(|(username, _)| username.clone())
+                    .collect(),
+                label,
+                days,
+            };
+            if let Err(e) = conversation::set(pool, user.id, &state).await {
+                tracing::error!("Could not store conversation state: {}", e);
+                return OutgoingReply::Error;
+            }
+            let mut reply = messages::fill(messages.follow_choose_pilot, &[("name", name)]);
+            reply.push('\n');
+            for (i, (username, pilot_name)) in pilots.iter().enumerate() {
+                reply.push_str(&format!("\n{}. {} ({})", i + 1, pilot_name, username));
+            }
+            OutgoingReply::Text(reply.into())
+        }
+    }
+}
+
+/// Return the usernames and display names of the pilots of the tenant whose
+/// name contains `name` (case-insensitive), most recently active first.
+async fn find_pilots_by_name(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    name: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut pilots: Vec<(String, String)> = vec![];
+    for pilot in db::search_pilot_titles(pool, tenant, &name).await? {
+        let pilot_name = match ParsedTitle::parse(&pilot.title) {
+            Some(parsed) => parsed.pilot_name,
+            None => continue,
+        };
+        if !pilot_name.to_lowercase().contains(&name.to_lowercase())
+            || pilots
+                .iter()
+                .any(|(username, _)| username.eq_ignore_ascii_case(&pilot.pilot_username))
+        {
+            continue;
+        }
+        pilots.push((pilot.pilot_username, pilot_name));
+        if pilots.len() == MAX_PILOT_CHOICES {
+            break;
+        }
+    }
+    Ok(pilots)
+}
+
+/// Subscribe the user to the pilot (with the specified display name), for
+/// `days` days if specified.
+async fn follow(
+    username: &str,
+    display_name: &str,
+    label: Option<&str>,
+    days: Option<u32>,
+    messages: &Messages,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    match db::add_subscription(pool, user.id, username, label, days).await {
+        Ok(temporary) => {
+            let mut reply = match label {
+                Some(label) => messages::fill(
+                    messages.follow_label_success,
+                    &[("pilot", display_name), ("label", label)],
+                ),
+                None => messages::fill(messages.follow_success, &[("pilot", display_name)]),
+            };
+            if let Some(days) = days.filter(|_| temporary) {
+                let end = Local::now() + chrono::Duration::days(days.into());
+                reply.push_str("\n\n");
+                reply.push_str(&messages::fill(
+                    messages.follow_expires,
+                    &[("date", &end.format("%d.%m.%Y").to_string())],
+                ));
+            }
+            OutgoingReply::Text(reply.into())
+        }
+        Err(e) => {
+            tracing::error!("Could not add subscription: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle a number sent as reply to a question of the bot
+async fn handle_choice(
+    choice: &str,
+    incoming: &IncomingCommand<'_>,
+    tenant: &TenantConfig,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let messages = tenant.messages();
+    let state = match conversation::get(pool, user.id).await {
+        Ok(state) => state,
+        Err(e) => {
+            tracing::error!("Could not fetch conversation state: {}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    match state {
+        Some(ConversationState::ChoosePilot {
+            pilots,
+            label,
+            days,
+        }) => {
+            let pilot = match choice
+                .parse::<usize>()
+                .ok()
+                .and_then(|choice| choice.checked_sub(1))
+                .and_then(|index| pilots.get(index))
+            {
+                Some(pilot) => pilot,
+                None => {
+                    return OutgoingReply::Text(
+                        messages::fill(
+                            messages.follow_invalid_choice,
+                            &[("count", &pilots.len().to_string())],
+                        )
+                        .into(),
+                    )
+                }
+            };
+            if let Err(e) = conversation::clear(pool, user.id).await {
+                tracing::error!("Could not reset conversation state: {}", e);
+                return OutgoingReply::Error;
+            }
+            follow(pilot, pilot, label.as_deref(), days, messages, user, pool).await
+        }
+        None => handle_unknown_command(choice, incoming, tenant).await,
+    }
+}
+
+/// Handle command to unfollow a pilot
+async fn handle_unfollow(
+    command_data: Option<Match<'_>>,
+    messages: &Messages,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let usage = messages.unfollow_usage;
+
+    let pilot = match command_data {
+        Some(data) => data.as_str().trim(),
+        None => return OutgoingReply::Text(Cow::Borrowed(usage)),
+    };
+
+    // Validate pilot name
+    if pilot.is_empty() {
+        return OutgoingReply::Text(Cow::Borrowed(usage));
+    }
+
+    // Remove all subscriptions with the label
+    if pilot.starts_with('#') {
+        let label = match parse_label(pilot) {
+            Some(label) => label,
+            None => return OutgoingReply::Text(Cow::Borrowed(messages.label_invalid)),
+        };
+        return match db::remove_labeled_subscriptions(pool, user.id, &label).await {
+            Ok(0) => OutgoingReply::Text(
+                messages::fill(messages.label_not_found, &[("label", &label)]).into(),
+            ),
+            Ok(count) => OutgoingReply::Text(
+                messages::fill(
+                    messages.unfollow_label_success,
+                    &[("count", &count.to_string()), ("label", &label)],
+                )
+                .into(),
+            ),
+            Err(e) => {
+                tracing::error!("Could not remove subscriptions: {}", e);
+                OutgoingReply::Error
+            }
+        };
+    }
+
+    // Remove subscription
+    match db::remove_subscription(pool, user.id, pilot).await {
+        Ok(true) => OutgoingReply::Text(
+            messages::fill(messages.unfollow_success, &[("pilot", pilot)]).into(),
+        ),
+        Ok(false) => OutgoingReply::Text(
+            messages::fill(messages.unfollow_not_following, &[("pilot", pilot)]).into(),
+        ),
+        Err(e) => {
+            tracing::error!("Could not remove subscription: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to move a subscription to another username of the pilot
+async fn handle_move(
+    command_data: Option<Match<'_>>,
+    messages: &Messages,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let usage = messages.move_usage;
+
+    // Parse old and new username
+    let (old_pilot, new_pilot) = match command_data
+        .map(|data| data.as_str().split_whitespace().collect::<Vec<_>>())
+        .as_deref()
+    {
+        Some([old_pilot, new_pilot]) => (old_pilot.to_string(), new_pilot.to_string()),
+        _ => return OutgoingReply::Text(Cow::Borrowed(usage)),
+    };
+
+    // Move subscription
+    match db::move_subscription(pool, user.id, &old_pilot, &new_pilot).await {
+        Ok(true) => OutgoingReply::Text(
+            messages::fill(
+                messages.move_success,
+                &[("old", &old_pilot), ("new", &new_pilot)],
+            )
+            .into(),
+        ),
+        Ok(false) => OutgoingReply::Text(
+            messages::fill(messages.unfollow_not_following, &[("pilot", &old_pilot)]).into(),
+        ),
+        Err(e) => {
+            tracing::error!("Could not move subscription: {}", e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to list subscriptions.
+///
+/// The list is paginated (`liste 2`), can be sorted by subscription date
+/// (`liste neu`) and filtered by label (`liste #team`).
+async fn handle_list(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let messages = tenant.messages();
+
+    // Parse options
+    let mut newest_first = false;
+    let mut label = None;
+    let mut page = 1;
+    for option in command_data
+        .map(|data| data.as_str())
+        .unwrap_or("")
+        .split_whitespace()
+    {
+        match option.to_lowercase().as_str() {
+            "neu" | "new" => newest_first = true,
+            other if other.starts_with('#') => match parse_label(other) {
+                Some(parsed) => label = Some(parsed),
+                None => return OutgoingReply::Text(Cow::Borrowed(messages.label_invalid)),
+            },
+            other => match other.parse::<usize>() {
+                Ok(number) if number > 0 => page = number,
+                _ => return OutgoingReply::Text(Cow::Borrowed(messages.list_usage)),
+            },
+        }
+    }
+
+    // Fetch subscriptions
+    let subscriptions: Vec<String> =
+        match db::get_subscription_list(pool, user.id, label.as_deref(), newest_first).await {
+            Ok(subscriptions) => subscriptions
+                .iter()
+                .map(|subscription| format_subscription(subscription, newest_first, messages))
+                .collect(),
+            Err(e) => {
+                tracing::error!("Could not fetch subscriptions for uid {}: {}", user.id, e);
+                return OutgoingReply::Error;
+            }
+        };
+    if let (true, Some(label)) = (subscriptions.is_empty(), &label) {
+        return OutgoingReply::Text(
+            messages::fill(messages.label_not_found, &[("label", label)]).into(),
+        );
+    }
+    if subscriptions.is_empty() {
+        return OutgoingReply::Text(
+            format!("{}\n\n{}", messages.list_empty, messages.follow_usage).into(),
+        );
+    }
+
+    // Reply with the requested page (or the last one)
+    let page_size = tenant.list_page_size();
+    let pages = subscriptions.len().div_ceil(page_size);
+    let page = page.min(pages);
+    let mut reply = match &label {
+        Some(label) => messages::fill(messages.list_header_label, &[("label", label)]),
+        None if newest_first => messages.list_header_newest.to_string(),
+        None => messages.list_header.to_string(),
+    };
+    reply.push('\n');
+    for pilot in subscriptions
+        .iter()
+        .skip((page - 1) * page_size)
+        .take(page_size)
+    {
+        reply.push_str("\n- ");
+        reply.push_str(pilot);
+    }
+    reply.push_str("\n\n");
+    reply.push_str(&messages::fill(
+        messages.list_total,
+        &[("count", &subscriptions.len().to_string())],
+    ));
+    if pages > 1 {
+        reply.push_str(" · ");
+        reply.push_str(&messages::fill(
+            messages.list_page,
+            &[("page", &page.to_string()), ("pages", &pages.to_string())],
+        ));
+    }
+    if page < pages {
+        let mut command = vec![messages.list_command];
+        if newest_first {
+            command.push(messages.list_sort_newest);
+        }
+        let label = label.map(|label| format!("#{}", label));
+        if let Some(label) = &label {
+            command.push(label);
+        }
+        let next_page = (page + 1).to_string();
+        command.push(&next_page);
+        reply.push('\n');
+        reply.push_str(&messages::fill(
+            messages.list_next_page,
+            &[("command", &command.join(" "))],
+        ));
+    }
+    OutgoingReply::Text(reply.into())
+}
+
+/// Format a list entry: The pilot (with the subscription date, if sorted by
+/// it), the end of a temporary subscription and the label.
+fn format_subscription(
+    subscription: &db::Subscription,
+    with_date: bool,
+    messages: &Messages,
+) -> String {
+    let mut entry = if with_date {
+        format_subscription_since(subscription, messages)
+    } else {
+        subscription.pilot_username.clone()
+    };
+    let expires_at = subscription
+        .expires_at
+        .as_deref()
+        .and_then(|expires_at| db::parse_sql_timestamp(expires_at).ok());
+    if let Some(expires_at) = expires_at {
+        entry.push(' ');
+        entry.push_str(&messages::fill(
+            messages.list_expires,
+            &[(
+                "date",
+                &expires_at
+                    .with_timezone(&Local)
+                    .format("%d.%m.%Y")
+                    .to_string(),
+            )],
+        ));
+    }
+    if let Some(label) = &subscription.label {
+        entry.push_str(" #");
+        entry.push_str(label);
+    }
+    entry
+}
+
+/// Format a subscription with its creation date.
+fn format_subscription_since(subscription: &db::Subscription, messages: &Messages) -> String {
+    match db::parse_sql_timestamp(&subscription.created_at) {
+        Ok(created_at) => messages::fill(
+            messages.list_entry_since,
+            &[
+                ("pilot", &subscription.pilot_username),
+                (
+                    "date",
+                    &created_at
+                        .with_timezone(&Local)
+                        .format("%d.%m.%Y")
+                        .to_string(),
+                ),
+            ],
+        ),
+        Err(_) => subscription.pilot_username.clone(),
+    }
+}
+
+/// Handle command to show or change the daily digest setting
+async fn handle_digest(
+    command_data: Option<Match<'_>>,
+    messages: &Messages,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let enabled = match command_data.map(|data| data.as_str().trim().to_lowercase()) {
+        Some(data) if data == "an" || data == "on" => true,
+        Some(data) if data == "aus" || data == "off" => false,
+        _ => {
+            return match db::get_digest(pool, user.id).await {
+                Ok(enabled) => {
+                    let status = if enabled {
+                        messages.digest_status_enabled
+                    } else {
+                        messages.digest_status_disabled
+                    };
+                    OutgoingReply::Text(
+                        messages::fill(messages.digest_status, &[("status", status)]).into(),
+                    )
+                }
+                Err(e) => {
+                    tracing::error!("Could not fetch digest setting for uid {}: {}", user.id, e);
+                    OutgoingReply::Error
+                }
+            };
+        }
+    };
+    match db::set_digest(pool, user.id, enabled).await {
+        Ok(()) if enabled => OutgoingReply::Text(Cow::Borrowed(messages.digest_enabled)),
+        Ok(()) => OutgoingReply::Text(Cow::Borrowed(messages.digest_disabled)),
+        Err(e) => {
+            tracing::error!("Could not update digest setting for uid {}: {}", user.id, e);
+            OutgoingReply::Error
+        }
+    }
+}
+
+/// Handle command to show the monthly leaderboard of the followed pilots
+async fn handle_leaderboard(
+    messages: &Messages,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let month = db::current_month();
+    let entries = match db::get_leaderboard(pool, &month, user.id).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!("Could not fetch leaderboard for uid {}: {}", user.id, e);
+            return OutgoingReply::Error;
+        }
+    };
+    if entries.is_empty() {
+        return OutgoingReply::Text(Cow::Borrowed(messages.leaderboard_empty));
+    }
+    let mut reply = format!("{}\n", messages.leaderboard_header);
+    for (i, entry) in entries.iter().enumerate() {
+        let flights = messages::fill(
+            if entry.flights == 1 {
+                messages.flights_one
+            } else {
+                messages.flights_other
+            },
+            &[("count", &entry.flights.to_string())],
+        );
+        reply.push('\n');
+        reply.push_str(&messages::fill(
+            messages.leaderboard_entry,
+            &[
+                ("rank", &(i + 1).to_string()),
+                ("pilot", &entry.pilot_username),
+                ("distance", &format!("{:.1}", entry.distance_km)),
+                ("flights", &flights),
+                ("max", &format!("{:.1}", entry.max_km)),
+            ],
+        ));
+    }
+    reply.push_str("\n\n");
+    reply.push_str(messages.leaderboard_footer);
+    OutgoingReply::Text(reply.into())
+}
+
+/// Handle command to subscribe to (`club <code>`) or unsubscribe from (`club
+/// <code> stopp`) the monthly leaderboard of a club
+async fn handle_club(
+    command_data: Option<Match<'_>>,
+    tenant: &TenantConfig,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    let messages = tenant.messages();
+    if tenant.clubs.is_empty() {
+        return OutgoingReply::Text(Cow::Borrowed(messages.club_none));
+    }
+    let mut args = command_data
+        .map_or("", |data| data.as_str())
+        .split_whitespace();
+    let (code, stop) = match (args.next(), args.next(), args.next()) {
+        (Some(code), None, None) => (code, false),
+        (Some(code), Some(stop), None)
+            if ["stopp", "stop", "aus", "off"].contains(&&*stop.to_lowercase()) =>
+        {
+            (code, true)
+        }
+        (None, _, _) => {
+            // Show usage, the clubs and the user's subscriptions
+            let codes = tenant
+                .clubs
+                .iter()
+                .map(|club| club.code.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut reply = format!(
+                "{}\n\n{}",
+                messages.club_usage,
+                messages::fill(messages.club_list, &[("codes", &codes)])
+            );
+            match db::get_club_subscriptions(pool, user.id).await {
+                Ok(subscriptions) if !subscriptions.is_empty() => {
+                    reply.push('\n');
+                    reply.push_str(&messages::fill(
+                        messages.club_subscriptions,
+                        &[("codes", &subscriptions.join(", "))],
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Could not fetch club subscriptions: {}", e);
+                    return OutgoingReply::Error;
+                }
+            }
+            return OutgoingReply::Text(reply.into());
+        }
+        _ => return OutgoingReply::Text(Cow::Borrowed(messages.club_usage)),
+    };
+    let club = match clubs::find(&tenant.clubs, code) {
+        Some(club) => club,
+        None => {
+            return OutgoingReply::Text(
+                messages::fill(messages.club_unknown, &[("code", code)]).into(),
+            )
+        }
+    };
+    let name = clubs::name(club);
+
+    // Unsubscribe
+    if stop {
+        return match db::remove_club_subscription(pool, user.id, &club.code).await {
+            Ok(true) => OutgoingReply::Text(
+                messages::fill(messages.club_unsubscribed, &[("name", name)]).into(),
+            ),
+            Ok(false) => OutgoingReply::Text(
+                messages::fill(messages.club_not_subscribed, &[("name", name)]).into(),
+            ),
+            Err(e) => {
+                tracing::error!("Could not remove club subscription: {}", e);
+                OutgoingReply::Error
+            }
+        };
+    }
+
+    // Subscribe and show the current leaderboard
+    if let Err(e) = db::add_club_subscription(pool, user.id, &club.code).await {
+        tracing::error!("Could not add club subscription: {}", e);
+        return OutgoingReply::Error;
+    }
+    let month = db::current_month();
+    let leaderboard = match clubs::leaderboard(pool, &tenant.id, club, &month).await {
+        Ok(entries) => clubs::format_leaderboard(club, &month, &entries, messages),
+        Err(e) => {
+            tracing::error!("Could not compute club leaderboard: {:#}", e);
+            return OutgoingReply::Error;
+        }
+    };
+    OutgoingReply::Text(
+        format!(
+            "{}\n\n{}",
+            messages::fill(
+                messages.club_subscribed,
+                &[("name", name), ("code", &club.code)]
+            ),
+            leaderboard
+        )
+        .into(),
+    )
+}
+
+/// Show information about source code of this bot
+async fn handle_github(messages: &Messages) -> OutgoingReply {
+    OutgoingReply::Text(Cow::Borrowed(messages.github))
+}
+
+/// Show information about bot version, uptime and the last fetch, so that
+/// users can check why they didn't get any notifications
+async fn handle_version(tenant: &TenantConfig, status: &BotStatus) -> OutgoingReply {
+    let messages = tenant.messages();
+    let uptime = format_uptime(Local::now() - status.started_at());
+    let mut lines = vec![
+        format!("xc-bot v{}", crate::VERSION),
+        messages::fill(messages.version_uptime, &[("uptime", &uptime)]),
+        match status.last_fetch() {
+            Some(time) => messages::fill(
+                messages.version_last_fetch,
+                &[("time", &time.format("%d.%m.%Y %H:%M").to_string())],
+            ),
+            None => messages.version_no_fetch.to_string(),
+        },
+        messages::fill(messages.version_feed, &[("url", tenant.feed_url())]),
+    ];
+    if let Some(throttling) = status.throttling() {
+        lines.push(messages::fill(
+            messages.version_throttled,
+            &[("time", &throttling.until.format("%H:%M").to_string())],
+        ));
+    }
+    OutgoingReply::Text(lines.join("\n").into())
+}
+
+/// Format an uptime like "3d 4h 12m".
+fn format_uptime(uptime: chrono::Duration) -> String {
+    let minutes = uptime.num_minutes().max(0);
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+/// Handle command sent by prefilled links: Record the referral code of the
+/// user (if any) and reply with the help text
+async fn handle_start(
+    data: Option<Match<'_>>,
+    incoming: &IncomingCommand<'_>,
+    tenant: &TenantConfig,
+    user: &User,
+    pool: &Pool<Sqlite>,
+) -> OutgoingReply {
+    if let Some(referral) = data.and_then(|data| parse_referral(data.as_str())) {
+        match db::set_referral(pool, user.id, &referral).await {
+            Ok(true) => tracing::info!("User {} was referred by {}", user.id, referral),
+            Ok(false) => tracing::debug!("User {} already has a referral code", user.id),
+            Err(e) => tracing::error!("Could not store referral code: {}", e),
+        }
+    }
+    OutgoingReply::Text(
+        messages::fill(
+            tenant.help_text(),
+            &[("nickname", incoming.nickname_or_sender())],
+        )
+        .into(),
+    )
+}
+
+/// Normalize a referral code (lowercase letters, digits, `-` and `_`), or
+/// return `None` if it is invalid.
+fn parse_referral(code: &str) -> Option<String> {
+    let code = code.trim().to_lowercase();
+    let valid = !code.is_empty()
+        && code.len() <= MAX_REFERRAL_LENGTH
+        && code
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        tracing::debug!("Ignoring invalid referral code {:?}", code);
+        return None;
+    }
+    Some(code)
+}
+
+/// Handle unknown command
+async fn handle_unknown_command(
+    command: &str,
+    incoming: &IncomingCommand<'_>,
+    tenant: &TenantConfig,
+) -> OutgoingReply {
+    tracing::debug!("Unknown command: {:?}", command);
+
+    // Suggest the intended command if it was just mistyped
+    let messages = tenant.messages();
+    if let Some((alias, command)) = suggest_alias(command) {
+        let mut reply = messages::fill(messages.did_you_mean, &[("command", alias)]);
+        if let Some(usage) = command.usage(messages) {
+            reply.push_str("\n\n");
+            reply.push_str(usage);
+        }
+        return OutgoingReply::Text(reply.into());
+    }
+
+    OutgoingReply::Text(
+        messages::fill(
+            tenant.help_text(),
+            &[("nickname", incoming.nickname_or_sender())],
+        )
+        .into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+        Pool, Sqlite,
+    };
+
+    use crate::{
+        config::{
+            ClubConfig, CommandsConfig, CompetitionConfig, FeaturesConfig, RetentionConfig,
+            TenantConfig, ThreemaConfig,
+        },
+        db::{self, User},
+        messages::Language,
+        middleware::Chain,
+        status::BotStatus,
+        tenants::DEFAULT_TENANT,
+        tokens,
+    };
+    use xcontest_client::{ParseFailure, PayloadKind};
+
+    use super::{
+        edit_distance, format_uptime, handle_command, parse_follow_duration, suggest_alias,
+        IncomingCommand, Job, OutgoingReply,
+    };
+
+    /// Create an SQLite test database (with applied migrations)
+    async fn _sqlite_test_db() -> Pool<Sqlite> {
+        // Create in-memory test DB
+        let connect_options = SqliteConnectOptions::from_str(":memory:")
+            .unwrap()
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new()
+            .min_connections(2)
+            .max_connections(5)
+            .connect_with(connect_options)
+            .await
+            .unwrap();
+
+        // Run migrations
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .expect("Test migrations failed");
+
+        pool
+    }
+
+    /// Return the pilots the user follows, sorted by name
+    async fn subscriptions(pool: &Pool<Sqlite>, user_id: i32) -> Vec<String> {
+        db::get_subscription_list(pool, user_id, None, false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|subscription| subscription.pilot_username)
+            .collect()
+    }
+
+    #[derive(Default)]
+    struct TextMessageTestProcessor {
+        text: String,
+        sender_identity: String,
+        sender_nickname: Option<String>,
+        is_admin: bool,
+        /// The default tenant unless set
+        tenant: Option<String>,
+        language: Option<Language>,
+        list_page_size: Option<usize>,
+        /// Disabled unless set, since many tests send the same command twice
+        duplicate_window_seconds: Option<u32>,
+        features: Option<FeaturesConfig>,
+        competitions: Vec<CompetitionConfig>,
+        clubs: Vec<ClubConfig>,
+        pool: Option<Pool<Sqlite>>,
+        user: Option<User>,
+    }
+
+    impl TextMessageTestProcessor {
+        fn new(text: impl Into<String>) -> Self {
+            Self {
+                text: text.into(),
+                sender_identity: "SENDERRR".into(),
+                duplicate_window_seconds: Some(0),
+                ..Default::default()
+            }
+        }
+
+        fn with_sender(mut self, identity: &str, nickname: Option<&str>) -> Self {
+            self.sender_identity = identity.into();
+            self.sender_nickname = nickname.map(ToOwned::to_owned);
+            self
+        }
+
+        fn with_admin_sender(mut self) -> Self {
+            self.is_admin = true;
+            self
+        }
+
+        fn with_tenant(mut self, tenant: &str) -> Self {
+            self.tenant = Some(tenant.into());
+            self
+        }
+
+        fn with_language(mut self, language: Language) -> Self {
+            self.language = Some(language);
+            self
+        }
+
+        fn with_list_page_size(mut self, list_page_size: usize) -> Self {
+            self.list_page_size = Some(list_page_size);
+            self
+        }
+
+        fn with_duplicate_window(mut self, seconds: u32) -> Self {
+            self.duplicate_window_seconds = Some(seconds);
+            self
+        }
+
+        fn with_competitions(mut self, competitions: Vec<CompetitionConfig>) -> Self {
+            self.competitions = competitions;
+            self
+        }
+
+        fn with_clubs(mut self, clubs: Vec<ClubConfig>) -> Self {
+            self.clubs = clubs;
+            self
+        }
+
+        fn with_features(mut self, features: FeaturesConfig) -> Self {
+            self.features = Some(features);
+            self
+        }
+
+        fn with_pool(mut self, pool: Pool<Sqlite>) -> Self {
+            self.pool = Some(pool);
+            self
+        }
+
+        fn with_user(mut self, user: User) -> Self {
+            self.user = Some(user);
+            self
+        }
+
+        async fn process(self) -> TextMessageTestProcessorResult {
+            let pool = match self.pool {
+                Some(pool) => pool,
+                None => _sqlite_test_db().await,
+            };
+
+            let user = match self.user {
+                Some(user) => user,
+                None => db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+                    .await
+                    .unwrap(),
+            };
+            let tenant = TenantConfig {
+                id: self.tenant.unwrap_or_else(|| DEFAULT_TENANT.into()),
+                threema: ThreemaConfig {
+                    gateway_id: "*XCBOTXX".into(),
+                    gateway_secret: "secret".into(),
+                    private_key: "".into(),
+                    admin_id: None,
+                    monthly_notification_cap: None,
+                    request_delivery_receipts: None,
+                    send_read_receipts: None,
+                },
+                feed_url: None,
+                flight_list_url: None,
+                language: self.language,
+                help_text: None,
+                commands: Some(CommandsConfig {
+                    list_page_size: self.list_page_size,
+                    duplicate_window_seconds: self.duplicate_window_seconds,
+                    ..Default::default()
+                }),
+                messages: None,
+                overridden_messages: None,
+                features: self.features.unwrap_or_default(),
+                retention: RetentionConfig::default(),
+                competitions: self.competitions.clone(),
+                clubs: self.clubs.clone(),
+                smtp: None,
+                slack: None,
+                ntfy: None,
+                pushover: None,
+                gotify: None,
+            };
+
+            TextMessageTestProcessorResult {
+                result: handle_command(
+                    &IncomingCommand {
+                        text: &self.text,
+                        sender: &self.sender_identity,
+                        sender_nickname: self.sender_nickname.as_deref(),
+                        is_admin: self.is_admin,
+                    },
+                    &tenant,
+                    &user,
+                    &pool,
+                    &BotStatus::default(),
+                    &Chain::new(),
+                )
+                .await,
+                pool,
+                user,
+            }
+        }
+    }
+
+    struct TextMessageTestProcessorResult {
+        result: OutgoingReply,
+        pool: Pool<Sqlite>,
+        user: User,
+    }
+
+    impl TextMessageTestProcessorResult {
+        fn assert_reply_contains_text(self, expected_text: &str) -> Self {
+            match &self.result {
+                OutgoingReply::Nothing => panic!("Unexpected OutgoingReply::Nothing"),
+                OutgoingReply::Error => panic!("Unexpected OutgoingReply::Error"),
+                OutgoingReply::Text(text) => assert!(
+                    text.contains(expected_text),
+                    "Reply text does not contain expected text {:?}: {:?}",
+                    expected_text,
+                    text
+                ),
+            }
+            self
+        }
+
+        fn assert_reply_does_not_contain_text(self, unexpected_text: &str) -> Self {
+            if let OutgoingReply::Text(text) = &self.result {
+                assert!(
+                    !text.contains(unexpected_text),
+                    "Reply text contains unexpected text {:?}: {:?}",
+                    unexpected_text,
+                    text
+                );
+            }
+            self
+        }
+
+        async fn assert_subscriptions(self, expected_subscriptions: Vec<&'static str>) -> Self {
+            let subscriptions = subscriptions(&self.pool, self.user.id).await;
+            assert_eq!(subscriptions, expected_subscriptions);
+            self
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_command_with_nickname() {
+        TextMessageTestProcessor::new("hello")
+            .with_sender("TESTTEST", Some("TestUser"))
+            .process()
+            .await
+            .assert_reply_contains_text("Hallo TestUser!")
+            .assert_reply_contains_text("Verfügbare Befehle:");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_command_without_nickname() {
+        TextMessageTestProcessor::new("hello")
+            .with_sender("TESTTEST", None)
+            .process()
+            .await
+            .assert_reply_contains_text("Hallo TESTTEST!")
+            .assert_reply_contains_text("Verfügbare Befehle:");
+    }
+
+    #[tokio::test]
+    async fn test_mistyped_command() {
+        TextMessageTestProcessor::new("folgee dbrgn")
+            .process()
+            .await
+            .assert_reply_contains_text("Meintest du *folge*?")
+            .assert_reply_contains_text("Um einem Piloten zu folgen, sende")
+            .assert_reply_does_not_contain_text("Verfügbare Befehle:")
+            .assert_subscriptions(vec![])
+            .await;
+        TextMessageTestProcessor::new("lsite")
+            .process()
+            .await
+            .assert_reply_contains_text("Meintest du *liste*?");
+        TextMessageTestProcessor::new("folow dbrgn")
+            .with_language(Language::English)
+            .process()
+            .await
+            .assert_reply_contains_text("Did you mean *follow*?");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("folge", "folge"), 0);
+        assert_eq!(edit_distance("folgee", "folge"), 1);
+        assert_eq!(edit_distance("lsite", "liste"), 1);
+        assert_eq!(edit_distance("lsite", "list"), 2);
+        assert_eq!(edit_distance("", "add"), 3);
+        assert_eq!(suggest_alias("hello"), None);
+        assert_eq!(suggest_alias("ad"), None);
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        TextMessageTestProcessor::new("version")
+            .process()
+            .await
+            .assert_reply_contains_text("xc-bot v")
+            .assert_reply_contains_text("Läuft seit: 0m")
+            .assert_reply_contains_text("noch nicht erfolgreich abgerufen")
+            .assert_reply_contains_text("Feed: https://www.xcontest.org/");
+    }
+
+    #[test]
+    fn uptime_format() {
+        let format = |minutes| format_uptime(chrono::Duration::minutes(minutes));
+        assert_eq!(format(5), "5m");
+        assert_eq!(format(125), "2h 5m");
+        assert_eq!(format(3 * 1440 + 61), "3d 1h 1m");
+    }
+
+    #[tokio::test]
+    async fn test_github() {
+        TextMessageTestProcessor::new("github")
+            .process()
+            .await
+            .assert_reply_contains_text("AGPLv3")
+            .assert_reply_contains_text("https://github.com/dbrgn/xc-bot/");
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+
+        // Initially, no subscriptions
+        assert_eq!(subscriptions(&pool, user.id).await.len(), 0);
+
+        // Add subscription
+        TextMessageTestProcessor::new("folge dbrgn")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst jetzt dbrgn!")
+            .assert_subscriptions(vec!["dbrgn"])
+            .await;
+
+        // Add second subscription
+        TextMessageTestProcessor::new("folge dbrgn2")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst jetzt dbrgn2!")
+            .assert_subscriptions(vec!["dbrgn", "dbrgn2"])
+            .await;
+
+        // Ignore duplicate subscription
+        TextMessageTestProcessor::new("folge dbrgn")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst jetzt dbrgn!")
+            .assert_subscriptions(vec!["dbrgn", "dbrgn2"])
+            .await;
+
+        // Unsubscribe
+        TextMessageTestProcessor::new("stopp dbrgn")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst jetzt dbrgn nicht mehr.")
+            .assert_subscriptions(vec!["dbrgn2"])
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_follow_by_name() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        for (pilot, title) in [
+            (
+                "chrigel",
+                "10.08.20 [101.50 km :: fai_triangle] Christian Maurer",
+            ),
+            (
+                "chrigel2",
+                "11.08.20 [20.00 km :: free_flight] Christian Maurer",
+            ),
+            ("dbrgn", "09.08.20 [21.98 km :: free_flight] Danilo Bargen"),
+        ] {
+            sqlx::query(
+                "INSERT INTO xcontest_flights (tenant, url, title, pilot_username, guid, seen_at) \
+                VALUES (?, ?, ?, ?, ?, CURRENT_TIMESTAMP)",
+            )
+            .bind(DEFAULT_TENANT)
+            .bind(format!("https://www.xcontest.org/{}", pilot))
+            .bind(title)
+            .bind(pilot)
+            .bind(format!("https://www.xcontest.org/{}", pilot))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        // Unknown name
+        TextMessageTestProcessor::new("folge Hans Muster")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("keinen Piloten namens Hans Muster")
+            .assert_subscriptions(vec![])
+            .await;
+
+        // Unique name
+        TextMessageTestProcessor::new("folge danilo bargen")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst jetzt Danilo Bargen (dbrgn)!")
+            .assert_subscriptions(vec!["dbrgn"])
+            .await;
+
+        // Ambiguous name
+        TextMessageTestProcessor::new("folge Christian Maurer")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("mehrere Piloten namens Christian Maurer")
+            .assert_reply_contains_text("1. Christian Maurer (chrigel2)")
+            .assert_reply_contains_text("2. Christian Maurer (chrigel)");
+        TextMessageTestProcessor::new("3")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Zahl zwischen 1 und 2");
+        TextMessageTestProcessor::new("2")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst jetzt chrigel!")
+            .assert_subscriptions(vec!["chrigel", "dbrgn"])
+            .await;
+
+        // The choice is only valid once
+        TextMessageTestProcessor::new("1")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Verfügbare Befehle:");
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_unknown_user() {
+        // Ignore unsubscriptions for non-subscribed users
+        TextMessageTestProcessor::new("stopp asfgasdg")
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst asfgasdg nicht.")
+            .assert_subscriptions(vec![])
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+
+        // Initially, empty list
+        TextMessageTestProcessor::new("liste")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst noch keinen Piloten.")
+            .assert_reply_contains_text("Um einem Piloten zu folgen, sende");
+
+        TextMessageTestProcessor::new("folge dbrgn1")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await;
+        TextMessageTestProcessor::new("folge dbrgn2")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await;
+        TextMessageTestProcessor::new("folge dbrgn3")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await;
+
+        // Initially, empty list
+        TextMessageTestProcessor::new("liste")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Du folgst folgenden Piloten:")
+            .assert_reply_contains_text("- dbrgn1")
+            .assert_reply_contains_text("- dbrgn2")
+            .assert_reply_contains_text("- dbrgn3");
+    }
+
+    #[tokio::test]
+    async fn test_list_pages() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        for pilot in ["charlie", "alpha", "echo", "delta", "bravo"] {
+            db::add_subscription(&pool, user.id, pilot, None, None)
+                .await
+                .unwrap();
+        }
+        let list = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .with_list_page_size(2)
+                .process()
+        };
+
+        list("liste")
+            .await
+            .assert_reply_contains_text("- alpha\n- bravo\n\nInsgesamt: 5 · Seite 1 von 3")
+            .assert_reply_contains_text("Sende \"liste 2\" für die nächste Seite.");
+        list("liste 3")
+            .await
+            .assert_reply_contains_text("- echo\n\nInsgesamt: 5 · Seite 3 von 3")
+            .assert_reply_does_not_contain_text("nächste Seite");
+        list("liste 42")
+            .await
+            .assert_reply_contains_text("Seite 3 von 3");
+        let today = chrono::Local::now().format("%d.%m.%Y").to_string();
+        list("liste neu")
+            .await
+            .assert_reply_contains_text("zuletzt hinzugefügte zuerst")
+            .assert_reply_contains_text(&format!(
+                "- bravo (seit {today})\n- delta (seit {today})\n\n"
+            ))
+            .assert_reply_contains_text("Sende \"liste neu 2\"");
+        list("liste neu 2")
+            .await
+            .assert_reply_contains_text(&format!(
+                "- echo (seit {today})\n- alpha (seit {today})\n\n"
+            ));
+        list("liste foo")
+            .await
+            .assert_reply_contains_text("\"liste 2\" für die zweite Seite");
+    }
+
+    #[tokio::test]
+    async fn test_labels() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        let send = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .process()
+        };
+
+        send("folge alpha #Team")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt alpha mit dem Label #team!");
+        send("folge bravo #team").await;
+        send("folge charlie").await;
+        send("folge delta #team!")
+            .await
+            .assert_reply_contains_text("Ein Label beginnt mit #");
+
+        // Labels are shown and can be filtered
+        send("liste")
+            .await
+            .assert_reply_contains_text("- alpha #team\n- bravo #team\n- charlie\n");
+        send("liste #team")
+            .await
+            .assert_reply_contains_text("Du folgst folgenden Piloten mit dem Label #team:")
+            .assert_reply_contains_text("Insgesamt: 2");
+        send("liste #club")
+            .await
+            .assert_reply_contains_text("Du folgst keinen Piloten mit dem Label #club.");
+
+        // Following again changes the label
+        send("folge bravo #club").await;
+        send("liste #club")
+            .await
+            .assert_reply_contains_text("- bravo #club\n\nInsgesamt: 1");
+
+        // Bulk unfollow
+        send("stopp #team")
+            .await
+            .assert_reply_contains_text("Du folgst den 1 Piloten mit dem Label #team nicht mehr.")
+            .assert_subscriptions(vec!["bravo", "charlie"])
+            .await;
+    }
+
+    #[test]
+    fn follow_durations() {
+        assert_eq!(parse_follow_duration("3d"), Some(Some(3)));
+        assert_eq!(parse_follow_duration("3T"), Some(Some(3)));
+        assert_eq!(parse_follow_duration("2w"), Some(Some(14)));
+        assert_eq!(parse_follow_duration("0d"), Some(None));
+        assert_eq!(parse_follow_duration("60w"), Some(None));
+        assert_eq!(parse_follow_duration("chrigel"), None);
+        assert_eq!(parse_follow_duration("2ü"), None);
+        assert_eq!(parse_follow_duration("w"), None);
+    }
+
+    #[tokio::test]
+    async fn test_temporary_follow() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        let send = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .process()
+        };
+        let end = (chrono::Local::now() + chrono::Duration::days(14))
+            .format("%d.%m.%Y")
+            .to_string();
+
+        send("folge alpha 2w #comp")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt alpha mit dem Label #comp!")
+            .assert_reply_contains_text(&format!("Das Abo endet am {}.", end));
+        send("folge bravo 3t").await;
+        send("folge charlie").await;
+        send("folge delta 400t")
+            .await
+            .assert_reply_contains_text("Ein befristetes Abo dauert 1 bis 365 Tage");
+        send("liste")
+            .await
+            .assert_reply_contains_text(&format!("- alpha (bis {}) #comp\n", end));
+
+        // Following again without a duration makes the subscription permanent
+        send("folge bravo").await;
+        sqlx::query("UPDATE subscriptions SET expires_at = datetime('now', '-1 minute')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE subscriptions SET expires_at = NULL WHERE pilot_username != 'alpha'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let expired = db::remove_expired_subscriptions(&pool).await.unwrap();
+        assert_eq!(expired, vec![(user.id, "alpha".to_string())]);
+        send("liste")
+            .await
+            .assert_subscriptions(vec!["bravo", "charlie"])
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_temporary_follow_of_permanent_subscription() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        let send = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .process()
+        };
+
+        // The permanent subscription is kept
+        send("folge alpha").await;
+        send("folge alpha 2w")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt alpha!")
+            .assert_reply_does_not_contain_text("Das Abo endet");
+        let subscriptions = db::get_subscription_list(&pool, user.id, None, false)
+            .await
+            .unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].expires_at, None);
+        assert!(db::remove_expired_subscriptions(&pool)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_follow_competition() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        db::add_subscription(&pool, user.id, "alpha", None, None)
+            .await
+            .unwrap();
+        let end_date = chrono::Local::now().date_naive() + chrono::Duration::days(1);
+        let competitions = vec![
+            CompetitionConfig {
+                code: "SwissLeague".into(),
+                name: None,
+                url: Some("https://www.swissleague.ch/".into()),
+                end_date: end_date.format("%Y-%m-%d").to_string(),
+                pilots: Some(vec!["alpha".into(), "bravo".into(), "charlie".into()]),
+                roster_file: None,
+            },
+            CompetitionConfig {
+                code: "old".into(),
+                name: Some("Old Comp".into()),
+                url: None,
+                end_date: "2020-01-01".into(),
+                pilots: Some(vec!["delta".into()]),
+                roster_file: None,
+            },
+        ];
+        let send = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .with_competitions(competitions.clone())
+                .process()
+        };
+
+        send("folge comp")
+            .await
+            .assert_reply_contains_text("folge comp _<code>_")
+            .assert_reply_contains_text("Bekannte Wettkämpfe: SwissLeague, old");
+        send("folge comp unknown")
+            .await
+            .assert_reply_contains_text("Ich kenne keinen Wettkampf unknown.")
+            .assert_reply_contains_text("Bekannte Wettkämpfe: SwissLeague, old");
+        send("folge comp old")
+            .await
+            .assert_reply_contains_text("Der Wettkampf Old Comp ist bereits vorbei.");
+
+        // Configured roster, the existing subscription stays permanent
+        let end = (chrono::Local::now() + chrono::Duration::days(2))
+            .format("%d.%m.%Y")
+            .to_string();
+        send("folge comp https://www.swissleague.ch")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt 2 Piloten mit dem Label #swissleague.")
+            .assert_reply_contains_text("1 Piloten folgst du bereits")
+            .assert_reply_contains_text(&format!("Das Abo endet am {}.", end));
+        send("liste #swissleague")
+            .await
+            .assert_subscriptions(vec!["alpha", "bravo", "charlie"])
+            .await;
+        let subscriptions = db::get_subscription_list(&pool, user.id, None, false)
+            .await
+            .unwrap();
+        assert!(subscriptions
+            .iter()
+            .all(|s| (s.pilot_username == "alpha") == s.expires_at.is_none()));
+
+        // Pasted roster
+        send("folge comp echo, foxtrot;golf 5t")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt 3 Piloten mit dem Label #comp.");
+        send("stopp #comp")
+            .await
+            .assert_reply_contains_text("Du folgst den 3 Piloten mit dem Label #comp nicht mehr.");
+    }
+
+    #[tokio::test]
+    async fn test_club() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        db::replace_leaderboard(
+            &pool,
+            DEFAULT_TENANT,
+            &db::current_month(),
+            &[
+                db::LeaderboardEntry {
+                    pilot_username: "chrigel".into(),
+                    flights: 2,
+                    distance_km: 120.0,
+                    max_km: 80.0,
+                },
+                db::LeaderboardEntry {
+                    pilot_username: "outsider".into(),
+                    flights: 1,
+                    distance_km: 200.0,
+                    max_km: 200.0,
+                },
+            ],
+        )
+        .await
+        .unwrap();
+        let clubs = vec![ClubConfig {
+            code: "alpin".into(),
+            name: Some("Club Alpin".into()),
+            pilots: Some(vec!["Chrigel".into(), "dbrgn".into()]),
+            roster_file: None,
+        }];
+        let send = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .with_clubs(clubs.clone())
+                .process()
+        };
+
+        TextMessageTestProcessor::new("club alpin")
+            .process()
+            .await
+            .assert_reply_contains_text("keine Clubs eingerichtet");
+        send("club")
+            .await
+            .assert_reply_contains_text("Clubs: alpin");
+        send("club other")
+            .await
+            .assert_reply_contains_text("Ich kenne keinen Club other.");
+        send("club ALPIN")
+            .await
+            .assert_reply_contains_text("die Rangliste von Club Alpin")
+            .assert_reply_contains_text("Total: 120.0 km (2 Flüge, 1 Piloten)")
+            .assert_reply_contains_text("1. chrigel: 120.0 km");
+        send("club")
+            .await
+            .assert_reply_contains_text("Deine Abos: alpin");
+        let subscribers = db::get_club_subscribers(&pool, DEFAULT_TENANT, "alpin")
+            .await
+            .unwrap();
+        assert_eq!(
+            subscribers.iter().map(|u| u.id).collect::<Vec<_>>(),
+            vec![user.id]
+        );
+        send("club alpin stopp")
+            .await
+            .assert_reply_contains_text("Du erhältst die Rangliste von Club Alpin nicht mehr.");
+        send("club alpin stopp")
+            .await
+            .assert_reply_contains_text("nicht abonniert");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_commands() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        let send = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .with_duplicate_window(10)
+                .process()
+        };
+
+        send("folge chrigel")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt chrigel!");
+        assert!(matches!(
+            send(" folge chrigel").await.result,
+            OutgoingReply::Nothing
+        ));
+        send("stopp chrigel")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt chrigel nicht mehr.");
+        send("folge chrigel")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt chrigel!");
+
+        // Choices are not deduplicated
+        for _ in 0..2 {
+            assert!(matches!(send("1").await.result, OutgoingReply::Text(_)));
+        }
+
+        // After the window, the command is processed again
+        sqlx::query("UPDATE recent_commands SET received_at = datetime('now', '-11 seconds')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        send("folge chrigel")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt chrigel!");
+    }
+
+    #[tokio::test]
+    async fn test_move() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        db::add_subscription(&pool, user.id, "Chrigel", None, None)
+            .await
+            .unwrap();
+        let process = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_user(user.clone())
+                .process()
+        };
+
+        process("umziehen chrigel")
+            .await
+            .assert_reply_contains_text("umziehen _<alter benutzername>_");
+        process("umziehen chrigel chrigel2")
+            .await
+            .assert_reply_contains_text("Du folgst jetzt chrigel2 statt chrigel.");
+        process("umziehen chrigel chrigel2")
+            .await
+            .assert_reply_contains_text("Du folgst chrigel nicht.");
+        assert_eq!(
+            subscriptions(&pool, user.id).await,
+            vec!["chrigel2".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_digest() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+
+        // Initially disabled
+        TextMessageTestProcessor::new("zusammenfassung")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Zusammenfassung ist deaktiviert");
+
+        // Enable
+        TextMessageTestProcessor::new("digest on")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("einmal täglich");
+        assert!(db::get_digest(&pool, user.id).await.unwrap());
+        assert_eq!(db::get_digest_users(&pool).await.unwrap().len(), 1);
+
+        // Disable
+        TextMessageTestProcessor::new("zusammenfassung aus")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("wieder sofort");
+        assert!(!db::get_digest(&pool, user.id).await.unwrap());
+
+        // Disabled by the operator
+        TextMessageTestProcessor::new("digest on")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .with_features(FeaturesConfig {
+                enable_digests: Some(false),
+                ..Default::default()
+            })
+            .process()
+            .await
+            .assert_reply_contains_text("deaktiviert");
+        assert!(!db::get_digest(&pool, user.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_leaderboard() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "testuser", "threema")
+            .await
+            .unwrap();
+        db::add_subscription(&pool, user.id, "dbrgn", None, None)
+            .await
+            .unwrap();
+
+        // No entries yet
+        TextMessageTestProcessor::new("rangliste")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("noch keine Flüge");
+
+        // Only followed pilots are shown
+        let month = db::current_month();
+        db::replace_leaderboard(
+            &pool,
+            DEFAULT_TENANT,
+            &month,
+            &[
+                db::LeaderboardEntry {
+                    pilot_username: "chrigel".into(),
+                    flights: 1,
+                    distance_km: 101.5,
+                    max_km: 101.5,
+                },
+                db::LeaderboardEntry {
+                    pilot_username: "dbrgn".into(),
+                    flights: 2,
+                    distance_km: 52.0,
+                    max_km: 30.02,
+                },
+            ],
+        )
+        .await
+        .unwrap();
+        TextMessageTestProcessor::new("rangliste")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("1. dbrgn: 52.0 km (2 Flüge, max. 30.0 km)")
+            .assert_reply_does_not_contain_text("chrigel");
+
+        // Leaderboards of other tenants are not shown
+        db::replace_leaderboard(
+            &pool,
+            "other",
+            &month,
+            &[db::LeaderboardEntry {
+                pilot_username: "dbrgn".into(),
+                flights: 1,
+                distance_km: 200.0,
+                max_km: 200.0,
+            }],
+        )
+        .await
+        .unwrap();
+        TextMessageTestProcessor::new("rangliste")
+            .with_pool(pool.clone())
+            .with_user(user.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("1. dbrgn: 52.0 km (2 Flüge, max. 30.0 km)")
+            .assert_reply_does_not_contain_text("200.0 km");
+    }
+
+    #[tokio::test]
+    async fn test_english() {
+        TextMessageTestProcessor::new("hello")
+            .with_sender("TESTTEST", Some("TestUser"))
+            .with_language(Language::English)
+            .process()
+            .await
+            .assert_reply_contains_text("Hi TestUser!")
+            .assert_reply_contains_text("Available commands:");
+        TextMessageTestProcessor::new("follow dbrgn")
+            .with_language(Language::English)
+            .process()
+            .await
+            .assert_reply_contains_text("You are now following dbrgn!");
+    }
+
+    #[tokio::test]
+    async fn test_admin_export() {
+        let pool = _sqlite_test_db().await;
+        for i in 0..6 {
+            let user =
+                db::get_or_create_user(&pool, DEFAULT_TENANT, &format!("user{}", i), "threema")
+                    .await
+                    .unwrap();
+            db::add_subscription(&pool, user.id, "chrigel", None, None)
+                .await
+                .unwrap();
+        }
+
+        let now = chrono::Utc::now();
+        TextMessageTestProcessor::new(format!("export {}", now.format("%Y")))
+            .with_pool(pool.clone())
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("- Users: 7\n")
+            .assert_reply_contains_text("- Followed pilots: 1\n")
+            .assert_reply_contains_text("- 0: <5\n- 1: 6\n- 2-5: <5\n")
+            .assert_reply_contains_text(&format!("- {}: 7 / <5", now.format("%Y-%m")))
+            .assert_reply_does_not_contain_text("user0");
+    }
+
+    #[tokio::test]
+    async fn test_admin_tokens() {
+        let pool = _sqlite_test_db().await;
+        let admin = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_admin_sender()
+                .process()
+        };
+
+        admin("tokens")
+            .await
+            .assert_reply_contains_text("No API tokens.");
+        let result = admin("token create club read")
+            .await
+            .assert_reply_contains_text("Created token 1 (club, read)");
+        let token = match result.result {
+            OutgoingReply::Text(text) => text
+                .split_whitespace()
+                .find(|word| word.starts_with("xcb_"))
+                .unwrap()
+                .to_string(),
+            _ => unreachable!(),
+        };
+        assert!(tokens::authenticate(&pool, &token, tokens::Scope::Read)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(tokens::authenticate(&pool, &token, tokens::Scope::Admin)
+            .await
+            .unwrap()
+            .is_none());
+        let stored = tokens::authenticate(&pool, &token, tokens::Scope::Read)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.tenant.as_deref(), Some(DEFAULT_TENANT));
+
+        // The tokens of other tenants are neither listed nor revoked
+        let (other, _) = tokens::create(&pool, Some("other"), "other", tokens::Scope::Admin)
+            .await
+            .unwrap();
+        admin("tokens")
+            .await
+            .assert_reply_contains_text(&format!("- 1 club (read, {})", DEFAULT_TENANT))
+            .assert_reply_does_not_contain_text("other")
+            .assert_reply_does_not_contain_text(&token);
+        admin(&format!("token revoke {}", other))
+            .await
+            .assert_reply_contains_text(&format!("Token {} does not exist.", other));
+        admin("token revoke 1")
+            .await
+            .assert_reply_contains_text("Revoked token 1.");
+        assert!(tokens::authenticate(&pool, &token, tokens::Scope::Read)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_admin_survey() {
+        let pool = _sqlite_test_db().await;
+        let admin = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_admin_sender()
+                .process()
+        };
+
+        // The test processor creates one user, plus two more
+        for username in ["ECHOECHO", "FOXTROTT"] {
+            db::get_or_create_user(&pool, DEFAULT_TENANT, username, "threema")
+                .await
+                .unwrap();
+        }
+
+        admin("survey Wie gefällt dir der Bot?")
+            .await
+            .assert_reply_contains_text("Usage: survey");
+        admin("survey Wie gefällt dir der Bot? | Gut | Schlecht")
+            .await
+            .assert_reply_contains_text("Created survey 1, sending the poll to 3 users.");
+        assert_eq!(db::count_jobs(&pool).await.unwrap(), 3);
+        admin("survey sample 2 Noch eine Frage? | Ja | Nein")
+            .await
+            .assert_reply_contains_text("Created survey 2, sending the poll to 2 users.");
+
+        db::set_survey_response(&pool, 1, 1, Some(0)).await.unwrap();
+        db::set_survey_response(&pool, 1, 2, Some(1)).await.unwrap();
+        db::set_survey_response(&pool, 1, 3, Some(0)).await.unwrap();
+        db::set_survey_response(&pool, 1, 3, Some(1)).await.unwrap();
+        admin("survey 1")
+            .await
+            .assert_reply_contains_text("Sent to 3 users, 3 responses:")
+            .assert_reply_contains_text("- Gut: 1 (33%)\n- Schlecht: 2 (66%)");
+        admin("surveys")
+            .await
+            .assert_reply_contains_text("- 2 (")
+            .assert_reply_contains_text("Wie gefällt dir der Bot? (sent to 3 users)");
+        admin("survey 3")
+            .await
+            .assert_reply_contains_text("Survey 3 does not exist.");
+    }
+
+    #[tokio::test]
+    async fn test_referrals() {
+        let pool = _sqlite_test_db().await;
+        let other = db::get_or_create_user(&pool, DEFAULT_TENANT, "ECHOECHO", "threema")
+            .await
+            .unwrap();
+
+        // The start command replies with the help text, the first code wins
+        TextMessageTestProcessor::new("start Club-Alpin")
+            .with_pool(pool.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Verfügbare Befehle:");
+        TextMessageTestProcessor::new("start campaign")
+            .with_pool(pool.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Verfügbare Befehle:");
+        TextMessageTestProcessor::new("start <script>")
+            .with_pool(pool.clone())
+            .with_user(other)
+            .process()
+            .await
+            .assert_reply_contains_text("Verfügbare Befehle:");
+
+        TextMessageTestProcessor::new("referrals")
+            .with_pool(pool.clone())
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("- club-alpin: 1 (1 in the last 30 days)")
+            .assert_reply_contains_text("- (none): 1 (1 in the last 30 days)");
+    }
+
+    #[tokio::test]
+    async fn test_admin_trend() {
+        let pool = _sqlite_test_db().await;
+        let admin = |text| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_admin_sender()
+                .process()
+        };
+        admin("trend")
+            .await
+            .assert_reply_contains_text("No stats snapshots yet.");
+
+        // An old snapshot and today's snapshot (with the admin and another user)
+        sqlx::query(
+            "INSERT INTO stats_history \
+             (tenant, date, users, subscriptions, flights, notifications, failures) \
+             VALUES ('default', date('now', 'localtime', '-10 days'), 0, 0, 0, 0, 3)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        db::get_or_create_user(&pool, DEFAULT_TENANT, "ECHOECHO", "threema")
+            .await
+            .unwrap();
+        db::record_stats_snapshot(&pool, DEFAULT_TENANT)
+            .await
+            .unwrap();
+        admin("trend")
+            .await
+            .assert_reply_contains_text("2 snapshots")
+            .assert_reply_contains_text("- Users: 0 → 2 (+2)")
+            .assert_reply_contains_text("- Parse failures: 3 → 0 (-3)");
+    }
+
+    #[tokio::test]
+    async fn test_admin_diagnose() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "ECHOECHO", "threema")
+            .await
+            .unwrap();
+        db::add_subscription(&pool, user.id, "chrigl", None, None)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO xcontest_flights (tenant, url, title, pilot_username, seen_at, notified_at) \
+             VALUES ('default', 'https://example.com/1', '42 km', 'chrigel', \
+             '2026-10-16 12:00:00', '2026-10-16 12:05:00')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let diagnose = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_admin_sender()
+                .process()
+        };
+
+        diagnose("diagnose")
+            .await
+            .assert_reply_contains_text("Usage: diagnose <pilot> [user]");
+        diagnose("diagnose nobody")
+            .await
+            .assert_reply_contains_text("No flights of nobody seen in the feed");
+        diagnose("diagnose chrigel echoecho")
+            .await
+            .assert_reply_contains_text(
+                "- 42 km (seen 2026-10-16 12:00:00, notified 2026-10-16 12:05:00)",
+            )
+            .assert_reply_contains_text("Subscribers: 0")
+            .assert_reply_contains_text("User threema/ECHOECHO:")
+            .assert_reply_contains_text(
+                "Does not follow chrigel (similar subscriptions: \"chrigl\")",
+            )
+            .assert_reply_contains_text("Delivered: 0 of the 1 recent flights");
+
+        db::add_subscription(&pool, user.id, "Chrigel", None, None)
+            .await
+            .unwrap();
+        db::insert_sent_message(
+            &pool,
+            "abc",
+            user.id,
+            "notification",
+            Some("https://example.com/1"),
+        )
+        .await
+        .unwrap();
+        diagnose("diagnose chrigel ECHOECHO")
+            .await
+            .assert_reply_contains_text("✅ Follows Chrigel")
+            .assert_reply_contains_text("Delivered: 1 of the 1 recent flights");
+        diagnose("diagnose chrigel unknown")
+            .await
+            .assert_reply_contains_text("User unknown not found.");
+    }
+
+    #[tokio::test]
+    async fn test_admin_simulate() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "ECHOECHO", "threema")
+            .await
+            .unwrap();
+        let simulate = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_admin_sender()
+                .process()
+        };
+
+        simulate("simulate echoecho")
+            .await
+            .assert_reply_contains_text("Usage: simulate <user> <flight-url> [title]");
+        simulate("simulate echoecho https://example.com/flight")
+            .await
+            .assert_reply_contains_text("Regex did not match XContest URL");
+        simulate("simulate unknown https://www.xcontest.org/world/en/flights/detail:chrigel/16.10.2026/09:47")
+            .await
+            .assert_reply_contains_text("User unknown not found.");
+        simulate(
+            "simulate echoecho https://www.xcontest.org/world/en/flights/detail:chrigel/16.10.2026/09:47 \
+             16.10.26 [12.34 km :: free_flight] Christian Maurer",
+        )
+        .await
+        .assert_reply_contains_text("queued for threema/ECHOECHO");
+
+        // The flight is notified by the job worker, it's not stored
+        let jobs = db::get_due_jobs(&pool, chrono::Utc::now()).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(
+            Job::from_payload(&jobs[0].payload).unwrap(),
+            Job::SimulateFlight {
+                user_id: user.id,
+                title: "16.10.26 [12.34 km :: free_flight] Christian Maurer".into(),
+                flight_url:
+                    "https://www.xcontest.org/world/en/flights/detail:chrigel/16.10.2026/09:47"
+                        .into(),
+            }
+        );
+        assert_eq!(db::count_flights(&pool, DEFAULT_TENANT).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_admin_prune() {
+        let pool = _sqlite_test_db().await;
+
+        // An old and a recent flight and parse failure
+        for (url, age) in [
+            ("https://example.com/old", 400),
+            ("https://example.com/new", 1),
+        ] {
+            sqlx::query(
+                "INSERT INTO xcontest_flights (tenant, url, title, pilot_username, seen_at) \
+                 VALUES ('default', ?, 'Flight', 'pilot', datetime('now', ?))",
+            )
+            .bind(url)
+            .bind(format!("-{} days", age))
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO parse_failures (kind, source, payload, error, first_seen, last_seen) \
+                 VALUES ('feed', ?, '', 'error', datetime('now', ?), datetime('now', ?))",
+            )
+            .bind(url)
+            .bind(format!("-{} days", age))
+            .bind(format!("-{} days", age))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        TextMessageTestProcessor::new("prune")
+            .with_pool(pool.clone())
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("Pruned 1 flights (older than 365 days)")
+            .assert_reply_contains_text("and 1 parse failures (older than 90 days)");
+        let remaining: i64 = sqlx::query_scalar("SELECT count(*) FROM xcontest_flights")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn test_admin_loglevel() {
+        let admin = |text| {
+            TextMessageTestProcessor::new(text)
+                .with_admin_sender()
+                .process()
+        };
+        admin("loglevel debug,xc_bot=[")
+            .await
+            .assert_reply_contains_text("Error: Invalid log filter");
+
+        // The global subscriber is not installed in tests
+        admin("loglevel")
+            .await
+            .assert_reply_contains_text("Error: Logging is not initialized");
+
+        // The log filter is process-wide, other tenants can't change it
+        TextMessageTestProcessor::new("loglevel debug")
+            .with_tenant("other")
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("only available to the admin of the default tenant");
+    }
+
+    #[tokio::test]
+    async fn test_admin_parse_failures() {
+        let pool = _sqlite_test_db().await;
+
+        // Non-admins cannot see parse failures
+        TextMessageTestProcessor::new("failures")
+            .with_pool(pool.clone())
+            .process()
+            .await
+            .assert_reply_contains_text("Verfügbare Befehle:");
+
+        // Initially, no failures
+        TextMessageTestProcessor::new("failures")
+            .with_pool(pool.clone())
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("No parse failures.");
+
+        // Record failure
+        db::record_parse_failure(
+            &pool,
+            &ParseFailure {
+                kind: PayloadKind::DetailPage,
+                source: "https://www.xcontest.org/flight".into(),
+                payload: "<html></html>".into(),
+                error: "No og:image".into(),
+            },
+        )
+        .await
+        .unwrap();
+        TextMessageTestProcessor::new("failures")
+            .with_pool(pool.clone())
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("#1 [detail_page] https://www.xcontest.org/flight (1x");
+        TextMessageTestProcessor::new("failure 1")
+            .with_pool(pool.clone())
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("Error: No og:image")
+            .assert_reply_contains_text("<html></html>");
+
+        // Retrying still fails
+        TextMessageTestProcessor::new("retry #1")
+            .with_pool(pool.clone())
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("still fails");
+
+        // The feed is shared, the admins of other tenants can't see the failures
+        TextMessageTestProcessor::new("failure 1")
+            .with_pool(pool.clone())
+            .with_tenant("other")
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("only available to the admin of the default tenant")
+            .assert_reply_does_not_contain_text("<html></html>");
+    }
+
+    #[tokio::test]
+    async fn test_admin_msginfo() {
+        let pool = _sqlite_test_db().await;
+        let user = db::get_or_create_user(&pool, DEFAULT_TENANT, "ECHOECHO", "threema")
+            .await
+            .unwrap();
+        let msginfo = |text: &str| {
+            TextMessageTestProcessor::new(text)
+                .with_pool(pool.clone())
+                .with_admin_sender()
+                .process()
+        };
+        msginfo("msginfo 0102030405060708")
+            .await
+            .assert_reply_contains_text("Message 0102030405060708 not found.");
+
+        db::insert_sent_message(
+            &pool,
+            "0102030405060708",
+            user.id,
+            "notification",
+            Some("https://example.com/1"),
+        )
+        .await
+        .unwrap();
+        msginfo("msginfo 0102030405060708")
+            .await
+            .assert_reply_contains_text("Kind: notification")
+            .assert_reply_contains_text("User: threema/ECHOECHO")
+            .assert_reply_contains_text("Flight: https://example.com/1")
+            .assert_reply_contains_text("Delivered: not confirmed")
+            .assert_reply_contains_text("Read: not confirmed");
+
+        // Read implies delivered, receipts of other users are ignored
+        assert!(
+            !db::mark_sent_message(&pool, "0102030405060708", user.id + 1, true)
+                .await
+                .unwrap()
+        );
+        assert!(
+            db::mark_sent_message(&pool, "0102030405060708", user.id, true)
+                .await
+                .unwrap()
+        );
+        let message = db::get_sent_message(&pool, DEFAULT_TENANT, "0102030405060708")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(message.delivered_at.is_some());
+        assert_eq!(message.read_at, message.delivered_at);
+        msginfo("msginfo 0102030405060708")
+            .await
+            .assert_reply_contains_text("Read: 20");
+
+        // The messages of other tenants are not shown
+        TextMessageTestProcessor::new("msginfo 0102030405060708")
+            .with_pool(pool.clone())
+            .with_tenant("other")
+            .with_admin_sender()
+            .process()
+            .await
+            .assert_reply_contains_text("Message 0102030405060708 not found.");
+    }
+}
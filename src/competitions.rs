@@ -0,0 +1,150 @@
+//! Competition rosters that users can follow temporarily (`folge comp
+//! <code>`), e.g. during a Swiss League weekend.
+//!
+//! A roster is either configured (see [`CompetitionConfig`]) or pasted by the
+//! user as a list of usernames.
+
+use std::{collections::HashSet, convert::TryFrom};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+
+use crate::config::CompetitionConfig;
+
+/// Maximum number of pilots of a roster
+pub const MAX_ROSTER_SIZE: usize = 200;
+
+/// Days the subscriptions to a pasted roster last (a competition weekend),
+/// unless the user specifies a duration
+pub const PASTED_ROSTER_DAYS: u32 = 3;
+
+/// Label of the subscriptions to a pasted roster, unless the user specifies
+/// a label
+pub const PASTED_ROSTER_LABEL: &str = "comp";
+
+/// Find the competition with the specified code or URL.
+pub fn find<'a>(
+    competitions: &'a [CompetitionConfig],
+    code_or_url: &str,
+) -> Option<&'a CompetitionConfig> {
+    let url = code_or_url.trim_end_matches('/');
+    competitions.iter().find(|competition| {
+        competition.code.eq_ignore_ascii_case(code_or_url)
+            || competition
+                .url
+                .as_deref()
+                .is_some_and(|competition_url| competition_url.trim_end_matches('/') == url)
+    })
+}
+
+/// Return the display name of the competition.
+pub fn name(competition: &CompetitionConfig) -> &str {
+    competition.name.as_deref().unwrap_or(&competition.code)
+}
+
+/// Return the number of days until the subscriptions to the roster end (the
+/// day after the competition), or `None` if the competition is over.
+pub fn days_left(competition: &CompetitionConfig, today: NaiveDate) -> Result<Option<u32>> {
+    let end_date = NaiveDate::parse_from_str(&competition.end_date, "%Y-%m-%d").context(
+        format!("Invalid end date of competition {}", competition.code),
+    )?;
+    Ok(u32::try_from((end_date - today).num_days() + 1)
+        .ok()
+        .filter(|days| *days > 0))
+}
+
+/// Return the usernames of the roster (configured and from the roster file),
+/// without duplicates.
+pub fn roster(competition: &CompetitionConfig) -> Result<Vec<String>> {
+    read_roster(
+        competition.pilots.as_deref(),
+        competition.roster_file.as_deref(),
+    )
+}
+
+/// Return the configured usernames and the ones of the roster file, without
+/// duplicates (also used for club members).
+pub fn read_roster(pilots: Option<&[String]>, roster_file: Option<&str>) -> Result<Vec<String>> {
+    let mut usernames = pilots.map(<[String]>::to_vec).unwrap_or_default();
+    if let Some(path) = roster_file {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Could not read roster file {:?}", path))?;
+        usernames.extend(
+            contents
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or("").trim())
+                .filter(|line| !line.is_empty())
+                .map(str::to_string),
+        );
+    }
+    Ok(dedup(usernames))
+}
+
+/// Parse a pasted roster (usernames separated by commas or whitespace),
+/// without duplicates.
+pub fn parse_usernames(text: &str) -> Vec<String> {
+    dedup(
+        text.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|username| !username.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Remove duplicate usernames (case-insensitive), keeping the first one.
+fn dedup(usernames: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    usernames
+        .into_iter()
+        .filter(|username| seen.insert(username.to_lowercase()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn competition(end_date: &str) -> CompetitionConfig {
+        CompetitionConfig {
+            code: "swissleague".into(),
+            name: None,
+            url: Some("https://www.swissleague.ch/".into()),
+            end_date: end_date.into(),
+            pilots: Some(vec!["chrigel".into(), "dbrgn".into(), "Chrigel".into()]),
+            roster_file: None,
+        }
+    }
+
+    #[test]
+    fn find_by_code_or_url() {
+        let competitions = [competition("2026-10-18")];
+        assert!(find(&competitions, "SwissLeague").is_some());
+        assert!(find(&competitions, "https://www.swissleague.ch").is_some());
+        assert!(find(&competitions, "other").is_none());
+        assert_eq!(name(&competitions[0]), "swissleague");
+        assert_eq!(roster(&competitions[0]).unwrap(), vec!["chrigel", "dbrgn"]);
+    }
+
+    #[test]
+    fn remaining_days() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 17).unwrap();
+        assert_eq!(
+            days_left(&competition("2026-10-18"), today).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            days_left(&competition("2026-10-17"), today).unwrap(),
+            Some(1)
+        );
+        assert_eq!(days_left(&competition("2026-10-16"), today).unwrap(), None);
+        assert!(days_left(&competition("18.10.2026"), today).is_err());
+    }
+
+    #[test]
+    fn pasted_usernames() {
+        assert_eq!(
+            parse_usernames("chrigel, dbrgn;pilot\nCHRIGEL"),
+            vec!["chrigel", "dbrgn", "pilot"]
+        );
+    }
+}
@@ -1,6 +1,15 @@
-use std::{fs::File, io::Read, path::Path};
+use std::{collections::HashMap, fs::File, io::Read, path::Path, time::Duration};
 
+use anyhow::Result;
 use serde_derive::Deserialize;
+use xcontest_client::DEFAULT_FEED_URL;
+
+use crate::{
+    db::PoolSettings,
+    messages::{Language, Messages},
+    reactions,
+    tenants::DEFAULT_TENANT,
+};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
@@ -8,6 +17,23 @@ pub struct Config {
     pub xcontest: Option<XcontestConfig>,
     pub server: ServerConfig,
     pub logging: Option<LoggingConfig>,
+    pub scheduler: Option<SchedulerConfig>,
+    pub alerts: Option<AlertsConfig>,
+    pub smtp: Option<SmtpConfig>,
+    pub slack: Option<SlackConfig>,
+    pub ntfy: Option<NtfyConfig>,
+    pub pushover: Option<PushoverConfig>,
+    pub gotify: Option<GotifyConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub database: Option<DatabaseConfig>,
+    pub cluster: Option<ClusterConfig>,
+    pub commands: Option<CommandsConfig>,
+    pub messages: Option<MessagesConfig>,
+    pub features: Option<FeaturesConfig>,
+    pub retention: Option<RetentionConfig>,
+    pub competitions: Option<Vec<CompetitionConfig>>,
+    pub clubs: Option<Vec<ClubConfig>>,
+    pub tenants: Option<Vec<TenantConfig>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -20,18 +46,134 @@ pub struct ThreemaConfig {
     pub private_key: String,
     /// Identity of the admin
     pub admin_id: Option<String>,
+    /// Maximum number of notifications per user and month, to control
+    /// gateway credit costs (default: unlimited)
+    pub monthly_notification_cap: Option<u32>,
+    /// Whether recipients should send delivery receipts for messages sent by
+    /// the bot (default: false)
+    pub request_delivery_receipts: Option<bool>,
+    /// Whether the bot should send a read receipt for every incoming message
+    /// (default: false)
+    pub send_read_receipts: Option<bool>,
+}
+
+impl ThreemaConfig {
+    /// Return whether recipients should send delivery receipts.
+    pub fn request_delivery_receipts(&self) -> bool {
+        self.request_delivery_receipts.unwrap_or(false)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct XcontestConfig {
     /// The query interval in seconds (default: 180)
     pub interval_seconds: Option<u64>,
+    /// Send animated previews (GIF/WebP) as-is if XContest provides one. This is
+    /// bandwidth-heavy, so it's disabled by default. (default: false)
+    pub animated_previews: Option<bool>,
+    /// How long fetched flight details are cached, in seconds. Set to 0 to
+    /// disable caching. (default: 86400)
+    pub details_cache_ttl_seconds: Option<u64>,
+    /// Minimum delay between two requests to xcontest.org, in milliseconds.
+    /// (default: 1000)
+    pub min_request_delay_ms: Option<u64>,
+    /// Maximum number of flight detail pages fetched per hour. Flights
+    /// exceeding the budget are notified without preview image. (default:
+    /// unlimited)
+    pub detail_fetches_per_hour: Option<u32>,
+    /// Number of detail pages fetched concurrently when several new flights
+    /// arrive at once. The requests are still spaced by
+    /// `min_request_delay_ms`. (default: 3)
+    pub detail_fetch_parallelism: Option<usize>,
+    /// The User-Agent sent to xcontest.org (default: `xc-bot/<version>`)
+    pub user_agent: Option<String>,
+    /// Operator contact (URL or e-mail address), appended to the User-Agent
+    /// and sent in the `From` header (if it is an e-mail address), so that
+    /// XContest can reach you.
+    pub contact: Option<String>,
+    /// Additional HTTP headers sent to xcontest.org
+    pub headers: Option<HashMap<String, String>>,
+    /// The RSS feed of the flights to notify about (default: the CCC feed)
+    pub feed_url: Option<String>,
+    /// The flight list (flight search page) of the contest, scraped when the
+    /// feed is unavailable or truncated (default: none)
+    pub flight_list_url: Option<String>,
+    /// A feed with fewer items is considered truncated and is supplemented
+    /// with the flight list (default: 1)
+    pub min_feed_items: Option<usize>,
+    /// Flights of the same pilot uploaded within this many seconds are
+    /// notified together in one message. Notifications are delayed by this
+    /// time. Set to 0 to notify every flight immediately. (default: 0)
+    pub group_window_seconds: Option<u64>,
+    /// A flight is marked as the pilot's first flight of the season if their
+    /// previous flight was seen more than this many months before. Set to 0
+    /// to disable the marker. (default: 4)
+    pub season_gap_months: Option<u32>,
+    /// Notify subscribers again when the title of a notified flight changes
+    /// (e.g. the distance was corrected after optimization) (default: false)
+    pub notify_corrections: Option<bool>,
+    /// Alert the admin when more than this percentage of the feed items of a
+    /// fetch cycle cannot be parsed (default: 10)
+    pub parse_failure_alert_percent: Option<u32>,
+    /// Regex of flight URLs, replacing the built-in patterns. The pilot
+    /// username is taken from the capture group `pilot`, the contest from the
+    /// optional capture group `contest`. (default: built-in)
+    pub url_pattern: Option<String>,
+    /// Regex of the preview image URL on flight detail pages, tried before
+    /// the built-in parsers. The URL is taken from the capture group `url`.
+    /// (default: built-in)
+    pub thumbnail_pattern: Option<String>,
+    /// Directory of the on-disk cache of detail pages and preview images
+    /// (default: no cache)
+    pub http_cache_dir: Option<String>,
+    /// How long cached responses are served past their freshness when
+    /// XContest cannot be reached, unless the response specifies
+    /// `stale-if-error` (default: 604800, one week)
+    pub http_cache_max_stale_seconds: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
     /// The HTTP server listening host:port string
     pub listen: String,
+    /// Listening host:port string of a second HTTP server for the internal
+    /// endpoints (health check and admin API). If set, these endpoints are
+    /// not served by the public server. (default: none)
+    pub internal_listen: Option<String>,
+    /// Compress responses with gzip or brotli, if the client supports it
+    /// (default: true)
+    pub compression: Option<bool>,
+    /// Log every request (method, path, status, latency and client IP) with
+    /// target `access_log` (default: true)
+    pub access_log: Option<bool>,
+    /// Log full client IPs in the access log instead of anonymized ones
+    /// (default: false)
+    pub access_log_full_ips: Option<bool>,
+    /// Serve a public landing page with a description of the bot, the Threema
+    /// link and the number of users and flights at `/` (default: true)
+    pub landing_page: Option<bool>,
+    /// Origins (e.g. `https://club.example.com`, or `*` for any origin)
+    /// allowed to query the API from a browser (default: none)
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// HTTP methods allowed for cross-origin API requests (default: `GET`)
+    pub cors_allowed_methods: Option<Vec<String>>,
+    /// Reverse proxies (IP addresses or networks) whose `X-Forwarded-For`
+    /// header is used to determine the client IP (default: none)
+    pub trusted_proxies: Option<Vec<String>>,
+    /// Only accept Threema callbacks from these IP addresses or networks
+    /// (default: from anywhere)
+    pub threema_allowlist: Option<Vec<String>>,
+    /// Maximum number of Threema callbacks processed concurrently (default: 3)
+    pub webhook_concurrency: Option<usize>,
+    /// Maximum number of Threema callbacks waiting to be processed. Further
+    /// callbacks are rejected with HTTP 429, so that the gateway retries them
+    /// later. (default: 20)
+    pub webhook_queue: Option<usize>,
+    /// Run as standby (e.g. against a restored backup): The feeds are
+    /// processed and the APIs served, but state-changing commands and API
+    /// requests are refused, no notifications are sent and the job queue is
+    /// paused (default: false)
+    pub read_only: Option<bool>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -41,6 +183,441 @@ pub struct LoggingConfig {
     pub filter: Option<String>,
 }
 
+/// Schedules of the periodic tasks, as cron expressions (`minute hour
+/// day-of-month month day-of-week`, local time).
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SchedulerConfig {
+    /// When to send the daily digest (default: `0 20 * * *`)
+    pub digest: Option<String>,
+    /// When to recompute the leaderboards (default: `0 3 * * *`)
+    pub leaderboards: Option<String>,
+    /// When to run database maintenance (default: `30 3 * * *`)
+    pub maintenance: Option<String>,
+    /// When to back up the database (default: `0 4 * * *`)
+    pub backup: Option<String>,
+    /// When to store the daily stats snapshot (default: `55 23 * * *`)
+    pub stats: Option<String>,
+    /// When to send the weekly report to the admin (default: `0 9 * * 1`)
+    pub weekly_report: Option<String>,
+    /// When to end the expired temporary subscriptions (default: `0 8 * * *`)
+    pub expire_follows: Option<String>,
+    /// When to send the club leaderboards of the previous month to their
+    /// subscribers (default: `0 18 1 * *`)
+    pub club_leaderboards: Option<String>,
+    /// Directory where database backups are written. Backups are disabled if
+    /// this is not set.
+    pub backup_dir: Option<String>,
+    /// Number of backups to keep (default: 7)
+    pub backup_keep: Option<usize>,
+}
+
+/// The SMTP server used to notify users with an e-mail address.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    /// Hostname of the SMTP server
+    pub host: String,
+    /// Port of the SMTP server (default: 465 with `tls`, 587 with `starttls`,
+    /// 25 with `none`)
+    pub port: Option<u16>,
+    /// Encryption of the connection: `starttls`, `tls` or `none` (default:
+    /// `starttls`)
+    pub encryption: Option<String>,
+    /// Username for the SMTP login (default: no login)
+    pub username: Option<String>,
+    /// Password for the SMTP login
+    pub password: Option<String>,
+    /// The sender of the e-mails, e.g. `XC Bot <xcbot@example.com>`
+    pub from: String,
+}
+
+/// The Slack app used to post flights to Slack channels.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlackConfig {
+    /// Bot token of the Slack app (`xoxb-...`), used to post the messages
+    pub bot_token: String,
+    /// Signing secret of the Slack app, used to verify slash commands
+    pub signing_secret: String,
+    /// Public base URL of the HTTP server (e.g. `https://xcbot.example.com`),
+    /// used to link the preview images (default: no images)
+    pub public_url: Option<String>,
+}
+
+/// The ntfy server used to push flights to phones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NtfyConfig {
+    /// Base URL of the ntfy server (default: `https://ntfy.sh`)
+    pub server: Option<String>,
+    /// Topic of users without their own topic (default: none, such users are
+    /// not notified)
+    pub topic: Option<String>,
+    /// Access token for protected topics (default: none)
+    pub token: Option<String>,
+}
+
+/// The Pushover application used to push flights to phones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushoverConfig {
+    /// API token of the Pushover application
+    pub app_token: String,
+    /// Flights of at least this distance are sent with `long_flight_priority`
+    /// (default: 100)
+    pub long_flight_km: Option<f64>,
+    /// Priority of long flights, from -2 (lowest) to 1 (high, bypasses quiet
+    /// hours). Emergency priority is not supported. (default: 1)
+    pub long_flight_priority: Option<i8>,
+}
+
+/// The Gotify server used to push flights to self-hosted infrastructure.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GotifyConfig {
+    /// Base URL of the Gotify server (e.g. `https://gotify.example.com`)
+    pub server: String,
+    /// Priority of the messages (default: 5)
+    pub priority: Option<u8>,
+}
+
+/// The MQTT broker new flights are published to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MqttConfig {
+    /// Hostname of the broker
+    pub host: String,
+    /// Port of the broker (default: 1883, or 8883 with TLS)
+    pub port: Option<u16>,
+    /// Connect with TLS (default: false)
+    pub tls: Option<bool>,
+    /// Username and password, if the broker requires authentication
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Client ID of the bot (default: `xc-bot`)
+    pub client_id: Option<String>,
+    /// Topic the flights are published to, with the placeholders `{tenant}`
+    /// and `{pilot}` (default: `xcbot/flights/{pilot}`)
+    pub topic: Option<String>,
+}
+
+/// Where admin alerts (errors, anomalies, ...) are sent.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertsConfig {
+    /// The alert channel, either `threema` or `webhook` (default: `threema`)
+    pub channel: Option<String>,
+    /// Threema ID that receives alerts (default: the `admin_id` in the
+    /// `[threema]` section)
+    pub threema_id: Option<String>,
+    /// HTTPS URL that alerts are POSTed to as JSON (`{"text": "..."}`), when
+    /// using the `webhook` channel. This is compatible with the incoming
+    /// webhooks of Slack and Mattermost.
+    pub webhook_url: Option<String>,
+    /// Send a message when a new user registers (default: false)
+    pub new_users: Option<bool>,
+    /// Send a message when the user or flight count of a tenant reaches a
+    /// milestone (100, 200, ..., 1000, ... users and 10'000, 20'000, ...
+    /// flights) (default: false)
+    pub milestones: Option<bool>,
+    /// Alert when the 90th percentile of the latency between the publication
+    /// of flights in the feed and their notification exceeds this many
+    /// seconds (default: no alert)
+    pub latency_slo_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DatabaseConfig {
+    /// Path to the SQLite database file (default: `data.db`, or
+    /// `/data/xc-bot.db` if the config is read from the environment)
+    pub path: Option<String>,
+    /// Number of connections kept open (default: 2)
+    pub min_connections: Option<u32>,
+    /// Maximum number of open connections (default: 5)
+    pub max_connections: Option<u32>,
+    /// Seconds to wait for a free connection before giving up. The pool state
+    /// is logged when this happens. (default: 30)
+    pub acquire_timeout_seconds: Option<u64>,
+    /// Seconds a write waits for a lock held by another connection before it
+    /// fails as busy (and is retried a few times) (default: 5)
+    pub busy_timeout_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ClusterConfig {
+    /// Run two instances against the same database, of which only the leader
+    /// (holding a lock in the database) polls the feeds, runs the jobs and
+    /// sends notifications, while the other one queues incoming commands for
+    /// the leader (default: false)
+    pub leader_election: Option<bool>,
+    /// Name of this instance, must be unique (default: hostname and process
+    /// ID)
+    pub instance_name: Option<String>,
+    /// Seconds the leader lock is valid without renewal, i.e. the time until
+    /// the other instance takes over if the leader stops (default: 30)
+    pub lease_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MessagesConfig {
+    /// TOML file with texts replacing the built-in texts (e.g.
+    /// `follow_success = "..."`), loaded at startup (default: none)
+    pub override_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CommandsConfig {
+    /// Number of pilots per page of the list command (default: 50)
+    pub list_page_size: Option<usize>,
+    /// A command identical to the previous command of the user within this
+    /// many seconds is ignored (e.g. when resent by a flaky client). Set to 0
+    /// to process all commands. (default: 10)
+    pub duplicate_window_seconds: Option<u32>,
+    /// Minimum time between two help texts (the reply to unknown commands) to
+    /// the same sender, in seconds. Only read from the top-level section.
+    /// (default: 300)
+    pub help_cooldown_seconds: Option<u64>,
+    /// Maximum number of replies to the same sender per hour. Only read from
+    /// the top-level section. (default: 60)
+    pub max_replies_per_hour: Option<usize>,
+}
+
+/// Subsystems that can be switched off per deployment.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FeaturesConfig {
+    /// Daily digests (if disabled, users with a digest are notified
+    /// immediately) (default: true)
+    pub enable_digests: Option<bool>,
+    /// The HTTP API at `/api/v1/` (default: true)
+    pub enable_api: Option<bool>,
+    /// Preview images in notifications (if disabled, flight details are not
+    /// fetched and notifications are text-only) (default: true)
+    pub enable_images: Option<bool>,
+    /// Surveys sent as Threema polls by the admin (default: true)
+    pub enable_surveys: Option<bool>,
+}
+
+impl FeaturesConfig {
+    pub fn digests(&self) -> bool {
+        self.enable_digests.unwrap_or(true)
+    }
+
+    pub fn api(&self) -> bool {
+        self.enable_api.unwrap_or(true)
+    }
+
+    pub fn images(&self) -> bool {
+        self.enable_images.unwrap_or(true)
+    }
+
+    pub fn surveys(&self) -> bool {
+        self.enable_surveys.unwrap_or(true)
+    }
+
+    /// Return all features and whether they are enabled.
+    pub fn list(&self) -> [(&'static str, bool); 4] {
+        [
+            ("digests", self.digests()),
+            ("api", self.api()),
+            ("images", self.images()),
+            ("surveys", self.surveys()),
+        ]
+    }
+
+    /// Format the features for logs and the admin, e.g. `digests on, api off`.
+    pub fn summary(&self) -> String {
+        self.list()
+            .iter()
+            .map(|(name, enabled)| format!("{} {}", name, if *enabled { "on" } else { "off" }))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// How long data is kept in the database before the maintenance task prunes
+/// it.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RetentionConfig {
+    /// Days to keep seen flights. Must be longer than flights stay in the
+    /// feed, otherwise they are notified again. (default: 365)
+    pub flights_days: Option<u32>,
+    /// Days to keep the message IDs of sent messages (reactions to older
+    /// notifications are ignored) (default: 30)
+    pub notifications_days: Option<u32>,
+    /// Days to keep quarantined parse failures after they were last seen
+    /// (default: 90)
+    pub parse_failures_days: Option<u32>,
+}
+
+impl RetentionConfig {
+    pub fn flights_days(&self) -> u32 {
+        self.flights_days.unwrap_or(365)
+    }
+
+    pub fn notifications_days(&self) -> u32 {
+        self.notifications_days
+            .unwrap_or(reactions::NOTIFICATION_MESSAGE_DAYS)
+    }
+
+    pub fn parse_failures_days(&self) -> u32 {
+        self.parse_failures_days.unwrap_or(90)
+    }
+}
+
+/// A competition whose roster users can follow temporarily
+/// (`folge comp <code>`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompetitionConfig {
+    /// Short code of the competition, also used as label of the subscriptions
+    /// (letters, digits, `-` and `_`)
+    pub code: String,
+    /// Display name of the competition (default: the code)
+    pub name: Option<String>,
+    /// URL of the competition, which users may send instead of the code
+    /// (default: none)
+    pub url: Option<String>,
+    /// The last day of the competition (`YYYY-MM-DD`), the subscriptions end
+    /// the day after
+    pub end_date: String,
+    /// XContest usernames of the pilots (default: none)
+    pub pilots: Option<Vec<String>>,
+    /// File with additional XContest usernames, one per line (`#` starts a
+    /// comment), read when the roster is followed (default: none)
+    pub roster_file: Option<String>,
+}
+
+/// A club whose monthly kilometre leaderboard users can subscribe to
+/// (`club <code>`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClubConfig {
+    /// Short code of the club (letters, digits, `-` and `_`)
+    pub code: String,
+    /// Display name of the club (default: the code)
+    pub name: Option<String>,
+    /// XContest usernames of the members (default: none)
+    pub pilots: Option<Vec<String>>,
+    /// File with additional XContest usernames, one per line (`#` starts a
+    /// comment), read whenever the leaderboard is generated (default: none)
+    pub roster_file: Option<String>,
+}
+
+/// An additional logical bot running in the same process, with its own
+/// gateway ID, feed and texts. Its users, flights and leaderboards are
+/// isolated from the other tenants.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantConfig {
+    /// Unique ID of the tenant, used in the webhook URL
+    /// (`/receive/threema/<id>/`) and in the database
+    pub id: String,
+    /// The Threema Gateway settings of this tenant
+    pub threema: ThreemaConfig,
+    /// The RSS feed of the flights to notify about (default: the CCC feed)
+    pub feed_url: Option<String>,
+    /// The flight list (flight search page) of the contest, scraped when the
+    /// feed is unavailable or truncated (default: none)
+    pub flight_list_url: Option<String>,
+    /// The language of the texts sent to users, `de` or `en` (default: `de`)
+    pub language: Option<Language>,
+    /// Custom help text, sent for unknown commands. `{nickname}` is replaced
+    /// with the nickname of the user. (default: the built-in help text)
+    pub help_text: Option<String>,
+    /// Settings of the user commands (default: the top-level `[commands]`
+    /// section)
+    pub commands: Option<CommandsConfig>,
+    /// Overrides of the texts sent to users (default: none)
+    pub messages: Option<MessagesConfig>,
+    /// The texts with the overrides applied (set by [`Self::load_messages`])
+    #[serde(skip)]
+    pub overridden_messages: Option<&'static Messages>,
+    /// The features of the deployment (copied from the `[features]` section)
+    #[serde(skip)]
+    pub features: FeaturesConfig,
+    /// The data retention of the deployment (copied from the `[retention]`
+    /// section)
+    #[serde(skip)]
+    pub retention: RetentionConfig,
+    /// The competitions of the deployment (copied from the `[[competitions]]`
+    /// sections)
+    #[serde(skip)]
+    pub competitions: Vec<CompetitionConfig>,
+    /// The clubs of the deployment (copied from the `[[clubs]]` sections)
+    #[serde(skip)]
+    pub clubs: Vec<ClubConfig>,
+    /// The SMTP server of the deployment (copied from the `[smtp]` section)
+    #[serde(skip)]
+    pub smtp: Option<SmtpConfig>,
+    /// The Slack app of the deployment (copied from the `[slack]` section)
+    #[serde(skip)]
+    pub slack: Option<SlackConfig>,
+    /// The ntfy server of the deployment (copied from the `[ntfy]` section)
+    #[serde(skip)]
+    pub ntfy: Option<NtfyConfig>,
+    /// The Pushover application of the deployment (copied from the
+    /// `[pushover]` section)
+    #[serde(skip)]
+    pub pushover: Option<PushoverConfig>,
+    /// The Gotify server of the deployment (copied from the `[gotify]`
+    /// section)
+    #[serde(skip)]
+    pub gotify: Option<GotifyConfig>,
+}
+
+impl TenantConfig {
+    /// Return the texts in the language of this tenant (with the overrides
+    /// applied, if they were loaded).
+    pub fn messages(&self) -> &'static Messages {
+        self.overridden_messages
+            .unwrap_or_else(|| self.language.unwrap_or_default().messages())
+    }
+
+    /// Load the override file of the texts, if configured.
+    pub fn load_messages(&mut self) -> Result<()> {
+        let path = match self
+            .messages
+            .as_ref()
+            .and_then(|m| m.override_file.as_ref())
+        {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let defaults = self.language.unwrap_or_default().messages();
+        self.overridden_messages = Some(defaults.load_overrides(Path::new(path))?);
+        Ok(())
+    }
+
+    /// Return the RSS feed URL of this tenant.
+    pub fn feed_url(&self) -> &str {
+        self.feed_url.as_deref().unwrap_or(DEFAULT_FEED_URL)
+    }
+
+    /// Return the number of pilots per page of the list command.
+    pub fn list_page_size(&self) -> usize {
+        self.commands
+            .as_ref()
+            .and_then(|commands| commands.list_page_size)
+            .unwrap_or(50)
+            .max(1)
+    }
+
+    /// Return the window in which identical consecutive commands are ignored,
+    /// in seconds.
+    pub fn duplicate_window_seconds(&self) -> u32 {
+        self.commands
+            .as_ref()
+            .and_then(|commands| commands.duplicate_window_seconds)
+            .unwrap_or(10)
+    }
+
+    /// Return the help text of this tenant.
+    pub fn help_text(&self) -> &str {
+        self.help_text
+            .as_deref()
+            .unwrap_or_else(|| self.messages().help)
+    }
+}
+
+/// Default database path.
+const DEFAULT_DATABASE_PATH: &str = "data.db";
+
+/// Prefix of the environment variables used if there is no config file.
+const ENV_PREFIX: &str = "XCBOT_";
+
+/// Default database path if the config is read from the environment.
+const ENV_DATABASE_PATH: &str = "/data/xc-bot.db";
+
 impl Config {
     pub fn load(path: &Path) -> Result<Config, String> {
         let mut file = File::open(path).map_err(|e| e.to_string())?;
@@ -49,4 +626,282 @@ impl Config {
             .map_err(|e| e.to_string())?;
         toml::from_str(&contents).map_err(|e| e.to_string())
     }
+
+    /// Load the config file. If it does not exist, but `XCBOT_*` environment
+    /// variables are set, read the config from the environment instead.
+    pub fn load_or_env(path: &Path) -> Result<Config, String> {
+        if !path.exists() && std::env::vars().any(|(name, _)| name.starts_with(ENV_PREFIX)) {
+            return Config::from_env(std::env::vars());
+        }
+        Config::load(path)
+    }
+
+    /// Read the config from environment variables.
+    ///
+    /// The variable names consist of the prefix `XCBOT_`, the section name
+    /// and the key, separated by two underscores (e.g.
+    /// `XCBOT_THREEMA__GATEWAY_ID`). Values are read as strings, except for
+    /// `true`, `false` and integers. To pass a string that looks like a
+    /// number, quote it (e.g. `"1234"`).
+    pub fn from_env(vars: impl Iterator<Item = (String, String)>) -> Result<Config, String> {
+        let mut table = toml::Table::new();
+        for (name, value) in vars {
+            let path = match name.strip_prefix(ENV_PREFIX) {
+                Some(path) => path.to_lowercase(),
+                None => continue,
+            };
+            let mut keys: Vec<&str> = path.split("__").collect();
+            let key = keys.pop().unwrap_or_default();
+            let mut section = &mut table;
+            for section_name in keys {
+                section = section
+                    .entry(section_name)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .ok_or(format!("Conflicting environment variable: {}", name))?;
+            }
+            section.insert(key.to_string(), parse_env_value(&value));
+        }
+        let mut config: Config = table.try_into().map_err(|e| e.to_string())?;
+        let database = config.database.get_or_insert_with(DatabaseConfig::default);
+        database
+            .path
+            .get_or_insert_with(|| ENV_DATABASE_PATH.to_string());
+        Ok(config)
+    }
+
+    /// Return all tenants. The first one is the default tenant, configured
+    /// by the top-level settings.
+    pub fn tenants(&self) -> Vec<TenantConfig> {
+        let default = TenantConfig {
+            id: DEFAULT_TENANT.to_string(),
+            threema: self.threema.clone(),
+            feed_url: self.xcontest.as_ref().and_then(|xc| xc.feed_url.clone()),
+            flight_list_url: self
+                .xcontest
+                .as_ref()
+                .and_then(|xc| xc.flight_list_url.clone()),
+            language: None,
+            help_text: None,
+            commands: self.commands.clone(),
+            messages: self.messages.clone(),
+            overridden_messages: None,
+            features: self.features(),
+            retention: self.retention(),
+            competitions: self.competitions.clone().unwrap_or_default(),
+            clubs: self.clubs.clone().unwrap_or_default(),
+            smtp: self.smtp.clone(),
+            slack: self.slack.clone(),
+            ntfy: self.ntfy.clone(),
+            pushover: self.pushover.clone(),
+            gotify: self.gotify.clone(),
+        };
+        std::iter::once(default)
+            .chain(self.tenants.iter().flatten().map(|tenant| TenantConfig {
+                features: self.features(),
+                retention: self.retention(),
+                competitions: self.competitions.clone().unwrap_or_default(),
+                clubs: self.clubs.clone().unwrap_or_default(),
+                smtp: self.smtp.clone(),
+                slack: self.slack.clone(),
+                ntfy: self.ntfy.clone(),
+                pushover: self.pushover.clone(),
+                gotify: self.gotify.clone(),
+                ..tenant.clone()
+            }))
+            .collect()
+    }
+
+    /// Return the features of the deployment.
+    pub fn features(&self) -> FeaturesConfig {
+        self.features.clone().unwrap_or_default()
+    }
+
+    /// Return the data retention of the deployment.
+    pub fn retention(&self) -> RetentionConfig {
+        self.retention.clone().unwrap_or_default()
+    }
+
+    /// Return the minimal gap (in months) before a first flight of the season.
+    pub fn season_gap_months(&self) -> u32 {
+        self.xcontest
+            .as_ref()
+            .and_then(|xc| xc.season_gap_months)
+            .unwrap_or(4)
+    }
+
+    /// Return the percentage of unparseable feed items per fetch cycle above
+    /// which the admin is alerted.
+    pub fn parse_failure_alert_percent(&self) -> u32 {
+        self.xcontest
+            .as_ref()
+            .and_then(|xc| xc.parse_failure_alert_percent)
+            .unwrap_or(10)
+    }
+
+    /// Return the number of detail pages fetched concurrently.
+    pub fn detail_fetch_parallelism(&self) -> usize {
+        self.xcontest
+            .as_ref()
+            .and_then(|xc| xc.detail_fetch_parallelism)
+            .unwrap_or(3)
+            .max(1)
+    }
+
+    /// Return the number of feed items below which the feed is considered
+    /// truncated.
+    pub fn min_feed_items(&self) -> usize {
+        self.xcontest
+            .as_ref()
+            .and_then(|xc| xc.min_feed_items)
+            .unwrap_or(1)
+    }
+
+    /// Return the notification latency (in seconds) above which the admin is
+    /// alerted, if any.
+    pub fn latency_slo_seconds(&self) -> Option<u64> {
+        self.alerts
+            .as_ref()
+            .and_then(|alerts| alerts.latency_slo_seconds)
+    }
+
+    /// Return whether the bot runs in read-only mode.
+    pub fn read_only(&self) -> bool {
+        self.server.read_only.unwrap_or(false)
+    }
+
+    /// Return whether subscribers are notified about corrected flights.
+    pub fn notify_corrections(&self) -> bool {
+        self.xcontest
+            .as_ref()
+            .and_then(|xc| xc.notify_corrections)
+            .unwrap_or(false)
+    }
+
+    /// Return the path to the SQLite database file.
+    pub fn database_path(&self) -> &str {
+        self.database
+            .as_ref()
+            .and_then(|database| database.path.as_deref())
+            .unwrap_or(DEFAULT_DATABASE_PATH)
+    }
+
+    /// Return the sizing of the database connection pool.
+    pub fn pool_settings(&self) -> PoolSettings {
+        let defaults = PoolSettings::default();
+        let database = self.database.as_ref();
+        PoolSettings {
+            min_connections: database
+                .and_then(|database| database.min_connections)
+                .unwrap_or(defaults.min_connections),
+            max_connections: database
+                .and_then(|database| database.max_connections)
+                .unwrap_or(defaults.max_connections),
+            acquire_timeout: database
+                .and_then(|database| database.acquire_timeout_seconds)
+                .map_or(defaults.acquire_timeout, Duration::from_secs),
+            busy_timeout: database
+                .and_then(|database| database.busy_timeout_seconds)
+                .map_or(defaults.busy_timeout, Duration::from_secs),
+        }
+    }
+}
+
+/// Parse the value of a config environment variable.
+fn parse_env_value(value: &str) -> toml::Value {
+    match value {
+        "true" => toml::Value::Boolean(true),
+        "false" => toml::Value::Boolean(false),
+        _ => {
+            if let Ok(number) = value.parse::<i64>() {
+                return toml::Value::Integer(number);
+            }
+            if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                return toml::Value::String(value[1..value.len() - 1].to_string());
+            }
+            toml::Value::String(value.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn config_from_env() {
+        let config = Config::from_env(env(&[
+            ("XCBOT_THREEMA__GATEWAY_ID", "*XCBOTXX"),
+            ("XCBOT_THREEMA__GATEWAY_SECRET", "\"12345\""),
+            ("XCBOT_THREEMA__PRIVATE_KEY", "abcd"),
+            ("XCBOT_THREEMA__SEND_READ_RECEIPTS", "true"),
+            ("XCBOT_SERVER__LISTEN", "0.0.0.0:3000"),
+            ("XCBOT_XCONTEST__INTERVAL_SECONDS", "300"),
+            ("XCBOT_DATABASE__MAX_CONNECTIONS", "8"),
+            ("HOME", "/root"),
+        ]))
+        .unwrap();
+        assert_eq!(config.threema.gateway_id, "*XCBOTXX");
+        assert_eq!(config.threema.gateway_secret, "12345");
+        assert_eq!(config.threema.send_read_receipts, Some(true));
+        assert_eq!(config.server.listen, "0.0.0.0:3000");
+        assert_eq!(
+            config.pool_settings(),
+            PoolSettings {
+                max_connections: 8,
+                ..PoolSettings::default()
+            }
+        );
+        assert_eq!(config.xcontest.unwrap().interval_seconds, Some(300));
+        assert_eq!(config.database.unwrap().path.unwrap(), "/data/xc-bot.db");
+    }
+
+    #[test]
+    fn tenants() {
+        let config: Config = toml::from_str(
+            r#"
+            [threema]
+            gateway_id = "*XCBOTXX"
+            gateway_secret = "secret"
+            private_key = "abcd"
+
+            [server]
+            listen = "127.0.0.1:3000"
+
+            [[tenants]]
+            id = "fr"
+            feed_url = "https://www.xcontest.org/rss/flights/?cfd"
+            language = "en"
+
+            [tenants.threema]
+            gateway_id = "*XCBOTFR"
+            gateway_secret = "secret"
+            private_key = "abcd"
+            "#,
+        )
+        .unwrap();
+        let tenants = config.tenants();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(tenants[0].id, DEFAULT_TENANT);
+        assert_eq!(tenants[0].feed_url(), DEFAULT_FEED_URL);
+        assert_eq!(tenants[0].language, None);
+        assert_eq!(tenants[1].id, "fr");
+        assert_eq!(tenants[1].threema.gateway_id, "*XCBOTFR");
+        assert_eq!(tenants[1].language, Some(Language::English));
+        assert!(tenants[1].help_text().starts_with("Hi {nickname}!"));
+        assert!(tenants[1].features.digests());
+    }
+
+    #[test]
+    fn config_from_env_missing_setting() {
+        let result = Config::from_env(env(&[("XCBOT_THREEMA__GATEWAY_ID", "*XCBOTXX")]));
+        assert!(result.is_err());
+    }
 }
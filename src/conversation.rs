@@ -0,0 +1,55 @@
+//! Conversation state.
+//!
+//! Commands that need a follow-up message of the user (e.g. picking one of
+//! several pilots) store what they are waiting for in the database. The state
+//! expires if the user doesn't answer in time.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde_derive::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+use crate::db;
+
+/// How long the bot waits for the follow-up message.
+const STATE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// What the bot expects as the next message of a user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ConversationState {
+    /// The user was asked to pick the pilot to follow from a numbered list of
+    /// usernames (the subscription gets the label and ends after the number
+    /// of days, if any).
+    ChoosePilot {
+        pilots: Vec<String>,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        days: Option<u32>,
+    },
+}
+
+/// Return the conversation state of the user, if any.
+pub async fn get(pool: &Pool<Sqlite>, user_id: i32) -> Result<Option<ConversationState>> {
+    match db::get_conversation_state(pool, user_id).await? {
+        Some(state) => serde_json::from_str(&state)
+            .map(Some)
+            .context("Could not deserialize conversation state"),
+        None => Ok(None),
+    }
+}
+
+/// Store the conversation state of the user.
+pub async fn set(pool: &Pool<Sqlite>, user_id: i32, state: &ConversationState) -> Result<()> {
+    let state = serde_json::to_string(state).context("Could not serialize conversation state")?;
+    let expires_at = Utc::now() + chrono::Duration::from_std(STATE_TTL).unwrap_or_default();
+    Ok(db::set_conversation_state(pool, user_id, &state, expires_at).await?)
+}
+
+/// Reset the conversation state of the user.
+pub async fn clear(pool: &Pool<Sqlite>, user_id: i32) -> Result<()> {
+    Ok(db::delete_conversation_state(pool, user_id).await?)
+}
@@ -1,12 +1,393 @@
 //! Database related functions.
+//!
+//! Functions used in the update cycle take an executor instead of the pool,
+//! so that the whole cycle can use a single connection.
+//!
+//! Errors are returned as [`Error`], classified by their cause (database
+//! unavailable, row not found, constraint violated, invalid stored data).
 
-use anyhow::{Context, Result};
-use sqlx::{sqlite::SqliteRow, FromRow, Pool, Row, Sqlite};
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use sqlx::{
+    error::DatabaseError,
+    migrate::{Migrate, MigrateError, Migrator},
+    pool::PoolConnection,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow},
+    Executor, FromRow, Pool, QueryBuilder, Row, Sqlite, SqliteConnection,
+};
 use threema_gateway::RecipientKey;
 
+use xcontest_client::{
+    Flight, FlightDetails, FlightTimes, ParseFailure, PayloadKind, PreviewFormat, TriangleStats,
+};
+
+/// Errors of the database functions.
+///
+/// The message of an error describes the failed operation, the underlying
+/// error is available as source.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The database is unavailable (no free connection, I/O error, ...)
+    #[error("{context}")]
+    Unavailable {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    /// A row that was expected to exist does not exist
+    #[error("{context}")]
+    NotFound { context: String },
+    /// The database is locked by another connection, retrying later may
+    /// succeed
+    #[error("{context}")]
+    Busy {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    /// A constraint (e.g. a unique constraint) was violated
+    #[error("{context}")]
+    Conflict {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    /// Data stored in the database could not be decoded
+    #[error("{context}")]
+    InvalidData {
+        context: String,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    /// The pool settings are invalid
+    #[error("Invalid database pool size: min {min}, max {max}")]
+    InvalidPoolSize { min: u32, max: u32 },
+    /// A migration could not be applied or reverted
+    #[error("{context}")]
+    Migration {
+        context: String,
+        #[source]
+        source: Option<MigrateError>,
+    },
+    /// Any other database error
+    #[error("{context}")]
+    Query {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+}
+
+/// SQLite result code: The database file is locked
+const SQLITE_BUSY: i32 = 5;
+
+/// SQLite result code: A table in the database is locked
+const SQLITE_LOCKED: i32 = 6;
+
+/// SQLite result code: A constraint was violated
+const SQLITE_CONSTRAINT: i32 = 19;
+
+/// Return the primary result code of a SQLite error. The error messages
+/// differ between SQLite versions, only the codes are stable.
+fn primary_code(error: &dyn DatabaseError) -> Option<i32> {
+    // Extended result codes carry the primary result code in the lowest byte
+    let code: i32 = error.code()?.parse().ok()?;
+    Some(code & 0xff)
+}
+
+impl Error {
+    /// Classify a sqlx error.
+    fn new(context: String, source: sqlx::Error) -> Self {
+        match &source {
+            sqlx::Error::RowNotFound => Error::NotFound { context },
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed
+            | sqlx::Error::Io(_) => Error::Unavailable { context, source },
+            sqlx::Error::Database(e) => match primary_code(e.as_ref()) {
+                Some(SQLITE_BUSY | SQLITE_LOCKED) => Error::Busy { context, source },
+                Some(SQLITE_CONSTRAINT) => Error::Conflict { context, source },
+                _ => Error::Query { context, source },
+            },
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => Error::InvalidData {
+                context,
+                source: Some(Box::new(source)),
+            },
+            _ => Error::Query { context, source },
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Attach a description of the failed operation to an error, like
+/// `anyhow::Context`.
+trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T>;
+}
+
+impl<T> Context<T> for std::result::Result<T, sqlx::Error> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::new(context.into(), source))
+    }
+}
+
+impl<T> Context<T> for std::result::Result<T, serde_json::Error> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::InvalidData {
+            context: context.into(),
+            source: Some(Box::new(source)),
+        })
+    }
+}
+
+impl<T> Context<T> for std::result::Result<T, chrono::ParseError> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::InvalidData {
+            context: context.into(),
+            source: Some(Box::new(source)),
+        })
+    }
+}
+
+impl<T> Context<T> for std::result::Result<T, MigrateError> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::Migration {
+            context: context.into(),
+            source: Some(source),
+        })
+    }
+}
+
+/// The migrations embedded into the binary.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Sizing of the database connection pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolSettings {
+    pub min_connections: u32,
+    pub max_connections: u32,
+    /// How long to wait for a free connection before giving up
+    pub acquire_timeout: Duration,
+    /// How long a statement waits for a lock held by another connection
+    /// before failing as busy
+    pub busy_timeout: Duration,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            min_connections: 2,
+            max_connections: 5,
+            acquire_timeout: Duration::from_secs(30),
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Connect to the SQLite database at the specified path, creating it if
+/// necessary.
+pub async fn connect(path: &str, settings: &PoolSettings) -> Result<Pool<Sqlite>> {
+    if settings.max_connections == 0 || settings.min_connections > settings.max_connections {
+        return Err(Error::InvalidPoolSize {
+            min: settings.min_connections,
+            max: settings.max_connections,
+        });
+    }
+    let connect_options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .foreign_keys(true)
+        .busy_timeout(settings.busy_timeout);
+    SqlitePoolOptions::new()
+        .min_connections(settings.min_connections)
+        .max_connections(settings.max_connections)
+        .acquire_timeout(settings.acquire_timeout)
+        .connect_with(connect_options)
+        .await
+        .context(format!("Could not open database {}", path))
+}
+
+/// Number of tasks currently waiting for a database connection.
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a task as waiting for a connection while alive.
+struct Waiting;
+
+impl Waiting {
+    fn new() -> Self {
+        WAITING.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        WAITING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Number of write operations retried because the database was busy.
+static BUSY_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Maximum number of retries of a write operation if the database is busy.
+const MAX_BUSY_RETRIES: u32 = 3;
+
+/// Return the number of write operations retried because the database was
+/// busy, since the start.
+pub fn busy_retries() -> u64 {
+    BUSY_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Evaluate a write operation (returning a [`Result`]) and retry it with a
+/// short, increasing delay while it fails with [`Error::Busy`].
+///
+/// SQLite already waits for the busy timeout before failing, but under lock
+/// contention between the update loop and the webhooks a write can still lose
+/// the race. The expression is evaluated again for every attempt.
+macro_rules! retry_busy {
+    ($operation:expr) => {{
+        let mut retries = 0;
+        loop {
+            match $operation {
+                Err(Error::Busy { context, .. }) if retries < MAX_BUSY_RETRIES => {
+                    retries += 1;
+                    BUSY_RETRIES.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        "{}: Database is busy, retrying ({}/{})",
+                        context,
+                        retries,
+                        MAX_BUSY_RETRIES
+                    );
+                    tokio::time::sleep(Duration::from_millis(100 * u64::from(retries))).await;
+                }
+                result => break result,
+            }
+        }
+    }};
+}
+
+/// Acquire a connection from the pool.
+///
+/// If no connection becomes free within the acquire timeout, the state of the
+/// pool (connections in use, other waiting tasks) is logged, to diagnose
+/// stalls.
+pub async fn acquire(pool: &Pool<Sqlite>) -> Result<PoolConnection<Sqlite>> {
+    let waiting = Waiting::new();
+    let result = pool.acquire().await;
+    drop(waiting);
+    match result {
+        Ok(conn) => Ok(conn),
+        Err(sqlx::Error::PoolTimedOut) => {
+            let idle = pool.num_idle();
+            let in_use = (pool.size() as usize).saturating_sub(idle);
+            let waiting = WAITING.load(Ordering::SeqCst);
+            let max = pool.options().get_max_connections();
+            tracing::error!(
+                in_use,
+                idle,
+                waiting,
+                max,
+                "Timed out acquiring db connection: {} of {} connections in use, {} other tasks waiting",
+                in_use,
+                max,
+                waiting
+            );
+            Err(Error::Unavailable {
+                context: "Timed out acquiring db connection".into(),
+                source: sqlx::Error::PoolTimedOut,
+            })
+        }
+        Err(e) => Err(e).context("Could not acquire db connection"),
+    }
+}
+
+/// Apply all pending migrations.
+pub async fn migrate(pool: &Pool<Sqlite>) -> Result<()> {
+    MIGRATOR
+        .run(pool)
+        .await
+        .context("Could not run database migrations")
+}
+
+/// An embedded migration and whether it was applied to the database.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+    pub reversible: bool,
+}
+
+/// Return all embedded migrations, in order.
+pub async fn get_migration_status(pool: &Pool<Sqlite>) -> Result<Vec<MigrationStatus>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    conn.ensure_migrations_table()
+        .await
+        .context("Could not create migrations table")?;
+    let applied: Vec<i64> = conn
+        .list_applied_migrations()
+        .await
+        .context("Could not list applied migrations")?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied: applied.contains(&migration.version),
+            reversible: migration.migration_type.is_reversible(),
+        })
+        .collect())
+}
+
+/// Revert the most recently applied migration and return it. Return `None`
+/// if no migration was applied.
+pub async fn revert_last_migration(pool: &Pool<Sqlite>) -> Result<Option<MigrationStatus>> {
+    let applied: Vec<MigrationStatus> = get_migration_status(pool)
+        .await?
+        .into_iter()
+        .filter(|migration| migration.applied)
+        .collect();
+    let (last, previous) = match applied.split_last() {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+    if !last.reversible {
+        return Err(Error::Migration {
+            context: format!(
+                "Migration {} ({}) is not reversible",
+                last.version, last.description
+            ),
+            source: None,
+        });
+    }
+    MIGRATOR
+        .undo(
+            pool,
+            previous.last().map_or(0, |migration| migration.version),
+        )
+        .await
+        .context(format!("Could not revert migration {}", last.version))?;
+    Ok(Some(last.clone()))
+}
+
 #[derive(Debug, Clone)]
 pub struct User {
     pub id: i32,
+    pub tenant: String,
     pub username: String,
     pub usertype: String,
     pub threema_public_key: Option<RecipientKey>,
@@ -16,6 +397,7 @@ impl FromRow<'_, SqliteRow> for User {
     fn from_row(row: &SqliteRow) -> std::result::Result<Self, sqlx::Error> {
         Ok(Self {
             id: row.try_get("id")?,
+            tenant: row.try_get("tenant")?,
             username: row.try_get("username")?,
             usertype: row.try_get("usertype")?,
             threema_public_key: row
@@ -31,8 +413,118 @@ pub struct Stats {
     pub user_count: u32,
     /// Number of subscriptions
     pub subscription_count: u32,
+    /// Number of subscriptions added within the last 30 days
+    pub new_subscription_count: u32,
     /// Number of flights
     pub flight_count: u32,
+    /// Number of notifications sent this month
+    pub notifications_this_month: u32,
+    /// Number of notifications with image sent this month
+    pub images_this_month: u32,
+}
+
+/// Notification counters for a user in a specific month.
+#[derive(Debug, Default, FromRow)]
+pub struct NotificationCounter {
+    /// Number of notification messages sent
+    pub messages: u32,
+    /// Whether the user was informed that the monthly cap was reached
+    pub capped: bool,
+}
+
+/// Notification counters of a user, used for reporting.
+#[derive(Debug, FromRow)]
+pub struct UserNotificationCounter {
+    pub username: String,
+    pub usertype: String,
+    pub messages: u32,
+    pub images: u32,
+}
+
+/// Number of users that signed up with a referral code.
+#[derive(Debug, FromRow)]
+pub struct ReferralCount {
+    /// The referral code (`None` for users without referral)
+    pub referral: Option<String>,
+    pub users: u32,
+    /// Number of users that signed up in the last 30 days
+    pub new_users: u32,
+}
+
+/// A flight stored in the database.
+#[derive(Debug, FromRow)]
+pub struct StoredFlight {
+    /// Database ID (e.g. for the thumbnail URL), only selected where it's
+    /// needed
+    #[sqlx(default)]
+    pub id: i64,
+    pub url: String,
+    pub title: String,
+    pub guid: Option<String>,
+    /// Start time (`%H:%M`), only selected where it's shown
+    #[sqlx(default)]
+    pub start_time: Option<String>,
+    /// Airtime in minutes, only selected where it's shown
+    #[sqlx(default)]
+    pub airtime_minutes: Option<u32>,
+}
+
+impl StoredFlight {
+    /// Convert the stored flight back into a parsed flight.
+    pub fn to_flight(&self) -> Result<Flight> {
+        let flight =
+            Flight::new(self.title.clone(), self.url.clone()).map_err(|e| Error::InvalidData {
+                context: format!("Invalid stored flight {}", self.url),
+                source: Some(e.into()),
+            })?;
+        Ok(flight.with_guid(self.guid.clone()).with_times(flight_times(
+            self.start_time.as_deref(),
+            self.airtime_minutes,
+        )))
+    }
+}
+
+/// Convert stored flight times, an invalid start time is ignored.
+fn flight_times(start_time: Option<&str>, airtime_minutes: Option<u32>) -> FlightTimes {
+    FlightTimes {
+        start: start_time.and_then(|start| NaiveTime::parse_from_str(start, "%H:%M").ok()),
+        airtime_minutes,
+    }
+}
+
+/// A quarantined payload that could not be parsed.
+#[derive(Debug, FromRow)]
+pub struct StoredParseFailure {
+    pub id: i64,
+    pub kind: String,
+    pub source: String,
+    pub payload: String,
+    pub error: String,
+    pub occurrences: u32,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+impl StoredParseFailure {
+    /// Return the payload kind, if known.
+    pub fn payload_kind(&self) -> Option<PayloadKind> {
+        PayloadKind::parse(&self.kind)
+    }
+}
+
+/// A message sent to a user, with its recipient.
+#[derive(Debug, FromRow)]
+pub struct SentMessage {
+    pub message_id: String,
+    pub user_id: i32,
+    pub usertype: String,
+    pub username: String,
+    pub kind: String,
+    pub flight_url: Option<String>,
+    pub flight_title: Option<String>,
+    pub sent_at: String,
+    pub delivered_at: Option<String>,
+    pub read_at: Option<String>,
 }
 
 /// Return the specified user.
@@ -40,27 +532,44 @@ pub struct Stats {
 /// If the user does not yet exist, create it.
 pub async fn get_or_create_user(
     pool: &Pool<Sqlite>,
+    tenant: &str,
     username: &str,
     usertype: &str,
 ) -> Result<User> {
+    Ok(get_or_register_user(pool, tenant, username, usertype)
+        .await?
+        .0)
+}
+
+/// Like [`get_or_create_user`], but also return whether the user was created.
+pub async fn get_or_register_user(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    username: &str,
+    usertype: &str,
+) -> Result<(User, bool)> {
     // Start transaction
     let mut transaction = pool.begin().await.context("Could not start transaction")?;
 
     // Ensure user exists
-    sqlx::query(
+    let created = sqlx::query(
         r#"
-        INSERT OR IGNORE INTO users (username, usertype, since)
-        VALUES (?, ?, CURRENT_TIMESTAMP)
+        INSERT OR IGNORE INTO users (tenant, username, usertype, since)
+        VALUES (?, ?, ?, CURRENT_TIMESTAMP)
         "#,
     )
+    .bind(tenant)
     .bind(username)
     .bind(usertype)
     .execute(&mut *transaction)
     .await
-    .context(format!("Could not create user {}/{}", usertype, username))?;
+    .context(format!("Could not create user {}/{}", usertype, username))?
+    .rows_affected()
+        > 0;
 
     // Fetch user
-    let user: User = sqlx::query_as("SELECT id, username, usertype, threema_public_key FROM users WHERE username = ? AND usertype = ?")
+    let user: User = sqlx::query_as("SELECT id, tenant, username, usertype, threema_public_key FROM users WHERE tenant = ? AND username = ? AND usertype = ?")
+        .bind(tenant)
         .bind(username)
         .bind(usertype)
         .fetch_one(&mut *transaction)
@@ -72,43 +581,143 @@ pub async fn get_or_create_user(
         .commit()
         .await
         .context("Could not commit transaction")?;
-    Ok(user)
+    Ok((user, created))
 }
 
-/// Return the subscriptions of the user with the specified user ID, sorted by name.
-pub async fn get_subscriptions(pool: &Pool<Sqlite>, user_id: i32) -> Result<Vec<String>> {
+/// Return the number of users of the tenant.
+pub async fn count_users(pool: &Pool<Sqlite>, tenant: &str) -> Result<u64> {
     // Get connection
-    let mut conn = pool
-        .acquire()
+    let mut conn = acquire(pool).await?;
+
+    // Count users
+    let count: i64 = sqlx::query_scalar("SELECT count(*) FROM users WHERE tenant = ?")
+        .bind(tenant)
+        .fetch_one(&mut *conn)
         .await
-        .context("Could not acquire db connection")?;
+        .context("Could not count users")?;
+    Ok(count as u64)
+}
 
-    // Fetch subscriptions
-    let subscriptions =
-        sqlx::query_scalar("SELECT pilot_username FROM subscriptions WHERE user_id = ? ORDER BY pilot_username COLLATE NOCASE ASC")
-            .bind(user_id)
-            .fetch_all(&mut *conn)
-            .await
-            .context("Could not fetch subscriptions")?;
+/// A subscription of a user.
+#[derive(Debug, FromRow)]
+pub struct Subscription {
+    pub pilot_username: String,
+    /// When the subscription was added (`%Y-%m-%d %H:%M:%S`, UTC)
+    pub created_at: String,
+    /// The label of the subscription (lowercase, without `#`)
+    pub label: Option<String>,
+    /// When a temporary subscription ends (`%Y-%m-%d %H:%M:%S`, UTC)
+    pub expires_at: Option<String>,
+}
+
+/// Return the subscriptions of the user (only those with the label, if
+/// specified), sorted by name or most recently added first.
+pub async fn get_subscription_list(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    label: Option<&str>,
+    newest_first: bool,
+) -> Result<Vec<Subscription>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
 
-    Ok(subscriptions)
+    // Fetch subscriptions
+    let order = if newest_first {
+        "created_at DESC, id DESC"
+    } else {
+        "pilot_username COLLATE NOCASE ASC"
+    };
+    sqlx::query_as(&format!(
+        r#"
+        SELECT pilot_username, created_at, label, expires_at
+        FROM subscriptions
+        WHERE user_id = ? AND (?2 IS NULL OR label = ?2)
+        ORDER BY {}
+        "#,
+        order
+    ))
+    .bind(user_id)
+    .bind(label)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch subscriptions")
 }
 
-/// Add a subscription for the user with the specified user ID.
-pub async fn add_subscription(pool: &Pool<Sqlite>, user_id: i32, pilot: &str) -> Result<()> {
+/// Add a subscription for the user with the specified user ID. A temporary
+/// subscription ends after `days` days.
+///
+/// If the user already follows the pilot, the label is updated (if
+/// specified). A temporary subscription ends as specified, a permanent one
+/// stays permanent.
+///
+/// Return whether the subscription is temporary.
+pub async fn add_subscription(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    pilot: &str,
+    label: Option<&str>,
+    days: Option<u32>,
+) -> Result<bool> {
+    add_subscriptions(pool, user_id, &[pilot], label, days).await?;
+
     // Get connection
-    let mut conn = pool
-        .acquire()
-        .await
-        .context("Could not acquire db connection")?;
+    let mut conn = acquire(pool).await?;
+
+    // Fetch expiry
+    sqlx::query_scalar(
+        "SELECT expires_at IS NOT NULL FROM subscriptions WHERE user_id = ? AND pilot_username = ?",
+    )
+    .bind(user_id)
+    .bind(pilot)
+    .fetch_one(&mut *conn)
+    .await
+    .context("Could not fetch subscription expiry")
+}
+
+/// Add subscriptions to several pilots at once (e.g. a competition roster),
+/// see [`add_subscription`].
+pub async fn add_subscriptions(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    pilots: &[impl AsRef<str>],
+    label: Option<&str>,
+    days: Option<u32>,
+) -> Result<()> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
 
-    // Add subscription
-    sqlx::query("INSERT OR IGNORE INTO subscriptions (user_id, pilot_username) VALUES (?, ?)")
+    // Add subscriptions
+    for pilot in pilots {
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (user_id, pilot_username, created_at, label, expires_at)
+            VALUES (?, ?, CURRENT_TIMESTAMP, ?, datetime('now', ?))
+            ON CONFLICT(user_id, pilot_username) DO UPDATE SET
+                label = coalesce(excluded.label, label),
+                expires_at = CASE WHEN expires_at IS NULL THEN NULL ELSE excluded.expires_at END
+            "#,
+        )
         .bind(user_id)
-        .bind(pilot)
-        .execute(&mut *conn)
+        .bind(pilot.as_ref())
+        .bind(label)
+        .bind(days.map(|days| format!("+{} days", days)))
+        .execute(&mut *transaction)
         .await
         .context("Could not add subscription")?;
+    }
+
+    // The user is no longer churned
+    sqlx::query("UPDATE users SET churned_at = NULL WHERE id = ?")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Could not reset churn")?;
+
+    // Commit transaction
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
 
     Ok(())
 }
@@ -134,6 +743,18 @@ pub async fn remove_subscription(pool: &Pool<Sqlite>, user_id: i32, pilot: &str)
         .await
         .context("Could not query number of deleted rows")?;
 
+    // Users without subscriptions are churned
+    sqlx::query(
+        r#"
+        UPDATE users SET churned_at = CURRENT_TIMESTAMP
+        WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM subscriptions WHERE user_id = ?1)
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not mark user as churned")?;
+
     // Commit transaction
     transaction
         .commit()
@@ -143,47 +764,3285 @@ pub async fn remove_subscription(pool: &Pool<Sqlite>, user_id: i32, pilot: &str)
     Ok(deleted)
 }
 
-/// Store a cached Threema public key for the specified user.
-pub async fn cache_public_key(
-    pool: &Pool<Sqlite>,
-    user_id: i32,
-    public_key: &RecipientKey,
-) -> Result<()> {
-    // Get connection
-    let mut conn = pool
-        .acquire()
+/// Merge the user `from` into the user `into` (both identified by their
+/// username in the tenant), e.g. after a duplicate was created by a case
+/// variation or an ID change. Subscriptions, notification counters, survey
+/// responses, reactions and pending jobs are moved, then the duplicate is
+/// removed.
+///
+/// Return the number of moved subscriptions (subscriptions to pilots that
+/// `into` already follows are dropped).
+pub async fn merge_users(pool: &Pool<Sqlite>, tenant: &str, from: &str, into: &str) -> Result<u64> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
+
+    // Look up users
+    let mut ids = [0; 2];
+    for (id, username) in ids.iter_mut().zip([from, into]) {
+        *id = sqlx::query_scalar("SELECT id FROM users WHERE tenant = ? AND username = ?")
+            .bind(tenant)
+            .bind(username)
+            .fetch_optional(&mut *transaction)
+            .await
+            .context(format!("Could not fetch user {}", username))?
+            .ok_or_else(|| Error::NotFound {
+                context: format!("User {} does not exist", username),
+            })?;
+    }
+    let [from_id, into_id]: [i32; 2] = ids;
+    if from_id == into_id {
+        return Err(Error::InvalidData {
+            context: "Cannot merge a user into itself".into(),
+            source: None,
+        });
+    }
+
+    // Move subscriptions (keeping the earlier subscription date of duplicates)
+    sqlx::query(
+        r#"
+        UPDATE subscriptions AS s SET created_at = d.created_at
+        FROM subscriptions AS d
+        WHERE s.user_id = ?2 AND d.user_id = ?1 AND d.pilot_username = s.pilot_username
+            AND d.created_at < s.created_at
+        "#,
+    )
+    .bind(from_id)
+    .bind(into_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not merge subscription dates")?;
+    let moved = sqlx::query("UPDATE OR IGNORE subscriptions SET user_id = ? WHERE user_id = ?")
+        .bind(into_id)
+        .bind(from_id)
+        .execute(&mut *transaction)
         .await
-        .context("Could not acquire db connection")?;
+        .context("Could not move subscriptions")?
+        .rows_affected();
 
-    // Update cached public key
-    sqlx::query("UPDATE users SET threema_public_key = ? WHERE id = ?")
-        .bind(public_key.as_bytes())
-        .bind(user_id)
-        .execute(&mut *conn)
+    // Add up notification counters
+    sqlx::query(
+        r#"
+        INSERT INTO notification_counters (user_id, month, messages, images, capped, failures)
+        SELECT ?, month, messages, images, capped, failures
+        FROM notification_counters WHERE user_id = ?
+        ON CONFLICT(user_id, month) DO UPDATE SET
+            messages = messages + excluded.messages,
+            images = images + excluded.images,
+            capped = capped OR excluded.capped,
+            failures = failures + excluded.failures
+        "#,
+    )
+    .bind(into_id)
+    .bind(from_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not merge notification counters")?;
+
+    // Move the remaining history (the votes and reactions of `into` win)
+    for table in [
+        "survey_responses",
+        "flight_reactions",
+        "sent_messages",
+        "club_subscriptions",
+    ] {
+        sqlx::query(&format!(
+            "UPDATE OR IGNORE {} SET user_id = ? WHERE user_id = ?",
+            table
+        ))
+        .bind(into_id)
+        .bind(from_id)
+        .execute(&mut *transaction)
         .await
-        .context("Could not cache public key")?;
+        .context(format!("Could not move {}", table))?;
+    }
 
-    Ok(())
+    // Move pending jobs
+    sqlx::query(
+        r#"
+        UPDATE jobs SET payload = json_set(payload, '$.user_id', ?)
+        WHERE json_extract(payload, '$.user_id') = ?
+        "#,
+    )
+    .bind(into_id)
+    .bind(from_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not move jobs")?;
+
+    // Keep the earlier registration and the referral
+    sqlx::query(
+        r#"
+        UPDATE users AS u SET
+            since = min(coalesce(u.since, d.since), coalesce(d.since, u.since)),
+            referral = coalesce(u.referral, d.referral),
+            churned_at = CASE
+                WHEN EXISTS (SELECT 1 FROM subscriptions WHERE user_id = u.id) THEN NULL
+                ELSE u.churned_at
+            END
+        FROM users AS d
+        WHERE u.id = ? AND d.id = ?
+        "#,
+    )
+    .bind(into_id)
+    .bind(from_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not merge user")?;
+
+    // Remove the duplicate and everything that couldn't be moved
+    for table in [
+        "subscriptions",
+        "notification_counters",
+        "survey_responses",
+        "flight_reactions",
+        "sent_messages",
+        "club_subscriptions",
+        "conversation_states",
+        "recent_commands",
+    ] {
+        sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?", table))
+            .bind(from_id)
+            .execute(&mut *transaction)
+            .await
+            .context(format!("Could not remove {} of duplicate user", table))?;
+    }
+    sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(from_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Could not remove duplicate user")?;
+
+    // Commit transaction
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
+
+    Ok(moved)
 }
 
-/// Return database stats.
-pub async fn get_stats(pool: &Pool<Sqlite>) -> Result<Stats> {
-    // Get connection
-    let mut conn = pool
-        .acquire()
+/// Remove all subscriptions of the user with the specified label.
+///
+/// Return the number of removed subscriptions.
+pub async fn remove_labeled_subscriptions(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    label: &str,
+) -> Result<u64> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
+
+    // Remove subscriptions
+    let removed = sqlx::query("DELETE FROM subscriptions WHERE user_id = ? AND label = ?")
+        .bind(user_id)
+        .bind(label)
+        .execute(&mut *transaction)
         .await
-        .context("Could not acquire db connection")?;
+        .context("Could not remove subscriptions")?
+        .rows_affected();
 
-    // Update cached public key
-    sqlx::query_as(
+    // Users without subscriptions are churned
+    sqlx::query(
         r#"
-        SELECT
-            (SELECT count(*) FROM users) as user_count,
-            (SELECT count(*) FROM subscriptions) as subscription_count,
-            (SELECT count(*) FROM xcontest_flights) as flight_count;
+        UPDATE users SET churned_at = CURRENT_TIMESTAMP
+        WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM subscriptions WHERE user_id = ?1)
         "#,
     )
-    .fetch_one(&mut *conn)
+    .bind(user_id)
+    .execute(&mut *transaction)
     .await
-    .context("Could not fetch stats")
+    .context("Could not mark user as churned")?;
+
+    // Commit transaction
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
+
+    Ok(removed)
+}
+
+/// Remove the expired temporary subscriptions.
+///
+/// Return the user IDs and pilots of the removed subscriptions.
+pub async fn remove_expired_subscriptions(pool: &Pool<Sqlite>) -> Result<Vec<(i32, String)>> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
+
+    // Remove subscriptions
+    let removed: Vec<(i32, String)> = sqlx::query_as(
+        r#"
+        DELETE FROM subscriptions WHERE expires_at <= CURRENT_TIMESTAMP
+        RETURNING user_id, pilot_username
+        "#,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Could not remove expired subscriptions")?;
+
+    // Users without subscriptions are churned
+    sqlx::query(
+        r#"
+        UPDATE users SET churned_at = CURRENT_TIMESTAMP
+        WHERE churned_at IS NULL
+            AND NOT EXISTS (SELECT 1 FROM subscriptions WHERE user_id = users.id)
+            AND id IN (SELECT value FROM json_each(?))
+        "#,
+    )
+    .bind(
+        serde_json::to_string(&removed.iter().map(|(id, _)| id).collect::<Vec<_>>())
+            .context("Could not serialize user IDs")?,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Could not mark users as churned")?;
+
+    // Commit transaction
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
+
+    Ok(removed)
+}
+
+/// Move the subscription of the user from one pilot to another (e.g. after
+/// the pilot changed their username). The subscription date is kept.
+///
+/// Return whether the user was subscribed to the old pilot.
+pub async fn move_subscription(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    old_pilot: &str,
+    new_pilot: &str,
+) -> Result<bool> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
+
+    // Rename subscription (ignored if the user already follows the new pilot)
+    sqlx::query(
+        r#"
+        UPDATE OR IGNORE subscriptions
+        SET pilot_username = ?
+        WHERE user_id = ? AND pilot_username = ? COLLATE NOCASE
+        "#,
+    )
+    .bind(new_pilot)
+    .bind(user_id)
+    .bind(old_pilot)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not move subscription")?;
+    let moved: bool = sqlx::query_scalar("SELECT changes() > 0")
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Could not query number of moved rows")?;
+
+    // Remove the old subscription, if it was not renamed
+    sqlx::query(
+        "DELETE FROM subscriptions WHERE user_id = ? AND pilot_username = ? COLLATE NOCASE",
+    )
+    .bind(user_id)
+    .bind(old_pilot)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not remove subscription")?;
+    let deleted: bool = sqlx::query_scalar("SELECT changes() > 0")
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Could not query number of deleted rows")?;
+
+    // Commit transaction
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
+
+    Ok(moved || deleted)
+}
+
+/// Return all users of the tenant following the pilot (including the ones
+/// receiving a digest).
+pub async fn get_pilot_subscribers(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    pilot: &str,
+) -> Result<Vec<User>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch subscribers
+    sqlx::query_as(
+        r#"
+        SELECT u.id, u.tenant, u.username, u.usertype, u.threema_public_key
+        FROM subscriptions s
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE s.pilot_username = ? COLLATE NOCASE
+        AND u.tenant = ?
+        "#,
+    )
+    .bind(pilot)
+    .bind(tenant)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch subscribers")
+}
+
+/// Maximum number of rows inserted or updated with one statement (SQLite
+/// limits the number of bound parameters)
+const MAX_BATCH_ROWS: usize = 1000;
+
+/// The result of storing the flights of a feed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UpsertedFlights {
+    /// URLs of the new flights
+    pub new: HashSet<String>,
+    /// URLs of the already notified flights whose title changed
+    pub corrected: HashSet<String>,
+}
+
+/// Store the flights of the tenant. Flights that are already stored (with the
+/// same URL or GUID) are skipped, but their title is updated if it changed.
+/// New flights are marked as notified at `notified_at`, if set.
+pub async fn upsert_flights(
+    conn: &mut SqliteConnection,
+    tenant: &str,
+    flights: &[Flight],
+    notified_at: Option<DateTime<Utc>>,
+) -> Result<UpsertedFlights> {
+    let notified_at = notified_at.map(sql_timestamp);
+    let mut upserted = UpsertedFlights::default();
+    for chunk in flights.chunks(MAX_BATCH_ROWS) {
+        // Insert flights or update changed titles. Only updated rows have a
+        // title update timestamp.
+        let mut query = QueryBuilder::new(
+            "INSERT INTO xcontest_flights (tenant, url, title, pilot_username, guid, contest, seen_at, notified_at) ",
+        );
+        query.push_values(chunk, |mut row, flight| {
+            row.push_bind(tenant)
+                .push_bind(&flight.url)
+                .push_bind(&flight.title)
+                .push_bind(&flight.pilot_username)
+                .push_bind(flight.dedup_key())
+                .push_bind(&flight.contest)
+                .push("CURRENT_TIMESTAMP")
+                .push_bind(notified_at.as_deref());
+        });
+        query.push(
+            r#"
+            ON CONFLICT(tenant, url) DO UPDATE SET
+                title = excluded.title,
+                title_updated_at = CURRENT_TIMESTAMP
+                WHERE title != excluded.title
+            ON CONFLICT DO NOTHING
+            RETURNING url, title_updated_at IS NOT NULL, notified_at IS NOT NULL
+            "#,
+        );
+        let rows: Vec<(String, bool, bool)> = query
+            .build_query_as()
+            .fetch_all(&mut *conn)
+            .await
+            .context("Could not store flights")?;
+        for (url, updated, notified) in rows {
+            if !updated {
+                upserted.new.insert(url);
+            } else if notified && !upserted.new.contains(&url) {
+                upserted.corrected.insert(url);
+            }
+        }
+    }
+    Ok(upserted)
+}
+
+/// Flights stored before GUIDs were tracked use their URL as GUID. Replace it
+/// with the real GUID of the flights, so that future URL changes are detected
+/// as well.
+pub async fn backfill_flight_guids(
+    conn: &mut SqliteConnection,
+    tenant: &str,
+    flights: &[Flight],
+) -> Result<()> {
+    let guids: Vec<(&str, &str)> = flights
+        .iter()
+        .filter_map(|flight| Some((flight.url.as_str(), flight.guid.as_deref()?)))
+        .collect();
+    for chunk in guids.chunks(MAX_BATCH_ROWS) {
+        // Update GUIDs, rows that would conflict are skipped
+        let mut query = QueryBuilder::new("WITH v(url, guid) AS (");
+        query.push_values(chunk, |mut row, (url, guid)| {
+            row.push_bind(*url).push_bind(*guid);
+        });
+        query
+            .push(
+                r#")
+                UPDATE OR IGNORE xcontest_flights
+                SET guid = (SELECT v.guid FROM v WHERE v.url = xcontest_flights.url)
+                WHERE tenant = "#,
+            )
+            .push_bind(tenant)
+            .push(" AND guid = url AND url IN (SELECT url FROM v)");
+        query
+            .build()
+            .execute(&mut *conn)
+            .await
+            .context("Could not backfill flight GUIDs")?;
+    }
+    Ok(())
+}
+
+/// Return the number of stored flights of the pilot.
+pub async fn count_pilot_flights(
+    executor: impl Executor<'_, Database = Sqlite>,
+    tenant: &str,
+    pilot: &str,
+) -> Result<u32> {
+    // Count flights
+    sqlx::query_scalar(
+        r#"
+        SELECT count(*)
+        FROM xcontest_flights
+        WHERE tenant = ? AND pilot_username = ? COLLATE NOCASE
+        "#,
+    )
+    .bind(tenant)
+    .bind(pilot)
+    .fetch_one(executor)
+    .await
+    .context("Could not count flights")
+}
+
+/// Return the number of stored flights of the tenant.
+pub async fn count_flights(
+    executor: impl Executor<'_, Database = Sqlite>,
+    tenant: &str,
+) -> Result<u64> {
+    // Count flights
+    let count: i64 = sqlx::query_scalar("SELECT count(*) FROM xcontest_flights WHERE tenant = ?")
+        .bind(tenant)
+        .fetch_one(executor)
+        .await
+        .context("Could not count flights")?;
+    Ok(count as u64)
+}
+
+/// Store a cached Threema public key for the specified user.
+pub async fn cache_public_key(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    public_key: &RecipientKey,
+) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update cached public key
+    retry_busy!(
+        sqlx::query("UPDATE users SET threema_public_key = ? WHERE id = ?")
+            .bind(public_key.as_bytes())
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await
+            .context("Could not cache public key")
+    )?;
+
+    Ok(())
+}
+
+/// Return database stats of the specified tenant.
+pub async fn get_stats(pool: &Pool<Sqlite>, tenant: &str) -> Result<Stats> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch stats
+    let month = current_month();
+    sqlx::query_as(
+        r#"
+        SELECT
+            (SELECT count(*) FROM users WHERE tenant = ?1) as user_count,
+            (SELECT count(*) FROM subscriptions s
+                INNER JOIN users u ON s.user_id = u.id
+                WHERE u.tenant = ?1) as subscription_count,
+            (SELECT count(*) FROM subscriptions s
+                INNER JOIN users u ON s.user_id = u.id
+                WHERE u.tenant = ?1 AND s.created_at > datetime('now', '-30 days'))
+                as new_subscription_count,
+            (SELECT count(*) FROM xcontest_flights WHERE tenant = ?1) as flight_count,
+            (SELECT coalesce(sum(c.messages), 0) FROM notification_counters c
+                INNER JOIN users u ON c.user_id = u.id
+                WHERE u.tenant = ?1 AND c.month = ?2) as notifications_this_month,
+            (SELECT coalesce(sum(c.images), 0) FROM notification_counters c
+                INNER JOIN users u ON c.user_id = u.id
+                WHERE u.tenant = ?1 AND c.month = ?2) as images_this_month;
+        "#,
+    )
+    .bind(tenant)
+    .bind(&month)
+    .fetch_one(&mut *conn)
+    .await
+    .context("Could not fetch stats")
+}
+
+/// Number of days of the stats trend shown to the admin.
+pub const STATS_TREND_DAYS: u32 = 90;
+
+/// A daily snapshot of the stats of a tenant.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct StatsSnapshot {
+    /// The day of the snapshot (`YYYY-MM-DD`, local time)
+    pub date: String,
+    pub users: u32,
+    pub subscriptions: u32,
+    pub flights: u32,
+    /// Number of notifications sent since the start
+    pub notifications: u32,
+    /// Number of quarantined parse failures (of all tenants)
+    pub failures: u32,
+    /// Number of notifications that failed on the first attempt since the
+    /// start
+    pub notification_failures: u32,
+}
+
+/// Store a snapshot of the current stats of the tenant, replacing an earlier
+/// snapshot of the same day.
+pub async fn record_stats_snapshot(pool: &Pool<Sqlite>, tenant: &str) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Insert snapshot
+    retry_busy!(sqlx::query(
+        r#"
+        INSERT INTO stats_history
+            (tenant, date, users, subscriptions, flights, notifications, failures,
+             notification_failures)
+        SELECT
+            ?1,
+            date('now', 'localtime'),
+            (SELECT count(*) FROM users WHERE tenant = ?1),
+            (SELECT count(*) FROM subscriptions s
+                INNER JOIN users u ON s.user_id = u.id
+                WHERE u.tenant = ?1),
+            (SELECT count(*) FROM xcontest_flights WHERE tenant = ?1),
+            (SELECT coalesce(sum(c.messages), 0) FROM notification_counters c
+                INNER JOIN users u ON c.user_id = u.id
+                WHERE u.tenant = ?1),
+            (SELECT count(*) FROM parse_failures),
+            (SELECT coalesce(sum(c.failures), 0) FROM notification_counters c
+                INNER JOIN users u ON c.user_id = u.id
+                WHERE u.tenant = ?1)
+        ON CONFLICT(tenant, date) DO UPDATE SET
+            users = excluded.users,
+            subscriptions = excluded.subscriptions,
+            flights = excluded.flights,
+            notifications = excluded.notifications,
+            failures = excluded.failures,
+            notification_failures = excluded.notification_failures
+        "#,
+    )
+    .bind(tenant)
+    .execute(&mut *conn)
+    .await
+    .context("Could not record stats snapshot"))?;
+    Ok(())
+}
+
+/// Return the stats snapshots of the tenant of the last `days` days, oldest
+/// first.
+pub async fn get_stats_history(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    days: u32,
+) -> Result<Vec<StatsSnapshot>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch snapshots
+    sqlx::query_as(
+        r#"
+        SELECT date, users, subscriptions, flights, notifications, failures,
+            notification_failures
+        FROM stats_history
+        WHERE tenant = ? AND date > date('now', 'localtime', ?)
+        ORDER BY date
+        "#,
+    )
+    .bind(tenant)
+    .bind(format!("-{} days", days))
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch stats history")
+}
+
+/// Activity of a tenant within the last week.
+#[derive(Debug, FromRow)]
+pub struct WeeklyActivity {
+    pub new_users: u32,
+    /// Users who removed their last subscription
+    pub churned_users: u32,
+    /// Flights seen for the first time
+    pub flights: u32,
+}
+
+/// Return the activity of the tenant within the last seven days.
+pub async fn get_weekly_activity(pool: &Pool<Sqlite>, tenant: &str) -> Result<WeeklyActivity> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch counts
+    sqlx::query_as(
+        r#"
+        SELECT
+            (SELECT count(*) FROM users
+                WHERE tenant = ?1 AND since > datetime('now', '-7 days')) as new_users,
+            (SELECT count(*) FROM users
+                WHERE tenant = ?1 AND churned_at > datetime('now', '-7 days')) as churned_users,
+            (SELECT count(*) FROM xcontest_flights
+                WHERE tenant = ?1 AND seen_at > datetime('now', '-7 days')) as flights
+        "#,
+    )
+    .bind(tenant)
+    .fetch_one(&mut *conn)
+    .await
+    .context("Could not fetch weekly activity")
+}
+
+/// Return the most frequent errors of the last seven days (parse failures and
+/// errors of jobs waiting for a retry) with their number of occurrences.
+pub async fn get_top_errors(pool: &Pool<Sqlite>, limit: u32) -> Result<Vec<(String, u32)>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch errors
+    sqlx::query_as(
+        r#"
+        SELECT error, count(*) as count FROM (
+            SELECT error FROM parse_failures WHERE last_seen > datetime('now', '-7 days')
+            UNION ALL
+            SELECT last_error as error FROM jobs WHERE last_error IS NOT NULL
+        )
+        GROUP BY error
+        ORDER BY count DESC, error
+        LIMIT ?
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch top errors")
+}
+
+/// Counts of less users than this are left out of the usage statistics, so
+/// that individual users can't be singled out.
+pub const USAGE_STATS_MIN_USERS: u32 = 5;
+
+/// Anonymized usage statistics of a tenant.
+///
+/// Counts based on less than [`USAGE_STATS_MIN_USERS`] users are `None`.
+#[derive(Debug)]
+pub struct UsageStats {
+    pub users: Option<u32>,
+    pub digest_users: Option<u32>,
+    pub subscriptions: Option<u32>,
+    /// Number of distinct pilots being followed
+    pub followed_pilots: Option<u32>,
+    pub flights: u32,
+    /// Number of users per subscription count bucket (lower bound of the
+    /// bucket, see [`USAGE_STATS_BUCKETS`])
+    pub subscriptions_per_user: Vec<(u32, Option<u32>)>,
+    /// Usage per month of the year
+    pub months: Vec<MonthlyUsage>,
+}
+
+/// Lower bounds of the subscription count buckets in the usage statistics.
+pub const USAGE_STATS_BUCKETS: [u32; 5] = [0, 1, 2, 6, 11];
+
+/// Anonymized usage of a tenant in one month.
+#[derive(Debug)]
+pub struct MonthlyUsage {
+    /// Month (`%Y-%m`)
+    pub month: String,
+    pub new_users: Option<u32>,
+    pub notifications: Option<u32>,
+}
+
+/// Return `count` if it is based on enough users to be published.
+fn anonymize(count: u32, users: u32) -> Option<u32> {
+    (users >= USAGE_STATS_MIN_USERS).then_some(count)
+}
+
+/// Return anonymized usage statistics of the tenant, with monthly figures of
+/// the specified year.
+pub async fn get_usage_stats(pool: &Pool<Sqlite>, tenant: &str, year: i32) -> Result<UsageStats> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch totals
+    let (users, digest_users, subscriptions, followed_pilots, flights): (u32, u32, u32, u32, u32) =
+        sqlx::query_as(
+            r#"
+            SELECT
+                (SELECT count(*) FROM users WHERE tenant = ?1),
+                (SELECT count(*) FROM users WHERE tenant = ?1 AND digest = 1),
+                (SELECT count(*) FROM subscriptions s
+                    INNER JOIN users u ON s.user_id = u.id
+                    WHERE u.tenant = ?1),
+                (SELECT count(DISTINCT lower(s.pilot_username)) FROM subscriptions s
+                    INNER JOIN users u ON s.user_id = u.id
+                    WHERE u.tenant = ?1),
+                (SELECT count(*) FROM xcontest_flights WHERE tenant = ?1)
+            "#,
+        )
+        .bind(tenant)
+        .fetch_one(&mut *conn)
+        .await
+        .context("Could not fetch usage totals")?;
+
+    // Fetch number of users per subscription count bucket
+    let buckets: Vec<(u32, u32)> = sqlx::query_as(
+        r#"
+        SELECT
+            CASE WHEN n <= 1 THEN n WHEN n <= 5 THEN 2 WHEN n <= 10 THEN 6 ELSE 11 END AS bucket,
+            count(*)
+        FROM (
+            SELECT count(s.id) AS n
+            FROM users u
+            LEFT JOIN subscriptions s ON s.user_id = u.id
+            WHERE u.tenant = ?
+            GROUP BY u.id
+        )
+        GROUP BY bucket
+        "#,
+    )
+    .bind(tenant)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch subscription counts")?;
+
+    // Fetch new users per month
+    let new_users: Vec<(String, u32)> = sqlx::query_as(
+        r#"
+        SELECT strftime('%Y-%m', since) AS month, count(*)
+        FROM users
+        WHERE tenant = ? AND strftime('%Y', since) = ?
+        GROUP BY month
+        "#,
+    )
+    .bind(tenant)
+    .bind(year.to_string())
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch new users")?;
+
+    // Fetch notifications per month, with the number of notified users
+    let notifications: Vec<(String, u32, u32)> = sqlx::query_as(
+        r#"
+        SELECT c.month, count(*), sum(c.messages)
+        FROM notification_counters c
+        INNER JOIN users u ON c.user_id = u.id
+        WHERE u.tenant = ? AND c.month LIKE ?
+        GROUP BY c.month
+        "#,
+    )
+    .bind(tenant)
+    .bind(format!("{}-%", year))
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch notification counters")?;
+
+    // Aggregate
+    let subscriptions_per_user = USAGE_STATS_BUCKETS
+        .iter()
+        .map(|bucket| {
+            let count = buckets
+                .iter()
+                .find(|(b, _)| b == bucket)
+                .map_or(0, |(_, count)| *count);
+            (*bucket, anonymize(count, count))
+        })
+        .collect();
+    let months = (1..=12)
+        .map(|month| {
+            let month = format!("{}-{:02}", year, month);
+            let new_users = new_users
+                .iter()
+                .find(|(m, _)| *m == month)
+                .map_or(0, |(_, count)| *count);
+            let notifications = notifications
+                .iter()
+                .find(|(m, _, _)| *m == month)
+                .map_or((0, 0), |(_, users, messages)| (*users, *messages));
+            MonthlyUsage {
+                new_users: anonymize(new_users, new_users),
+                notifications: anonymize(notifications.1, notifications.0),
+                month,
+            }
+        })
+        .collect();
+    Ok(UsageStats {
+        users: anonymize(users, users),
+        digest_users: anonymize(digest_users, digest_users),
+        subscriptions: anonymize(subscriptions, users),
+        followed_pilots: anonymize(followed_pilots, users),
+        flights,
+        subscriptions_per_user,
+        months,
+    })
+}
+
+/// Return the cached details for the flight with the specified URL, if they
+/// were fetched less than `ttl_seconds` ago.
+pub async fn get_cached_flight_details(
+    pool: &Pool<Sqlite>,
+    url: &str,
+    ttl_seconds: u64,
+) -> Result<Option<FlightDetails>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch cache entry
+    let row = sqlx::query(
+        r#"
+        SELECT thumbnail_large, thumbnail_small, format, animated, start_time, airtime_minutes,
+            closing_percent, speed_kmh
+        FROM flight_details_cache
+        WHERE url = ? AND fetched_at > datetime('now', ?)
+        "#,
+    )
+    .bind(url)
+    .bind(format!("-{} seconds", ttl_seconds))
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch cached flight details")?;
+
+    Ok(match row {
+        Some(row) => {
+            let invalid = "Invalid cached flight details";
+            let format: String = row.try_get("format").context(invalid)?;
+            Some(FlightDetails {
+                thumbnail_large: Bytes::from(
+                    row.try_get::<Vec<u8>, _>("thumbnail_large")
+                        .context(invalid)?,
+                ),
+                thumbnail_small: Bytes::from(
+                    row.try_get::<Vec<u8>, _>("thumbnail_small")
+                        .context(invalid)?,
+                ),
+                format: PreviewFormat::from_extension(&format).ok_or_else(|| {
+                    Error::InvalidData {
+                        context: format!("Invalid cached preview format: {}", format),
+                        source: None,
+                    }
+                })?,
+                animated: row.try_get("animated").context(invalid)?,
+                times: flight_times(
+                    row.try_get::<Option<String>, _>("start_time")
+                        .context(invalid)?
+                        .as_deref(),
+                    row.try_get("airtime_minutes").context(invalid)?,
+                ),
+                triangle: TriangleStats {
+                    closing_percent: row.try_get("closing_percent").context(invalid)?,
+                    speed_kmh: row.try_get("speed_kmh").context(invalid)?,
+                },
+            })
+        }
+        None => None,
+    })
+}
+
+/// Store the details for the flight with the specified URL in the cache.
+pub async fn cache_flight_details(
+    pool: &Pool<Sqlite>,
+    url: &str,
+    details: &FlightDetails,
+) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Insert or replace cache entry
+    retry_busy!(sqlx::query(
+        r#"
+        INSERT OR REPLACE INTO flight_details_cache
+            (url, thumbnail_large, thumbnail_small, format, animated, start_time,
+             airtime_minutes, closing_percent, speed_kmh, fetched_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+        "#,
+    )
+    .bind(url)
+    .bind(&details.thumbnail_large[..])
+    .bind(&details.thumbnail_small[..])
+    .bind(details.format.extension())
+    .bind(details.animated)
+    .bind(
+        details
+            .times
+            .start
+            .map(|start| start.format("%H:%M").to_string()),
+    )
+    .bind(details.times.airtime_minutes)
+    .bind(details.triangle.closing_percent)
+    .bind(details.triangle.speed_kmh)
+    .execute(&mut *conn)
+    .await
+    .context("Could not cache flight details"))?;
+
+    Ok(())
+}
+
+/// Store the start time and airtime of the flight with the specified URL (in
+/// all tenants), unless they're already known.
+pub async fn set_flight_times(pool: &Pool<Sqlite>, url: &str, times: &FlightTimes) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update flights
+    retry_busy!(sqlx::query(
+        r#"
+        UPDATE xcontest_flights
+        SET start_time = ?, airtime_minutes = ?
+        WHERE url = ? AND start_time IS NULL AND airtime_minutes IS NULL
+        "#,
+    )
+    .bind(times.start.map(|start| start.format("%H:%M").to_string()))
+    .bind(times.airtime_minutes)
+    .bind(url)
+    .execute(&mut *conn)
+    .await
+    .context("Could not store flight times"))?;
+
+    Ok(())
+}
+
+/// Evict cached flight details that were fetched more than `ttl_seconds` ago.
+///
+/// Return the number of evicted entries.
+pub async fn evict_flight_details(pool: &Pool<Sqlite>, ttl_seconds: u64) -> Result<u64> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Evict expired entries
+    let result = retry_busy!(sqlx::query(
+        "DELETE FROM flight_details_cache WHERE fetched_at <= datetime('now', ?)"
+    )
+    .bind(format!("-{} seconds", ttl_seconds))
+    .execute(&mut *conn)
+    .await
+    .context("Could not evict expired flight details"))?;
+    Ok(result.rows_affected())
+}
+
+/// Store a payload that could not be parsed in the quarantine.
+///
+/// If the same payload source failed before, the entry is updated instead.
+pub async fn record_parse_failure(
+    executor: impl Executor<'_, Database = Sqlite>,
+    failure: &ParseFailure,
+) -> Result<()> {
+    // Insert or update failure
+    sqlx::query(
+        r#"
+        INSERT INTO parse_failures (kind, source, payload, error, first_seen, last_seen)
+        VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        ON CONFLICT(kind, source) DO UPDATE SET
+            payload = excluded.payload,
+            error = excluded.error,
+            occurrences = occurrences + 1,
+            last_seen = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(failure.kind.as_str())
+    .bind(&failure.source)
+    .bind(&failure.payload)
+    .bind(&failure.error)
+    .execute(executor)
+    .await
+    .context("Could not record parse failure")?;
+
+    Ok(())
+}
+
+/// Return the most recent parse failures.
+pub async fn get_parse_failures(
+    pool: &Pool<Sqlite>,
+    limit: u32,
+) -> Result<Vec<StoredParseFailure>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch failures
+    sqlx::query_as(
+        r#"
+        SELECT id, kind, source, payload, error, occurrences, first_seen, last_seen
+        FROM parse_failures
+        ORDER BY last_seen DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch parse failures")
+}
+
+/// Return the parse failure with the specified ID.
+pub async fn get_parse_failure(pool: &Pool<Sqlite>, id: i64) -> Result<Option<StoredParseFailure>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch failure
+    sqlx::query_as(
+        r#"
+        SELECT id, kind, source, payload, error, occurrences, first_seen, last_seen
+        FROM parse_failures
+        WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch parse failure")
+}
+
+/// Remove the parse failure with the specified ID from the quarantine.
+pub async fn delete_parse_failure(pool: &Pool<Sqlite>, id: i64) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Delete failure
+    retry_busy!(sqlx::query("DELETE FROM parse_failures WHERE id = ?")
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .context("Could not delete parse failure"))?;
+
+    Ok(())
+}
+
+/// Return the current month in the format used for notification counters.
+pub fn current_month() -> String {
+    chrono::Local::now().format("%Y-%m").to_string()
+}
+
+/// Return the notification counter of the user for the specified month.
+pub async fn get_notification_counter(
+    executor: impl Executor<'_, Database = Sqlite>,
+    user_id: i32,
+    month: &str,
+) -> Result<NotificationCounter> {
+    // Fetch counter
+    let counter = sqlx::query_as(
+        "SELECT messages, capped FROM notification_counters WHERE user_id = ? AND month = ?",
+    )
+    .bind(user_id)
+    .bind(month)
+    .fetch_optional(executor)
+    .await
+    .context("Could not fetch notification counter")?;
+
+    Ok(counter.unwrap_or_default())
+}
+
+/// Count a notification sent to the user in the specified month.
+pub async fn increment_notification_counter(
+    executor: impl Executor<'_, Database = Sqlite>,
+    user_id: i32,
+    month: &str,
+    with_image: bool,
+) -> Result<()> {
+    // Increment counter
+    sqlx::query(
+        r#"
+        INSERT INTO notification_counters (user_id, month, messages, images)
+        VALUES (?, ?, 1, ?)
+        ON CONFLICT(user_id, month) DO UPDATE SET
+            messages = messages + 1,
+            images = images + excluded.images
+        "#,
+    )
+    .bind(user_id)
+    .bind(month)
+    .bind(with_image as u32)
+    .execute(executor)
+    .await
+    .context("Could not increment notification counter")?;
+
+    Ok(())
+}
+
+/// Count a notification to the user that failed on the first attempt.
+pub async fn increment_notification_failures(
+    executor: impl Executor<'_, Database = Sqlite>,
+    user_id: i32,
+    month: &str,
+) -> Result<()> {
+    // Increment counter
+    sqlx::query(
+        r#"
+        INSERT INTO notification_counters (user_id, month, failures)
+        VALUES (?, ?, 1)
+        ON CONFLICT(user_id, month) DO UPDATE SET failures = failures + 1
+        "#,
+    )
+    .bind(user_id)
+    .bind(month)
+    .execute(executor)
+    .await
+    .context("Could not increment notification failures")?;
+
+    Ok(())
+}
+
+/// Mark the user as informed about the monthly notification cap.
+pub async fn set_notification_capped(
+    executor: impl Executor<'_, Database = Sqlite>,
+    user_id: i32,
+    month: &str,
+) -> Result<()> {
+    // Set flag
+    sqlx::query(
+        r#"
+        INSERT INTO notification_counters (user_id, month, capped)
+        VALUES (?, ?, 1)
+        ON CONFLICT(user_id, month) DO UPDATE SET capped = 1
+        "#,
+    )
+    .bind(user_id)
+    .bind(month)
+    .execute(executor)
+    .await
+    .context("Could not set notification cap flag")?;
+
+    Ok(())
+}
+
+/// Return the users of the tenant with the most notifications in the
+/// specified month.
+pub async fn get_top_notification_counters(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    month: &str,
+    limit: u32,
+) -> Result<Vec<UserNotificationCounter>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch counters
+    sqlx::query_as(
+        r#"
+        SELECT u.username, u.usertype, c.messages, c.images
+        FROM notification_counters c
+        INNER JOIN users u ON c.user_id = u.id
+        WHERE u.tenant = ? AND c.month = ?
+        ORDER BY c.messages DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(month)
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch notification counters")
+}
+
+/// Store the referral code of the user, unless the user already has one.
+///
+/// Return whether the referral code was stored.
+pub async fn set_referral(pool: &Pool<Sqlite>, user_id: i32, referral: &str) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update referral code, the first one wins
+    let result = retry_busy!(sqlx::query(
+        "UPDATE users SET referral = ? WHERE id = ? AND referral IS NULL"
+    )
+    .bind(referral)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await
+    .context("Could not store referral code"))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Return the number of users per referral code of the tenant, sorted by
+/// number of users.
+pub async fn get_referral_counts(pool: &Pool<Sqlite>, tenant: &str) -> Result<Vec<ReferralCount>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Count users per referral code
+    sqlx::query_as(
+        r#"
+        SELECT
+            referral,
+            count(*) as users,
+            coalesce(sum(since > datetime('now', '-30 days')), 0) as new_users
+        FROM users
+        WHERE tenant = ?
+        GROUP BY referral
+        ORDER BY users DESC, referral
+        "#,
+    )
+    .bind(tenant)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch referral counts")
+}
+
+/// Return the flight with the specified ID.
+pub async fn get_flight(pool: &Pool<Sqlite>, id: i64) -> Result<Option<StoredFlight>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flight
+    sqlx::query_as("SELECT url, title, guid FROM xcontest_flights WHERE rowid = ?")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("Could not fetch flight")
+}
+
+/// Format a timestamp the way SQLite's `CURRENT_TIMESTAMP` does (UTC).
+pub fn sql_timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Parse a timestamp in the format of SQLite's `CURRENT_TIMESTAMP` (UTC).
+pub fn parse_sql_timestamp(timestamp: &str) -> Result<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+        .context(format!("Invalid timestamp: {}", timestamp))?;
+    Ok(Utc.from_utc_datetime(&naive))
+}
+
+/// Return the time when the specified scheduler task last ran.
+pub async fn get_last_run(pool: &Pool<Sqlite>, task: &str) -> Result<Option<DateTime<Utc>>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch last run
+    let last_run: Option<String> =
+        sqlx::query_scalar("SELECT last_run FROM scheduler_runs WHERE task = ?")
+            .bind(task)
+            .fetch_optional(&mut *conn)
+            .await
+            .context("Could not fetch last run")?;
+    last_run.as_deref().map(parse_sql_timestamp).transpose()
+}
+
+/// Store the time when the specified scheduler task last ran.
+pub async fn set_last_run(pool: &Pool<Sqlite>, task: &str, time: DateTime<Utc>) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Store last run
+    retry_busy!(sqlx::query(
+        "INSERT OR REPLACE INTO scheduler_runs (task, last_run) VALUES (?, ?)"
+    )
+    .bind(task)
+    .bind(sql_timestamp(time))
+    .execute(&mut *conn)
+    .await
+    .context("Could not store last run"))?;
+    Ok(())
+}
+
+/// Return whether the user receives a daily digest instead of instant
+/// notifications.
+pub async fn get_digest(pool: &Pool<Sqlite>, user_id: i32) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch digest flag
+    sqlx::query_scalar("SELECT digest FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("Could not fetch digest setting")
+}
+
+/// Enable or disable the daily digest for the user.
+pub async fn set_digest(pool: &Pool<Sqlite>, user_id: i32, enabled: bool) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update digest flag
+    retry_busy!(sqlx::query("UPDATE users SET digest = ? WHERE id = ?")
+        .bind(enabled)
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("Could not update digest setting"))?;
+    Ok(())
+}
+
+/// Return the ntfy topic of the user, if set.
+pub async fn get_ntfy_topic(pool: &Pool<Sqlite>, user_id: i32) -> Result<Option<String>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch topic
+    sqlx::query_scalar("SELECT ntfy_topic FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map(Option::flatten)
+        .context("Could not fetch ntfy topic")
+}
+
+/// Set the ntfy topic of the user (`None`: the configured default topic).
+pub async fn set_ntfy_topic(pool: &Pool<Sqlite>, user_id: i32, topic: Option<&str>) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update topic
+    retry_busy!(sqlx::query("UPDATE users SET ntfy_topic = ? WHERE id = ?")
+        .bind(topic)
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .context("Could not update ntfy topic"))?;
+    Ok(())
+}
+
+/// Return the Pushover user key of the user, if set.
+pub async fn get_pushover_user_key(pool: &Pool<Sqlite>, user_id: i32) -> Result<Option<String>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch user key
+    sqlx::query_scalar("SELECT pushover_user_key FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map(Option::flatten)
+        .context("Could not fetch Pushover user key")
+}
+
+/// Set the Pushover user key of the user.
+pub async fn set_pushover_user_key(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    user_key: &str,
+) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update user key
+    retry_busy!(
+        sqlx::query("UPDATE users SET pushover_user_key = ? WHERE id = ?")
+            .bind(user_key)
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await
+            .context("Could not update Pushover user key")
+    )?;
+    Ok(())
+}
+
+/// Return the Gotify application token of the user, if set.
+pub async fn get_gotify_app_token(pool: &Pool<Sqlite>, user_id: i32) -> Result<Option<String>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch application token
+    sqlx::query_scalar("SELECT gotify_app_token FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map(Option::flatten)
+        .context("Could not fetch Gotify application token")
+}
+
+/// Set the Gotify application token of the user.
+pub async fn set_gotify_app_token(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    app_token: &str,
+) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update application token
+    retry_busy!(
+        sqlx::query("UPDATE users SET gotify_app_token = ? WHERE id = ?")
+            .bind(app_token)
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await
+            .context("Could not update Gotify application token")
+    )?;
+    Ok(())
+}
+
+/// Return all users that receive a daily digest.
+pub async fn get_digest_users(pool: &Pool<Sqlite>) -> Result<Vec<User>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch users
+    sqlx::query_as(
+        "SELECT id, tenant, username, usertype, threema_public_key FROM users WHERE digest = 1 ORDER BY id",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch digest users")
+}
+
+/// Return the flights of pilots followed by the user that were seen after
+/// `since`, oldest first.
+pub async fn get_digest_flights(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    since: DateTime<Utc>,
+) -> Result<Vec<StoredFlight>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flights
+    sqlx::query_as(
+        r#"
+        SELECT f.url, f.title, f.guid, f.start_time, f.airtime_minutes
+        FROM xcontest_flights f
+        INNER JOIN subscriptions s ON s.pilot_username = f.pilot_username COLLATE NOCASE
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE s.user_id = ? AND f.tenant = u.tenant AND f.seen_at > ?
+        ORDER BY f.seen_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(sql_timestamp(since))
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch digest flights")
+}
+
+/// A pilot's entry in the monthly leaderboard.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct LeaderboardEntry {
+    pub pilot_username: String,
+    /// Number of flights
+    pub flights: u32,
+    /// Total distance of all flights
+    pub distance_km: f64,
+    /// Distance of the longest flight
+    pub max_km: f64,
+}
+
+/// Return all flights of the tenant that were seen in the specified month
+/// (`%Y-%m`, local time).
+pub async fn get_month_flights(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    month: &str,
+) -> Result<Vec<StoredFlight>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flights
+    sqlx::query_as(
+        r#"
+        SELECT url, title, guid
+        FROM xcontest_flights
+        WHERE tenant = ? AND strftime('%Y-%m', seen_at, 'localtime') = ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(month)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch flights of month")
+}
+
+/// Replace the leaderboard of the tenant in the specified month.
+pub async fn replace_leaderboard(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    month: &str,
+    entries: &[LeaderboardEntry],
+) -> Result<()> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
+
+    // Replace entries
+    sqlx::query("DELETE FROM leaderboard WHERE tenant = ? AND month = ?")
+        .bind(tenant)
+        .bind(month)
+        .execute(&mut *transaction)
+        .await
+        .context("Could not delete leaderboard")?;
+    for entry in entries {
+        sqlx::query(
+            r#"
+            INSERT INTO leaderboard
+                (tenant, month, pilot_username, flights, distance_km, max_km, computed_at)
+            VALUES (?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+            "#,
+        )
+        .bind(tenant)
+        .bind(month)
+        .bind(&entry.pilot_username)
+        .bind(entry.flights)
+        .bind(entry.distance_km)
+        .bind(entry.max_km)
+        .execute(&mut *transaction)
+        .await
+        .context("Could not insert leaderboard entry")?;
+    }
+
+    // Commit transaction
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
+    Ok(())
+}
+
+/// Return the leaderboard of the specified month, restricted to the pilots
+/// followed by the user, ordered by total distance.
+pub async fn get_leaderboard(
+    pool: &Pool<Sqlite>,
+    month: &str,
+    user_id: i32,
+) -> Result<Vec<LeaderboardEntry>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch entries
+    sqlx::query_as(
+        r#"
+        SELECT l.pilot_username, l.flights, l.distance_km, l.max_km
+        FROM leaderboard l
+        INNER JOIN subscriptions s ON s.pilot_username = l.pilot_username COLLATE NOCASE
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE l.month = ? AND s.user_id = ? AND l.tenant = u.tenant
+        ORDER BY l.distance_km DESC, l.pilot_username
+        "#,
+    )
+    .bind(month)
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch leaderboard")
+}
+
+/// Return the leaderboard of the specified pilots (e.g. the members of a club)
+/// in the month.
+pub async fn get_club_leaderboard(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    month: &str,
+    members: &[String],
+) -> Result<Vec<LeaderboardEntry>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch entries
+    let members: Vec<String> = members.iter().map(|member| member.to_lowercase()).collect();
+    sqlx::query_as(
+        r#"
+        SELECT pilot_username, flights, distance_km, max_km
+        FROM leaderboard
+        WHERE tenant = ? AND month = ?
+        AND lower(pilot_username) IN (SELECT value FROM json_each(?))
+        ORDER BY distance_km DESC, pilot_username
+        "#,
+    )
+    .bind(tenant)
+    .bind(month)
+    .bind(serde_json::to_string(&members).context("Could not serialize club members")?)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch club leaderboard")
+}
+
+/// Record that a flight of a member of the club was seen in the feed.
+///
+/// Return `false` if the member was seen before.
+pub async fn record_club_member(
+    conn: &mut SqliteConnection,
+    tenant: &str,
+    club: &str,
+    pilot: &str,
+) -> Result<bool> {
+    // Insert member
+    let result = retry_busy!(sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO club_members (tenant, club, pilot_username, first_seen)
+        VALUES (?, ?, ?, CURRENT_TIMESTAMP)
+        "#
+    )
+    .bind(tenant)
+    .bind(club.to_lowercase())
+    .bind(pilot)
+    .execute(&mut *conn)
+    .await
+    .context("Could not record club member"))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Subscribe the user to the monthly leaderboard of the club.
+///
+/// Return `false` if the user was already subscribed.
+pub async fn add_club_subscription(pool: &Pool<Sqlite>, user_id: i32, club: &str) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Insert subscription
+    let result = retry_busy!(sqlx::query(
+        "INSERT OR IGNORE INTO club_subscriptions (user_id, club) VALUES (?, ?)"
+    )
+    .bind(user_id)
+    .bind(club.to_lowercase())
+    .execute(&mut *conn)
+    .await
+    .context("Could not add club subscription"))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Unsubscribe the user from the monthly leaderboard of the club.
+///
+/// Return `false` if the user was not subscribed.
+pub async fn remove_club_subscription(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    club: &str,
+) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Delete subscription
+    let result = retry_busy!(sqlx::query(
+        "DELETE FROM club_subscriptions WHERE user_id = ? AND club = ?"
+    )
+    .bind(user_id)
+    .bind(club.to_lowercase())
+    .execute(&mut *conn)
+    .await
+    .context("Could not remove club subscription"))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Return the codes of the clubs the user is subscribed to.
+pub async fn get_club_subscriptions(pool: &Pool<Sqlite>, user_id: i32) -> Result<Vec<String>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch clubs
+    sqlx::query_scalar("SELECT club FROM club_subscriptions WHERE user_id = ? ORDER BY club")
+        .bind(user_id)
+        .fetch_all(&mut *conn)
+        .await
+        .context("Could not fetch club subscriptions")
+}
+
+/// Return the users of the tenant subscribed to the club.
+pub async fn get_club_subscribers(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    club: &str,
+) -> Result<Vec<User>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch subscribers
+    sqlx::query_as(
+        r#"
+        SELECT u.id, u.tenant, u.username, u.usertype, u.threema_public_key
+        FROM club_subscriptions c
+        INNER JOIN users u ON c.user_id = u.id
+        WHERE u.tenant = ? AND c.club = ?
+        ORDER BY u.id
+        "#,
+    )
+    .bind(tenant)
+    .bind(club.to_lowercase())
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch club subscribers")
+}
+
+/// Write a consistent copy of the database to the specified path.
+pub async fn backup(pool: &Pool<Sqlite>, path: &str) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Write backup
+    retry_busy!(sqlx::query("VACUUM INTO ?")
+        .bind(path)
+        .execute(&mut *conn)
+        .await
+        .context(format!("Could not back up database to {}", path)))?;
+    Ok(())
+}
+
+/// Let SQLite update its query planner statistics.
+pub async fn optimize(pool: &Pool<Sqlite>) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Optimize
+    retry_busy!(sqlx::query("PRAGMA optimize")
+        .execute(&mut *conn)
+        .await
+        .context("Could not optimize database"))?;
+    Ok(())
+}
+
+/// Return the user with the specified ID.
+pub async fn get_user(pool: &Pool<Sqlite>, id: i32) -> Result<Option<User>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch user
+    sqlx::query_as(
+        "SELECT id, tenant, username, usertype, threema_public_key FROM users WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await
+    .context(format!("Could not fetch user {}", id))
+}
+
+/// Return whether the user sent the same command within the last
+/// `window_seconds` seconds.
+pub async fn is_duplicate_command(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    command: &str,
+    window_seconds: u32,
+) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Look up command
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM recent_commands
+            WHERE user_id = ? AND command = ? AND received_at > datetime('now', ?)
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(command)
+    .bind(format!("-{} seconds", window_seconds))
+    .fetch_one(&mut *conn)
+    .await
+    .context("Could not check for duplicate command")
+}
+
+/// Record the last handled command of the user. Duplicates are not recorded,
+/// so that a client resending in a loop can't extend the window forever.
+pub async fn record_command(pool: &Pool<Sqlite>, user_id: i32, command: &str) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Upsert command
+    retry_busy!(sqlx::query(
+        r#"
+        INSERT INTO recent_commands (user_id, command, received_at)
+        VALUES (?, ?, CURRENT_TIMESTAMP)
+        ON CONFLICT(user_id) DO UPDATE SET
+            command = excluded.command,
+            received_at = excluded.received_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(command)
+    .execute(&mut *conn)
+    .await
+    .context("Could not record command"))?;
+    Ok(())
+}
+
+/// Return the user of the tenant with the specified username (e.g. the
+/// Threema ID, case-insensitive).
+pub async fn find_user(pool: &Pool<Sqlite>, tenant: &str, username: &str) -> Result<Option<User>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch user
+    sqlx::query_as(
+        r#"
+        SELECT id, tenant, username, usertype, threema_public_key
+        FROM users
+        WHERE tenant = ? AND username = ? COLLATE NOCASE
+        ORDER BY id
+        LIMIT 1
+        "#,
+    )
+    .bind(tenant)
+    .bind(username)
+    .fetch_optional(&mut *conn)
+    .await
+    .context(format!("Could not fetch user {}", username))
+}
+
+/// A flight of a pilot with its notification state.
+#[derive(Debug, FromRow)]
+pub struct PilotFlight {
+    pub url: String,
+    pub title: String,
+    pub seen_at: Option<String>,
+    /// Not set while the notifications of the pilot are grouped
+    pub notified_at: Option<String>,
+}
+
+/// Return the most recently seen flights of the pilot.
+pub async fn get_pilot_flights(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    pilot: &str,
+    limit: u32,
+) -> Result<Vec<PilotFlight>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flights
+    sqlx::query_as(
+        r#"
+        SELECT url, title, seen_at, notified_at
+        FROM xcontest_flights
+        WHERE tenant = ? AND pilot_username = ? COLLATE NOCASE
+        ORDER BY rowid DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(pilot)
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch flights of pilot")
+}
+
+/// Return the URLs of the pilot's flights the user received a notification
+/// message about (as long as the messages are retained).
+pub async fn get_notified_pilot_flights(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    pilot: &str,
+) -> Result<HashSet<String>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flight URLs
+    let urls: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT DISTINCT n.flight_url
+        FROM sent_messages n
+        INNER JOIN xcontest_flights f ON f.url = n.flight_url
+        WHERE n.user_id = ? AND f.pilot_username = ? COLLATE NOCASE
+        "#,
+    )
+    .bind(user_id)
+    .bind(pilot)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch notification messages")?;
+    Ok(urls.into_iter().collect())
+}
+
+/// Return the attempts and the last error of the queued notification retries
+/// for the user.
+pub async fn get_pending_notifications(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+) -> Result<Vec<(u32, Option<String>)>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch jobs
+    sqlx::query_as(
+        r#"
+        SELECT attempts, last_error
+        FROM jobs
+        WHERE json_extract(payload, '$.user_id') = ?
+        AND json_extract(payload, '$.kind') IN ('notify', 'notify_group')
+        ORDER BY id
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch pending notifications")
+}
+
+/// Return the flight of the tenant with the specified URL.
+pub async fn get_flight_by_url(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    url: &str,
+) -> Result<Option<StoredFlight>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flight
+    sqlx::query_as(
+        r#"
+        SELECT rowid AS id, url, title, guid, start_time, airtime_minutes
+        FROM xcontest_flights
+        WHERE tenant = ? AND url = ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(url)
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch flight")
+}
+
+/// Return whether the pilot's previous flight (in the same tenant) was seen more
+/// than `gap_months` months before this flight.
+///
+/// Returns false for the very first flight of a pilot, since the flight
+/// history might just be incomplete.
+pub async fn is_first_flight_of_season(
+    executor: impl Executor<'_, Database = Sqlite>,
+    tenant: &str,
+    url: &str,
+    gap_months: u32,
+) -> Result<bool> {
+    // Compare with previous flight
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1
+            FROM xcontest_flights f
+            WHERE f.tenant = ? AND f.url = ?
+            AND (
+                SELECT max(p.seen_at)
+                FROM xcontest_flights p
+                WHERE p.tenant = f.tenant
+                AND p.pilot_username = f.pilot_username COLLATE NOCASE
+                AND p.rowid < f.rowid
+            ) < datetime(f.seen_at, ?)
+        )
+        "#,
+    )
+    .bind(tenant)
+    .bind(url)
+    .bind(format!("-{} months", gap_months))
+    .fetch_one(executor)
+    .await
+    .context("Could not fetch previous flight")
+}
+
+/// A pilot's username together with the title of one of their flights.
+#[derive(Debug, FromRow)]
+pub struct PilotTitle {
+    pub pilot_username: String,
+    pub title: String,
+}
+
+/// Return the pilots of the tenant with a flight title containing `name`
+/// (case-insensitive for ASCII characters), most recent flights first.
+pub async fn search_pilot_titles(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    name: &str,
+) -> Result<Vec<PilotTitle>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Search flights
+    let pattern = format!(
+        "%{}%",
+        name.replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+    sqlx::query_as(
+        r#"
+        SELECT pilot_username, title
+        FROM xcontest_flights
+        WHERE tenant = ? AND title LIKE ? ESCAPE '\'
+        ORDER BY rowid DESC
+        "#,
+    )
+    .bind(tenant)
+    .bind(pattern)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not search pilots")
+}
+
+/// Return the number of flights of the pilot that are not yet notified.
+pub async fn count_pending_flights(
+    executor: impl Executor<'_, Database = Sqlite>,
+    tenant: &str,
+    pilot: &str,
+) -> Result<u32> {
+    // Count flights
+    sqlx::query_scalar(
+        r#"
+        SELECT count(*)
+        FROM xcontest_flights
+        WHERE tenant = ? AND pilot_username = ? COLLATE NOCASE AND notified_at IS NULL
+        "#,
+    )
+    .bind(tenant)
+    .bind(pilot)
+    .fetch_one(executor)
+    .await
+    .context("Could not count pending flights")
+}
+
+/// Return the flights of the pilot that are not yet notified (oldest first)
+/// and mark them as notified.
+pub async fn take_pending_flights(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    pilot: &str,
+) -> Result<Vec<StoredFlight>> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
+
+    // Fetch flights and mark them as notified
+    let flights = sqlx::query_as(
+        r#"
+        SELECT url, title, guid, start_time, airtime_minutes
+        FROM xcontest_flights
+        WHERE tenant = ? AND pilot_username = ? COLLATE NOCASE AND notified_at IS NULL
+        ORDER BY seen_at, rowid
+        "#,
+    )
+    .bind(tenant)
+    .bind(pilot)
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Could not fetch pending flights")?;
+    sqlx::query(
+        r#"
+        UPDATE xcontest_flights
+        SET notified_at = CURRENT_TIMESTAMP
+        WHERE tenant = ? AND pilot_username = ? COLLATE NOCASE AND notified_at IS NULL
+        "#,
+    )
+    .bind(tenant)
+    .bind(pilot)
+    .execute(&mut *transaction)
+    .await
+    .context("Could not mark pending flights as notified")?;
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
+    Ok(flights)
+}
+
+/// A job in the persistent job queue.
+#[derive(Debug, FromRow)]
+pub struct StoredJob {
+    pub id: i64,
+    pub payload: String,
+    /// Number of failed attempts so far
+    pub attempts: u32,
+}
+
+/// Add a job to the queue.
+pub async fn insert_job(
+    executor: impl Executor<'_, Database = Sqlite>,
+    payload: &str,
+    run_at: DateTime<Utc>,
+) -> Result<()> {
+    // Insert job
+    sqlx::query("INSERT INTO jobs (payload, run_at, created_at) VALUES (?, ?, CURRENT_TIMESTAMP)")
+        .bind(payload)
+        .bind(sql_timestamp(run_at))
+        .execute(executor)
+        .await
+        .context("Could not insert job")?;
+    Ok(())
+}
+
+/// Add a scheduled task run to the queue and store it as the last run of the
+/// task, atomically.
+pub async fn enqueue_task_run(
+    pool: &Pool<Sqlite>,
+    task: &str,
+    payload: &str,
+    time: DateTime<Utc>,
+) -> Result<()> {
+    // Start transaction
+    let mut transaction = pool.begin().await.context("Could not start transaction")?;
+
+    // Insert job
+    sqlx::query("INSERT INTO jobs (payload, run_at, created_at) VALUES (?, ?, CURRENT_TIMESTAMP)")
+        .bind(payload)
+        .bind(sql_timestamp(time))
+        .execute(&mut *transaction)
+        .await
+        .context("Could not insert job")?;
+
+    // Store last run
+    sqlx::query("INSERT OR REPLACE INTO scheduler_runs (task, last_run) VALUES (?, ?)")
+        .bind(task)
+        .bind(sql_timestamp(time))
+        .execute(&mut *transaction)
+        .await
+        .context("Could not store last run")?;
+
+    // Commit transaction
+    transaction
+        .commit()
+        .await
+        .context("Could not commit transaction")?;
+    Ok(())
+}
+
+/// Return the jobs that are due at the specified time, oldest first.
+pub async fn get_due_jobs(pool: &Pool<Sqlite>, now: DateTime<Utc>) -> Result<Vec<StoredJob>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch jobs
+    sqlx::query_as("SELECT id, payload, attempts FROM jobs WHERE run_at <= ? ORDER BY run_at, id")
+        .bind(sql_timestamp(now))
+        .fetch_all(&mut *conn)
+        .await
+        .context("Could not fetch due jobs")
+}
+
+/// Return the number of pending jobs.
+pub async fn count_jobs(pool: &Pool<Sqlite>) -> Result<u32> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Count jobs
+    sqlx::query_scalar("SELECT COUNT(*) FROM jobs")
+        .fetch_one(&mut *conn)
+        .await
+        .context("Could not count jobs")
+}
+
+/// The pending jobs of one kind (and channel, for jobs of a user).
+#[derive(Debug, FromRow, PartialEq, Eq)]
+pub struct QueueDepth {
+    /// The kind of the jobs (e.g. `notify`)
+    pub kind: String,
+    /// The user type of the recipients (e.g. `threema`), if the jobs belong
+    /// to a user
+    pub channel: Option<String>,
+    /// Number of pending jobs
+    pub pending: u32,
+    /// Number of pending jobs that failed at least once
+    pub retrying: u32,
+    /// Number of failed attempts of the pending jobs
+    pub failed_attempts: u32,
+    /// Age of the oldest pending job in seconds
+    pub oldest_age_seconds: i64,
+}
+
+/// Return the pending jobs per kind and channel, to detect a stuck channel.
+pub async fn get_queue_depths(pool: &Pool<Sqlite>) -> Result<Vec<QueueDepth>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Group jobs
+    sqlx::query_as(
+        r#"
+        SELECT json_extract(j.payload, '$.kind') AS kind,
+               u.usertype AS channel,
+               COUNT(*) AS pending,
+               SUM(j.attempts > 0) AS retrying,
+               SUM(j.attempts) AS failed_attempts,
+               CAST(strftime('%s', 'now') - strftime('%s', MIN(j.created_at)) AS INTEGER)
+                   AS oldest_age_seconds
+        FROM jobs j
+        LEFT JOIN users u ON u.id = json_extract(j.payload, '$.user_id')
+        GROUP BY kind, channel
+        ORDER BY kind, channel
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch queue depths")
+}
+
+/// Acquire or renew the leader lock for the instance `holder` until
+/// `expires_at`. The lock of another instance is only taken over once it has
+/// expired.
+///
+/// Return whether the instance holds the lock.
+pub async fn acquire_leader_lock(
+    pool: &Pool<Sqlite>,
+    holder: &str,
+    now: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Take or renew lock
+    let result = retry_busy!(sqlx::query(
+        "INSERT INTO leader_lock (name, holder, expires_at) VALUES ('leader', ?, ?) \
+         ON CONFLICT (name) DO UPDATE \
+         SET holder = excluded.holder, expires_at = excluded.expires_at \
+         WHERE leader_lock.holder = excluded.holder OR leader_lock.expires_at <= ?"
+    )
+    .bind(holder)
+    .bind(sql_timestamp(expires_at))
+    .bind(sql_timestamp(now))
+    .execute(&mut *conn)
+    .await
+    .context("Could not acquire leader lock"))?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Release the leader lock, if it's held by the instance `holder`.
+pub async fn release_leader_lock(pool: &Pool<Sqlite>, holder: &str) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Delete lock
+    retry_busy!(
+        sqlx::query("DELETE FROM leader_lock WHERE name = 'leader' AND holder = ?")
+            .bind(holder)
+            .execute(&mut *conn)
+            .await
+            .context("Could not release leader lock")
+    )?;
+    Ok(())
+}
+
+/// Remove a job from the queue.
+pub async fn delete_job(pool: &Pool<Sqlite>, id: i64) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Delete job
+    retry_busy!(sqlx::query("DELETE FROM jobs WHERE id = ?")
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .context("Could not delete job"))?;
+    Ok(())
+}
+
+/// Record a failed attempt of a job and schedule the next attempt.
+pub async fn reschedule_job(
+    pool: &Pool<Sqlite>,
+    id: i64,
+    run_at: DateTime<Utc>,
+    error: &str,
+) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update job
+    retry_busy!(sqlx::query(
+        "UPDATE jobs SET attempts = attempts + 1, run_at = ?, last_error = ? WHERE id = ?"
+    )
+    .bind(sql_timestamp(run_at))
+    .bind(error)
+    .bind(id)
+    .execute(&mut *conn)
+    .await
+    .context("Could not reschedule job"))?;
+    Ok(())
+}
+
+/// Return the conversation state of the user, unless it expired.
+pub async fn get_conversation_state(pool: &Pool<Sqlite>, user_id: i32) -> Result<Option<String>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch state
+    sqlx::query_scalar("SELECT state FROM conversation_states WHERE user_id = ? AND expires_at > ?")
+        .bind(user_id)
+        .bind(sql_timestamp(Utc::now()))
+        .fetch_optional(&mut *conn)
+        .await
+        .context("Could not fetch conversation state")
+}
+
+/// Store the conversation state of the user, replacing the previous state.
+pub async fn set_conversation_state(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    state: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Upsert state
+    retry_busy!(sqlx::query(
+        r#"
+        INSERT INTO conversation_states (user_id, state, expires_at)
+        VALUES (?, ?, ?)
+        ON CONFLICT(user_id) DO UPDATE SET state = excluded.state, expires_at = excluded.expires_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(state)
+    .bind(sql_timestamp(expires_at))
+    .execute(&mut *conn)
+    .await
+    .context("Could not store conversation state"))?;
+    Ok(())
+}
+
+/// Remove the conversation state of the user.
+pub async fn delete_conversation_state(pool: &Pool<Sqlite>, user_id: i32) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Delete state
+    retry_busy!(
+        sqlx::query("DELETE FROM conversation_states WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await
+            .context("Could not delete conversation state")
+    )?;
+    Ok(())
+}
+
+/// A token of the HTTP API.
+#[derive(Debug, FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    /// The tenant the token is restricted to (`None`: all tenants)
+    pub tenant: Option<String>,
+    pub name: String,
+    pub scope: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+/// Store a new API token (with the hash of the token) and return its ID.
+pub async fn insert_api_token(
+    pool: &Pool<Sqlite>,
+    tenant: Option<&str>,
+    name: &str,
+    token_hash: &str,
+    scope: &str,
+) -> Result<i64> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Insert token
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO api_tokens (tenant, name, token_hash, scope, created_at)
+        VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP)
+        RETURNING id
+        "#,
+    )
+    .bind(tenant)
+    .bind(name)
+    .bind(token_hash)
+    .bind(scope)
+    .fetch_one(&mut *conn)
+    .await
+    .context("Could not insert API token")
+}
+
+/// Return the API tokens of the tenant (all tokens if `None`).
+pub async fn get_api_tokens(pool: &Pool<Sqlite>, tenant: Option<&str>) -> Result<Vec<ApiToken>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch tokens
+    sqlx::query_as(
+        r#"
+        SELECT id, tenant, name, scope, created_at, last_used_at
+        FROM api_tokens
+        WHERE ?1 IS NULL OR tenant = ?1
+        ORDER BY id
+        "#,
+    )
+    .bind(tenant)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch API tokens")
+}
+
+/// Return the API token with the specified hash and mark it as used.
+pub async fn use_api_token(pool: &Pool<Sqlite>, token_hash: &str) -> Result<Option<ApiToken>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update and fetch token
+    sqlx::query_as(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = CURRENT_TIMESTAMP
+        WHERE token_hash = ?
+        RETURNING id, tenant, name, scope, created_at, last_used_at
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch API token")
+}
+
+/// Delete the API token with the specified ID (only if it belongs to the
+/// tenant, if specified).
+///
+/// Return whether the token existed.
+pub async fn delete_api_token(pool: &Pool<Sqlite>, tenant: Option<&str>, id: i64) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Delete token
+    let result = retry_busy!(sqlx::query(
+        "DELETE FROM api_tokens WHERE id = ?1 AND (?2 IS NULL OR tenant = ?2)"
+    )
+    .bind(id)
+    .bind(tenant)
+    .execute(&mut *conn)
+    .await
+    .context("Could not delete API token"))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// A flight of the tenant, as returned by the HTTP API.
+#[derive(Debug, FromRow)]
+pub struct RecentFlight {
+    pub id: i64,
+    pub url: String,
+    pub title: String,
+    pub pilot_username: String,
+    pub contest: Option<String>,
+    pub start_time: Option<String>,
+    pub airtime_minutes: Option<u32>,
+    pub seen_at: Option<String>,
+}
+
+/// Return the most recently seen flights of the tenant.
+pub async fn get_recent_flights(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    limit: u32,
+) -> Result<Vec<RecentFlight>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flights
+    sqlx::query_as(
+        r#"
+        SELECT rowid AS id, url, title, pilot_username, contest, start_time, airtime_minutes,
+            seen_at
+        FROM xcontest_flights
+        WHERE tenant = ?
+        ORDER BY rowid DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch flights")
+}
+
+/// A survey sent to users as Threema poll.
+#[derive(Debug, FromRow)]
+pub struct StoredSurvey {
+    pub id: i64,
+    pub tenant: String,
+    pub question: String,
+    /// The answer options as JSON array
+    pub options: String,
+    /// The hex encoded ballot ID of the poll
+    pub ballot_id: String,
+    /// Number of users the poll was sent to
+    pub recipients: u32,
+    pub created_at: String,
+}
+
+impl StoredSurvey {
+    /// Return the answer options of the survey.
+    pub fn options(&self) -> Result<Vec<String>> {
+        serde_json::from_str(&self.options).context("Invalid survey options")
+    }
+}
+
+/// Store a new survey and return its ID.
+pub async fn insert_survey(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    question: &str,
+    options: &[String],
+    ballot_id: &str,
+    recipients: u32,
+) -> Result<i64> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Insert survey
+    let options = serde_json::to_string(options).context("Could not serialize survey options")?;
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO surveys (tenant, question, options, ballot_id, recipients, created_at)
+        VALUES (?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+        RETURNING id
+        "#,
+    )
+    .bind(tenant)
+    .bind(question)
+    .bind(options)
+    .bind(ballot_id)
+    .bind(recipients)
+    .fetch_one(&mut *conn)
+    .await
+    .context("Could not insert survey")
+}
+
+/// Return the survey with the specified ID.
+pub async fn get_survey(pool: &Pool<Sqlite>, id: i64) -> Result<Option<StoredSurvey>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch survey
+    sqlx::query_as(
+        r#"
+        SELECT id, tenant, question, options, ballot_id, recipients, created_at
+        FROM surveys
+        WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch survey")
+}
+
+/// Return the survey of the tenant with the specified (hex encoded) ballot ID.
+pub async fn get_survey_by_ballot_id(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    ballot_id: &str,
+) -> Result<Option<StoredSurvey>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch survey
+    sqlx::query_as(
+        r#"
+        SELECT id, tenant, question, options, ballot_id, recipients, created_at
+        FROM surveys
+        WHERE tenant = ? AND ballot_id = ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(ballot_id)
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch survey")
+}
+
+/// Return the most recent surveys of the tenant, newest first.
+pub async fn get_surveys(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    limit: u32,
+) -> Result<Vec<StoredSurvey>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch surveys
+    sqlx::query_as(
+        r#"
+        SELECT id, tenant, question, options, ballot_id, recipients, created_at
+        FROM surveys
+        WHERE tenant = ?
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch surveys")
+}
+
+/// Return the Threema users of the tenant in random order, optionally limited
+/// to a sample of the specified size.
+pub async fn get_survey_recipients(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    sample: Option<u32>,
+) -> Result<Vec<User>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch users (a negative limit means no limit in SQLite)
+    sqlx::query_as(
+        r#"
+        SELECT id, tenant, username, usertype, threema_public_key
+        FROM users
+        WHERE tenant = ? AND usertype = 'threema'
+        ORDER BY RANDOM()
+        LIMIT ?
+        "#,
+    )
+    .bind(tenant)
+    .bind(sample.map_or(-1, i64::from))
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch survey recipients")
+}
+
+/// Store the response of a user to a survey, replacing a previous response.
+///
+/// If `choice` is `None`, the response of the user is removed.
+pub async fn set_survey_response(
+    pool: &Pool<Sqlite>,
+    survey_id: i64,
+    user_id: i32,
+    choice: Option<u32>,
+) -> Result<()> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Store or remove response
+    retry_busy!(match choice {
+        Some(choice) => sqlx::query(
+            r#"
+            INSERT INTO survey_responses (survey_id, user_id, choice, updated_at)
+            VALUES (?, ?, ?, CURRENT_TIMESTAMP)
+            ON CONFLICT (survey_id, user_id)
+            DO UPDATE SET choice = excluded.choice, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(survey_id)
+        .bind(user_id)
+        .bind(choice),
+        None => sqlx::query("DELETE FROM survey_responses WHERE survey_id = ? AND user_id = ?")
+            .bind(survey_id)
+            .bind(user_id),
+    }
+    .execute(&mut *conn)
+    .await
+    .context("Could not store survey response"))?;
+    Ok(())
+}
+
+/// Return the number of responses per choice of a survey (choices without
+/// responses are omitted).
+pub async fn get_survey_results(pool: &Pool<Sqlite>, survey_id: i64) -> Result<Vec<(u32, u32)>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Count responses
+    sqlx::query_as(
+        r#"
+        SELECT choice, count(*)
+        FROM survey_responses
+        WHERE survey_id = ?
+        GROUP BY choice
+        ORDER BY choice
+        "#,
+    )
+    .bind(survey_id)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Could not fetch survey results")
+}
+
+/// Remember the gateway message ID of a message sent to a user, together
+/// with the kind of the message and the flight it is about (if any).
+pub async fn insert_sent_message(
+    executor: impl Executor<'_, Database = Sqlite>,
+    message_id: &str,
+    user_id: i32,
+    kind: &str,
+    flight_url: Option<&str>,
+) -> Result<()> {
+    // Insert message
+    sqlx::query(
+        r#"
+        INSERT OR REPLACE INTO sent_messages (message_id, user_id, kind, flight_url, sent_at)
+        VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP)
+        "#,
+    )
+    .bind(message_id)
+    .bind(user_id)
+    .bind(kind)
+    .bind(flight_url)
+    .execute(executor)
+    .await
+    .context("Could not insert sent message")?;
+    Ok(())
+}
+
+/// Record that a message sent to the user was delivered, or read (which
+/// implies delivered). Earlier timestamps are kept.
+///
+/// Return whether the message is a known message of the user.
+pub async fn mark_sent_message(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+    user_id: i32,
+    read: bool,
+) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Update timestamps
+    let result = retry_busy!(sqlx::query(
+        r#"
+        UPDATE sent_messages SET
+            delivered_at = coalesce(delivered_at, CURRENT_TIMESTAMP),
+            read_at = CASE WHEN ? THEN coalesce(read_at, CURRENT_TIMESTAMP) ELSE read_at END
+        WHERE message_id = ? AND user_id = ?
+        "#,
+    )
+    .bind(read)
+    .bind(message_id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await
+    .context("Could not update sent message"))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Return the message of the tenant with the specified gateway message ID,
+/// together with the recipient and the title of the flight.
+pub async fn get_sent_message(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    message_id: &str,
+) -> Result<Option<SentMessage>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch message
+    sqlx::query_as(
+        r#"
+        SELECT m.message_id, m.user_id, u.usertype, u.username, m.kind,
+               m.flight_url, f.title AS flight_title,
+               m.sent_at, m.delivered_at, m.read_at
+        FROM sent_messages m
+        INNER JOIN users u ON u.id = m.user_id
+        LEFT JOIN xcontest_flights f ON f.url = m.flight_url
+        WHERE m.message_id = ? AND u.tenant = ?
+        "#,
+    )
+    .bind(message_id)
+    .bind(tenant)
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch sent message")
+}
+
+/// Remove the sent messages sent more than `days` days ago (reactions to them
+/// can't be attributed to the flight anymore).
+///
+/// Return the number of removed messages.
+pub async fn evict_sent_messages(pool: &Pool<Sqlite>, days: u32) -> Result<u64> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Remove old messages
+    let result = retry_busy!(sqlx::query(
+        "DELETE FROM sent_messages WHERE sent_at <= datetime('now', ?)"
+    )
+    .bind(format!("-{} days", days))
+    .execute(&mut *conn)
+    .await
+    .context("Could not evict sent messages"))?;
+    Ok(result.rows_affected())
+}
+
+/// Remove the flights seen more than `days` days ago.
+///
+/// Return the number of removed flights.
+pub async fn prune_flights(pool: &Pool<Sqlite>, days: u32) -> Result<u64> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Remove old flights
+    let result = retry_busy!(sqlx::query(
+        "DELETE FROM xcontest_flights WHERE seen_at <= datetime('now', ?)"
+    )
+    .bind(format!("-{} days", days))
+    .execute(&mut *conn)
+    .await
+    .context("Could not prune flights"))?;
+    Ok(result.rows_affected())
+}
+
+/// Remove the parse failures last seen more than `days` days ago.
+///
+/// Return the number of removed parse failures.
+pub async fn prune_parse_failures(pool: &Pool<Sqlite>, days: u32) -> Result<u64> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Remove old parse failures
+    let result = retry_busy!(sqlx::query(
+        "DELETE FROM parse_failures WHERE last_seen <= datetime('now', ?)"
+    )
+    .bind(format!("-{} days", days))
+    .execute(&mut *conn)
+    .await
+    .context("Could not prune parse failures"))?;
+    Ok(result.rows_affected())
+}
+
+/// Store the reaction of a user to the flight of a notification message,
+/// replacing a previous reaction to the flight.
+///
+/// Return whether the message is a known notification message of the user.
+pub async fn set_flight_reaction(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+    user_id: i32,
+    reaction: i32,
+) -> Result<bool> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Store reaction
+    let result = retry_busy!(sqlx::query(
+        r#"
+        INSERT INTO flight_reactions (user_id, flight_url, reaction, updated_at)
+        SELECT user_id, flight_url, ?, CURRENT_TIMESTAMP
+        FROM sent_messages
+        WHERE message_id = ? AND user_id = ? AND flight_url IS NOT NULL
+        ON CONFLICT (user_id, flight_url)
+        DO UPDATE SET reaction = excluded.reaction, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(reaction)
+    .bind(message_id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await
+    .context("Could not store flight reaction"))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Return the flight of the tenant seen after `since` with the most likes,
+/// together with the number of likes.
+pub async fn get_most_liked_flight(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    since: DateTime<Utc>,
+) -> Result<Option<(StoredFlight, u32)>> {
+    // Get connection
+    let mut conn = acquire(pool).await?;
+
+    // Fetch flight
+    let row = sqlx::query(
+        r#"
+        SELECT f.url, f.title, f.guid, f.start_time, f.airtime_minutes, count(*) AS likes
+        FROM flight_reactions r
+        INNER JOIN users u ON r.user_id = u.id
+        INNER JOIN xcontest_flights f ON f.tenant = u.tenant AND f.url = r.flight_url
+        WHERE u.tenant = ? AND r.reaction > 0 AND f.seen_at > ?
+        GROUP BY f.url
+        ORDER BY likes DESC, f.seen_at DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(tenant)
+    .bind(sql_timestamp(since))
+    .fetch_optional(&mut *conn)
+    .await
+    .context("Could not fetch most liked flight")?;
+    match row {
+        Some(row) => {
+            let flight = StoredFlight::from_row(&row).context("Could not parse flight")?;
+            let likes = row.try_get("likes").context("Could not parse likes")?;
+            Ok(Some((flight, likes)))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::ConnectOptions;
+
+    use super::*;
+    use crate::jobs::Job;
+
+    fn flight(url_date: &str, guid: Option<&str>) -> Flight {
+        Flight::new(
+            "17.10.26 [42.00 km :: free_flight] Christian Maurer".to_string(),
+            format!(
+                "https://www.xcontest.org/switzerland/en/flights/detail:chrigel/{}",
+                url_date
+            ),
+        )
+        .unwrap()
+        .with_guid(guid.map(str::to_string))
+    }
+
+    #[tokio::test]
+    async fn classify_errors() {
+        let path = std::env::temp_dir().join(format!("xc-bot-test-{}.db", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let pool = connect(&path, &PoolSettings::default()).await.unwrap();
+        migrate(&pool).await.unwrap();
+
+        // Unique constraint
+        let insert_user = "INSERT INTO users (tenant, username, usertype, since) \
+                           VALUES ('default', 'ECHOECHO', 'threema', CURRENT_TIMESTAMP)";
+        sqlx::query(insert_user).execute(&pool).await.unwrap();
+        let result = sqlx::query(insert_user)
+            .execute(&pool)
+            .await
+            .context("Could not create user");
+        assert!(matches!(result, Err(Error::Conflict { .. })));
+
+        // Missing row
+        let result = sqlx::query("SELECT id FROM users WHERE username = 'NOTFOUND'")
+            .fetch_one(&pool)
+            .await
+            .context("Could not fetch user");
+        assert!(matches!(result, Err(Error::NotFound { .. })));
+
+        // Database locked by another connection
+        let mut writer = acquire(&pool).await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut *writer)
+            .await
+            .unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .busy_timeout(Duration::ZERO);
+        let mut other = options.connect().await.unwrap();
+        let result = sqlx::query("DELETE FROM users")
+            .execute(&mut other)
+            .await
+            .context("Could not delete users");
+        assert!(matches!(result, Err(Error::Busy { .. })));
+
+        // Busy writes are retried until the lock is released
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sqlx::query("COMMIT").execute(&mut *writer).await.unwrap();
+        });
+        let retries = busy_retries();
+        let result = retry_busy!(sqlx::query("DELETE FROM users WHERE username = 'NOTFOUND'")
+            .execute(&mut other)
+            .await
+            .context("Could not delete users"));
+        assert!(result.is_ok());
+        assert!(busy_retries() > retries);
+        release.await.unwrap();
+
+        drop(other);
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn upsert_flights_batch() {
+        let settings = PoolSettings {
+            min_connections: 1,
+            max_connections: 1,
+            ..PoolSettings::default()
+        };
+        let pool = connect(":memory:", &settings).await.unwrap();
+        migrate(&pool).await.unwrap();
+        let mut conn = acquire(&pool).await.unwrap();
+
+        // Duplicates in the feed are only inserted once
+        let a = flight("17.10.2026/10:00", None);
+        let b = flight("17.10.2026/12:00", Some("guid-b"));
+        let upserted = upsert_flights(
+            &mut conn,
+            "default",
+            &[a.clone(), b.clone(), a.clone()],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(upserted.new, HashSet::from([a.url.clone(), b.url.clone()]));
+        let contests: Vec<Option<String>> =
+            sqlx::query_scalar("SELECT contest FROM xcontest_flights")
+                .fetch_all(&mut *conn)
+                .await
+                .unwrap();
+        assert_eq!(contests, vec![Some("switzerland".to_string()); 2]);
+
+        // Only new flights are returned, also if their GUID is already known
+        let c = flight("17.10.2026/14:00", None);
+        let b_moved = flight("17.10.2026/12:01", Some("guid-b"));
+        let now = Some(Utc::now());
+        let upserted = upsert_flights(&mut conn, "default", &[a.clone(), b_moved, c.clone()], now)
+            .await
+            .unwrap();
+        assert_eq!(upserted.new, HashSet::from([c.url.clone()]));
+        assert!(upserted.corrected.is_empty());
+
+        // Changed titles are updated, notified flights are corrected
+        let mut c_corrected = c.clone();
+        c_corrected.title = "17.10.26 [45.10 km :: free_flight] Christian Maurer".to_string();
+        let mut a_corrected = a.clone();
+        a_corrected.title = c_corrected.title.clone();
+        let upserted = upsert_flights(&mut conn, "default", &[a_corrected, c_corrected], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            upserted,
+            UpsertedFlights {
+                new: HashSet::new(),
+                corrected: HashSet::from([c.url.clone()]),
+            }
+        );
+        let title: String = sqlx::query_scalar("SELECT title FROM xcontest_flights WHERE url = ?")
+            .bind(&a.url)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert!(title.contains("45.10 km"));
+
+        // The URL used as GUID of old flights is replaced with the real GUID
+        let a_with_guid = flight("17.10.2026/10:00", Some("guid-a"));
+        backfill_flight_guids(&mut conn, "default", &[a_with_guid])
+            .await
+            .unwrap();
+        let a_moved = flight("17.10.2026/10:01", Some("guid-a"));
+        let upserted = upsert_flights(&mut conn, "default", &[a_moved], None)
+            .await
+            .unwrap();
+        assert!(upserted.new.is_empty());
+    }
+
+    #[tokio::test]
+    async fn store_flight_times() {
+        let settings = PoolSettings {
+            min_connections: 1,
+            max_connections: 1,
+            ..PoolSettings::default()
+        };
+        let pool = connect(":memory:", &settings).await.unwrap();
+        migrate(&pool).await.unwrap();
+        let a = flight("17.10.2026/10:00", None);
+        let mut conn = acquire(&pool).await.unwrap();
+        upsert_flights(&mut conn, "default", std::slice::from_ref(&a), None)
+            .await
+            .unwrap();
+        drop(conn);
+
+        // Times are only stored once
+        let times = FlightTimes {
+            start: NaiveTime::from_hms_opt(10, 0, 0),
+            airtime_minutes: Some(95),
+        };
+        set_flight_times(&pool, &a.url, &times).await.unwrap();
+        set_flight_times(&pool, &a.url, &FlightTimes::default())
+            .await
+            .unwrap();
+        let stored = get_flight_by_url(&pool, "default", &a.url)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.id, 1);
+        assert_eq!(stored.start_time.as_deref(), Some("10:00"));
+        assert_eq!(stored.to_flight().unwrap().times, times);
+    }
+
+    #[tokio::test]
+    async fn merge_duplicate_users() {
+        let settings = PoolSettings {
+            min_connections: 1,
+            max_connections: 1,
+            ..PoolSettings::default()
+        };
+        let pool = connect(":memory:", &settings).await.unwrap();
+        migrate(&pool).await.unwrap();
+        let from = get_or_create_user(&pool, "default", "echoecho", "threema")
+            .await
+            .unwrap();
+        let into = get_or_create_user(&pool, "default", "ECHOECHO", "threema")
+            .await
+            .unwrap();
+        for (user, pilot) in [(&from, "chrigel"), (&from, "pilot"), (&into, "chrigel")] {
+            add_subscription(&pool, user.id, pilot, None, None)
+                .await
+                .unwrap();
+        }
+        let month = current_month();
+        for user in [&from, &into] {
+            let mut conn = acquire(&pool).await.unwrap();
+            increment_notification_failures(&mut *conn, user.id, &month)
+                .await
+                .unwrap();
+        }
+        let job = Job::Notify {
+            user_id: from.id,
+            flight_url: "https://example.com/".into(),
+        };
+        sqlx::query("INSERT INTO jobs (payload, run_at, created_at) VALUES (?, 0, 0)")
+            .bind(job.to_payload().unwrap())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // Unknown users and merging a user into itself fail
+        let result = merge_users(&pool, "default", "NOTFOUND", "ECHOECHO").await;
+        assert!(matches!(result, Err(Error::NotFound { .. })));
+        let result = merge_users(&pool, "default", "ECHOECHO", "ECHOECHO").await;
+        assert!(matches!(result, Err(Error::InvalidData { .. })));
+
+        // Only the subscription to a new pilot is moved
+        let moved = merge_users(&pool, "default", "echoecho", "ECHOECHO")
+            .await
+            .unwrap();
+        assert_eq!(moved, 1);
+        let subscriptions: Vec<String> = get_subscription_list(&pool, into.id, None, false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|subscription| subscription.pilot_username)
+            .collect();
+        assert_eq!(subscriptions, vec!["chrigel", "pilot"]);
+        assert!(get_user(&pool, from.id).await.unwrap().is_none());
+        let failures: i64 =
+            sqlx::query_scalar("SELECT failures FROM notification_counters WHERE user_id = ?")
+                .bind(into.id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(failures, 2);
+        let job_user: i32 =
+            sqlx::query_scalar("SELECT json_extract(payload, '$.user_id') FROM jobs")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(job_user, into.id);
+    }
+
+    #[tokio::test]
+    async fn queue_depths() {
+        let settings = PoolSettings {
+            min_connections: 1,
+            max_connections: 1,
+            ..PoolSettings::default()
+        };
+        let pool = connect(":memory:", &settings).await.unwrap();
+        migrate(&pool).await.unwrap();
+        assert_eq!(get_queue_depths(&pool).await.unwrap(), vec![]);
+
+        let threema = get_or_create_user(&pool, "default", "ECHOECHO", "threema")
+            .await
+            .unwrap();
+        let email = get_or_create_user(&pool, "default", "pilot@example.com", "email")
+            .await
+            .unwrap();
+        let now = Utc::now();
+        for user in [&threema, &threema, &email] {
+            let job = Job::Notify {
+                user_id: user.id,
+                flight_url: "https://example.com/".into(),
+            };
+            insert_job(&pool, &job.to_payload().unwrap(), now)
+                .await
+                .unwrap();
+        }
+        insert_job(&pool, r#"{"kind":"maintenance"}"#, now)
+            .await
+            .unwrap();
+        let job = get_due_jobs(&pool, now).await.unwrap().remove(0);
+        reschedule_job(&pool, job.id, now, "Gateway unavailable")
+            .await
+            .unwrap();
+        reschedule_job(&pool, job.id, now, "Gateway unavailable")
+            .await
+            .unwrap();
+
+        let depths = get_queue_depths(&pool).await.unwrap();
+        let summary: Vec<_> = depths
+            .iter()
+            .map(|depth| {
+                (
+                    depth.kind.as_str(),
+                    depth.channel.as_deref(),
+                    depth.pending,
+                    depth.retrying,
+                    depth.failed_attempts,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("maintenance", None, 1, 0, 0),
+                ("notify", Some("email"), 1, 0, 0),
+                ("notify", Some("threema"), 2, 1, 2),
+            ]
+        );
+        assert!(depths.iter().all(|depth| depth.oldest_age_seconds < 60));
+    }
+
+    #[tokio::test]
+    async fn leader_lock() {
+        let settings = PoolSettings {
+            min_connections: 1,
+            max_connections: 1,
+            ..PoolSettings::default()
+        };
+        let pool = connect(":memory:", &settings).await.unwrap();
+        migrate(&pool).await.unwrap();
+        let now = Utc::now();
+        let lease = chrono::Duration::seconds(30);
+
+        // The first instance becomes leader and renews its lock
+        assert!(acquire_leader_lock(&pool, "a", now, now + lease)
+            .await
+            .unwrap());
+        assert!(acquire_leader_lock(&pool, "a", now, now + lease)
+            .await
+            .unwrap());
+
+        // The other instance can't take over until the lock expires
+        assert!(!acquire_leader_lock(&pool, "b", now, now + lease)
+            .await
+            .unwrap());
+        let later = now + lease;
+        assert!(acquire_leader_lock(&pool, "b", later, later + lease)
+            .await
+            .unwrap());
+        assert!(!acquire_leader_lock(&pool, "a", later, later + lease)
+            .await
+            .unwrap());
+
+        // Only the holder can release the lock
+        release_leader_lock(&pool, "a").await.unwrap();
+        assert!(!acquire_leader_lock(&pool, "a", later, later + lease)
+            .await
+            .unwrap());
+        release_leader_lock(&pool, "b").await.unwrap();
+        assert!(acquire_leader_lock(&pool, "a", later, later + lease)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn club_members() {
+        let settings = PoolSettings {
+            min_connections: 1,
+            max_connections: 1,
+            ..PoolSettings::default()
+        };
+        let pool = connect(":memory:", &settings).await.unwrap();
+        migrate(&pool).await.unwrap();
+        let mut conn = acquire(&pool).await.unwrap();
+
+        // Members are recorded once per tenant and club
+        assert!(record_club_member(&mut conn, "default", "alpin", "dbrgn")
+            .await
+            .unwrap());
+        assert!(!record_club_member(&mut conn, "default", "Alpin", "DBRGN")
+            .await
+            .unwrap());
+        assert!(record_club_member(&mut conn, "default", "jura", "dbrgn")
+            .await
+            .unwrap());
+        assert!(record_club_member(&mut conn, "other", "alpin", "dbrgn")
+            .await
+            .unwrap());
+    }
 }
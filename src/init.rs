@@ -0,0 +1,49 @@
+//! The `init` command: Write a commented config template.
+
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+
+/// Fully commented config template, with all sections and defaults.
+pub const CONFIG_TEMPLATE: &str = include_str!("../config.example.toml");
+
+/// Write the config template to the specified path.
+pub fn run(path: &Path) -> Result<()> {
+    write_config(path, None)?;
+    println!("Config template written to {:?}", path);
+    println!("Fill in the Threema Gateway credentials, then run `xc-bot selftest`.");
+    Ok(())
+}
+
+/// Write the config template, optionally filling in the private key.
+/// Existing files are never overwritten.
+pub fn write_config(path: &Path, private_key: Option<&str>) -> Result<()> {
+    let config = match private_key {
+        Some(key) => CONFIG_TEMPLATE.replacen(
+            "private_key = \"\"",
+            &format!("private_key = \"{}\"", key),
+            1,
+        ),
+        None => CONFIG_TEMPLATE.to_string(),
+    };
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .context(format!("Could not create {:?}", path))?;
+    file.write_all(config.as_bytes())
+        .context(format!("Could not write {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn template_is_valid_config() {
+        let config: Config = toml::from_str(CONFIG_TEMPLATE).unwrap();
+        assert_eq!(config.server.listen, "127.0.0.1:3000");
+        assert!(config.threema.private_key.is_empty());
+    }
+}
@@ -0,0 +1,461 @@
+//! Persistent job queue.
+//!
+//! Jobs (scheduled task runs, notification retries, ...) are stored in the
+//! database before they are executed and are only removed once they
+//! succeeded, so that they survive restarts (at-least-once execution).
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use reqwest::Client;
+use serde_derive::{Deserialize, Serialize};
+use sqlx::{Executor, Pool, Sqlite};
+use tokio::task::JoinHandle;
+use xcontest_client::{Flight, FlightDetails, XContest};
+
+use crate::{
+    alerts::Alerter,
+    cache::DetailsCache,
+    clubs,
+    commands::{self, IncomingCommand, OutgoingReply},
+    config::Config,
+    db::{self, User},
+    leader::Leadership,
+    middleware::Chain,
+    mqtt::MqttPublisher,
+    notifiers::{self, format, Notifier},
+    renames, scheduler,
+    status::BotStatus,
+    surveys,
+    tenants::{Tenant, Tenants},
+    threema::{self, MessageKind},
+};
+
+/// How often the queue is checked for due jobs.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of attempts after which a failing job is given up.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry of a failed job. The delay is doubled for
+/// every further attempt.
+pub const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// A job in the queue.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// Run a scheduled task. `last_run` is the time of the previous run.
+    Task { task: String, last_run: String },
+    /// Notify a user about a flight (used to retry failed notifications).
+    Notify { user_id: i32, flight_url: String },
+    /// Notify the subscribers of a pilot about the flights collected during
+    /// the grouping window.
+    NotifyPilot { tenant: String, pilot: String },
+    /// Notify a user about several flights of one pilot (used to retry failed
+    /// grouped notifications).
+    NotifyGroup {
+        user_id: i32,
+        pilot: String,
+        flight_urls: Vec<String>,
+    },
+    /// Look for previous usernames of a pilot seen for the first time.
+    DetectRename {
+        tenant: String,
+        pilot: String,
+        pilot_name: String,
+    },
+    /// Note a club member seen in the feed for the first time to the
+    /// subscribers of the club.
+    NoteClubMember {
+        tenant: String,
+        club: String,
+        pilot: String,
+        flight_url: String,
+    },
+    /// Send the poll of a survey to a user.
+    SendSurvey { survey_id: i64, user_id: i32 },
+    /// Notify a user about a simulated flight (marked as test message), to
+    /// verify their notification path. The flight is not stored.
+    SimulateFlight {
+        user_id: i32,
+        title: String,
+        flight_url: String,
+    },
+    /// Store a flight submitted through the API and notify the subscribers if
+    /// it's new (like a flight from the feed).
+    SubmitFlight {
+        tenant: String,
+        title: String,
+        flight_url: String,
+    },
+    /// Handle a Threema command received while this instance was a follower
+    /// (see [`crate::leader`]) and send the reply.
+    HandleCommand {
+        user_id: i32,
+        text: String,
+        nickname: Option<String>,
+    },
+}
+
+impl Job {
+    /// Serialize the job for storage in the database.
+    pub fn to_payload(&self) -> Result<String> {
+        serde_json::to_string(self).context("Could not serialize job")
+    }
+
+    /// Deserialize a job stored in the database.
+    pub fn from_payload(payload: &str) -> Result<Self> {
+        serde_json::from_str(payload).context("Could not deserialize job")
+    }
+}
+
+/// Add a job to the queue, to be run after the specified delay.
+pub async fn enqueue(
+    executor: impl Executor<'_, Database = Sqlite>,
+    job: &Job,
+    delay: Duration,
+) -> Result<()> {
+    let run_at = Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+    Ok(db::insert_job(executor, &job.to_payload()?, run_at).await?)
+}
+
+/// Everything the jobs (and the fetch loop) need to do their work.
+#[derive(Clone)]
+pub struct JobContext {
+    pub pool: Pool<Sqlite>,
+    pub tenants: Tenants,
+    pub client: Client,
+    pub config: Config,
+    pub xc: Arc<XContest>,
+    pub details_cache: DetailsCache,
+    pub status: Arc<BotStatus>,
+    pub middleware: Arc<Chain>,
+    pub leadership: Arc<Leadership>,
+    /// Publishes new flights to MQTT, if configured
+    pub mqtt: Option<MqttPublisher>,
+}
+
+/// Executes due jobs in the background.
+pub struct Worker {
+    context: JobContext,
+    alerter: Alerter,
+}
+
+impl Worker {
+    pub fn new(context: JobContext, alerter: Alerter) -> Self {
+        Self { context, alerter }
+    }
+
+    /// Run the worker in a background task.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(self) {
+        match db::count_jobs(&self.context.pool).await {
+            Ok(0) => {}
+            Ok(pending) => tracing::info!("Resuming {} pending jobs", pending),
+            Err(e) => tracing::error!("Could not count pending jobs: {}", e),
+        }
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !self.context.leadership.is_leader() {
+                continue;
+            }
+            if let Err(e) = self.run_due_jobs().await {
+                tracing::error!("Could not process job queue: {}", e);
+            }
+        }
+    }
+
+    async fn run_due_jobs(&self) -> Result<()> {
+        let pool = &self.context.pool;
+        for stored in db::get_due_jobs(pool, Utc::now()).await? {
+            let result = match Job::from_payload(&stored.payload) {
+                Ok(job) => {
+                    tracing::debug!("Running job {}: {:?}", stored.id, job);
+                    self.run_job(&job).await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => db::delete_job(pool, stored.id).await?,
+                Err(e)
+                    if e.downcast_ref::<notifiers::Error>()
+                        .is_some_and(|e| !e.is_retryable()) =>
+                {
+                    tracing::warn!("Job {} failed permanently, giving up: {:#}", stored.id, e);
+                    db::delete_job(pool, stored.id).await?;
+                }
+                Err(e) if stored.attempts + 1 >= MAX_ATTEMPTS => {
+                    self.alerter
+                        .alert(&format!(
+                            "Giving up job {} after {} attempts: {:#}\n\n{}",
+                            stored.id,
+                            stored.attempts + 1,
+                            e,
+                            stored.payload
+                        ))
+                        .await;
+                    db::delete_job(pool, stored.id).await?;
+                }
+                Err(e) => {
+                    let delay = RETRY_DELAY * 2u32.pow(stored.attempts);
+                    tracing::warn!("Job {} failed, retrying in {:?}: {:#}", stored.id, delay, e);
+                    let run_at = Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+                    db::reschedule_job(pool, stored.id, run_at, &format!("{:#}", e)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn notifier(&self, tenant: &Tenant) -> Result<Notifier> {
+        Notifier::new(
+            self.context.pool.clone(),
+            self.context.client.clone(),
+            tenant,
+            self.context.config.season_gap_months(),
+        )
+    }
+
+    /// Fetch the details of a flight for the preview image, unless images are
+    /// disabled.
+    async fn fetch_details(&self, tenant: &Tenant, flight: &Flight) -> Option<FlightDetails> {
+        if !tenant.config.features.images() {
+            return None;
+        }
+        self.context
+            .details_cache
+            .get_or_fetch(&self.context.xc, flight)
+            .await
+            .ok()
+    }
+
+    /// Return the tenant with the specified name.
+    fn tenant(&self, name: &str) -> Result<&Tenant> {
+        self.context
+            .tenants
+            .get(name)
+            .context(format!("Tenant {} does not exist", name))
+    }
+
+    /// Return the user with the specified ID and their tenant.
+    async fn user_and_tenant(&self, user_id: i32) -> Result<(User, &Tenant)> {
+        let user = db::get_user(&self.context.pool, user_id)
+            .await?
+            .context(format!("User {} does not exist", user_id))?;
+        let tenant = self.tenant(&user.tenant)?;
+        Ok((user, tenant))
+    }
+
+    async fn run_job(&self, job: &Job) -> Result<()> {
+        match job {
+            Job::Task { task, last_run } => {
+                let last_run = db::parse_sql_timestamp(last_run)?;
+                scheduler::run_task(task, &self.context, &self.alerter, last_run).await
+            }
+            Job::Notify {
+                user_id,
+                flight_url,
+            } => {
+                let pool = &self.context.pool;
+                let (user, tenant) = self.user_and_tenant(*user_id).await?;
+                let flight = match db::get_flight_by_url(pool, tenant.id(), flight_url).await? {
+                    Some(stored) => stored.to_flight()?,
+                    None => bail!("Flight {} does not exist", flight_url),
+                };
+                let details = self.fetch_details(tenant, &flight).await;
+                let mut notifier = self.notifier(tenant)?;
+                let mut conn = db::acquire(pool).await?;
+                let first_of_season = notifier.is_first_of_season(&mut conn, &flight).await;
+                notifier
+                    .notify_user(&mut conn, &flight, details.as_ref(), first_of_season, &user)
+                    .await?;
+                Ok(())
+            }
+            Job::NotifyPilot { tenant, pilot } => {
+                let pool = &self.context.pool;
+                let tenant = self.tenant(tenant)?;
+                let flights = db::take_pending_flights(pool, tenant.id(), pilot)
+                    .await?
+                    .iter()
+                    .map(|stored| stored.to_flight())
+                    .collect::<db::Result<Vec<_>>>()?;
+                let mut notifier = self.notifier(tenant)?;
+                match &flights[..] {
+                    [] => {}
+                    [flight] => {
+                        let details = self.fetch_details(tenant, flight).await;
+                        let mut conn = db::acquire(pool).await?;
+                        notifier.notify(&mut conn, flight, details).await?;
+                    }
+                    flights => {
+                        let mut conn = db::acquire(pool).await?;
+                        notifier.notify_group(&mut conn, pilot, flights).await?
+                    }
+                }
+                Ok(())
+            }
+            Job::NotifyGroup {
+                user_id,
+                pilot,
+                flight_urls,
+            } => {
+                let pool = &self.context.pool;
+                let (user, tenant) = self.user_and_tenant(*user_id).await?;
+                let mut flights = vec![];
+                for flight_url in flight_urls {
+                    match db::get_flight_by_url(pool, tenant.id(), flight_url).await? {
+                        Some(stored) => flights.push(stored.to_flight()?),
+                        None => bail!("Flight {} does not exist", flight_url),
+                    }
+                }
+                let mut notifier = self.notifier(tenant)?;
+                let mut conn = db::acquire(pool).await?;
+                let first_of_season = match flights.first() {
+                    Some(flight) => notifier.is_first_of_season(&mut conn, flight).await,
+                    None => false,
+                };
+                notifier
+                    .notify_user_group(&mut conn, pilot, &flights, first_of_season, &user)
+                    .await?;
+                Ok(())
+            }
+            Job::DetectRename {
+                tenant,
+                pilot,
+                pilot_name,
+            } => {
+                let tenant = self.tenant(tenant)?;
+                renames::detect_rename(&self.context.pool, &self.alerter, tenant, pilot, pilot_name)
+                    .await
+            }
+            Job::NoteClubMember {
+                tenant,
+                club,
+                pilot,
+                flight_url,
+            } => {
+                let tenant = self.tenant(tenant)?;
+                clubs::note_new_member(&self.context.pool, tenant, club, pilot, flight_url).await
+            }
+            Job::SendSurvey { survey_id, user_id } => {
+                let pool = &self.context.pool;
+                let (user, tenant) = self.user_and_tenant(*user_id).await?;
+                surveys::send(pool, tenant, *survey_id, &user).await
+            }
+            Job::SimulateFlight {
+                user_id,
+                title,
+                flight_url,
+            } => {
+                let pool = &self.context.pool;
+                let (user, tenant) = self.user_and_tenant(*user_id).await?;
+                let flight = Flight::new(title.clone(), flight_url.clone())?;
+                let details = self.fetch_details(tenant, &flight).await;
+                tracing::info!(
+                    "Notifying {}/{} about simulated flight {} ({})",
+                    user.usertype,
+                    user.username,
+                    flight.url,
+                    if details.is_some() {
+                        "with image"
+                    } else {
+                        "text only"
+                    },
+                );
+                let mut notifier = self.notifier(tenant)?.simulated();
+                let mut conn = db::acquire(pool).await?;
+                notifier
+                    .notify_user(&mut conn, &flight, details.as_ref(), false, &user)
+                    .await?;
+                Ok(())
+            }
+            Job::SubmitFlight {
+                tenant,
+                title,
+                flight_url,
+            } => {
+                let tenant = self.tenant(tenant)?;
+                let flight = Flight::new(title.clone(), flight_url.clone())?;
+                let mut conn = db::acquire(&self.context.pool).await?;
+                let new_flights =
+                    crate::process_flights(&self.context, &mut conn, tenant, &[flight]).await?;
+                if new_flights == 0 {
+                    tracing::info!("Submitted flight {} was already known", flight_url);
+                }
+                Ok(())
+            }
+            Job::HandleCommand {
+                user_id,
+                text,
+                nickname,
+            } => {
+                let pool = &self.context.pool;
+                let (user, tenant) = self.user_and_tenant(*user_id).await?;
+                let config = &tenant.config;
+                let incoming = IncomingCommand {
+                    text,
+                    sender: &user.username,
+                    sender_nickname: nickname.as_deref(),
+                    is_admin: Some(&*user.username) == config.threema.admin_id.as_deref(),
+                };
+                let reply = commands::handle_command(
+                    &incoming,
+                    config,
+                    &user,
+                    pool,
+                    &self.context.status,
+                    &self.context.middleware,
+                )
+                .await;
+                let text = match reply {
+                    OutgoingReply::Text(text) => text,
+                    OutgoingReply::Nothing => return Ok(()),
+                    OutgoingReply::Error => bail!("Could not handle command of {}", user.username),
+                };
+
+                // The command was handled, so a failed reply is not retried
+                for part in format::split_text(&text, format::MAX_TEXT_CHARS) {
+                    let result = threema::send_text_message(
+                        &user,
+                        &part,
+                        MessageKind::Reply,
+                        &tenant.api,
+                        pool,
+                        config.threema.request_delivery_receipts(),
+                    )
+                    .await;
+                    if let Err(e) = result {
+                        tracing::error!("Could not send reply to {}: {}", user.username, e);
+                        break;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_payload_roundtrip() {
+        let job = Job::Notify {
+            user_id: 42,
+            flight_url: "https://www.xcontest.org/flight".into(),
+        };
+        let payload = job.to_payload().unwrap();
+        assert_eq!(
+            payload,
+            r#"{"kind":"notify","user_id":42,"flight_url":"https://www.xcontest.org/flight"}"#
+        );
+        assert_eq!(Job::from_payload(&payload).unwrap(), job);
+    }
+}
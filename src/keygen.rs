@@ -0,0 +1,29 @@
+//! The `keygen` command: Generate a Threema E2E keypair for a new gateway ID.
+
+use std::path::Path;
+
+use anyhow::Result;
+use crypto_box::{aead::OsRng, SecretKey};
+
+use crate::init;
+
+/// Generate a new keypair and print it. If `config_path` is set, a config
+/// template containing the private key is written to that path as well.
+pub fn run(config_path: Option<&Path>) -> Result<()> {
+    let secret_key = SecretKey::generate(&mut OsRng);
+    let private_key = hex::encode(secret_key.to_bytes());
+    let public_key = hex::encode(secret_key.public_key().as_bytes());
+
+    println!("Private key: {}", private_key);
+    println!("Public key:  {}", public_key);
+    println!();
+    println!("Register the public key for your gateway ID at https://gateway.threema.ch/");
+    println!("and keep the private key secret.");
+
+    if let Some(path) = config_path {
+        init::write_config(path, Some(&private_key))?;
+        println!();
+        println!("Config template written to {:?}", path);
+    }
+    Ok(())
+}
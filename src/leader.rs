@@ -0,0 +1,142 @@
+//! Leader election between instances sharing the database.
+//!
+//! With leader election enabled, two instances can run against the same
+//! database (e.g. during a restart): Only the instance holding the leader lock
+//! polls the feeds, runs the jobs and scheduled tasks and sends notifications.
+//! The other instance (the follower) serves the webhooks and queues incoming
+//! commands for the leader. The lock is renewed regularly, and taken over by
+//! the follower once it has expired.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use sqlx::{Pool, Sqlite};
+use tokio::task::JoinHandle;
+
+use crate::{config::Config, db};
+
+/// Default number of seconds the leader lock is valid without renewal.
+pub const DEFAULT_LEASE_SECONDS: u64 = 30;
+
+/// Whether this instance is the leader.
+#[derive(Debug)]
+pub struct Leadership {
+    /// The name of this instance, `None` if leader election is disabled
+    instance: Option<String>,
+    lease: Duration,
+    leader: AtomicBool,
+}
+
+impl Leadership {
+    /// A single instance, which is always the leader.
+    pub fn single() -> Self {
+        Self {
+            instance: None,
+            lease: Duration::from_secs(DEFAULT_LEASE_SECONDS),
+            leader: AtomicBool::new(true),
+        }
+    }
+
+    /// An instance taking part in the election, which is a follower until it
+    /// acquires the lock.
+    pub fn elected(instance: String, lease: Duration) -> Self {
+        Self {
+            instance: Some(instance),
+            lease,
+            leader: AtomicBool::new(false),
+        }
+    }
+
+    /// Create the leadership as configured. Read-only instances don't take
+    /// part in the election.
+    pub fn from_config(config: &Config) -> Self {
+        match &config.cluster {
+            Some(cluster) if cluster.leader_election.unwrap_or(false) && !config.read_only() => {
+                let instance = cluster.instance_name.clone().unwrap_or_else(|| {
+                    format!(
+                        "{}-{}",
+                        std::env::var("HOSTNAME").unwrap_or_else(|_| "xc-bot".into()),
+                        std::process::id()
+                    )
+                });
+                let lease = cluster.lease_seconds.unwrap_or(DEFAULT_LEASE_SECONDS);
+                Self::elected(instance, Duration::from_secs(lease.max(3)))
+            }
+            _ => Self::single(),
+        }
+    }
+
+    /// Return whether this instance is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::SeqCst)
+    }
+
+    /// Return the name of this instance, if leader election is enabled.
+    pub fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+
+    /// Return the interval at which the lock is renewed.
+    pub fn renew_interval(&self) -> Duration {
+        self.lease / 3
+    }
+
+    /// Acquire or renew the leader lock. If the database can't be reached,
+    /// the leadership is given up, since another instance may take over.
+    ///
+    /// Return whether this instance is the leader.
+    pub async fn renew(&self, pool: &Pool<Sqlite>) -> bool {
+        let instance = match &self.instance {
+            Some(instance) => instance,
+            None => return true,
+        };
+        let now = chrono::Utc::now();
+        let expires_at = now + chrono::Duration::from_std(self.lease).unwrap_or_default();
+        let leader = match db::acquire_leader_lock(pool, instance, now, expires_at).await {
+            Ok(leader) => leader,
+            Err(e) => {
+                tracing::error!("Could not renew leader lock: {}", e);
+                false
+            }
+        };
+        if self.leader.swap(leader, Ordering::SeqCst) != leader {
+            if leader {
+                tracing::info!("Instance {} is now the leader", instance);
+            } else {
+                tracing::warn!("Instance {} is now a follower", instance);
+            }
+        }
+        leader
+    }
+
+    /// Release the leader lock (if held), so that another instance can take
+    /// over at once.
+    pub async fn release(&self, pool: &Pool<Sqlite>) {
+        let instance = match &self.instance {
+            Some(instance) => instance,
+            None => return,
+        };
+        self.leader.store(false, Ordering::SeqCst);
+        if let Err(e) = db::release_leader_lock(pool, instance).await {
+            tracing::error!("Could not release leader lock: {}", e);
+        }
+    }
+
+    /// Renew the leader lock in a background task, if leader election is
+    /// enabled.
+    pub fn spawn(self: Arc<Self>, pool: Pool<Sqlite>) -> Option<JoinHandle<()>> {
+        self.instance.as_ref()?;
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.renew_interval());
+            loop {
+                interval.tick().await;
+                self.renew(&pool).await;
+            }
+        }))
+    }
+}
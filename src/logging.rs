@@ -0,0 +1,71 @@
+//! Logging setup.
+//!
+//! The log filter can be replaced at runtime (with the `loglevel` admin
+//! command), e.g. to enable verbose logging during an incident without
+//! restarting the bot.
+
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{anyhow, Context, Result};
+use tracing_subscriber::{
+    fmt::{self, format::FmtSpan},
+    prelude::*,
+    reload, EnvFilter, Registry,
+};
+
+/// The reloadable log filter of the global subscriber.
+struct Filter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// The filter from the config
+    configured: String,
+    /// The currently active filter
+    current: Mutex<String>,
+}
+
+static FILTER: OnceLock<Filter> = OnceLock::new();
+
+/// Install the global tracing subscriber, with the specified log filter.
+pub fn init(filter: &str) -> Result<()> {
+    let (filter_layer, handle) = reload::Layer::new(EnvFilter::new(filter));
+    let subscriber = tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(fmt::layer().with_span_events(FmtSpan::CLOSE));
+    tracing::subscriber::set_global_default(subscriber)
+        .context("Could not set global tracing subscriber")?;
+    FILTER
+        .set(Filter {
+            handle,
+            configured: filter.to_string(),
+            current: Mutex::new(filter.to_string()),
+        })
+        .map_err(|_| anyhow!("Logging was already initialized"))
+}
+
+fn filter() -> Result<&'static Filter> {
+    FILTER.get().context("Logging is not initialized")
+}
+
+/// Return the currently active log filter.
+pub fn current_filter() -> Result<String> {
+    Ok(filter()?.current.lock().unwrap().clone())
+}
+
+/// Replace the log filter.
+pub fn set_filter(new_filter: &str) -> Result<()> {
+    let env_filter = EnvFilter::try_new(new_filter).context("Invalid log filter")?;
+    let filter = filter()?;
+    filter
+        .handle
+        .reload(env_filter)
+        .context("Could not replace log filter")?;
+    *filter.current.lock().unwrap() = new_filter.to_string();
+    tracing::info!("Log filter changed to {}", new_filter);
+    Ok(())
+}
+
+/// Restore the log filter from the config and return it.
+pub fn reset_filter() -> Result<String> {
+    let configured = filter()?.configured.clone();
+    set_filter(&configured)?;
+    Ok(configured)
+}
@@ -1,42 +1,105 @@
-use std::{net::SocketAddr, process, str::FromStr, time::Duration};
+use std::{collections::HashSet, net::SocketAddr, path::Path, process, sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
-use reqwest::Client;
-use sqlx::{
-    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
-    Pool, Sqlite,
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Client,
 };
+use sqlx::SqliteConnection;
+use threema_gateway::E2eApi;
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::format::FmtSpan, FmtSubscriber};
 
+mod alerts;
+mod cache;
+mod card;
 mod cli;
+mod clubs;
+mod commands;
+mod competitions;
 mod config;
+mod conversation;
 mod db;
+mod init;
+mod jobs;
+mod keygen;
+mod leader;
+mod logging;
+mod merge;
+mod messages;
+mod middleware;
+mod migrate;
+mod mqtt;
 mod notifiers;
+mod panics;
+mod reactions;
+mod renames;
+mod scheduler;
+mod selftest;
 mod server;
+mod status;
+mod surveys;
+mod systemd;
+mod tenants;
 mod threema;
-mod xcontest;
+mod tokens;
 
-use config::Config;
-use xcontest::XContest;
+use alerts::Alerter;
+use cache::DetailsCache;
+use config::{Config, ThreemaConfig};
+use futures::StreamExt;
+use jobs::{Job, JobContext};
+use leader::Leadership;
+use panics::PanicReporter;
+use status::{BotStatus, ParseCoverage};
+use tenants::{Tenant, Tenants};
+use xcontest_client::{self as xcontest, FeedItems, Flight, FlightDetails, XContest};
 
 pub(crate) const NAME: &str = "XC Bot";
 pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub(crate) const AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
 pub(crate) const DESCRIPTION: &str =
     "A chat bot that notifies you about new paragliding cross-country flights.";
+/// Upper bound for the polling delay while XContest is throttling requests.
+const MAX_THROTTLE_BACKOFF: Duration = Duration::from_secs(6 * 3600);
+pub(crate) const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse command line args
-    let app = cli::App::new(NAME, VERSION, DESCRIPTION, AUTHOR, "config.toml");
+    let args = cli::App::new(NAME, VERSION, DESCRIPTION, AUTHOR, "config.toml").parse();
 
+    match args.command {
+        cli::Command::Run { read_only } => run(&args.configfile, read_only).await,
+        cli::Command::SelfTest => {
+            let passed = selftest::run(&args.configfile).await;
+            process::exit(if passed { 0 } else { 1 });
+        }
+        cli::Command::SendTest {
+            to,
+            with_image,
+            tenant,
+        } => send_test(&args.configfile, &to, with_image, tenant.as_deref()).await,
+        cli::Command::Keygen { write_config } => keygen::run(write_config.as_deref()),
+        cli::Command::Init { path } => init::run(path.as_deref().unwrap_or(&args.configfile)),
+        cli::Command::Migrate { action } => migrate::run(&args.configfile, action).await,
+        cli::Command::Token { action } => tokens::run(&args.configfile, action).await,
+        cli::Command::MergeUsers { from, into, tenant } => {
+            merge::run(&args.configfile, &from, &into, tenant.as_deref()).await
+        }
+    }
+}
+
+/// Run the bot (in read-only mode if `read_only` is set, regardless of the
+/// config).
+async fn run(configfile: &Path, read_only: bool) -> Result<()> {
     // Load config
-    let configfile = app.get_configfile();
-    let config = Config::load(&configfile).unwrap_or_else(|e| {
+    let mut config = Config::load_or_env(configfile).unwrap_or_else(|e| {
         eprintln!("Could not load config file {:?}: {}", configfile, e);
         process::exit(2);
     });
+    if read_only {
+        config.server.read_only = Some(true);
+    }
 
     // Init logging
     LogTracer::init()?;
@@ -45,73 +108,205 @@ async fn main() -> Result<()> {
         .as_ref()
         .and_then(|logging| logging.filter.to_owned())
         .unwrap_or_else(|| "info,sqlx::query=warn".into());
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(&filter)
-        .with_span_events(FmtSpan::CLOSE)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting tracing default failed");
+    logging::init(&filter)?;
     tracing::info!("Starting {} v{}", NAME, VERSION);
+    tracing::info!("Features: {}", config.features().summary());
+    if config.read_only() {
+        tracing::warn!(
+            "Running in read-only mode: No notifications are sent, state-changing commands \
+             are refused and the job queue is paused"
+        );
+    }
 
-    // Connect to database
-    let connect_options = SqliteConnectOptions::from_str("sqlite:data.db")?
-        .create_if_missing(true)
-        .journal_mode(SqliteJournalMode::Wal)
-        .foreign_keys(true);
-    let pool = SqlitePoolOptions::new()
-        .min_connections(2)
-        .max_connections(5)
-        .connect_with(connect_options)
-        .await?;
+    // Bind HTTP server early, so that an invalid address or a port that is
+    // already in use aborts the startup. With socket activation, systemd
+    // passes an already bound socket.
+    let listener = match systemd::take_listener()? {
+        Some(listener) => {
+            tracing::info!("Using socket passed by systemd");
+            listener
+        }
+        None => {
+            let addr: SocketAddr = config.server.listen.parse().context(format!(
+                "Could not parse HTTP server listening address {:?}",
+                config.server.listen
+            ))?;
+            server::bind(addr).await?
+        }
+    };
+    let internal_listener = match &config.server.internal_listen {
+        Some(internal_listen) => {
+            let addr: SocketAddr = internal_listen.parse().context(format!(
+                "Could not parse internal HTTP server listening address {:?}",
+                internal_listen
+            ))?;
+            Some(server::bind(addr).await?)
+        }
+        None => None,
+    };
 
-    // Run migrations
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    // Connect to database and run migrations
+    let pool = db::connect(config.database_path(), &config.pool_settings()).await?;
+    db::migrate(&pool).await?;
 
     // Create shared HTTP client
-    let client = Client::builder()
-        .https_only(true)
-        .pool_idle_timeout(Duration::from_secs(300))
-        .user_agent(concat!(
-            env!("CARGO_PKG_NAME"),
-            "/",
-            env!("CARGO_PKG_VERSION")
-        ))
-        .build()
-        .context("Could not create HTTP client")?;
+    let client = build_http_client()?;
 
     // Create XContest client
-    let xc = XContest::new(client.clone());
+    let xc_config = config.xcontest.as_ref();
+    let xc = Arc::new(build_xcontest(&config, client.clone())?);
 
-    // Create Threema Gateway API instance
-    let api = threema_gateway::ApiBuilder::new(
-        &config.threema.gateway_id,
-        &config.threema.gateway_secret,
+    // Create tenants, with their Threema Gateway API instances
+    let tenants = Tenants::from_config(&config)?;
+
+    // Create flight details cache
+    let details_cache = DetailsCache::new(
+        pool.clone(),
+        xc_config
+            .and_then(|xc| xc.details_cache_ttl_seconds)
+            .unwrap_or(cache::DEFAULT_TTL_SECONDS),
+    );
+
+    // Shared runtime status and admin alerts
+    let status = Arc::new(BotStatus::default());
+    let alerter = Alerter::from_config(
+        &config,
+        tenants.default_tenant().api.clone(),
+        pool.clone(),
+        client.clone(),
     )
-    .with_private_key_str(&config.threema.private_key)
-    .and_then(|builder| builder.into_e2e())
-    .context("Could not create Threema Gateway API client")?;
+    .context("Could not create alerter")?;
 
-    // Listening address for HTTP server
-    let addr: SocketAddr = config
-        .server
-        .listen
-        .parse()
-        .context("Could not parse HTTP server listening address")?;
+    let panic_reporter = PanicReporter::new(alerter.clone(), status.clone());
+
+    // Make sure the XContest parsers still work with the known payloads
+    match xcontest::parser_self_test() {
+        Ok((feed_parser, detail_parser)) => tracing::info!(
+            "Parser self-test passed (feed: {}, detail page: {})",
+            feed_parser,
+            detail_parser
+        ),
+        Err(e) => {
+            alerter
+                .alert(&format!("XContest parser self-test failed: {}", e))
+                .await
+        }
+    }
+
+    // Middleware run around every command
+    let commands_config = config.commands.as_ref();
+    let middleware = middleware::Chain::new()
+        .with(middleware::AuditLog)
+        .with(middleware::Metrics::new(status.clone()))
+        .with(middleware::ReplyThrottle::new(
+            commands_config
+                .and_then(|commands| commands.help_cooldown_seconds)
+                .map_or(middleware::DEFAULT_HELP_COOLDOWN, Duration::from_secs),
+            commands_config
+                .and_then(|commands| commands.max_replies_per_hour)
+                .unwrap_or(middleware::DEFAULT_MAX_REPLIES_PER_HOUR),
+        ));
+    let middleware = if config.read_only() {
+        middleware.with(middleware::ReadOnly::new(
+            tenants
+                .iter()
+                .map(|tenant| (tenant.id().to_string(), tenant.config.messages())),
+        ))
+    } else {
+        middleware
+    };
+    let middleware = Arc::new(middleware);
+
+    // Take part in the leader election, if enabled. The lock is acquired
+    // before the fetch loop starts, so that a single instance leads at once.
+    let leadership = Arc::new(Leadership::from_config(&config));
+    if let Some(instance) = leadership.instance() {
+        tracing::info!("Leader election enabled, instance name: {}", instance);
+        leadership.renew(&pool).await;
+        leadership.clone().spawn(pool.clone());
+    }
+
+    // Start job queue worker and scheduler for periodic tasks (they are idle
+    // while this instance is a follower)
+    let context = JobContext {
+        pool: pool.clone(),
+        tenants: tenants.clone(),
+        client,
+        config: config.clone(),
+        xc: xc.clone(),
+        details_cache: details_cache.clone(),
+        status: status.clone(),
+        middleware: middleware.clone(),
+        leadership: leadership.clone(),
+        mqtt: config
+            .mqtt
+            .as_ref()
+            .filter(|_| !config.read_only())
+            .map(mqtt::MqttPublisher::spawn),
+    };
+    // In read-only mode, the jobs are left for the primary instance
+    if !config.read_only() {
+        jobs::Worker::new(context.clone(), alerter.clone()).spawn();
+        scheduler::Scheduler::new(config.scheduler.as_ref(), pool.clone(), leadership.clone())
+            .context("Could not create scheduler")?
+            .spawn();
+    }
 
     // Start HTTP server, listening for incoming messages
-    server::serve(
+    let server = server::serve(
         server::SharedState {
-            api,
+            tenants: tenants.clone(),
             pool: pool.clone(),
-            config: config.clone(),
+            status: status.clone(),
+            xc: xc.clone(),
+            details_cache: details_cache.clone(),
+            landing_page: config
+                .server
+                .landing_page
+                .unwrap_or(true)
+                .then(server::LandingPage::new),
+            middleware,
+            read_only: config.read_only(),
+            leadership,
+            alerter: alerter.clone(),
+            panic_reporter: panic_reporter.clone(),
         },
-        addr,
-    )
-    .await;
+        server::ServerOptions::from_config(&config.server, &config.features())?,
+        listener,
+        internal_listener,
+    );
+
+    // Startup is complete
+    systemd::notify("READY=1");
+
+    // Run the fetch loop until the HTTP server stops
+    let result = tokio::select! {
+        result = server => match result {
+            Ok(Ok(())) => Err(anyhow::anyhow!("HTTP server stopped unexpectedly")),
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(e).context("HTTP server task failed"),
+        },
+        _ = fetch_loop(&context, &status, &alerter, &panic_reporter) => {
+            unreachable!("The fetch loop never returns")
+        }
+    };
+
+    // Let the other instance take over without waiting for the lock to expire
+    context.leadership.release(&pool).await;
+    result
+}
 
-    // Main loop, run at specified interval
+/// Fetch new flights at the configured interval, forever.
+async fn fetch_loop(
+    context: &JobContext,
+    status: &BotStatus,
+    alerter: &Alerter,
+    panic_reporter: &PanicReporter,
+) {
     let interval_seconds = std::cmp::max(
         60,
-        config
+        context
+            .config
             .xcontest
             .as_ref()
             .and_then(|xc| xc.interval_seconds)
@@ -119,96 +314,637 @@ async fn main() -> Result<()> {
     );
     let interval_duration = Duration::from_secs(interval_seconds);
     let mut interval = tokio::time::interval(interval_duration);
+    if let Some(timeout) = systemd::watchdog_timeout() {
+        if timeout <= interval_duration {
+            tracing::warn!(
+                "The systemd watchdog timeout ({:?}) is shorter than the fetch interval",
+                timeout
+            );
+        }
+    }
     tracing::info!(
         "Starting XContest fetch loop with {:?} interval",
         interval_duration
     );
+    let mut throttle_backoff: Option<Duration> = None;
+    let mut parser_mismatch = false;
+    let mut parse_failures = false;
+    let parse_failure_alert_percent = context.config.parse_failure_alert_percent();
+    let mut latency_slo_exceeded = false;
+    let latency_slo_seconds = context.config.latency_slo_seconds();
     loop {
         interval.tick().await;
-        match update(&pool, &xc, &client, &config).await {
-            Ok(_) => {}
-            Err(e) => tracing::warn!("Update failed: {}", e),
+
+        // Only the leader polls the feeds, a follower just stays alive
+        if !context.leadership.is_leader() {
+            tracing::debug!("Not the leader, skipping update");
+            systemd::notify("WATCHDOG=1");
+            continue;
+        }
+
+        // A panic (e.g. on a weird feed item) is reported, the next update is
+        // attempted in the next interval
+        let result = match panic_reporter
+            .catch("update loop", update(context, alerter))
+            .await
+        {
+            Some(result) => result,
+            None => continue,
+        };
+        match result {
+            Ok(coverage) => {
+                systemd::notify("WATCHDOG=1");
+                status.record_fetch();
+                status.record_parse_coverage(coverage);
+                // Only alert once, not every cycle
+                if coverage.exceeds(parse_failure_alert_percent) != parse_failures {
+                    parse_failures = !parse_failures;
+                    if parse_failures {
+                        alerter
+                            .alert(&format!(
+                                "{} of {} XContest feed items ({:.0}%) could not be parsed, \
+                                 these flights are not notified (see `failures`)",
+                                coverage.failed,
+                                coverage.parsed + coverage.failed,
+                                coverage.failure_percent(),
+                            ))
+                            .await;
+                    } else {
+                        alerter
+                            .alert(&format!(
+                                "At most {}% of the XContest feed items fail to parse again",
+                                parse_failure_alert_percent
+                            ))
+                            .await;
+                    }
+                }
+                throttle_backoff = None;
+                if parser_mismatch {
+                    parser_mismatch = false;
+                    alerter
+                        .alert("XContest feed matches a known parser again")
+                        .await;
+                }
+                if status.clear_throttled() {
+                    tracing::info!("XContest is no longer throttling requests");
+                }
+                // Only alert once, not every cycle
+                if let (Some(slo), Some(latency)) =
+                    (latency_slo_seconds, status.notification_latency())
+                {
+                    if (latency.p90 > slo) != latency_slo_exceeded {
+                        latency_slo_exceeded = !latency_slo_exceeded;
+                        let text = if latency_slo_exceeded {
+                            format!(
+                                "Notifications are slow: 90% of the last {} notified flights \
+                                 were notified within {}s of their publication, the SLO is {}s \
+                                 (median {}s, 99th percentile {}s)",
+                                latency.samples, latency.p90, slo, latency.p50, latency.p99,
+                            )
+                        } else {
+                            format!(
+                                "Notifications are within the latency SLO of {}s again \
+                                 (90th percentile {}s)",
+                                slo, latency.p90,
+                            )
+                        };
+                        alerter.alert(&text).await;
+                    }
+                }
+            }
+            Err(e) => match e.downcast_ref::<xcontest::Error>() {
+                Some(xcontest::Error::RateLimited(throttled)) => {
+                    // Back off exponentially, unless XContest tells us how long to wait
+                    let backoff = throttled
+                        .retry_after
+                        .unwrap_or_else(|| {
+                            throttle_backoff.map_or(interval_duration * 2, |backoff| backoff * 2)
+                        })
+                        .min(MAX_THROTTLE_BACKOFF);
+                    throttle_backoff = Some(backoff);
+                    let until = chrono::Local::now()
+                        + chrono::Duration::from_std(backoff).unwrap_or_default();
+                    tracing::warn!("{}, backing off for {:?}", throttled, backoff);
+                    if status.set_throttled(throttled.status, until) {
+                        alerter
+                            .alert(&format!(
+                                "{}. Pausing fetches until {}.",
+                                throttled,
+                                until.format("%Y-%m-%d %H:%M")
+                            ))
+                            .await;
+                    }
+                    tokio::time::sleep(backoff).await;
+                    interval.reset();
+                }
+                Some(xcontest::Error::NoMatchingParser(_)) => {
+                    // Only alert once, not every cycle
+                    if !parser_mismatch {
+                        parser_mismatch = true;
+                        alerter.alert(&e.to_string()).await;
+                    }
+                }
+                _ => tracing::warn!("Update failed: {}", e),
+            },
         };
     }
 }
 
-/// This function will be called regularly to fetch new flights.
-#[tracing::instrument(level = "debug", skip(pool, xc, client, config))]
-async fn update(
-    pool: &Pool<Sqlite>,
-    xc: &XContest,
-    client: &Client,
-    config: &Config,
+/// Send a canned flight notification through the notifier stack, to verify
+/// the gateway credentials and key setup.
+async fn send_test(
+    configfile: &Path,
+    to: &str,
+    with_image: bool,
+    tenant: Option<&str>,
 ) -> Result<()> {
+    let config = Config::load_or_env(configfile)
+        .map_err(|e| anyhow::anyhow!("Could not load config file {:?}: {}", configfile, e))?;
+    let pool = db::connect(config.database_path(), &config.pool_settings()).await?;
+    db::migrate(&pool).await?;
+    let client = build_http_client()?;
+
+    // Canned flight
+    let flight = xcontest::Flight::new(
+        "17.10.26 [42.00 km :: free_flight] XC Bot Test".to_string(),
+        "https://www.xcontest.org/switzerland/en/flights/detail:xcbot/17.10.2026/10:00".to_string(),
+    )?;
+    let details = if with_image {
+        let xc = build_xcontest(&config, client.clone())?;
+        Some(xc.process_preview(bytes::Bytes::from_static(xcontest::SAMPLE_THUMBNAIL))?)
+    } else {
+        None
+    };
+
+    // Send
+    let tenants = Tenants::from_config(&config)?;
+    let tenant = match tenant {
+        Some(id) => tenants.get(id).context(format!("Unknown tenant: {}", id))?,
+        None => tenants.default_tenant(),
+    };
+    let user = db::get_or_create_user(&pool, tenant.id(), to, "threema").await?;
+    let mut conn = db::acquire(&pool).await?;
+    let mut notifier = notifiers::Notifier::new(pool, client, tenant, config.season_gap_months())?;
+    notifier
+        .notify_user(&mut conn, &flight, details.as_ref(), false, &user)
+        .await
+        .context(format!("Could not send test notification to {}", to))?;
+    println!("Test notification sent to {}", to);
+    Ok(())
+}
+
+/// Create the shared HTTP client.
+fn build_http_client() -> Result<Client> {
+    Client::builder()
+        .https_only(true)
+        .pool_idle_timeout(Duration::from_secs(300))
+        .user_agent(USER_AGENT)
+        .build()
+        .context("Could not create HTTP client")
+}
+
+/// Create the XContest client.
+fn build_xcontest(config: &Config, client: Client) -> Result<XContest> {
+    let xc_config = config.xcontest.as_ref();
+    if let Some(pattern) = xc_config.and_then(|xc| xc.url_pattern.as_deref()) {
+        xcontest::set_url_pattern(pattern).context("Could not set flight URL pattern")?;
+    }
+    let xc = XContest::new(client)
+        .with_animated_previews(
+            xc_config
+                .and_then(|xc| xc.animated_previews)
+                .unwrap_or(false),
+        )
+        .with_min_request_delay(Duration::from_millis(
+            xc_config
+                .and_then(|xc| xc.min_request_delay_ms)
+                .unwrap_or(1000),
+        ))
+        .with_detail_budget(xc_config.and_then(|xc| xc.detail_fetches_per_hour))
+        .with_headers(xcontest_headers(config)?);
+    let xc = match xc_config.and_then(|xc| xc.http_cache_dir.as_deref()) {
+        Some(dir) => xc.with_http_cache(xcontest::HttpCache::new(
+            dir,
+            Duration::from_secs(
+                xc_config
+                    .and_then(|xc| xc.http_cache_max_stale_seconds)
+                    .unwrap_or(7 * 24 * 3600),
+            ),
+        )?),
+        None => xc,
+    };
+    match xc_config.and_then(|xc| xc.thumbnail_pattern.as_deref()) {
+        Some(pattern) => xc
+            .with_thumbnail_pattern(pattern)
+            .context("Could not set thumbnail pattern"),
+        None => Ok(xc),
+    }
+}
+
+/// Create the Threema Gateway API client.
+fn build_threema_api(config: &ThreemaConfig) -> Result<E2eApi> {
+    threema_gateway::ApiBuilder::new(&config.gateway_id, &config.gateway_secret)
+        .with_private_key_str(&config.private_key)
+        .and_then(|builder| builder.into_e2e())
+        .context("Could not create Threema Gateway API client")
+}
+
+/// Return the HTTP headers to be sent with every XContest request.
+fn xcontest_headers(config: &Config) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let xc_config = match config.xcontest.as_ref() {
+        Some(xc_config) => xc_config,
+        None => return Ok(headers),
+    };
+
+    // User agent and contact
+    let user_agent = xc_config.user_agent.as_deref().unwrap_or(USER_AGENT);
+    let user_agent = match xc_config.contact.as_deref() {
+        Some(contact) => format!("{} (+{})", user_agent, contact),
+        None => user_agent.to_string(),
+    };
+    headers.insert(
+        reqwest::header::USER_AGENT,
+        HeaderValue::from_str(&user_agent).context("Invalid User-Agent")?,
+    );
+    if let Some(contact) = xc_config.contact.as_deref().filter(|c| c.contains('@')) {
+        headers.insert(
+            reqwest::header::FROM,
+            HeaderValue::from_str(contact).context("Invalid contact")?,
+        );
+    }
+
+    // Custom headers
+    for (name, value) in xc_config.headers.iter().flatten() {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())
+                .context(format!("Invalid header name: {}", name))?,
+            HeaderValue::from_str(value).context(format!("Invalid value for header {}", name))?,
+        );
+    }
+
+    Ok(headers)
+}
+
+/// This function will be called regularly to fetch new flights.
+///
+/// Return the parse coverage of the fetched feed items.
+#[tracing::instrument(level = "debug", skip(context, alerter))]
+async fn update(context: &JobContext, alerter: &Alerter) -> Result<ParseCoverage> {
     tracing::info!("Update started");
+    let tenants = &context.tenants;
+
+    // All database work of the cycle uses the same connection
+    let mut conn = db::acquire(&context.pool).await?;
 
-    // Connect to XContest, fetch flights
-    let flights = xc.fetch_flights().await?;
+    // Fetch every feed only once, even if several tenants use it
+    let mut feed_urls: Vec<&str> = tenants
+        .iter()
+        .map(|tenant| tenant.config.feed_url())
+        .collect();
+    feed_urls.sort_unstable();
+    feed_urls.dedup();
+    let mut coverage = ParseCoverage::default();
+    for feed_url in feed_urls {
+        // Connect to XContest, fetch flights
+        let FeedItems { flights, failures } = fetch_feed_items(context, feed_url).await?;
+        coverage.parsed += flights.len() as u64;
+        coverage.failed += failures.len() as u64;
+
+        // Quarantine feed items that could not be parsed
+        for failure in &failures {
+            if let Err(e) = db::record_parse_failure(&mut *conn, failure).await {
+                tracing::error!("Could not record parse failure: {}", e);
+            }
+        }
+
+        for tenant in tenants
+            .iter()
+            .filter(|tenant| tenant.config.feed_url() == feed_url)
+        {
+            let new_flights = process_flights(context, &mut conn, tenant, &flights).await?;
+            alerter
+                .new_flights(&mut *conn, tenant.id(), new_flights)
+                .await;
+        }
+    }
+    if coverage.failed > 0 {
+        tracing::warn!(
+            "{} of {} feed items could not be parsed",
+            coverage.failed,
+            coverage.parsed + coverage.failed
+        );
+    }
+    Ok(coverage)
+}
+
+/// Fetch the flights in the feed. If the feed is unavailable or truncated and
+/// a tenant using it has a flight list configured, the flights on the list
+/// are merged in.
+async fn fetch_feed_items(context: &JobContext, feed_url: &str) -> Result<FeedItems> {
+    let list_url = context
+        .tenants
+        .iter()
+        .filter(|tenant| tenant.config.feed_url() == feed_url)
+        .find_map(|tenant| tenant.config.flight_list_url.as_deref());
+    let list_url = match list_url {
+        Some(list_url) => list_url,
+        None => return Ok(context.xc.fetch_flights(feed_url).await?),
+    };
+    let min_items = context.config.min_feed_items();
+    let (mut items, feed_error) = match context.xc.fetch_flights(feed_url).await {
+        Ok(items) if items.flights.len() >= min_items => return Ok(items),
+        Ok(items) => {
+            tracing::warn!(
+                "Feed {} has only {} flights, falling back to flight list {}",
+                feed_url,
+                items.flights.len(),
+                list_url
+            );
+            (items, None)
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Could not fetch feed {}, falling back to flight list {}: {}",
+                feed_url,
+                list_url,
+                e
+            );
+            (FeedItems::default(), Some(e))
+        }
+    };
+    match context.xc.fetch_flight_list(list_url).await {
+        Ok(list) => {
+            // Flights that are in the feed as well are skipped
+            let known: HashSet<String> = items
+                .flights
+                .iter()
+                .map(|flight| flight.url.clone())
+                .collect();
+            tracing::info!(
+                "Found {} flights on flight list {}",
+                list.flights.len(),
+                list_url
+            );
+            items.flights.extend(
+                list.flights
+                    .into_iter()
+                    .filter(|flight| !known.contains(&flight.url)),
+            );
+            items.failures.extend(list.failures);
+            Ok(items)
+        }
+        Err(e) => {
+            tracing::warn!("Could not fetch flight list {}: {}", list_url, e);
+            match feed_error {
+                Some(feed_error) => Err(feed_error.into()),
+                None => Ok(items),
+            }
+        }
+    }
+}
+
+/// Store the flights of a tenant and notify its users about new ones.
+///
+/// Return the number of new flights.
+async fn process_flights(
+    context: &JobContext,
+    conn: &mut SqliteConnection,
+    tenant: &Tenant,
+    flights: &[Flight],
+) -> Result<u64> {
+    // Flights of the same pilot within this window are notified together
+    let group_window = context
+        .config
+        .xcontest
+        .as_ref()
+        .and_then(|xc| xc.group_window_seconds)
+        .unwrap_or(0);
+
+    // One notifier for all flights
+    let mut notifier = notifiers::Notifier::new(
+        context.pool.clone(),
+        context.client.clone(),
+        tenant,
+        context.config.season_gap_months(),
+    )
+    .context("Could not instantiate notifier")?;
+
+    // Store flights, new and corrected flights are returned
+    let notified_at = (group_window == 0).then(chrono::Utc::now);
+    let db::UpsertedFlights {
+        new: mut new_urls,
+        corrected,
+    } = db::upsert_flights(conn, tenant.id(), flights, notified_at).await?;
+    if let Err(e) = db::backfill_flight_guids(conn, tenant.id(), flights).await {
+        tracing::warn!("Could not backfill flight GUIDs: {}", e);
+    }
 
     // Process flights
-    let mut conn = pool.acquire().await?;
     let total_flights = flights.len();
     let mut new_flights = 0;
+    let mut to_notify = vec![];
     for flight in flights {
-        // Store flight in database.
-        let result = sqlx::query(
-            r#"
-            INSERT INTO xcontest_flights (url, title, pilot_username)
-            VALUES (?, ?, ?)
-            "#,
-        )
-        .bind(&flight.url)
-        .bind(&flight.title)
-        .bind(&flight.pilot_username)
-        .execute(&mut *conn)
-        .await;
-
-        // If inserting fails with a unique constraint, that means that the
-        // flight was already processed before.
-        match result {
-            Err(sqlx::Error::Database(e))
-                if e.message() == "UNIQUE constraint failed: xcontest_flights.url" =>
-            {
-                tracing::debug!("Flight {} already processed, skipping", flight.url);
-                continue;
-            }
-            Err(other) => {
-                // Uh oh...
-                tracing::error!(
-                    "Error inserting flight {} into database: {}",
-                    flight.url,
-                    other
-                );
-                continue;
-            }
-            Ok(_) => { /* Database entry did not yet exist, carry on with processing */ }
+        // Flights that are not new (with the same URL or GUID) were already
+        // processed before. Removing the URL also skips duplicates in the feed.
+        if !new_urls.remove(&flight.url) {
+            tracing::debug!("Flight {} already processed, skipping", flight.url);
+            continue;
         }
 
         // Notify
-        tracing::info!("New flight: {}", flight.title);
+        tracing::info!("New flight for tenant {}: {}", tenant.id(), flight.title);
         new_flights += 1;
-        // TODO: Only fetch if subscribers present
-        let details = match xc.fetch_flight_details(&flight).await {
-            Ok(details) => Some(details),
-            Err(e) => {
+        if context.config.read_only() {
+            continue;
+        }
+        if let Some(mqtt) = &context.mqtt {
+            mqtt.publish(tenant.id(), flight);
+        }
+
+        // The first flight of a username might belong to a renamed pilot
+        if let Err(e) = schedule_rename_detection(conn, tenant, flight).await {
+            tracing::error!(
+                "Could not schedule rename detection of {}: {}",
+                flight.url,
+                e
+            );
+        }
+
+        if let Err(e) = schedule_club_member_notes(conn, tenant, flight).await {
+            tracing::error!(
+                "Could not schedule club member notes of {}: {}",
+                flight.url,
+                e
+            );
+        }
+
+        // When grouping, the first flight of the pilot starts the window. The
+        // flights are notified once it has passed.
+        if group_window > 0 {
+            if let Err(e) = schedule_pilot_notification(conn, tenant, flight, group_window).await {
+                tracing::error!("Could not schedule notification of {}: {}", flight.url, e);
+            }
+            continue;
+        }
+
+        to_notify.push(flight);
+    }
+
+    // Fetch the details of the new flights concurrently, then notify in feed
+    // order
+    // TODO: Only fetch if subscribers present
+    let details = fetch_all_details(context, tenant, &to_notify).await;
+    for (flight, details) in to_notify.into_iter().zip(details) {
+        let details = match details {
+            None => None,
+            Some(Ok(details)) => Some(details),
+            Some(Err(xcontest::Error::Parse(failure))) => {
+                tracing::warn!("Could not fetch flight details: {}", failure);
+                if let Err(e) = db::record_parse_failure(&mut *conn, &failure).await {
+                    tracing::error!("Could not record parse failure: {}", e);
+                }
+                None
+            }
+            Some(Err(xcontest::Error::BudgetExhausted(_))) => {
+                tracing::info!("Detail fetch budget exhausted, sending text-only notification");
+                None
+            }
+            Some(Err(e)) => {
                 tracing::warn!("Could not fetch flight details: {}", e);
                 None
             }
         };
-        let mut notifier = match notifiers::Notifier::new(pool.clone(), client.clone(), config) {
-            Ok(n) => n,
-            Err(e) => {
-                tracing::error!("Could not instantiate notifier: {}", e);
-                continue;
+        let notified = notifier.notify(conn, flight, details).await?;
+
+        // Track how long it took from the publication in the feed until the
+        // subscribers were notified
+        if notified > 0 {
+            if let Some(published) = flight.published {
+                let latency = chrono::Utc::now().signed_duration_since(published);
+                context
+                    .status
+                    .record_notification_latency(latency.num_seconds().max(0) as u64);
             }
-        };
-        notifier.notify(&flight, details).await?;
+        }
+    }
+
+    // Notify corrected flights (e.g. distance changed after optimization)
+    for flight in flights
+        .iter()
+        .filter(|flight| corrected.contains(&flight.url))
+    {
+        tracing::info!(
+            "Corrected flight for tenant {}: {}",
+            tenant.id(),
+            flight.title
+        );
+        if context.config.notify_corrections() && !context.config.read_only() {
+            notifier.notify_correction(conn, flight).await?;
+        }
     }
 
     tracing::info!(
-        "Update done, found {}/{} new flights",
+        "Update of tenant {} done, found {}/{} new flights",
+        tenant.id(),
         new_flights,
         total_flights
     );
+    Ok(new_flights)
+}
+
+/// Fetch the details of the flights with bounded parallelism (the requests
+/// are still spaced by the politeness delay of the client).
+///
+/// The results are returned in the order of the flights, `None` if images are
+/// disabled.
+async fn fetch_all_details(
+    context: &JobContext,
+    tenant: &Tenant,
+    flights: &[&Flight],
+) -> Vec<Option<xcontest::Result<FlightDetails>>> {
+    if !tenant.config.features.images() {
+        return flights.iter().map(|_| None).collect();
+    }
+    let fetches: Vec<_> = flights
+        .iter()
+        .map(|flight| context.details_cache.get_or_fetch(&context.xc, flight))
+        .collect();
+    futures::stream::iter(fetches)
+        .buffered(context.config.detail_fetch_parallelism())
+        .map(Some)
+        .collect()
+        .await
+}
+
+/// Schedule the detection of a renamed pilot, if this is the first flight of
+/// the username.
+async fn schedule_rename_detection(
+    conn: &mut SqliteConnection,
+    tenant: &Tenant,
+    flight: &Flight,
+) -> Result<()> {
+    let pilot_name = match &flight.parsed_title {
+        Some(parsed) => parsed.pilot_name.clone(),
+        None => return Ok(()),
+    };
+    if db::count_pilot_flights(&mut *conn, tenant.id(), &flight.pilot_username).await? > 1 {
+        return Ok(());
+    }
+    let job = Job::DetectRename {
+        tenant: tenant.id().to_string(),
+        pilot: flight.pilot_username.clone(),
+        pilot_name,
+    };
+    jobs::enqueue(conn, &job, Duration::ZERO).await
+}
+
+/// Schedule the "new member spotted" note for the clubs that list the pilot as
+/// a member, if this is the first flight of the pilot. Members are recorded on
+/// every flight, so that a member is only noted once per club.
+async fn schedule_club_member_notes(
+    conn: &mut SqliteConnection,
+    tenant: &Tenant,
+    flight: &Flight,
+) -> Result<()> {
+    for club in clubs::member_clubs(&tenant.config.clubs, &flight.pilot_username)? {
+        if !db::record_club_member(&mut *conn, tenant.id(), &club.code, &flight.pilot_username)
+            .await?
+        {
+            continue;
+        }
+        if db::count_pilot_flights(&mut *conn, tenant.id(), &flight.pilot_username).await? > 1 {
+            continue;
+        }
+        let job = Job::NoteClubMember {
+            tenant: tenant.id().to_string(),
+            club: club.code.clone(),
+            pilot: flight.pilot_username.clone(),
+            flight_url: flight.url.clone(),
+        };
+        jobs::enqueue(&mut *conn, &job, Duration::ZERO).await?;
+    }
     Ok(())
 }
+
+/// Schedule the notification about the flights of the pilot at the end of the
+/// grouping window, unless it's already scheduled.
+async fn schedule_pilot_notification(
+    conn: &mut SqliteConnection,
+    tenant: &Tenant,
+    flight: &Flight,
+    group_window: u64,
+) -> Result<()> {
+    if db::count_pending_flights(&mut *conn, tenant.id(), &flight.pilot_username).await? > 1 {
+        tracing::debug!(
+            "Notification of {} already scheduled",
+            flight.pilot_username
+        );
+        return Ok(());
+    }
+    let job = Job::NotifyPilot {
+        tenant: tenant.id().to_string(),
+        pilot: flight.pilot_username.clone(),
+    };
+    jobs::enqueue(conn, &job, Duration::from_secs(group_window)).await
+}
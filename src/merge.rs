@@ -0,0 +1,26 @@
+//! The `merge-users` command: Merge a duplicate user (e.g. created by a case
+//! variation or an ID change) into another user.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{config::Config, db, tenants::DEFAULT_TENANT};
+
+/// Merge the user `from` into the user `into` in the configured database.
+pub async fn run(configfile: &Path, from: &str, into: &str, tenant: Option<&str>) -> Result<()> {
+    let config = Config::load_or_env(configfile)
+        .map_err(|e| anyhow::anyhow!("Could not load config file {:?}: {}", configfile, e))?;
+    let pool = db::connect(config.database_path(), &config.pool_settings()).await?;
+    db::migrate(&pool).await?;
+
+    let tenant = tenant.unwrap_or(DEFAULT_TENANT);
+    let moved = db::merge_users(&pool, tenant, from, into).await?;
+    println!(
+        "Merged user {} into {} (tenant {}), moved {} subscription(s).",
+        from, into, tenant, moved
+    );
+
+    pool.close().await;
+    Ok(())
+}
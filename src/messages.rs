@@ -0,0 +1,587 @@
+//! User-facing texts, per language.
+//!
+//! Placeholders like `{pilot}` are replaced with [`fill`].
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_derive::Deserialize;
+
+/// The language of the user-facing texts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "de")]
+    German,
+    #[serde(rename = "en")]
+    English,
+}
+
+impl Language {
+    /// Return the texts in this language.
+    pub fn messages(self) -> &'static Messages {
+        match self {
+            Language::German => &GERMAN,
+            Language::English => &ENGLISH,
+        }
+    }
+}
+
+/// Define the [`Messages`] struct and a setter that looks up the texts by
+/// field name (used for the override files).
+macro_rules! messages {
+    (
+        $(#[$struct_meta:meta])*
+        pub struct Messages {
+            $($(#[$meta:meta])* pub $field:ident: &'static str,)*
+        }
+    ) => {
+        $(#[$struct_meta])*
+        pub struct Messages {
+            $($(#[$meta])* pub $field: &'static str,)*
+        }
+
+        impl Messages {
+            /// Replace the text with the specified (field) name.
+            ///
+            /// Return `false` if there is no text with this name.
+            fn set(&mut self, name: &str, text: &'static str) -> bool {
+                match name {
+                    $(stringify!($field) => self.$field = text,)*
+                    _ => return false,
+                }
+                true
+            }
+        }
+    };
+}
+
+messages! {
+    /// All user-facing texts.
+    #[derive(Debug, Clone)]
+    pub struct Messages {
+        /// Help text, sent for unknown commands (placeholder: `nickname`)
+        pub help: &'static str,
+        pub follow_usage: &'static str,
+        /// Placeholder: `name`
+        pub follow_name_not_found: &'static str,
+        /// Followed by a numbered list of pilots (placeholder: `name`)
+        pub follow_choose_pilot: &'static str,
+        /// Placeholder: `count`
+        pub follow_invalid_choice: &'static str,
+        /// Placeholder: `pilot`
+        pub follow_success: &'static str,
+        /// Placeholders: `pilot`, `label`
+        pub follow_label_success: &'static str,
+        /// End of a temporary subscription (placeholder: `date`)
+        pub follow_expires: &'static str,
+        pub follow_duration_invalid: &'static str,
+        /// A temporary subscription ended (placeholder: `pilots`)
+        pub follow_expired: &'static str,
+        pub comp_usage: &'static str,
+        /// The configured competitions (placeholder: `codes`)
+        pub comp_list: &'static str,
+        /// Placeholder: `code`
+        pub comp_unknown: &'static str,
+        /// Placeholder: `name`
+        pub comp_over: &'static str,
+        /// Placeholder: `max`
+        pub comp_too_many: &'static str,
+        /// Placeholders: `count`, `label`
+        pub comp_success: &'static str,
+        /// Pilots of the roster that were already followed (placeholder: `count`)
+        pub comp_skipped: &'static str,
+        pub label_invalid: &'static str,
+        /// Placeholder: `label`
+        pub label_not_found: &'static str,
+        pub unfollow_usage: &'static str,
+        /// Placeholder: `pilot`
+        pub unfollow_success: &'static str,
+        /// Placeholder: `pilot`
+        pub unfollow_not_following: &'static str,
+        /// Placeholders: `count`, `label`
+        pub unfollow_label_success: &'static str,
+        pub move_usage: &'static str,
+        /// Placeholders: `old`, `new`
+        pub move_success: &'static str,
+        /// Hint about a possibly renamed pilot (placeholders: `old`, `new`, `name`)
+        pub rename_detected: &'static str,
+        pub list_usage: &'static str,
+        pub list_empty: &'static str,
+        pub list_header: &'static str,
+        /// Header of the list sorted by subscription date
+        pub list_header_newest: &'static str,
+        /// Header of the list filtered by label (placeholder: `label`)
+        pub list_header_label: &'static str,
+        /// End of a temporary subscription in the list (placeholder: `date`)
+        pub list_expires: &'static str,
+        /// Entry of the list sorted by subscription date (placeholders: `pilot`,
+        /// `date`)
+        pub list_entry_since: &'static str,
+        /// Placeholder: `count`
+        pub list_total: &'static str,
+        /// Placeholders: `page`, `pages`
+        pub list_page: &'static str,
+        /// Placeholder: `command`
+        pub list_next_page: &'static str,
+        /// The list command and its sort option, as typed by the user
+        pub list_command: &'static str,
+        pub list_sort_newest: &'static str,
+        pub digest_usage: &'static str,
+        /// Placeholder: `status`
+        pub digest_status: &'static str,
+        pub digest_status_enabled: &'static str,
+        pub digest_status_disabled: &'static str,
+        pub digest_enabled: &'static str,
+        pub digest_disabled: &'static str,
+        pub digest_header: &'static str,
+        /// Placeholder: `count`
+        pub digest_more_flights: &'static str,
+        /// Header of the most liked flight of the week (placeholder: `count`)
+        pub digest_most_liked: &'static str,
+        /// Header of grouped flights of one pilot (placeholders: `count`, `pilot`)
+        pub group_header: &'static str,
+        /// Marker of a pilot's first flight after a longer break
+        pub first_flight_of_season: &'static str,
+        /// Marker of a simulated flight sent by the admin to test notifications
+        pub simulated_flight: &'static str,
+        /// Header of a notification about a flight whose title changed
+        pub flight_corrected: &'static str,
+        /// Time in the air of a flight (placeholders: `hours`, `minutes`)
+        pub flight_airtime: &'static str,
+        /// Closing of a triangle (placeholder: `percent`)
+        pub triangle_closing: &'static str,
+        /// Average speed on a triangle (placeholder: `speed`)
+        pub triangle_speed: &'static str,
+        pub leaderboard_usage: &'static str,
+        pub leaderboard_empty: &'static str,
+        pub leaderboard_header: &'static str,
+        /// Placeholders: `rank`, `pilot`, `distance`, `flights`, `max`
+        pub leaderboard_entry: &'static str,
+        pub leaderboard_footer: &'static str,
+        pub club_usage: &'static str,
+        pub club_none: &'static str,
+        /// The configured clubs (placeholder: `codes`)
+        pub club_list: &'static str,
+        /// The clubs the user is subscribed to (placeholder: `codes`)
+        pub club_subscriptions: &'static str,
+        /// Placeholder: `code`
+        pub club_unknown: &'static str,
+        /// Followed by the current leaderboard (placeholders: `name`, `code`)
+        pub club_subscribed: &'static str,
+        /// Placeholder: `name`
+        pub club_unsubscribed: &'static str,
+        /// Placeholder: `name`
+        pub club_not_subscribed: &'static str,
+        /// Placeholders: `name`, `month`
+        pub club_leaderboard_header: &'static str,
+        /// Placeholders: `distance`, `flights`, `pilots`
+        pub club_leaderboard_total: &'static str,
+        /// Placeholders: `name`, `month`
+        pub club_leaderboard_empty: &'static str,
+        /// Placeholders: `name`, `pilot`, `url`
+        pub club_new_member: &'static str,
+        /// Placeholder: `count`
+        pub flights_one: &'static str,
+        /// Placeholder: `count`
+        pub flights_other: &'static str,
+        pub github_usage: &'static str,
+        pub github: &'static str,
+        /// Placeholder: `uptime`
+        pub version_uptime: &'static str,
+        /// Placeholder: `time`
+        pub version_last_fetch: &'static str,
+        pub version_no_fetch: &'static str,
+        /// Placeholder: `url`
+        pub version_feed: &'static str,
+        /// Placeholder: `time`
+        pub version_throttled: &'static str,
+        /// Suggestion for a mistyped command (placeholder: `command`)
+        pub did_you_mean: &'static str,
+        /// Reply to commands of features disabled by the operator
+        pub feature_disabled: &'static str,
+        /// Reply to state-changing commands while the bot runs in read-only
+        /// mode
+        pub read_only: &'static str,
+        /// Reply to media messages (placeholder: `kind`, one of the `media_*`
+        /// texts)
+        pub unsupported_media: &'static str,
+        pub media_image: &'static str,
+        pub media_video: &'static str,
+        pub media_voice: &'static str,
+        pub media_file: &'static str,
+        pub media_location: &'static str,
+        /// Placeholder: `count`
+        pub notification_cap_reached: &'static str,
+        /// Description of the bot on the public landing page
+        pub landing_description: &'static str,
+        /// Label of the Threema link on the landing page
+        pub landing_link: &'static str,
+        /// Placeholders: `users`, `flights`
+        pub landing_stats: &'static str,
+    }
+}
+
+const GERMAN: Messages = Messages {
+    help: "Hallo {nickname}! 👋\n\n\
+        Mit diesem Bot kannst du Piloten im CCC (XContest Schweiz) folgen. Du kriegst dann eine sofortige Benachrichtigung, wenn diese einen neuen Flug hochladen. 🪂\n\n\
+        Verfügbare Befehle:\n\n\
+        - *folge _<benutzername>_ _[dauer] [#label]_*: Werde benachrichtigt, wenn der Pilot _<benutzername>_ einen neuen Flug hochlädt. Du kannst dabei den Benutzernamen von XContest oder den vollen Namen des Piloten verwenden.\n\
+        - *stopp _<benutzername>_*: Werde nicht mehr benachrichtigt, wenn der Pilot _<benutzername>_ einen neuen Flug hochlädt. Du musst dabei den Benutzernamen von XContest verwenden. Mit _#label_ entfolgst du allen Piloten mit diesem Label.\n\
+        - *umziehen _<alt>_ _<neu>_*: Übertrage dein Abo auf den neuen Benutzernamen eines Piloten.\n\
+        - *liste _[neu] [#label] [seite]_*: Zeige die Liste der Piloten, deren Flüge du abonniert hast (mit \"neu\" die zuletzt hinzugefügten zuerst).\n\
+        - *zusammenfassung an/aus*: Erhalte statt sofortiger Benachrichtigungen einmal täglich eine Zusammenfassung.\n\
+        - *rangliste*: Zeige die Monatsrangliste der Piloten, denen du folgst.\n\
+        - *club _<code>_*: Erhalte jeden Monat die Kilometer-Rangliste eines Clubs.\n\
+        - *github*: Zeige den Link zum Quellcode dieses Bots.\n\n\
+        Bei Fragen, schicke einfach eine Threema-Nachricht an https://threema.id/EBEP4UCA?text= !",
+    follow_usage: "Um einem Piloten zu folgen, sende \"folge _<benutzername>_\" \
+        (Beispiel: \"folge chrigel\" oder \"folge Christian Maurer\"). \
+        Du kannst dabei den Benutzernamen von XContest oder den vollen Namen des Piloten verwenden. \
+        Mit einem Label kannst du Piloten gruppieren (Beispiel: \"folge chrigel #team\"), \
+        mit einer Dauer folgst du nur befristet (Beispiel: \"folge chrigel 2w\" für zwei Wochen). \
+        Mit \"folge comp _<code>_\" folgst du allen Piloten eines Wettkampfs.",
+    follow_name_not_found: "⚠️ Ich kenne keinen Piloten namens {name}. \
+        Versuche es mit dem Benutzernamen von XContest.",
+    follow_choose_pilot: "Es gibt mehrere Piloten namens {name}. \
+        Antworte mit der Nummer des Piloten, dem du folgen möchtest:",
+    follow_invalid_choice: "Bitte antworte mit einer Zahl zwischen 1 und {count}.",
+    follow_success: "Du folgst jetzt {pilot}!",
+    follow_label_success: "Du folgst jetzt {pilot} mit dem Label #{label}!",
+    follow_expires: "⏱️ Das Abo endet am {date}.",
+    follow_duration_invalid: "⚠️ Ein befristetes Abo dauert 1 bis 365 Tage \
+        (Beispiel: \"folge chrigel 2w\" für zwei Wochen, \"folge chrigel 3t\" für drei Tage).",
+    follow_expired: "⏱️ Dein befristetes Abo ist abgelaufen, du folgst {pilots} nicht mehr. \
+        Sende \"folge _<benutzername>_\", um wieder zu folgen.",
+    comp_usage: "Um allen Piloten eines Wettkampfs befristet zu folgen, sende \"folge comp _<code>_\" \
+        oder eine Liste von Benutzernamen (Beispiel: \"folge comp chrigel, dbrgn, pilot 3t\").",
+    comp_list: "Bekannte Wettkämpfe: {codes}",
+    comp_unknown: "⚠️ Ich kenne keinen Wettkampf {code}.",
+    comp_over: "⚠️ Der Wettkampf {name} ist bereits vorbei.",
+    comp_too_many: "⚠️ Du kannst höchstens {max} Piloten auf einmal folgen.",
+    comp_success: "Du folgst jetzt {count} Piloten mit dem Label #{label}. \
+        Sende \"stopp #{label}\", um ihnen nicht mehr zu folgen.",
+    comp_skipped: "{count} Piloten folgst du bereits, diese Abos bleiben unverändert.",
+    label_invalid: "⚠️ Ein Label beginnt mit # und besteht aus höchstens 32 Buchstaben, \
+        Ziffern, - oder _ (Beispiel: #team).",
+    label_not_found: "Du folgst keinen Piloten mit dem Label #{label}.",
+    unfollow_usage: "Um einem Piloten zu entfolgen, sende \"stopp _<benutzername>_\" \
+        (Beispiel: \"stopp chrigel\"). \
+        Du musst dabei den Benutzernamen von XContest verwenden. \
+        Mit \"stopp #team\" entfolgst du allen Piloten mit dem Label #team.",
+    unfollow_success: "Du folgst jetzt {pilot} nicht mehr.",
+    unfollow_not_following: "Du folgst {pilot} nicht.",
+    unfollow_label_success: "Du folgst den {count} Piloten mit dem Label #{label} nicht mehr.",
+    move_usage: "Um dein Abo von einem Piloten auf einen anderen Benutzernamen zu übertragen, \
+        sende \"umziehen _<alter benutzername>_ _<neuer benutzername>_\" \
+        (Beispiel: \"umziehen chrigel chrigel2\").",
+    move_success: "Du folgst jetzt {new} statt {old}.",
+    rename_detected: "ℹ️ {name} hat möglicherweise den Benutzernamen gewechselt: \
+        Es wurde ein Flug unter dem neuen Benutzernamen {new} hochgeladen.\n\n\
+        Sende \"umziehen {old} {new}\", um statt {old} neu {new} zu folgen.",
+    list_usage: "Sende \"liste\", um die Piloten anzuzeigen, denen du folgst \
+        (\"liste 2\" für die zweite Seite, \"liste neu\" für die zuletzt hinzugefügten zuerst, \
+        \"liste #team\" für die Piloten mit dem Label #team).",
+    list_empty: "Du folgst noch keinen Piloten.",
+    list_header: "Du folgst folgenden Piloten:",
+    list_header_newest: "Du folgst folgenden Piloten (zuletzt hinzugefügte zuerst):",
+    list_header_label: "Du folgst folgenden Piloten mit dem Label #{label}:",
+    list_expires: "(bis {date})",
+    list_entry_since: "{pilot} (seit {date})",
+    list_total: "Insgesamt: {count}",
+    list_page: "Seite {page} von {pages}",
+    list_next_page: "Sende \"{command}\" für die nächste Seite.",
+    list_command: "liste",
+    list_sort_newest: "neu",
+    digest_usage: "Sende \"zusammenfassung an\", um statt sofortiger Benachrichtigungen \
+        einmal täglich eine Zusammenfassung der neuen Flüge zu erhalten, \
+        oder \"zusammenfassung aus\", um wieder sofort benachrichtigt zu werden.",
+    digest_status: "Die tägliche Zusammenfassung ist {status}.\n\n\
+        Sende \"zusammenfassung an\", um statt sofortiger Benachrichtigungen \
+        einmal täglich eine Zusammenfassung der neuen Flüge zu erhalten, \
+        oder \"zusammenfassung aus\", um wieder sofort benachrichtigt zu werden.",
+    digest_status_enabled: "aktiviert",
+    digest_status_disabled: "deaktiviert",
+    digest_enabled: "Du erhältst ab jetzt einmal täglich eine Zusammenfassung der neuen Flüge.",
+    digest_disabled: "Du wirst ab jetzt wieder sofort über neue Flüge benachrichtigt.",
+    digest_header: "*Neue Flüge* 🪂",
+    digest_more_flights: "… und {count} weitere Flüge",
+    digest_most_liked: "*Beliebtester Flug der Woche* ({count} 👍)",
+    group_header: "*{count} neue Flüge von {pilot}* 🪂",
+    first_flight_of_season: "🎉 Erster Flug der Saison!",
+    simulated_flight: "🧪 Testnachricht: Dies ist kein echter Flug.",
+    flight_corrected: "✏️ Korrigiert:",
+    flight_airtime: "{hours} h {minutes} min in der Luft",
+    triangle_closing: "{percent}% geschlossen",
+    triangle_speed: "Ø {speed} km/h",
+    leaderboard_usage: "Sende \"rangliste\", um die Monatsrangliste der Piloten anzuzeigen, \
+        denen du folgst.",
+    leaderboard_empty: "Die Piloten, denen du folgst, haben diesen Monat noch keine Flüge \
+        hochgeladen. (Die Rangliste wird einmal täglich aktualisiert.)",
+    leaderboard_header: "*Rangliste diesen Monat* 🏆",
+    leaderboard_entry: "{rank}. {pilot}: {distance} km ({flights}, max. {max} km)",
+    leaderboard_footer: "(Die Rangliste wird einmal täglich aktualisiert.)",
+    club_usage: "Sende \"club _<code>_\", um die monatliche Kilometer-Rangliste eines Clubs \
+        zu abonnieren, und \"club _<code>_ stopp\", um sie abzubestellen.",
+    club_none: "Bei diesem Bot sind keine Clubs eingerichtet.",
+    club_list: "Clubs: {codes}",
+    club_subscriptions: "Deine Abos: {codes}",
+    club_unknown: "⚠️ Ich kenne keinen Club {code}.",
+    club_subscribed: "Du erhältst jetzt jeden Monatsanfang die Rangliste von {name}. \
+        Sende \"club {code} stopp\", um sie abzubestellen.",
+    club_unsubscribed: "Du erhältst die Rangliste von {name} nicht mehr.",
+    club_not_subscribed: "Du hast die Rangliste von {name} nicht abonniert.",
+    club_leaderboard_header: "*Rangliste {name} {month}* 🏆",
+    club_leaderboard_total: "Total: {distance} km ({flights}, {pilots} Piloten)",
+    club_leaderboard_empty: "Die Mitglieder von {name} haben im {month} keine Flüge hochgeladen.",
+    club_new_member: "👋 Neues Mitglied von {name} gesichtet: {pilot} hat den ersten Flug \
+        hochgeladen.\n{url}",
+    flights_one: "{count} Flug",
+    flights_other: "{count} Flüge",
+    github_usage: "Sende \"github\", um den Link zum Quellcode dieses Bots anzuzeigen.",
+    github: "Dieser Bot ist Open Source (AGPLv3). \
+        Den Quellcode findest du hier: https://github.com/dbrgn/xc-bot/",
+    version_uptime: "Läuft seit: {uptime}",
+    version_last_fetch: "Letzter Abruf von XContest: {time}",
+    version_no_fetch: "XContest wurde seit dem Start noch nicht erfolgreich abgerufen.",
+    version_feed: "Feed: {url}",
+    version_throttled: "⚠️ XContest drosselt die Anfragen, nächster Versuch um {time}.",
+    did_you_mean: "Meintest du *{command}*?",
+    feature_disabled: "Diese Funktion ist bei diesem Bot leider deaktiviert.",
+    read_only: "Der Bot ist im Moment im Wartungsmodus, Änderungen sind leider nicht \
+        möglich. Bitte versuche es später nochmals.",
+    unsupported_media: "Ich verstehe leider nur Textbefehle, keine {kind}. 🙈 \
+        Sende \"hilfe\", um die verfügbaren Befehle anzuzeigen.",
+    media_image: "Bilder",
+    media_video: "Videos",
+    media_voice: "Sprachnachrichten",
+    media_file: "Dateien",
+    media_location: "Standorte",
+    notification_cap_reached: "Du hast diesen Monat bereits {count} Benachrichtigungen \
+        erhalten, damit ist das monatliche Limit erreicht. 🙏\n\n\
+        Ab nächstem Monat wirst du wieder über neue Flüge benachrichtigt.",
+    landing_description: "Mit diesem Threema-Bot kannst du Piloten auf XContest folgen. \
+        Du kriegst dann eine sofortige Benachrichtigung, wenn diese einen neuen Flug hochladen.",
+    landing_link: "Bot in Threema öffnen",
+    landing_stats: "{users} Benutzer · {flights} erfasste Flüge",
+};
+
+const ENGLISH: Messages = Messages {
+    help: "Hi {nickname}! 👋\n\n\
+        With this bot you can follow pilots on XContest. You will be notified immediately when they upload a new flight. 🪂\n\n\
+        Available commands:\n\n\
+        - *follow _<username>_ _[duration] [#label]_*: Get notified when the pilot _<username>_ uploads a new flight. You can use the XContest username or the full name of the pilot.\n\
+        - *stop _<username>_*: Stop getting notified when the pilot _<username>_ uploads a new flight. You need to use the XContest username. With _#label_ you unfollow all pilots with this label.\n\
+        - *move _<old>_ _<new>_*: Move your subscription to the new username of a pilot.\n\
+        - *list _[new] [#label] [page]_*: Show the list of pilots you are following (with \"new\" the most recently added first).\n\
+        - *digest on/off*: Get a daily digest instead of immediate notifications.\n\
+        - *leaderboard*: Show the monthly leaderboard of the pilots you are following.\n\
+        - *club _<code>_*: Receive the monthly kilometre leaderboard of a club.\n\
+        - *github*: Show the link to the source code of this bot.",
+    follow_usage: "To follow a pilot, send \"follow _<username>_\" \
+        (example: \"follow chrigel\" or \"follow Christian Maurer\"). \
+        You can use the XContest username or the full name of the pilot. \
+        Add a label to group pilots (example: \"follow chrigel #team\") \
+        or a duration to follow temporarily (example: \"follow chrigel 2w\" for two weeks). \
+        Send \"follow comp _<code>_\" to follow all pilots of a competition.",
+    follow_name_not_found: "⚠️ I don't know any pilot named {name}. \
+        Try the XContest username instead.",
+    follow_choose_pilot: "There are several pilots named {name}. \
+        Reply with the number of the pilot you want to follow:",
+    follow_invalid_choice: "Please reply with a number between 1 and {count}.",
+    follow_success: "You are now following {pilot}!",
+    follow_label_success: "You are now following {pilot} with the label #{label}!",
+    follow_expires: "⏱️ The subscription ends on {date}.",
+    follow_duration_invalid: "⚠️ A temporary subscription lasts 1 to 365 days \
+        (example: \"follow chrigel 2w\" for two weeks, \"follow chrigel 3d\" for three days).",
+    follow_expired: "⏱️ Your temporary subscription ended, you are no longer following {pilots}. \
+        Send \"follow _<username>_\" to follow again.",
+    comp_usage: "To temporarily follow all pilots of a competition, send \"follow comp _<code>_\" \
+        or a list of usernames (example: \"follow comp chrigel, dbrgn, pilot 3d\").",
+    comp_list: "Known competitions: {codes}",
+    comp_unknown: "⚠️ I don't know any competition {code}.",
+    comp_over: "⚠️ The competition {name} is already over.",
+    comp_too_many: "⚠️ You can follow at most {max} pilots at once.",
+    comp_success: "You are now following {count} pilots with the label #{label}. \
+        Send \"stop #{label}\" to unfollow them.",
+    comp_skipped: "You were already following {count} pilots, these subscriptions are unchanged.",
+    label_invalid: "⚠️ A label starts with # and consists of at most 32 letters, \
+        digits, - or _ (example: #team).",
+    label_not_found: "You are not following any pilots with the label #{label}.",
+    unfollow_usage: "To unfollow a pilot, send \"stop _<username>_\" \
+        (example: \"stop chrigel\"). \
+        You need to use the XContest username. \
+        Send \"stop #team\" to unfollow all pilots with the label #team.",
+    unfollow_success: "You are no longer following {pilot}.",
+    unfollow_not_following: "You are not following {pilot}.",
+    unfollow_label_success: "You are no longer following the {count} pilots with the label #{label}.",
+    move_usage: "To move your subscription of a pilot to another username, \
+        send \"move _<old username>_ _<new username>_\" \
+        (example: \"move chrigel chrigel2\").",
+    move_success: "You are now following {new} instead of {old}.",
+    rename_detected: "ℹ️ {name} may have changed their username: \
+        A flight was uploaded with the new username {new}.\n\n\
+        Send \"move {old} {new}\" to follow {new} instead of {old}.",
+    list_usage: "Send \"list\" to show the pilots you are following \
+        (\"list 2\" for the second page, \"list new\" for the most recently added first, \
+        \"list #team\" for the pilots with the label #team).",
+    list_empty: "You are not following any pilots yet.",
+    list_header: "You are following these pilots:",
+    list_header_newest: "You are following these pilots (most recently added first):",
+    list_header_label: "You are following these pilots with the label #{label}:",
+    list_expires: "(until {date})",
+    list_entry_since: "{pilot} (since {date})",
+    list_total: "Total: {count}",
+    list_page: "Page {page} of {pages}",
+    list_next_page: "Send \"{command}\" for the next page.",
+    list_command: "list",
+    list_sort_newest: "new",
+    digest_usage: "Send \"digest on\" to get a daily digest of the new flights instead of \
+        immediate notifications, or \"digest off\" to be notified immediately again.",
+    digest_status: "The daily digest is {status}.\n\n\
+        Send \"digest on\" to get a daily digest of the new flights instead of \
+        immediate notifications, or \"digest off\" to be notified immediately again.",
+    digest_status_enabled: "enabled",
+    digest_status_disabled: "disabled",
+    digest_enabled: "From now on, you will get a daily digest of the new flights.",
+    digest_disabled: "From now on, you will be notified about new flights immediately again.",
+    digest_header: "*New flights* 🪂",
+    digest_more_flights: "… and {count} more flights",
+    digest_most_liked: "*Most liked flight of the week* ({count} 👍)",
+    group_header: "*{count} new flights by {pilot}* 🪂",
+    first_flight_of_season: "🎉 First flight of the season!",
+    simulated_flight: "🧪 Test message: This is not a real flight.",
+    flight_corrected: "✏️ Corrected:",
+    flight_airtime: "{hours} h {minutes} min airborne",
+    triangle_closing: "{percent}% closed",
+    triangle_speed: "avg. {speed} km/h",
+    leaderboard_usage: "Send \"leaderboard\" to show the monthly leaderboard of the pilots \
+        you are following.",
+    leaderboard_empty: "The pilots you are following haven't uploaded any flights this \
+        month yet. (The leaderboard is updated once a day.)",
+    leaderboard_header: "*Leaderboard this month* 🏆",
+    leaderboard_entry: "{rank}. {pilot}: {distance} km ({flights}, max. {max} km)",
+    leaderboard_footer: "(The leaderboard is updated once a day.)",
+    club_usage: "Send \"club _<code>_\" to subscribe to the monthly kilometre leaderboard \
+        of a club, and \"club _<code>_ stop\" to unsubscribe.",
+    club_none: "There are no clubs on this bot.",
+    club_list: "Clubs: {codes}",
+    club_subscriptions: "Your subscriptions: {codes}",
+    club_unknown: "⚠️ I don't know any club {code}.",
+    club_subscribed: "You will now receive the leaderboard of {name} at the beginning of \
+        every month. Send \"club {code} stop\" to unsubscribe.",
+    club_unsubscribed: "You will no longer receive the leaderboard of {name}.",
+    club_not_subscribed: "You are not subscribed to the leaderboard of {name}.",
+    club_leaderboard_header: "*Leaderboard {name} {month}* 🏆",
+    club_leaderboard_total: "Total: {distance} km ({flights}, {pilots} pilots)",
+    club_leaderboard_empty: "The members of {name} didn't upload any flights in {month}.",
+    club_new_member: "👋 New member of {name} spotted: {pilot} uploaded their first flight.\n{url}",
+    flights_one: "{count} flight",
+    flights_other: "{count} flights",
+    github_usage: "Send \"github\" to show the link to the source code of this bot.",
+    github: "This bot is open source (AGPLv3). \
+        You can find the source code here: https://github.com/dbrgn/xc-bot/",
+    version_uptime: "Uptime: {uptime}",
+    version_last_fetch: "Last XContest fetch: {time}",
+    version_no_fetch: "XContest has not been fetched successfully since the start.",
+    version_feed: "Feed: {url}",
+    version_throttled: "⚠️ XContest is throttling requests, next attempt at {time}.",
+    did_you_mean: "Did you mean *{command}*?",
+    feature_disabled: "Sorry, this feature is disabled on this bot.",
+    read_only: "Sorry, the bot is in maintenance mode, changes are not possible at \
+        the moment. Please try again later.",
+    unsupported_media: "Sorry, I only understand text commands, no {kind}. 🙈 \
+        Send \"help\" to show the available commands.",
+    media_image: "images",
+    media_video: "videos",
+    media_voice: "voice messages",
+    media_file: "files",
+    media_location: "locations",
+    notification_cap_reached: "You have already received {count} notifications this month, \
+        which is the monthly limit. 🙏\n\n\
+        You will be notified about new flights again next month.",
+    landing_description: "With this Threema bot you can follow pilots on XContest. \
+        You will be notified immediately when they upload a new flight.",
+    landing_link: "Open the bot in Threema",
+    landing_stats: "{users} users · {flights} flights tracked",
+};
+
+impl Messages {
+    /// Return a copy of the texts with the texts of an override file (TOML,
+    /// with the field names as keys) replaced.
+    fn with_overrides(&self, overrides: &str) -> Result<Messages> {
+        let table: toml::Table = toml::from_str(overrides).context("Invalid TOML")?;
+        let mut messages = self.clone();
+        for (name, value) in table {
+            let text = match value {
+                toml::Value::String(text) => text,
+                _ => bail!("Text {} is not a string", name),
+            };
+            // The texts are loaded once at startup and live until the end
+            if !messages.set(&name, Box::leak(text.into_boxed_str())) {
+                bail!("Unknown text: {}", name);
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Load an override file and return the texts with the overridden texts
+    /// replaced.
+    pub fn load_overrides(&self, path: &Path) -> Result<&'static Messages> {
+        let overrides = std::fs::read_to_string(path)
+            .context(format!("Could not read message overrides {:?}", path))?;
+        let messages = self
+            .with_overrides(&overrides)
+            .context(format!("Invalid message overrides {:?}", path))?;
+        Ok(Box::leak(Box::new(messages)))
+    }
+}
+
+/// Replace the `{name}` placeholders in a message with the specified values.
+pub fn fill(message: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(message.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_placeholders() {
+        assert_eq!(
+            fill(
+                GERMAN.leaderboard_entry,
+                &[
+                    ("rank", "1"),
+                    ("pilot", "dbrgn"),
+                    ("distance", "52.0"),
+                    ("flights", "2 Flüge"),
+                    ("max", "30.0")
+                ]
+            ),
+            "1. dbrgn: 52.0 km (2 Flüge, max. 30.0 km)"
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let messages = GERMAN
+            .with_overrides(
+                r#"
+                follow_success = "Du folgst jetzt {pilot}! 🪂"
+                digest_header = "*Neui Flüüg*"
+                "#,
+            )
+            .unwrap();
+        assert_eq!(messages.follow_success, "Du folgst jetzt {pilot}! 🪂");
+        assert_eq!(messages.digest_header, "*Neui Flüüg*");
+        assert_eq!(messages.unfollow_success, GERMAN.unfollow_success);
+
+        assert!(GERMAN.with_overrides("follow_sucess = \"typo\"").is_err());
+        assert!(GERMAN.with_overrides("list_page = 3").is_err());
+    }
+}
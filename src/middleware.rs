@@ -0,0 +1,418 @@
+//! Middleware around command handling.
+//!
+//! Concerns that apply to all commands (audit logging, metrics, ...) are
+//! implemented as middleware instead of in every command handler. A middleware
+//! sees every command before it's handled (and may answer it instead of the
+//! handler) and sees the reply afterwards.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    commands::OutgoingReply,
+    messages::{Language, Messages},
+    status::BotStatus,
+};
+
+/// A command that is about to be handled.
+pub struct CommandInfo<'a> {
+    /// The canonical name of the command (e.g. `follow` for `folge`), or
+    /// `unknown` for unknown commands
+    pub name: &'static str,
+    /// The tenant receiving the command
+    pub tenant: &'a str,
+    /// The identity of the sender
+    pub sender: &'a str,
+    /// Whether the sender is the admin of the bot
+    pub is_admin: bool,
+}
+
+/// Hooks run around every command.
+pub trait Middleware: Send + Sync {
+    /// Called before the command is handled. If a reply is returned, the
+    /// command handler (and the remaining middleware) are skipped.
+    fn before(&self, _command: &CommandInfo<'_>) -> Option<OutgoingReply> {
+        None
+    }
+
+    /// Called with the reply after the command was handled.
+    fn after(&self, _command: &CommandInfo<'_>, _reply: &OutgoingReply) {}
+}
+
+/// The middleware run around every command, in order.
+#[derive(Default)]
+pub struct Chain {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a middleware to the end of the chain.
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Run the command handler wrapped in the middleware.
+    ///
+    /// The `after` hooks are run in reverse order, and only for the middleware
+    /// whose `before` hook was run.
+    pub async fn run(
+        &self,
+        command: &CommandInfo<'_>,
+        handler: impl Future<Output = OutgoingReply>,
+    ) -> OutgoingReply {
+        let mut reply = None;
+        let mut entered = 0;
+        for middleware in &self.middleware {
+            entered += 1;
+            reply = middleware.before(command);
+            if reply.is_some() {
+                break;
+            }
+        }
+        let reply = match reply {
+            Some(reply) => reply,
+            None => handler.await,
+        };
+        for middleware in self.middleware[..entered].iter().rev() {
+            middleware.after(command, &reply);
+        }
+        reply
+    }
+}
+
+/// Log all admin commands (with target `audit`).
+pub struct AuditLog;
+
+impl Middleware for AuditLog {
+    fn after(&self, command: &CommandInfo<'_>, reply: &OutgoingReply) {
+        if command.is_admin {
+            tracing::info!(
+                target: "audit",
+                "Admin {} ran command {} in tenant {} ({})",
+                command.sender,
+                command.name,
+                command.tenant,
+                match reply {
+                    OutgoingReply::Error => "failed",
+                    _ => "ok",
+                }
+            );
+        }
+    }
+}
+
+/// Count the handled commands (shown in the admin stats).
+pub struct Metrics {
+    status: Arc<BotStatus>,
+}
+
+impl Metrics {
+    pub fn new(status: Arc<BotStatus>) -> Self {
+        Self { status }
+    }
+}
+
+impl Middleware for Metrics {
+    fn after(&self, command: &CommandInfo<'_>, reply: &OutgoingReply) {
+        self.status
+            .record_command(command.name, matches!(reply, OutgoingReply::Error));
+    }
+}
+
+/// Default minimum time between two help texts to the same sender
+pub const DEFAULT_HELP_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Default maximum number of replies to the same sender per hour
+pub const DEFAULT_MAX_REPLIES_PER_HOUR: usize = 60;
+
+/// Number of tracked senders above which inactive senders are forgotten
+const MAX_TRACKED_SENDERS: usize = 1000;
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// The recent replies to a sender.
+#[derive(Debug, Default)]
+struct SenderReplies {
+    /// When the help text (the reply to an unknown command) was last sent
+    last_help: Option<Instant>,
+    /// When the replies of the last hour were sent
+    replies: VecDeque<Instant>,
+}
+
+/// Throttle the replies to a sender, so that a looping client can't drain
+/// the gateway credits: The reply to unknown commands (the help text) is
+/// sent at most once per cooldown, and the number of replies per hour is
+/// capped. Throttled commands are handled without a reply. The admin is
+/// exempt.
+pub struct ReplyThrottle {
+    help_cooldown: Duration,
+    max_per_hour: usize,
+    senders: Mutex<HashMap<(String, String), SenderReplies>>,
+}
+
+impl ReplyThrottle {
+    pub fn new(help_cooldown: Duration, max_per_hour: usize) -> Self {
+        Self {
+            help_cooldown,
+            max_per_hour,
+            senders: Mutex::default(),
+        }
+    }
+
+    /// Return whether the reply to the command must be suppressed.
+    fn is_throttled(&self, command: &CommandInfo<'_>, now: Instant) -> bool {
+        let mut senders = self.senders.lock().unwrap();
+        let sender =
+            match senders.get_mut(&(command.tenant.to_string(), command.sender.to_string())) {
+                Some(sender) => sender,
+                None => return false,
+            };
+        while sender
+            .replies
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= HOUR)
+        {
+            sender.replies.pop_front();
+        }
+        if sender.replies.len() >= self.max_per_hour {
+            tracing::debug!("Reply limit of {} reached, not replying", command.sender);
+            return true;
+        }
+        if command.name == "unknown"
+            && sender
+                .last_help
+                .is_some_and(|sent| now.duration_since(sent) < self.help_cooldown)
+        {
+            tracing::debug!("Help cooldown of {} active, not replying", command.sender);
+            return true;
+        }
+        false
+    }
+
+    /// Record a reply sent to the sender of the command.
+    fn record_reply(&self, command: &CommandInfo<'_>, now: Instant) {
+        let mut senders = self.senders.lock().unwrap();
+        if senders.len() >= MAX_TRACKED_SENDERS {
+            senders.retain(|_, sender| {
+                sender
+                    .replies
+                    .back()
+                    .is_some_and(|sent| now.duration_since(*sent) < HOUR)
+            });
+        }
+        let sender = senders
+            .entry((command.tenant.to_string(), command.sender.to_string()))
+            .or_default();
+        sender.replies.push_back(now);
+        if command.name == "unknown" {
+            sender.last_help = Some(now);
+        }
+        if sender.replies.len() == self.max_per_hour {
+            tracing::warn!(
+                "Sender {} of tenant {} reached the limit of {} replies per hour",
+                command.sender,
+                command.tenant,
+                self.max_per_hour
+            );
+        }
+    }
+}
+
+impl Middleware for ReplyThrottle {
+    fn before(&self, command: &CommandInfo<'_>) -> Option<OutgoingReply> {
+        if !command.is_admin && self.is_throttled(command, Instant::now()) {
+            return Some(OutgoingReply::Nothing);
+        }
+        None
+    }
+
+    fn after(&self, command: &CommandInfo<'_>, reply: &OutgoingReply) {
+        if !command.is_admin && matches!(reply, OutgoingReply::Text(_)) {
+            self.record_reply(command, Instant::now());
+        }
+    }
+}
+
+/// The commands that change the stored state (e.g. subscriptions), refused in
+/// read-only mode
+const STATE_CHANGING_COMMANDS: &[&str] = &[
+    "follow", "stop", "move", "digest", "start", "choice", "token", "survey", "retry", "prune",
+    "simulate",
+];
+
+/// Refuse the commands that change the stored state, for a bot running in
+/// read-only mode (e.g. a standby against a restored backup).
+pub struct ReadOnly {
+    /// The texts of every tenant
+    messages: HashMap<String, &'static Messages>,
+}
+
+impl ReadOnly {
+    pub fn new(messages: impl IntoIterator<Item = (String, &'static Messages)>) -> Self {
+        Self {
+            messages: messages.into_iter().collect(),
+        }
+    }
+}
+
+impl Middleware for ReadOnly {
+    fn before(&self, command: &CommandInfo<'_>) -> Option<OutgoingReply> {
+        if !STATE_CHANGING_COMMANDS.contains(&command.name) {
+            return None;
+        }
+        tracing::info!(
+            "Read-only mode, refusing command {} of {}",
+            command.name,
+            command.sender
+        );
+        let messages = self
+            .messages
+            .get(command.tenant)
+            .copied()
+            .unwrap_or_else(|| Language::default().messages());
+        Some(OutgoingReply::Text(messages.read_only.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the hook calls and optionally answers all commands.
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+        reply: Option<&'static str>,
+    }
+
+    impl Middleware for Recorder {
+        fn before(&self, _command: &CommandInfo<'_>) -> Option<OutgoingReply> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("before {}", self.name));
+            self.reply.map(|text| OutgoingReply::Text(text.into()))
+        }
+
+        fn after(&self, _command: &CommandInfo<'_>, _reply: &OutgoingReply) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("after {}", self.name));
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_order() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorder = |name, reply| Recorder {
+            name,
+            calls: calls.clone(),
+            reply,
+        };
+        let status = Arc::new(BotStatus::default());
+        let command = CommandInfo {
+            name: "follow",
+            tenant: "default",
+            sender: "ECHOECHO",
+            is_admin: false,
+        };
+
+        // All hooks are run around the handler
+        let chain = Chain::new()
+            .with(recorder("a", None))
+            .with(recorder("b", None))
+            .with(Metrics::new(status.clone()));
+        let reply = chain
+            .run(&command, async { OutgoingReply::Text("handled".into()) })
+            .await;
+        assert!(matches!(reply, OutgoingReply::Text(text) if text == "handled"));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["before a", "before b", "after b", "after a"]
+        );
+        assert_eq!(status.command_counts(), [("follow".to_string(), 1, 0)]);
+
+        // A middleware can answer the command instead of the handler
+        calls.lock().unwrap().clear();
+        let chain = Chain::new()
+            .with(recorder("a", Some("blocked")))
+            .with(recorder("b", None));
+        let reply = chain
+            .run(&command, async { panic!("Handler must not run") })
+            .await;
+        assert!(matches!(reply, OutgoingReply::Text(text) if text == "blocked"));
+        assert_eq!(*calls.lock().unwrap(), ["before a", "after a"]);
+    }
+
+    #[test]
+    fn reply_throttle() {
+        let throttle = ReplyThrottle::new(Duration::from_secs(300), 3);
+        let command = |name| CommandInfo {
+            name,
+            tenant: "default",
+            sender: "ECHOECHO",
+            is_admin: false,
+        };
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        // The help text is sent at most once per cooldown
+        assert!(!throttle.is_throttled(&command("unknown"), at(0)));
+        throttle.record_reply(&command("unknown"), at(0));
+        assert!(throttle.is_throttled(&command("unknown"), at(299)));
+        assert!(!throttle.is_throttled(&command("list"), at(299)));
+        assert!(!throttle.is_throttled(&command("unknown"), at(300)));
+
+        // At most three replies per hour
+        throttle.record_reply(&command("list"), at(1000));
+        throttle.record_reply(&command("list"), at(2000));
+        assert!(throttle.is_throttled(&command("list"), at(3599)));
+        assert!(!throttle.is_throttled(&command("list"), at(3600)));
+
+        // Other senders are not affected
+        let other = CommandInfo {
+            sender: "OTHEROTH",
+            ..command("list")
+        };
+        assert!(!throttle.is_throttled(&other, at(3599)));
+    }
+
+    #[test]
+    fn read_only() {
+        let read_only = ReadOnly::new([("en".to_string(), Language::English.messages())]);
+        let command = |name, tenant| CommandInfo {
+            name,
+            tenant,
+            sender: "ECHOECHO",
+            is_admin: false,
+        };
+
+        // State-changing commands are refused in the language of the tenant
+        match read_only.before(&command("follow", "en")) {
+            Some(OutgoingReply::Text(text)) => {
+                assert_eq!(text, Language::English.messages().read_only)
+            }
+            _ => panic!("follow was not refused"),
+        }
+        match read_only.before(&command("stop", "default")) {
+            Some(OutgoingReply::Text(text)) => {
+                assert_eq!(text, Language::German.messages().read_only)
+            }
+            _ => panic!("stop was not refused"),
+        }
+
+        // Other commands are handled
+        assert!(read_only.before(&command("list", "en")).is_none());
+        assert!(read_only.before(&command("stats", "en")).is_none());
+    }
+}
@@ -0,0 +1,59 @@
+//! The `migrate` command: Inspect and apply the embedded database migrations
+//! without starting the bot.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{cli::MigrateAction, config::Config, db};
+
+/// Run the specified migration action against the configured database.
+pub async fn run(configfile: &Path, action: MigrateAction) -> Result<()> {
+    let config = Config::load_or_env(configfile)
+        .map_err(|e| anyhow::anyhow!("Could not load config file {:?}: {}", configfile, e))?;
+    let pool = db::connect(config.database_path(), &config.pool_settings()).await?;
+
+    match action {
+        MigrateAction::Status => {
+            let migrations = db::get_migration_status(&pool).await?;
+            for migration in &migrations {
+                println!(
+                    "[{}] {} {}{}",
+                    if migration.applied {
+                        "applied"
+                    } else {
+                        "pending"
+                    },
+                    migration.version,
+                    migration.description,
+                    if migration.reversible {
+                        " (reversible)"
+                    } else {
+                        ""
+                    },
+                );
+            }
+            let pending = migrations.iter().filter(|m| !m.applied).count();
+            println!("\n{} migration(s) pending.", pending);
+        }
+        MigrateAction::Run => {
+            let pending = db::get_migration_status(&pool)
+                .await?
+                .into_iter()
+                .filter(|m| !m.applied)
+                .count();
+            db::migrate(&pool).await?;
+            println!("Applied {} migration(s).", pending);
+        }
+        MigrateAction::Revert => match db::revert_last_migration(&pool).await? {
+            Some(migration) => println!(
+                "Reverted migration {} {}.",
+                migration.version, migration.description
+            ),
+            None => println!("No migrations applied, nothing to revert."),
+        },
+    }
+
+    pool.close().await;
+    Ok(())
+}
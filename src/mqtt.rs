@@ -0,0 +1,153 @@
+//! Publishing of new flights to an MQTT broker.
+//!
+//! Every new flight is published as retained JSON message to the topic of the
+//! pilot, so that home automation and dashboards get the latest flight of a
+//! pilot as soon as they subscribe, without polling the API.
+
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, Transport};
+use serde_json::json;
+use xcontest_client::Flight;
+
+use crate::{config::MqttConfig, messages};
+
+/// Topic the flights are published to, if not configured.
+const DEFAULT_TOPIC: &str = "xcbot/flights/{pilot}";
+
+/// Number of messages buffered while the broker can't be reached.
+const QUEUE_CAPACITY: usize = 100;
+
+/// Delay before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Publishes new flights to the MQTT broker.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic: String,
+}
+
+impl MqttPublisher {
+    /// Connect to the broker in a background task, which reconnects if the
+    /// connection is lost.
+    pub fn spawn(config: &MqttConfig) -> Self {
+        let tls = config.tls.unwrap_or(false);
+        let port = config.port.unwrap_or(if tls { 8883 } else { 1883 });
+        let client_id = config.client_id.as_deref().unwrap_or("xc-bot");
+        let mut options = MqttOptions::new(client_id, &config.host, port);
+        options.set_keep_alive(Duration::from_secs(60));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password);
+        }
+        if tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+
+        // The event loop sends the published messages, connection errors are
+        // only logged once until the connection is back
+        let broker = format!("{}:{}", config.host, port);
+        tokio::spawn(async move {
+            let mut failing = false;
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        tracing::info!("Connected to MQTT broker {}", broker);
+                        failing = false;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if !failing {
+                            tracing::warn!("Connection to MQTT broker {} failed: {}", broker, e);
+                            failing = true;
+                        }
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+
+        Self {
+            client,
+            topic: config
+                .topic
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TOPIC.to_string()),
+        }
+    }
+
+    /// Publish a new flight of the tenant. If the broker can't be reached for
+    /// long (and the buffer is full), the flight is dropped.
+    pub fn publish(&self, tenant: &str, flight: &Flight) {
+        let topic = build_topic(&self.topic, tenant, &flight.pilot_username);
+        let payload = build_payload(tenant, flight).to_string();
+        match self
+            .client
+            .try_publish(&topic, QoS::AtLeastOnce, true, payload)
+        {
+            Ok(()) => tracing::debug!("Published flight {} to {}", flight.url, topic),
+            Err(e) => tracing::warn!("Could not publish flight {} to MQTT: {}", flight.url, e),
+        }
+    }
+}
+
+/// Fill in the topic pattern. Characters with a special meaning in topics
+/// (levels and wildcards) are replaced in the values.
+fn build_topic(pattern: &str, tenant: &str, pilot: &str) -> String {
+    let escape = |value: &str| value.replace(['/', '+', '#'], "_");
+    messages::fill(
+        pattern,
+        &[("tenant", &escape(tenant)), ("pilot", &escape(pilot))],
+    )
+}
+
+/// Build the JSON message of the flight.
+fn build_payload(tenant: &str, flight: &Flight) -> serde_json::Value {
+    let parsed = flight.parsed_title.as_ref();
+    json!({
+        "tenant": tenant,
+        "url": flight.url,
+        "title": flight.title,
+        "pilot_username": flight.pilot_username,
+        "pilot_name": parsed.map(|parsed| &parsed.pilot_name),
+        "date": parsed.and_then(|parsed| parsed.date).map(|date| date.to_string()),
+        "distance_km": parsed.and_then(|parsed| parsed.distance_km),
+        "flight_type": parsed
+            .and_then(|parsed| parsed.flight_type.as_ref())
+            .map(|flight_type| flight_type.to_string()),
+        "contest": flight.contest,
+        "published": flight.published.map(|published| published.to_rfc3339()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_and_payload() {
+        assert_eq!(
+            build_topic(DEFAULT_TOPIC, "default", "dbrgn"),
+            "xcbot/flights/dbrgn"
+        );
+        assert_eq!(
+            build_topic("{tenant}/{pilot}", "club/a", "a+b#"),
+            "club_a/a_b_"
+        );
+
+        let flight = Flight::new(
+            "09.08.20 [21.98 km :: free_flight] Danilo Bargen".to_string(),
+            "https://www.xcontest.org/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+                .to_string(),
+        )
+        .unwrap();
+        let payload = build_payload("default", &flight);
+        assert_eq!(payload["pilot_username"], "dbrgn");
+        assert_eq!(payload["pilot_name"], "Danilo Bargen");
+        assert_eq!(payload["date"], "2020-08-09");
+        assert_eq!(payload["distance_km"], 21.98);
+        assert_eq!(payload["contest"], "switzerland");
+        assert!(payload["published"].is_null());
+    }
+}
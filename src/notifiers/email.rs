@@ -0,0 +1,345 @@
+//! E-mail notification channel (SMTP).
+//!
+//! Every notification is a multipart mail: A plain text part, and an HTML part
+//! with the pilot name emphasized, the flight linked and the preview image
+//! embedded inline.
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use lettre::{
+    message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use xcontest_client::{Flight, FlightDetails};
+
+use super::{format, Channel, Error};
+use crate::{
+    config::{SmtpConfig, TenantConfig},
+    db::User,
+    messages::{self, Messages},
+};
+
+/// Content ID of the embedded preview image.
+const PREVIEW_CID: &str = "preview";
+
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    messages: &'static Messages,
+    /// Whether flight notifications are marked as test messages
+    simulated: bool,
+}
+
+impl EmailNotifier {
+    pub fn new(tenant: &TenantConfig, config: &SmtpConfig) -> Result<Self> {
+        let builder = match config.encryption.as_deref() {
+            None | Some("starttls") => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                    .context("Could not create SMTP transport")?
+            }
+            Some("tls") => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+                .context("Could not create SMTP transport")?,
+            Some("none") => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            Some(other) => bail!("Unknown SMTP encryption: {}", other),
+        };
+        let builder = match config.port {
+            Some(port) => builder.port(port),
+            None => builder,
+        };
+        let builder = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            (Some(_), None) => bail!("The SMTP login requires a password"),
+            _ => builder,
+        };
+        Ok(Self {
+            transport: builder.build(),
+            from: config
+                .from
+                .parse()
+                .context(format!("Invalid SMTP sender: {}", config.from))?,
+            messages: tenant.messages(),
+            simulated: false,
+        })
+    }
+
+    async fn send(&self, message: Message) -> Result<(), Error> {
+        let response = self
+            .transport
+            .send(message)
+            .await
+            .map_err(|source| Error::Smtp {
+                context: "Could not send e-mail",
+                source,
+            })?;
+        tracing::debug!("E-mail sent: {:?}", response.first_line());
+        Ok(())
+    }
+}
+
+impl Channel for EmailNotifier {
+    fn usertype() -> &'static str {
+        "email"
+    }
+
+    fn section() -> &'static str {
+        "smtp"
+    }
+
+    fn validate(
+        address: &str,
+        _setting: Option<&str>,
+    ) -> Result<(String, Option<String>), &'static str> {
+        let address = address.trim().to_lowercase();
+        if address.parse::<lettre::Address>().is_err() {
+            return Err("invalid address");
+        }
+        Ok((address, None))
+    }
+
+    fn set_simulated(&mut self) {
+        self.simulated = true;
+    }
+
+    /// Notify the specified e-mail user about the flight.
+    fn notify<'a>(
+        &'a self,
+        flight: &'a Flight,
+        details: Option<&'a FlightDetails>,
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let marker = if self.simulated {
+                Some(self.messages.simulated_flight)
+            } else if first_of_season {
+                Some(self.messages.first_flight_of_season)
+            } else {
+                None
+            };
+            let flight = match details {
+                Some(details) => flight.clone().with_details(details),
+                None => flight.clone(),
+            };
+            let mut text = format::format_flight(&flight, self.messages, format::MAX_TEXT_CHARS);
+            let mut plain =
+                format::format_flight_plain(&flight, self.messages, format::MAX_TEXT_CHARS);
+            if let Some(marker) = marker {
+                text = format!("{}\n{}", marker, text);
+                plain = format!("{}\n{}", marker, plain);
+            }
+            let message = build_message(
+                self.from.clone(),
+                user,
+                &flight.title,
+                (&text, &plain),
+                details,
+            )?;
+            self.send(message).await
+        })
+    }
+
+    /// Notify the specified e-mail user about several flights of one pilot.
+    fn notify_group<'a>(
+        &'a self,
+        pilot: &'a str,
+        flights: &'a [Flight],
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let count = flights.len().to_string();
+            let values = [("count", count.as_str()), ("pilot", pilot)];
+            let mut header = messages::fill(self.messages.group_header, &values);
+            let subject =
+                messages::fill(&format::plain_template(self.messages.group_header), &values);
+            let mut plain_header = subject.clone();
+            if first_of_season {
+                for header in [&mut header, &mut plain_header] {
+                    header.push('\n');
+                    header.push_str(self.messages.first_flight_of_season);
+                }
+            }
+            let text =
+                format::format_flights(&header, flights, self.messages, format::MAX_TEXT_CHARS);
+            let plain = format::format_flights_plain(
+                &plain_header,
+                flights,
+                self.messages,
+                format::MAX_TEXT_CHARS,
+            );
+            let message = build_message(self.from.clone(), user, &subject, (&text, &plain), None)?;
+            self.send(message).await
+        })
+    }
+
+    /// Notify the specified e-mail user that the title of the flight changed.
+    fn notify_correction<'a>(
+        &'a self,
+        flight: &'a Flight,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let text = format!(
+                "{}\n{}",
+                self.messages.flight_corrected,
+                format::format_flight(flight, self.messages, format::MAX_TEXT_CHARS)
+            );
+            let plain = format!(
+                "{}\n{}",
+                self.messages.flight_corrected,
+                format::format_flight_plain(flight, self.messages, format::MAX_TEXT_CHARS)
+            );
+            let subject = format!("{} {}", self.messages.flight_corrected, flight.title);
+            let message = build_message(self.from.clone(), user, &subject, (&text, &plain), None)?;
+            self.send(message).await
+        })
+    }
+}
+
+/// Build the e-mail for the notification text, given using Threema markdown
+/// and as plain text: A plain text part and an HTML part, with the preview
+/// image embedded if available.
+fn build_message(
+    from: Mailbox,
+    user: &User,
+    subject: &str,
+    (text, plain): (&str, &str),
+    details: Option<&FlightDetails>,
+) -> Result<Message, Error> {
+    let to: Mailbox = user
+        .username
+        .parse()
+        .map_err(|e| Error::message("Invalid e-mail address", e))?;
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><body style=\"font-family: sans-serif\">\n{}\n",
+        to_html(text)
+    );
+    if details.is_some() {
+        html.push_str(&format!(
+            "<p><img src=\"cid:{}\" alt=\"\"></p>\n",
+            PREVIEW_CID
+        ));
+    }
+    html.push_str("</body></html>\n");
+    let mut related = MultiPart::related().singlepart(SinglePart::html(html));
+    if let Some(details) = details {
+        related = related.singlepart(Attachment::new_inline(PREVIEW_CID.to_string()).body(
+            details.thumbnail_small.to_vec(),
+            ContentType::parse("image/jpeg").unwrap(),
+        ));
+    }
+    Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(SinglePart::plain(plain.to_string()))
+                .multipart(related),
+        )
+        .map_err(|e| Error::message("Could not build e-mail", e))
+}
+
+/// Convert a notification text to HTML paragraphs: Bold lines (`*...*`) are
+/// emphasized and URLs are linked.
+fn to_html(text: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| {
+            let lines: Vec<String> = paragraph
+                .lines()
+                .map(|line| {
+                    let escaped = escape_html(line);
+                    if line.len() > 2 && line.starts_with('*') && line.ends_with('*') {
+                        format!("<strong>{}</strong>", &escaped[1..escaped.len() - 1])
+                    } else if line.starts_with("https://") {
+                        format!("<a href=\"{0}\">{0}</a>", escaped)
+                    } else {
+                        escaped
+                    }
+                })
+                .collect();
+            format!("<p>{}</p>", lines.join("<br>\n"))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Escape text for HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use xcontest_client::{FlightTimes, PreviewFormat, TriangleStats};
+
+    use super::*;
+
+    #[test]
+    fn html_text() {
+        assert_eq!(
+            to_html("*Danilo <Bargen>*\n21.98 km\nhttps://example.com/?a&b\n\n3 weitere Flüge"),
+            "<p><strong>Danilo &lt;Bargen&gt;</strong><br>\n\
+             21.98 km<br>\n\
+             <a href=\"https://example.com/?a&amp;b\">https://example.com/?a&amp;b</a></p>\n\
+             <p>3 weitere Flüge</p>"
+        );
+    }
+
+    #[test]
+    fn message_with_preview() {
+        let user = User {
+            id: 1,
+            tenant: "default".into(),
+            username: "pilot@example.com".into(),
+            usertype: "email".into(),
+            threema_public_key: None,
+        };
+        let details = FlightDetails {
+            thumbnail_large: Bytes::from_static(b"png"),
+            thumbnail_small: Bytes::from_static(b"jpeg"),
+            format: PreviewFormat::Png,
+            animated: false,
+            times: FlightTimes::default(),
+            triangle: TriangleStats::default(),
+        };
+        let message = build_message(
+            "XC Bot <xcbot@example.com>".parse().unwrap(),
+            &user,
+            "09.08.20 [21.98 km :: free_flight] Danilo Bargen",
+            (
+                "*Danilo_B*\nhttps://www.xcontest.org/detail:danilo_b/",
+                "Danilo_B\nhttps://www.xcontest.org/detail:danilo_b/",
+            ),
+            Some(&details),
+        )
+        .unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("To: pilot@example.com"));
+        assert!(formatted.contains("Content-Type: multipart/alternative"));
+        assert!(formatted.contains("Content-ID: <preview>"));
+        assert!(formatted.contains("cid:preview"));
+        assert!(formatted.contains("Danilo_B\r\nhttps://www.xcontest.org/detail:danilo_b/"));
+
+        // Invalid addresses are rejected
+        let user = User {
+            username: "ECHOECHO".into(),
+            ..user
+        };
+        assert!(build_message(
+            "xcbot@example.com".parse().unwrap(),
+            &user,
+            "Subject",
+            ("Text", "Text"),
+            None
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! Formatting of notification texts.
+
+use xcontest_client::{Flight, FlightType};
+
+use crate::messages::{self, Messages};
+
+/// Maximum number of characters of a file message description (caption).
+pub const MAX_DESCRIPTION_CHARS: usize = 1000;
+
+/// Maximum number of characters of a text message. (The gateway limit is 3500
+/// bytes, this leaves room for multi-byte characters.)
+pub const MAX_TEXT_CHARS: usize = 1500;
+
+/// Format the notification text for a flight, using Threema markdown.
+///
+/// The pilot name is printed in bold, followed by the distance, flight type and
+/// date (if they could be parsed from the title), and the start time and
+/// airtime (if known from the detail page). Triangles get a line with their
+/// closing and average speed (if known). The link is always on its own line
+/// and is never truncated. If the text exceeds `max_chars`, the lines above
+/// the link are truncated.
+pub fn format_flight(flight: &Flight, messages: &Messages, max_chars: usize) -> String {
+    format_flight_with(flight, messages, max_chars, false)
+}
+
+/// Format the notification text for a flight as plain text (for channels
+/// without markdown), like [`format_flight`] but without bold pilot name.
+pub fn format_flight_plain(flight: &Flight, messages: &Messages, max_chars: usize) -> String {
+    format_flight_with(flight, messages, max_chars, true)
+}
+
+fn format_flight_with(
+    flight: &Flight,
+    messages: &Messages,
+    max_chars: usize,
+    plain: bool,
+) -> String {
+    let header = match &flight.parsed_title {
+        Some(parsed) if !parsed.pilot_name.is_empty() => {
+            let mut details = vec![];
+            if let Some(distance) = parsed.distance_km {
+                details.push(format!("{:.2} km", distance));
+            }
+            if let Some(flight_type) = &parsed.flight_type {
+                details.push(flight_type.to_string());
+            }
+            let start = flight.times.start.map(|start| start.format("%H:%M"));
+            match (parsed.date, start) {
+                (Some(date), Some(start)) => {
+                    details.push(format!("{} {}", date.format("%d.%m.%Y"), start))
+                }
+                (Some(date), None) => details.push(date.format("%d.%m.%Y").to_string()),
+                (None, Some(start)) => details.push(start.to_string()),
+                (None, None) => {}
+            }
+            if let Some(airtime) = flight.times.airtime_minutes {
+                details.push(messages::fill(
+                    messages.flight_airtime,
+                    &[
+                        ("hours", &(airtime / 60).to_string()),
+                        ("minutes", &(airtime % 60).to_string()),
+                    ],
+                ));
+            }
+            let mut header = if plain {
+                parsed.pilot_name.clone()
+            } else {
+                format!("*{}*", escape_markdown(&parsed.pilot_name))
+            };
+            if !details.is_empty() {
+                header.push('\n');
+                header.push_str(&details.join(" · "));
+            }
+            let is_triangle = matches!(
+                parsed.flight_type,
+                Some(FlightType::FlatTriangle | FlightType::FaiTriangle)
+            );
+            if is_triangle && !flight.triangle.is_empty() {
+                let mut triangle = vec![];
+                if let Some(closing) = flight.triangle.closing_percent {
+                    triangle.push(messages::fill(
+                        messages.triangle_closing,
+                        &[("percent", &format!("{:.1}", closing))],
+                    ));
+                }
+                if let Some(speed) = flight.triangle.speed_kmh {
+                    triangle.push(messages::fill(
+                        messages.triangle_speed,
+                        &[("speed", &format!("{:.1}", speed))],
+                    ));
+                }
+                header.push_str("\n△ ");
+                header.push_str(&triangle.join(" · "));
+            }
+            header
+        }
+        _ => flight.title.clone(),
+    };
+    let max_header_chars = max_chars.saturating_sub(flight.url.chars().count() + 1);
+    format!("{}\n{}", truncate(&header, max_header_chars), flight.url)
+}
+
+/// Format a list of flights below a header (used for digests and grouped
+/// notifications).
+///
+/// Flights that don't fit into `max_chars` are summarized in a last line.
+pub fn format_flights(
+    header: &str,
+    flights: &[Flight],
+    messages: &Messages,
+    max_chars: usize,
+) -> String {
+    format_flights_with(header, flights, messages, max_chars, false)
+}
+
+/// Format a list of flights below a header as plain text, like
+/// [`format_flights`].
+pub fn format_flights_plain(
+    header: &str,
+    flights: &[Flight],
+    messages: &Messages,
+    max_chars: usize,
+) -> String {
+    format_flights_with(header, flights, messages, max_chars, true)
+}
+
+fn format_flights_with(
+    header: &str,
+    flights: &[Flight],
+    messages: &Messages,
+    max_chars: usize,
+    plain: bool,
+) -> String {
+    let mut text = String::from(header);
+    for (i, flight) in flights.iter().enumerate() {
+        let entry = format_flight_with(flight, messages, max_chars, plain);
+        let remaining = flights.len() - i - 1;
+        let reserve = if remaining > 0 { 40 } else { 0 };
+        if text.chars().count() + entry.chars().count() + 2 + reserve > max_chars {
+            text.push_str("\n\n");
+            text.push_str(&messages::fill(
+                messages.digest_more_flights,
+                &[("count", &(flights.len() - i).to_string())],
+            ));
+            break;
+        }
+        text.push_str("\n\n");
+        text.push_str(&entry);
+    }
+    text
+}
+
+/// Split a long text into several messages of at most `max_chars` characters.
+///
+/// The text is split at line breaks if possible. If it needs to be split, every
+/// part ends with a marker like `(1/3)`.
+pub fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+    if text.chars().count() <= max_chars {
+        return vec![text.to_string()];
+    }
+
+    // Leave room for the marker
+    let max_part_chars = max_chars.saturating_sub(12).max(1);
+    let mut parts: Vec<String> = vec![];
+    let mut part = String::new();
+    for line in text.split('\n') {
+        let mut line = line.to_string();
+        loop {
+            let separator = usize::from(!part.is_empty());
+            let free = max_part_chars.saturating_sub(part.chars().count() + separator);
+            if line.chars().count() <= free {
+                if separator == 1 {
+                    part.push('\n');
+                }
+                part.push_str(&line);
+                break;
+            }
+            if part.is_empty() {
+                // The line itself is too long, split it
+                let rest = line.chars().skip(max_part_chars).collect();
+                parts.push(line.chars().take(max_part_chars).collect());
+                line = rest;
+            } else {
+                parts.push(std::mem::take(&mut part));
+            }
+        }
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+
+    let count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| format!("{}\n\n({}/{})", part.trim_end(), i + 1, count))
+        .collect()
+}
+
+/// Remove characters that would be interpreted as Threema markdown.
+pub fn escape_markdown(text: &str) -> String {
+    text.replace(['*', '_', '~'], "")
+}
+
+/// Remove the markdown markers of a message template (e.g. the bold group
+/// header) for plain text notifications. Placeholders are kept, so the
+/// template must be filled afterwards: The filled in values are not changed.
+pub fn plain_template(template: &str) -> String {
+    let mut plain = String::with_capacity(template.len());
+    let mut in_placeholder = false;
+    for c in template.chars() {
+        match c {
+            '{' => in_placeholder = true,
+            '}' => in_placeholder = false,
+            '*' | '~' => continue,
+            '_' if !in_placeholder => continue,
+            _ => {}
+        }
+        plain.push(c);
+    }
+    plain
+}
+
+/// Truncate text to at most `max_chars` characters, adding an ellipsis if
+/// necessary.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+    use xcontest_client::{FlightTimes, TriangleStats};
+
+    use super::*;
+
+    fn flight(title: &str) -> Flight {
+        Flight::new(
+            title.to_string(),
+            "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+                .to_string(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn format_parsed_title() {
+        assert_eq!(
+            format_flight(
+                &flight("09.08.20 [21.98 km :: free_flight] Danilo *Bargen*"),
+                messages::Language::German.messages(),
+                MAX_DESCRIPTION_CHARS
+            ),
+            "*Danilo Bargen*\n\
+            21.98 km · free flight · 09.08.2020\n\
+            https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+        );
+    }
+
+    #[test]
+    fn format_times() {
+        let flight =
+            flight("09.08.20 [21.98 km :: free_flight] Danilo Bargen").with_times(FlightTimes {
+                start: NaiveTime::from_hms_opt(10, 45, 0),
+                airtime_minutes: Some(222),
+            });
+        assert_eq!(
+            format_flight(
+                &flight,
+                messages::Language::English.messages(),
+                MAX_DESCRIPTION_CHARS
+            ),
+            "*Danilo Bargen*\n\
+            21.98 km · free flight · 09.08.2020 10:45 · 3 h 42 min airborne\n\
+            https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+        );
+    }
+
+    #[test]
+    fn format_triangle() {
+        let triangle = TriangleStats {
+            closing_percent: Some(97.5),
+            speed_kmh: Some(24.25),
+        };
+        let messages = messages::Language::German.messages();
+        assert_eq!(
+            format_flight(
+                &Flight {
+                    triangle,
+                    ..flight("09.08.20 [61.50 km :: fai_triangle] Danilo Bargen")
+                },
+                messages,
+                MAX_DESCRIPTION_CHARS
+            ),
+            "*Danilo Bargen*\n\
+            61.50 km · FAI triangle · 09.08.2020\n\
+            △ 97.5% geschlossen · Ø 24.2 km/h\n\
+            https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+        );
+
+        // Only triangles are annotated
+        let free_flight = flight("09.08.20 [21.98 km :: free_flight] Danilo Bargen");
+        assert_eq!(
+            format_flight(
+                &Flight {
+                    triangle,
+                    ..free_flight.clone()
+                },
+                messages,
+                MAX_DESCRIPTION_CHARS
+            ),
+            format_flight(&free_flight, messages, MAX_DESCRIPTION_CHARS)
+        );
+    }
+
+    #[test]
+    fn format_plain() {
+        let flight = Flight::new(
+            "09.08.20 [21.98 km :: free_flight] Danilo_B".to_string(),
+            "https://www.xcontest.org/2020/switzerland/en/flights/detail:danilo_b/9.8.2020/10:45"
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            format_flight_plain(
+                &flight,
+                messages::Language::German.messages(),
+                MAX_TEXT_CHARS
+            ),
+            "Danilo_B\n\
+            21.98 km · free flight · 09.08.2020\n\
+            https://www.xcontest.org/2020/switzerland/en/flights/detail:danilo_b/9.8.2020/10:45"
+        );
+        assert_eq!(
+            plain_template("*{count} neue Flüge von {pilot_name}* 🪂 _neu_"),
+            "{count} neue Flüge von {pilot_name} 🪂 neu"
+        );
+    }
+
+    #[test]
+    fn format_unparseable_title() {
+        assert_eq!(
+            format_flight(
+                &flight("Some weird title"),
+                messages::Language::German.messages(),
+                MAX_DESCRIPTION_CHARS
+            ),
+            "Some weird title\n\
+            https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+        );
+    }
+
+    #[test]
+    fn format_flights_truncated() {
+        let flights = vec![flight("09.08.20 [21.98 km :: free_flight] Danilo Bargen"); 30];
+        let messages = messages::Language::German.messages();
+        let text = format_flights(messages.digest_header, &flights, messages, MAX_TEXT_CHARS);
+        assert!(text.chars().count() <= MAX_TEXT_CHARS);
+        assert!(text.starts_with("*Neue Flüge* 🪂\n\n*Danilo Bargen*"));
+        assert!(text.ends_with("weitere Flüge"));
+    }
+
+    #[test]
+    fn split_short_text() {
+        assert_eq!(split_text("short", 100), vec!["short"]);
+    }
+
+    #[test]
+    fn split_long_text() {
+        let text = (1..=100)
+            .map(|i| format!("- pilot{}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let parts = split_text(&text, 300);
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|part| part.chars().count() <= 300));
+        assert!(parts[0].starts_with("- pilot1\n- pilot2\n"));
+        assert!(parts[0].ends_with("\n\n(1/4)"));
+        assert!(parts[3].ends_with("- pilot100\n\n(4/4)"));
+
+        // No line is lost or split
+        let joined = parts
+            .iter()
+            .map(|part| part.rsplit_once("\n\n").unwrap().0)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn split_long_line() {
+        let parts = split_text(&"x".repeat(250), 100);
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| part.chars().count() <= 100));
+        assert_eq!(parts[2], format!("{}\n\n(3/3)", "x".repeat(250 - 2 * 88)));
+    }
+
+    #[test]
+    fn format_truncated() {
+        let text = format_flight(
+            &flight(&"x".repeat(200)),
+            messages::Language::German.messages(),
+            100,
+        );
+        assert_eq!(text.chars().count(), 100);
+        assert!(text.ends_with(
+            "…\nhttps://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+        ));
+    }
+}
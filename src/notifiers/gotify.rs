@@ -0,0 +1,251 @@
+//! Gotify notification channel (self-hosted push notifications).
+//!
+//! Every user has their own application on the configured server, whose token
+//! is stored when subscribing through the API. Gotify has no attachments, so
+//! the messages are text only and clicking them opens the flight.
+
+use futures::future::BoxFuture;
+use reqwest::{header, Client};
+use serde_json::json;
+use sqlx::{Pool, Sqlite};
+use xcontest_client::{Flight, FlightDetails};
+
+use super::{check_response, format, Channel, Error};
+use crate::{
+    config::{GotifyConfig, TenantConfig},
+    db::{self, User},
+    messages::{self, Messages},
+};
+
+/// Default priority of the messages (shown as notification by the Android
+/// app).
+const DEFAULT_PRIORITY: u8 = 5;
+
+pub struct GotifyNotifier {
+    client: Client,
+    pool: Pool<Sqlite>,
+    server: String,
+    priority: u8,
+    messages: &'static Messages,
+    /// Whether flight notifications are marked as test messages
+    simulated: bool,
+}
+
+impl GotifyNotifier {
+    pub fn new(
+        tenant: &TenantConfig,
+        config: &GotifyConfig,
+        client: Client,
+        pool: Pool<Sqlite>,
+    ) -> Self {
+        Self {
+            client,
+            pool,
+            server: config.server.trim_end_matches('/').to_string(),
+            priority: config.priority.unwrap_or(DEFAULT_PRIORITY),
+            messages: tenant.messages(),
+            simulated: false,
+        }
+    }
+
+    async fn send(&self, message: serde_json::Value, user: &User) -> Result<(), Error> {
+        let context = "Could not send Gotify message";
+        let app_token = db::get_gotify_app_token(&self.pool, user.id)
+            .await?
+            .ok_or_else(|| Error::NotFound {
+                context: "Could not send Gotify message, no application token stored",
+                recipient: user.username.clone(),
+            })?;
+        let response = self
+            .client
+            .post(format!("{}/message", self.server))
+            .header("X-Gotify-Key", app_token)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(message.to_string())
+            .send()
+            .await
+            .map_err(|source| Error::Http { context, source })?;
+        check_response(context, response, |status, _| {
+            Error::message(
+                context,
+                format!("The Gotify server rejected the message (HTTP {})", status),
+            )
+        })
+        .await?;
+        tracing::debug!("Gotify message sent for {}", user.username);
+        Ok(())
+    }
+}
+
+impl Channel for GotifyNotifier {
+    fn usertype() -> &'static str {
+        "gotify"
+    }
+
+    fn section() -> &'static str {
+        "gotify"
+    }
+
+    fn validate(
+        name: &str,
+        app_token: Option<&str>,
+    ) -> Result<(String, Option<String>), &'static str> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("invalid name");
+        }
+        let app_token = match app_token.map(str::trim) {
+            Some(token)
+                if token.is_empty()
+                    || token.len() > 64
+                    || !token
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')) =>
+            {
+                return Err("invalid app_token");
+            }
+            token => token.map(str::to_string),
+        };
+        Ok((name.to_string(), app_token))
+    }
+
+    /// Store the application token, which is required for new users.
+    fn store_setting<'a>(
+        pool: &'a Pool<Sqlite>,
+        user: &'a User,
+        app_token: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<Result<(), &'static str>>> {
+        Box::pin(async move {
+            match app_token {
+                Some(app_token) => db::set_gotify_app_token(pool, user.id, app_token).await?,
+                None if db::get_gotify_app_token(pool, user.id).await?.is_none() => {
+                    return Ok(Err("missing app_token"));
+                }
+                None => {}
+            }
+            Ok(Ok(()))
+        })
+    }
+
+    fn set_simulated(&mut self) {
+        self.simulated = true;
+    }
+
+    /// Notify the specified Gotify user about the flight.
+    fn notify<'a>(
+        &'a self,
+        flight: &'a Flight,
+        details: Option<&'a FlightDetails>,
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let title = if self.simulated {
+                self.messages.simulated_flight
+            } else if first_of_season {
+                self.messages.first_flight_of_season
+            } else {
+                &flight.title
+            };
+            let flight = match details {
+                Some(details) => flight.clone().with_details(details),
+                None => flight.clone(),
+            };
+            let text = format::format_flight_plain(&flight, self.messages, format::MAX_TEXT_CHARS);
+            let message = build_message(title, &text, Some(&flight.url), self.priority);
+            self.send(message, user).await
+        })
+    }
+
+    /// Notify the specified Gotify user about several flights of one pilot.
+    fn notify_group<'a>(
+        &'a self,
+        pilot: &'a str,
+        flights: &'a [Flight],
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let title = messages::fill(
+                &format::plain_template(self.messages.group_header),
+                &[("count", &flights.len().to_string()), ("pilot", pilot)],
+            );
+            let header = if first_of_season {
+                self.messages.first_flight_of_season
+            } else {
+                ""
+            };
+            let text = format::format_flights_plain(
+                header,
+                flights,
+                self.messages,
+                format::MAX_TEXT_CHARS,
+            );
+            let message = build_message(&title, text.trim_start(), None, self.priority);
+            self.send(message, user).await
+        })
+    }
+
+    /// Notify the specified Gotify user that the title of the flight changed.
+    fn notify_correction<'a>(
+        &'a self,
+        flight: &'a Flight,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let text = format::format_flight_plain(flight, self.messages, format::MAX_TEXT_CHARS);
+            let message = build_message(
+                self.messages.flight_corrected,
+                &text,
+                Some(&flight.url),
+                self.priority,
+            );
+            self.send(message, user).await
+        })
+    }
+}
+
+/// Build the message for the plain notification title and text, opening the
+/// URL on click if present.
+fn build_message(title: &str, text: &str, url: Option<&str>, priority: u8) -> serde_json::Value {
+    let mut message = json!({
+        "title": title,
+        "message": text,
+        "priority": priority,
+    });
+    if let Some(url) = url {
+        message["extras"] = json!({
+            "client::notification": { "click": { "url": url } },
+        });
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_json() {
+        let message = build_message(
+            "09.08.20 [21.98 km :: free_flight] Danilo Bargen",
+            "Danilo_B\n21.98 km",
+            Some("https://www.xcontest.org/"),
+            5,
+        );
+        assert_eq!(
+            message["title"],
+            "09.08.20 [21.98 km :: free_flight] Danilo Bargen"
+        );
+        assert_eq!(message["message"], "Danilo_B\n21.98 km");
+        assert_eq!(message["priority"], 5);
+        assert_eq!(
+            message["extras"]["client::notification"]["click"]["url"],
+            "https://www.xcontest.org/"
+        );
+
+        // Without link
+        let message = build_message("Title", "Text", None, 8);
+        assert!(message.get("extras").is_none());
+    }
+}
@@ -1,50 +1,408 @@
 use anyhow::{Context, Result};
-use futures::TryStreamExt;
+use futures::future::BoxFuture;
 use reqwest::Client;
-use sqlx::{Pool, Sqlite};
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use threema_gateway::errors::ApiError;
+use xcontest_client::{Flight, FlightDetails};
 
 use crate::{
-    config::Config,
-    db::User,
-    xcontest::{Flight, FlightDetails},
+    db::{self, User},
+    jobs::{self, Job},
+    tenants::Tenant,
 };
 
+mod email;
+pub mod format;
+mod gotify;
+mod ntfy;
+mod pushover;
+mod slack;
 mod threema;
 
+pub use email::EmailNotifier;
+pub use gotify::GotifyNotifier;
+pub use ntfy::NtfyNotifier;
+pub use pushover::PushoverNotifier;
+use slack::SlackNotifier;
+
+/// Errors when sending a message to a user.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The gateway could not be reached or returned an unexpected response
+    #[error("{context}")]
+    Network {
+        context: &'static str,
+        #[source]
+        source: ApiError,
+    },
+    /// The recipient does not exist (anymore)
+    #[error("{context}: Recipient {recipient} not found")]
+    NotFound {
+        context: &'static str,
+        recipient: String,
+    },
+    /// The gateway account cannot send messages (bad credentials, no credits
+    /// left) until the admin fixes it
+    #[error("{context}")]
+    Account {
+        context: &'static str,
+        #[source]
+        source: ApiError,
+    },
+    /// The gateway rejected the message (e.g. message too long)
+    #[error("{context}")]
+    Rejected {
+        context: &'static str,
+        #[source]
+        source: ApiError,
+    },
+    /// The SMTP server could not be reached or rejected the e-mail
+    #[error("{context}")]
+    Smtp {
+        context: &'static str,
+        #[source]
+        source: lettre::transport::smtp::Error,
+    },
+    /// The HTTP API of the channel (Slack, ntfy, Pushover, Gotify) could not
+    /// be reached
+    #[error("{context}")]
+    Http {
+        context: &'static str,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The Slack API returned an error (e.g. `ratelimited`)
+    #[error("{context}: {error}")]
+    Slack {
+        context: &'static str,
+        error: String,
+    },
+    /// The message could not be built or encrypted
+    #[error("{context}")]
+    Message {
+        context: &'static str,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The channel of the recipient is not configured (the section is
+    /// missing in the config)
+    #[error("Cannot notify {channel} user, the [{section}] section is missing")]
+    NotConfigured {
+        channel: &'static str,
+        section: &'static str,
+    },
+    #[error(transparent)]
+    Database(#[from] db::Error),
+}
+
+impl Error {
+    /// Classify an error of the gateway API.
+    pub fn api(context: &'static str, source: ApiError, recipient: &str) -> Self {
+        match source {
+            ApiError::RequestError(_)
+            | ApiError::IoError(_)
+            | ApiError::ServerError
+            | ApiError::ParseError(_)
+            | ApiError::Other(_) => Error::Network { context, source },
+            ApiError::IdNotFound | ApiError::BadSenderOrRecipient => Error::NotFound {
+                context,
+                recipient: recipient.to_string(),
+            },
+            ApiError::BadCredentials | ApiError::NoCredits => Error::Account { context, source },
+            source => Error::Rejected { context, source },
+        }
+    }
+
+    /// Return an error for a message that could not be built or encrypted.
+    pub fn message(
+        context: &'static str,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Error::Message {
+            context,
+            source: source.into(),
+        }
+    }
+
+    /// Return whether sending the message again later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Network { .. }
+            | Error::Account { .. }
+            | Error::Http { .. }
+            | Error::Database(_) => true,
+            Error::Smtp { source, .. } => !source.is_permanent(),
+            Error::Slack { error, .. } => matches!(
+                &**error,
+                "ratelimited"
+                    | "request_timeout"
+                    | "service_unavailable"
+                    | "internal_error"
+                    | "fatal_error"
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Check the status of a response of a channel API.
+///
+/// Client errors (except rate limiting) are permanent, `rejected` builds the
+/// error from the status and the response body. Other error statuses are
+/// retried.
+async fn check_response(
+    context: &'static str,
+    response: reqwest::Response,
+    rejected: impl FnOnce(reqwest::StatusCode, &[u8]) -> Error,
+) -> Result<reqwest::Response, Error> {
+    let status = response.status();
+    if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let body = response
+            .bytes()
+            .await
+            .map_err(|source| Error::Http { context, source })?;
+        return Err(rejected(status, &body));
+    }
+    response
+        .error_for_status()
+        .map_err(|source| Error::Http { context, source })
+}
+
+/// A notification channel besides Threema (which needs the connection of the
+/// update cycle). The users of a channel have its user type, they subscribe
+/// through the API (Slack channels with the slash command).
+pub trait Channel: Send + Sync {
+    /// The user type of the users of the channel (e.g. `email`)
+    fn usertype() -> &'static str
+    where
+        Self: Sized;
+
+    /// The config section of the channel, without it the users of the
+    /// channel can't be notified
+    fn section() -> &'static str
+    where
+        Self: Sized;
+
+    /// Normalize the name and setting of a user subscribing through the API,
+    /// or return an error if they are invalid. By default, the setting is
+    /// ignored.
+    fn validate(
+        name: &str,
+        _setting: Option<&str>,
+    ) -> Result<(String, Option<String>), &'static str>
+    where
+        Self: Sized,
+    {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("invalid name");
+        }
+        Ok((name.to_string(), None))
+    }
+
+    /// Store the validated setting of a user subscribing through the API.
+    ///
+    /// Return an error for the client if the user can't be notified without
+    /// a setting.
+    fn store_setting<'a>(
+        _pool: &'a Pool<Sqlite>,
+        _user: &'a User,
+        _setting: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Result<(), &'static str>>>
+    where
+        Self: Sized,
+    {
+        Box::pin(async { Ok(Ok(())) })
+    }
+
+    /// Mark the flight notifications as test messages (for simulated flights).
+    fn set_simulated(&mut self);
+
+    /// Notify the user about the flight.
+    fn notify<'a>(
+        &'a self,
+        flight: &'a Flight,
+        details: Option<&'a FlightDetails>,
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Notify the user about several flights of one pilot.
+    fn notify_group<'a>(
+        &'a self,
+        pilot: &'a str,
+        flights: &'a [Flight],
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Notify the user that the title of the flight changed.
+    fn notify_correction<'a>(
+        &'a self,
+        flight: &'a Flight,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// A channel known to the notifier.
+struct RegisteredChannel {
+    usertype: &'static str,
+    section: &'static str,
+    /// `None` if the channel is not configured
+    channel: Option<Box<dyn Channel>>,
+}
+
+/// Count a failed notification of the user (for the weekly report).
+async fn record_failure(conn: &mut SqliteConnection, user: &User) {
+    if let Err(e) = db::increment_notification_failures(conn, user.id, &db::current_month()).await {
+        tracing::warn!("Could not count failed notification: {}", e);
+    }
+}
+
 pub struct Notifier {
-    pool: Pool<Sqlite>,
+    tenant: String,
+    season_gap_months: u32,
+    /// Whether users with a digest are skipped (they are notified immediately
+    /// if digests are disabled)
+    digests: bool,
     threema: threema::ThreemaNotifier,
+    channels: Vec<RegisteredChannel>,
 }
 
 impl Notifier {
-    pub fn new(pool: Pool<Sqlite>, client: Client, config: &Config) -> Result<Self> {
-        Ok(Self {
-            pool: pool.clone(),
-            threema: threema::ThreemaNotifier::new(&config.threema, client, pool)?,
-        })
-    }
-
-    /// Notify all subscribers about this flight.
-    pub async fn notify(&mut self, flight: &Flight, details: Option<FlightDetails>) -> Result<()> {
-        // Get connection
-        let mut conn = self
-            .pool
-            .acquire()
+    /// Create a notifier for the tenant. Flights after a break of more than
+    /// `season_gap_months` are marked as first flight of the season (0
+    /// disables the marker).
+    pub fn new(
+        pool: Pool<Sqlite>,
+        client: Client,
+        tenant: &Tenant,
+        season_gap_months: u32,
+    ) -> Result<Self> {
+        let config = &tenant.config;
+        let mut notifier = Self {
+            tenant: tenant.id().to_string(),
+            season_gap_months,
+            digests: config.features.digests(),
+            threema: threema::ThreemaNotifier::new(config, client.clone(), pool.clone())?,
+            channels: vec![],
+        };
+        notifier.register(match &config.smtp {
+            Some(smtp) => Some(EmailNotifier::new(config, smtp)?),
+            None => None,
+        });
+        notifier.register(
+            config
+                .slack
+                .as_ref()
+                .map(|slack| SlackNotifier::new(config, slack, client.clone(), pool.clone())),
+        );
+        notifier.register(
+            config
+                .ntfy
+                .as_ref()
+                .map(|ntfy| NtfyNotifier::new(config, ntfy, client.clone(), pool.clone())),
+        );
+        notifier.register(
+            config.pushover.as_ref().map(|pushover| {
+                PushoverNotifier::new(config, pushover, client.clone(), pool.clone())
+            }),
+        );
+        notifier.register(
+            config
+                .gotify
+                .as_ref()
+                .map(|gotify| GotifyNotifier::new(config, gotify, client.clone(), pool.clone())),
+        );
+        Ok(notifier)
+    }
+
+    /// Add a channel (`None` if it's not configured).
+    fn register<C: Channel + 'static>(&mut self, channel: Option<C>) {
+        self.channels.push(RegisteredChannel {
+            usertype: C::usertype(),
+            section: C::section(),
+            channel: channel.map(|channel| Box::new(channel) as Box<dyn Channel>),
+        });
+    }
+
+    /// Mark the flight notifications as test messages (for simulated flights).
+    pub fn simulated(mut self) -> Self {
+        self.threema.simulated = true;
+        for registered in &mut self.channels {
+            if let Some(channel) = &mut registered.channel {
+                channel.set_simulated();
+            }
+        }
+        self
+    }
+
+    /// Return the channel of the user type, `None` if the user type is
+    /// unknown, or an error if the channel is not configured.
+    fn channel(&self, usertype: &str) -> Result<Option<&dyn Channel>, Error> {
+        let registered = match self
+            .channels
+            .iter()
+            .find(|registered| registered.usertype == usertype)
+        {
+            Some(registered) => registered,
+            None => return Ok(None),
+        };
+        match &registered.channel {
+            Some(channel) => Ok(Some(&**channel)),
+            None => Err(Error::NotConfigured {
+                channel: registered.usertype,
+                section: registered.section,
+            }),
+        }
+    }
+
+    /// Return whether this is the pilot's first flight of the season.
+    pub async fn is_first_of_season(&self, conn: &mut SqliteConnection, flight: &Flight) -> bool {
+        if self.season_gap_months == 0 {
+            return false;
+        }
+        db::is_first_flight_of_season(conn, &self.tenant, &flight.url, self.season_gap_months)
             .await
-            .context("Could not acquire db connection")?;
+            .unwrap_or_else(|e| {
+                tracing::warn!("Could not check for first flight of season: {}", e);
+                false
+            })
+    }
 
-        let mut subscribers = sqlx::query_as::<_, User>(
+    /// Return the subscribers of the pilot in this tenant that want to be
+    /// notified immediately (all subscribers if digests are disabled).
+    async fn get_subscribers(&self, conn: &mut SqliteConnection, pilot: &str) -> Result<Vec<User>> {
+        sqlx::query_as::<_, User>(
             r#"
-            SELECT u.id, u.username, u.usertype, u.threema_public_key
+            SELECT u.id, u.tenant, u.username, u.usertype, u.threema_public_key
             FROM subscriptions s
             INNER JOIN users u ON s.user_id = u.id
             WHERE s.pilot_username = ? COLLATE NOCASE
+            AND u.tenant = ?
+            AND (u.digest = 0 OR ? = 0)
             "#,
         )
-        .bind(&flight.pilot_username)
-        .fetch(&mut *conn);
+        .bind(pilot)
+        .bind(self.tenant.clone())
+        .bind(self.digests)
+        .fetch_all(conn)
+        .await
+        .context("Could not fetch subscribers")
+    }
 
-        while let Some(subscriber) = subscribers.try_next().await? {
+    /// Notify all subscribers of the tenant about this flight.
+    ///
+    /// Return the number of subscribers that were notified successfully.
+    pub async fn notify(
+        &mut self,
+        conn: &mut SqliteConnection,
+        flight: &Flight,
+        details: Option<FlightDetails>,
+    ) -> Result<u64> {
+        let first_of_season = self.is_first_of_season(conn, flight).await;
+        let mut notified = 0;
+        for subscriber in self.get_subscribers(conn, &flight.pilot_username).await? {
             tracing::info!(
                 "Notifying {}/{} about flight {}",
                 subscriber.usertype,
@@ -52,15 +410,280 @@ impl Notifier {
                 flight.url,
             );
 
-            match &*subscriber.usertype {
-                "threema" => self
-                    .threema
-                    .notify(flight, details.as_ref(), &subscriber)
-                    .await
-                    .unwrap_or_else(|e| tracing::error!("Could not notify threema user: {}", e)),
-                other => tracing::warn!("Unsupported notification channel: {}", other),
+            // Failed notifications are retried later (unless retrying is futile)
+            let result = self
+                .notify_user(conn, flight, details.as_ref(), first_of_season, &subscriber)
+                .await;
+            if result.is_err() {
+                record_failure(conn, &subscriber).await;
+            }
+            match result {
+                Ok(()) => notified += 1,
+                Err(e) if !e.is_retryable() => tracing::error!(
+                    "Could not notify {}/{}: {:#}",
+                    subscriber.usertype,
+                    subscriber.username,
+                    e
+                ),
+                Err(e) => {
+                    tracing::error!(
+                        "Could not notify {}/{}, retrying later: {}",
+                        subscriber.usertype,
+                        subscriber.username,
+                        e
+                    );
+                    let job = Job::Notify {
+                        user_id: subscriber.id,
+                        flight_url: flight.url.clone(),
+                    };
+                    if let Err(e) = jobs::enqueue(&mut *conn, &job, jobs::RETRY_DELAY).await {
+                        tracing::error!("Could not enqueue notification retry: {}", e);
+                    }
+                }
+            }
+        }
+        Ok(notified)
+    }
+
+    /// Notify all subscribers of the tenant about several flights of one
+    /// pilot in a single message.
+    pub async fn notify_group(
+        &mut self,
+        conn: &mut SqliteConnection,
+        pilot: &str,
+        flights: &[Flight],
+    ) -> Result<()> {
+        let first_of_season = match flights.first() {
+            Some(flight) => self.is_first_of_season(conn, flight).await,
+            None => false,
+        };
+        for subscriber in self.get_subscribers(conn, pilot).await? {
+            tracing::info!(
+                "Notifying {}/{} about {} flights of {}",
+                subscriber.usertype,
+                subscriber.username,
+                flights.len(),
+                pilot,
+            );
+
+            // Failed notifications are retried later (unless retrying is futile)
+            let result = self
+                .notify_user_group(conn, pilot, flights, first_of_season, &subscriber)
+                .await;
+            if result.is_err() {
+                record_failure(conn, &subscriber).await;
+            }
+            match result {
+                Ok(()) => {}
+                Err(e) if !e.is_retryable() => tracing::error!(
+                    "Could not notify {}/{}: {:#}",
+                    subscriber.usertype,
+                    subscriber.username,
+                    e
+                ),
+                Err(e) => {
+                    tracing::error!(
+                        "Could not notify {}/{}, retrying later: {}",
+                        subscriber.usertype,
+                        subscriber.username,
+                        e
+                    );
+                    let job = Job::NotifyGroup {
+                        user_id: subscriber.id,
+                        pilot: pilot.to_string(),
+                        flight_urls: flights.iter().map(|flight| flight.url.clone()).collect(),
+                    };
+                    if let Err(e) = jobs::enqueue(&mut *conn, &job, jobs::RETRY_DELAY).await {
+                        tracing::error!("Could not enqueue notification retry: {}", e);
+                    }
+                }
             }
         }
         Ok(())
     }
+
+    /// Notify all subscribers of the tenant that the title of this flight
+    /// changed. Failed notifications are not retried.
+    pub async fn notify_correction(
+        &mut self,
+        conn: &mut SqliteConnection,
+        flight: &Flight,
+    ) -> Result<()> {
+        for subscriber in self.get_subscribers(conn, &flight.pilot_username).await? {
+            tracing::info!(
+                "Notifying {}/{} about corrected flight {}",
+                subscriber.usertype,
+                subscriber.username,
+                flight.url,
+            );
+            let result = match &*subscriber.usertype {
+                "threema" => {
+                    self.threema
+                        .notify_correction(conn, flight, &subscriber)
+                        .await
+                }
+                usertype => match self.channel(usertype) {
+                    Ok(Some(channel)) => channel.notify_correction(flight, &subscriber).await,
+                    Ok(None) => {
+                        tracing::warn!("Unsupported notification channel: {}", usertype);
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                },
+            };
+            if let Err(e) = result {
+                tracing::error!(
+                    "Could not notify {}/{} about correction: {}",
+                    subscriber.usertype,
+                    subscriber.username,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Notify a single user about this flight.
+    pub async fn notify_user(
+        &mut self,
+        conn: &mut SqliteConnection,
+        flight: &Flight,
+        details: Option<&FlightDetails>,
+        first_of_season: bool,
+        user: &User,
+    ) -> Result<(), Error> {
+        if user.usertype == "threema" {
+            return self
+                .threema
+                .notify(conn, flight, details, first_of_season, user)
+                .await;
+        }
+        match self.channel(&user.usertype)? {
+            Some(channel) => channel.notify(flight, details, first_of_season, user).await,
+            None => {
+                tracing::warn!("Unsupported notification channel: {}", user.usertype);
+                Ok(())
+            }
+        }
+    }
+
+    /// Notify a single user about several flights of one pilot.
+    pub async fn notify_user_group(
+        &mut self,
+        conn: &mut SqliteConnection,
+        pilot: &str,
+        flights: &[Flight],
+        first_of_season: bool,
+        user: &User,
+    ) -> Result<(), Error> {
+        if user.usertype == "threema" {
+            return self
+                .threema
+                .notify_group(conn, pilot, flights, first_of_season, user)
+                .await;
+        }
+        match self.channel(&user.usertype)? {
+            Some(channel) => {
+                channel
+                    .notify_group(pilot, flights, first_of_season, user)
+                    .await
+            }
+            None => {
+                tracing::warn!("Unsupported notification channel: {}", user.usertype);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_api_errors() {
+        let error = Error::api("Could not send", ApiError::IdNotFound, "ECHOECHO");
+        assert!(matches!(&error, Error::NotFound { recipient, .. } if recipient == "ECHOECHO"));
+        assert!(!error.is_retryable());
+        assert_eq!(
+            error.to_string(),
+            "Could not send: Recipient ECHOECHO not found"
+        );
+
+        let error = Error::api("Could not send", ApiError::ServerError, "ECHOECHO");
+        assert!(matches!(error, Error::Network { .. }));
+        assert!(error.is_retryable());
+
+        // Unexpected status codes (e.g. 429) may be temporary
+        let error = Error::api(
+            "Could not send",
+            ApiError::Other("Bad response status code: 429".into()),
+            "ECHOECHO",
+        );
+        assert!(error.is_retryable());
+
+        let error = Error::api("Could not send", ApiError::NoCredits, "ECHOECHO");
+        assert!(matches!(error, Error::Account { .. }));
+        assert!(error.is_retryable());
+
+        let error = Error::api("Could not send", ApiError::MessageTooLong, "ECHOECHO");
+        assert!(matches!(error, Error::Rejected { .. }));
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn validate_subscriptions() {
+        // Only the name is checked by default
+        assert_eq!(
+            SlackNotifier::validate(" C0123 ", Some("ignored")),
+            Ok(("C0123".to_string(), None))
+        );
+        assert_eq!(SlackNotifier::validate(" ", None), Err("invalid name"));
+
+        // E-mail addresses are normalized
+        assert_eq!(
+            EmailNotifier::validate(" Danilo@Example.com ", None),
+            Ok(("danilo@example.com".to_string(), None))
+        );
+        assert_eq!(
+            EmailNotifier::validate("danilo", None),
+            Err("invalid address")
+        );
+
+        // The settings are optional, but must be valid if given
+        assert_eq!(
+            NtfyNotifier::validate("danilo", Some(" xc-flights_1 ")),
+            Ok(("danilo".to_string(), Some("xc-flights_1".to_string())))
+        );
+        assert_eq!(
+            NtfyNotifier::validate("danilo", Some("xc flights")),
+            Err("invalid topic")
+        );
+        assert_eq!(
+            PushoverNotifier::validate("danilo", None),
+            Ok(("danilo".to_string(), None))
+        );
+        assert_eq!(
+            PushoverNotifier::validate("danilo", Some("uQiRzpo4DXghDmr9QzzfQu27cmVRs")),
+            Err("invalid user_key")
+        );
+        assert_eq!(
+            GotifyNotifier::validate("danilo", Some("AbC/def")),
+            Err("invalid app_token")
+        );
+    }
+
+    #[test]
+    fn channel_not_configured() {
+        // Counted as failure, but not retried
+        let error = Error::NotConfigured {
+            channel: "e-mail",
+            section: "smtp",
+        };
+        assert!(!error.is_retryable());
+        assert_eq!(
+            error.to_string(),
+            "Cannot notify e-mail user, the [smtp] section is missing"
+        );
+    }
 }
@@ -0,0 +1,256 @@
+//! ntfy notification channel (push notifications to the ntfy app).
+//!
+//! Every user has a topic (by default the configured one) the notifications are
+//! published to, as plain text since ntfy doesn't render markdown by default.
+//! With a preview image, the image is the body and the text goes into the
+//! `Message` header.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+use reqwest::{Client, RequestBuilder};
+use sqlx::{Pool, Sqlite};
+use xcontest_client::{Flight, FlightDetails};
+
+use super::{check_response, format, Channel, Error};
+use crate::{
+    config::{NtfyConfig, TenantConfig},
+    db::{self, User},
+    messages::{self, Messages},
+};
+
+/// The public ntfy server.
+const DEFAULT_SERVER: &str = "https://ntfy.sh";
+
+pub struct NtfyNotifier {
+    client: Client,
+    pool: Pool<Sqlite>,
+    server: String,
+    default_topic: Option<String>,
+    token: Option<String>,
+    messages: &'static Messages,
+    /// Whether flight notifications are marked as test messages
+    simulated: bool,
+}
+
+impl NtfyNotifier {
+    pub fn new(
+        tenant: &TenantConfig,
+        config: &NtfyConfig,
+        client: Client,
+        pool: Pool<Sqlite>,
+    ) -> Self {
+        Self {
+            client,
+            pool,
+            server: config
+                .server
+                .as_deref()
+                .unwrap_or(DEFAULT_SERVER)
+                .trim_end_matches('/')
+                .to_string(),
+            default_topic: config.topic.clone(),
+            token: config.token.clone(),
+            messages: tenant.messages(),
+            simulated: false,
+        }
+    }
+
+    /// Return the topic of the user (or the default topic).
+    async fn topic(&self, user: &User) -> Result<String, Error> {
+        match db::get_ntfy_topic(&self.pool, user.id).await? {
+            Some(topic) => Ok(topic),
+            None => self.default_topic.clone().ok_or_else(|| Error::NotFound {
+                context: "Could not send ntfy notification, no topic configured",
+                recipient: user.username.clone(),
+            }),
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder, user: &User) -> Result<(), Error> {
+        let context = "Could not send ntfy notification";
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request
+            .send()
+            .await
+            .map_err(|source| Error::Http { context, source })?;
+        check_response(context, response, |status, _| {
+            Error::message(
+                context,
+                format!("The ntfy server rejected the message (HTTP {})", status),
+            )
+        })
+        .await?;
+        tracing::debug!("ntfy notification sent to {}", user.username);
+        Ok(())
+    }
+}
+
+impl Channel for NtfyNotifier {
+    fn usertype() -> &'static str {
+        "ntfy"
+    }
+
+    fn section() -> &'static str {
+        "ntfy"
+    }
+
+    fn validate(name: &str, topic: Option<&str>) -> Result<(String, Option<String>), &'static str> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("invalid name");
+        }
+        let topic = match topic.map(str::trim) {
+            Some(topic)
+                if topic.is_empty()
+                    || topic.len() > 64
+                    || !topic
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') =>
+            {
+                return Err("invalid topic");
+            }
+            topic => topic.map(str::to_string),
+        };
+        Ok((name.to_string(), topic))
+    }
+
+    /// Store the topic, without one the configured topic is used.
+    fn store_setting<'a>(
+        pool: &'a Pool<Sqlite>,
+        user: &'a User,
+        topic: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<Result<(), &'static str>>> {
+        Box::pin(async move {
+            if let Some(topic) = topic {
+                db::set_ntfy_topic(pool, user.id, Some(topic)).await?;
+            }
+            Ok(Ok(()))
+        })
+    }
+
+    fn set_simulated(&mut self) {
+        self.simulated = true;
+    }
+
+    /// Notify the specified ntfy user about the flight.
+    fn notify<'a>(
+        &'a self,
+        flight: &'a Flight,
+        details: Option<&'a FlightDetails>,
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let title = if self.simulated {
+                Some(self.messages.simulated_flight)
+            } else if first_of_season {
+                Some(self.messages.first_flight_of_season)
+            } else {
+                None
+            };
+            let flight = match details {
+                Some(details) => flight.clone().with_details(details),
+                None => flight.clone(),
+            };
+            let text = format::format_flight_plain(&flight, self.messages, format::MAX_TEXT_CHARS);
+            let topic = self.topic(user).await?;
+            let request = match details {
+                // The text is sent in a header, since the body is the attachment
+                Some(details) => self
+                    .client
+                    .put(format!("{}/{}", self.server, topic))
+                    .header("Filename", format!("{}.jpg", flight.pilot_username))
+                    .header("Message", encode_header(&text))
+                    .body(details.thumbnail_small.to_vec()),
+                None => self
+                    .client
+                    .post(format!("{}/{}", self.server, topic))
+                    .body(text),
+            };
+            let request = request.header("Click", &flight.url);
+            let request = match title {
+                Some(title) => request.header("Title", encode_header(title)),
+                None => request,
+            };
+            self.send(request, user).await
+        })
+    }
+
+    /// Notify the specified ntfy user about several flights of one pilot.
+    fn notify_group<'a>(
+        &'a self,
+        pilot: &'a str,
+        flights: &'a [Flight],
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut header = messages::fill(
+                &format::plain_template(self.messages.group_header),
+                &[("count", &flights.len().to_string()), ("pilot", pilot)],
+            );
+            if first_of_season {
+                header.push('\n');
+                header.push_str(self.messages.first_flight_of_season);
+            }
+            let text = format::format_flights_plain(
+                &header,
+                flights,
+                self.messages,
+                format::MAX_TEXT_CHARS,
+            );
+            let topic = self.topic(user).await?;
+            let request = self
+                .client
+                .post(format!("{}/{}", self.server, topic))
+                .body(text);
+            self.send(request, user).await
+        })
+    }
+
+    /// Notify the specified ntfy user that the title of the flight changed.
+    fn notify_correction<'a>(
+        &'a self,
+        flight: &'a Flight,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let text = format::format_flight_plain(flight, self.messages, format::MAX_TEXT_CHARS);
+            let topic = self.topic(user).await?;
+            let request = self
+                .client
+                .post(format!("{}/{}", self.server, topic))
+                .header("Title", encode_header(self.messages.flight_corrected))
+                .header("Click", &flight.url)
+                .body(text);
+            self.send(request, user).await
+        })
+    }
+}
+
+/// Encode a header value, which must be ASCII: Non-ASCII text is sent
+/// RFC 2047 encoded (supported by ntfy).
+fn encode_header(text: &str) -> String {
+    if text.is_ascii() && !text.contains(['\n', '\r']) {
+        return text.to_string();
+    }
+    format!("=?UTF-8?B?{}?=", STANDARD.encode(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_encoding() {
+        assert_eq!(encode_header("Danilo Bargen"), "Danilo Bargen");
+        assert_eq!(
+            encode_header("🎉 Erster Flug"),
+            "=?UTF-8?B?8J+OiSBFcnN0ZXIgRmx1Zw==?="
+        );
+        assert_eq!(encode_header("a\nb"), "=?UTF-8?B?YQpi?=");
+    }
+}
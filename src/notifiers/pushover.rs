@@ -0,0 +1,309 @@
+//! Pushover notification channel (push notifications to the Pushover app).
+//!
+//! All messages are sent as the configured application, to the user key stored
+//! for every user. Long flights get a raised priority, which bypasses the quiet
+//! hours of the user. Users with an invalid key are reported as not found.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+use reqwest::{header, Client};
+use sqlx::{Pool, Sqlite};
+use xcontest_client::{Flight, FlightDetails};
+
+use super::{check_response, format, Channel, Error};
+use crate::{
+    config::{PushoverConfig, TenantConfig},
+    db::{self, User},
+    messages::{self, Messages},
+};
+
+/// Endpoint of the Pushover API to send messages.
+const MESSAGES_URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// Maximum length of a Pushover message.
+const MAX_MESSAGE_CHARS: usize = 1024;
+
+/// Priority of flights that are not long.
+const NORMAL_PRIORITY: i8 = 0;
+
+/// A Pushover message, sent as form parameters.
+#[derive(Debug, Default)]
+struct PushoverMessage<'a> {
+    message: String,
+    title: Option<&'a str>,
+    url: Option<&'a str>,
+    priority: i8,
+    /// JPEG image attached to the notification
+    attachment: Option<&'a [u8]>,
+}
+
+pub struct PushoverNotifier {
+    client: Client,
+    pool: Pool<Sqlite>,
+    app_token: String,
+    long_flight_km: f64,
+    long_flight_priority: i8,
+    messages: &'static Messages,
+    /// Whether flight notifications are marked as test messages
+    simulated: bool,
+}
+
+impl PushoverNotifier {
+    pub fn new(
+        tenant: &TenantConfig,
+        config: &PushoverConfig,
+        client: Client,
+        pool: Pool<Sqlite>,
+    ) -> Self {
+        Self {
+            client,
+            pool,
+            app_token: config.app_token.clone(),
+            long_flight_km: config.long_flight_km.unwrap_or(100.0),
+            long_flight_priority: config.long_flight_priority.unwrap_or(1).clamp(-2, 1),
+            messages: tenant.messages(),
+            simulated: false,
+        }
+    }
+
+    /// Return the priority of a notification about the flights: Raised if
+    /// one of them is long.
+    fn priority(&self, flights: &[Flight]) -> i8 {
+        let long = flights.iter().any(|flight| {
+            flight
+                .parsed_title
+                .as_ref()
+                .and_then(|parsed| parsed.distance_km)
+                .is_some_and(|distance| distance >= self.long_flight_km)
+        });
+        if long {
+            self.long_flight_priority
+        } else {
+            NORMAL_PRIORITY
+        }
+    }
+
+    async fn send(&self, message: PushoverMessage<'_>, user: &User) -> Result<(), Error> {
+        let context = "Could not send Pushover notification";
+        let user_key = db::get_pushover_user_key(&self.pool, user.id)
+            .await?
+            .ok_or_else(|| Error::NotFound {
+                context: "Could not send Pushover notification, no user key stored",
+                recipient: user.username.clone(),
+            })?;
+        let response = self
+            .client
+            .post(MESSAGES_URL)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(build_form(&self.app_token, &user_key, &message))
+            .send()
+            .await
+            .map_err(|source| Error::Http { context, source })?;
+        check_response(context, response, |status, body| {
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap_or_default();
+            if body["user"].as_str() == Some("invalid") {
+                return Error::NotFound {
+                    context,
+                    recipient: user.username.clone(),
+                };
+            }
+            Error::message(
+                context,
+                format!(
+                    "Pushover rejected the message (HTTP {}): {}",
+                    status, body["errors"]
+                ),
+            )
+        })
+        .await?;
+        tracing::debug!("Pushover notification sent to {}", user.username);
+        Ok(())
+    }
+}
+
+impl Channel for PushoverNotifier {
+    fn usertype() -> &'static str {
+        "pushover"
+    }
+
+    fn section() -> &'static str {
+        "pushover"
+    }
+
+    fn validate(
+        name: &str,
+        user_key: Option<&str>,
+    ) -> Result<(String, Option<String>), &'static str> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("invalid name");
+        }
+        // User and group keys are 30 characters long
+        let user_key = match user_key.map(str::trim) {
+            Some(key) if key.len() != 30 || !key.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                return Err("invalid user_key");
+            }
+            key => key.map(str::to_string),
+        };
+        Ok((name.to_string(), user_key))
+    }
+
+    /// Store the user key, which is required for new users.
+    fn store_setting<'a>(
+        pool: &'a Pool<Sqlite>,
+        user: &'a User,
+        user_key: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<Result<(), &'static str>>> {
+        Box::pin(async move {
+            match user_key {
+                Some(user_key) => db::set_pushover_user_key(pool, user.id, user_key).await?,
+                None if db::get_pushover_user_key(pool, user.id).await?.is_none() => {
+                    return Ok(Err("missing user_key"));
+                }
+                None => {}
+            }
+            Ok(Ok(()))
+        })
+    }
+
+    fn set_simulated(&mut self) {
+        self.simulated = true;
+    }
+
+    /// Notify the specified Pushover user about the flight.
+    fn notify<'a>(
+        &'a self,
+        flight: &'a Flight,
+        details: Option<&'a FlightDetails>,
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let title = if self.simulated {
+                Some(self.messages.simulated_flight)
+            } else if first_of_season {
+                Some(self.messages.first_flight_of_season)
+            } else {
+                None
+            };
+            let flight = match details {
+                Some(details) => flight.clone().with_details(details),
+                None => flight.clone(),
+            };
+            let message = PushoverMessage {
+                message: format::format_flight_plain(&flight, self.messages, MAX_MESSAGE_CHARS),
+                title,
+                url: Some(&flight.url),
+                priority: self.priority(std::slice::from_ref(&flight)),
+                attachment: details.map(|details| &details.thumbnail_small[..]),
+            };
+            self.send(message, user).await
+        })
+    }
+
+    /// Notify the specified Pushover user about several flights of one pilot.
+    fn notify_group<'a>(
+        &'a self,
+        pilot: &'a str,
+        flights: &'a [Flight],
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut header = messages::fill(
+                &format::plain_template(self.messages.group_header),
+                &[("count", &flights.len().to_string()), ("pilot", pilot)],
+            );
+            if first_of_season {
+                header.push('\n');
+                header.push_str(self.messages.first_flight_of_season);
+            }
+            let message = PushoverMessage {
+                message: format::format_flights_plain(
+                    &header,
+                    flights,
+                    self.messages,
+                    MAX_MESSAGE_CHARS,
+                ),
+                priority: self.priority(flights),
+                ..Default::default()
+            };
+            self.send(message, user).await
+        })
+    }
+
+    /// Notify the specified Pushover user that the title of the flight
+    /// changed.
+    fn notify_correction<'a>(
+        &'a self,
+        flight: &'a Flight,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let message = PushoverMessage {
+                message: format::format_flight_plain(flight, self.messages, MAX_MESSAGE_CHARS),
+                title: Some(self.messages.flight_corrected),
+                url: Some(&flight.url),
+                priority: NORMAL_PRIORITY,
+                attachment: None,
+            };
+            self.send(message, user).await
+        })
+    }
+}
+
+/// Build the form body of the `messages.json` request.
+fn build_form(app_token: &str, user_key: &str, message: &PushoverMessage<'_>) -> String {
+    let priority = message.priority.to_string();
+    let attachment = message.attachment.map(|image| STANDARD.encode(image));
+    let mut params = vec![
+        ("token", app_token),
+        ("user", user_key),
+        ("message", &message.message),
+        ("priority", &priority),
+    ];
+    if let Some(title) = message.title {
+        params.push(("title", title));
+    }
+    if let Some(url) = message.url {
+        params.push(("url", url));
+    }
+    if let Some(attachment) = &attachment {
+        params.push(("attachment_base64", attachment));
+        params.push(("attachment_type", "image/jpeg"));
+    }
+    // Cannot fail, all values are strings
+    serde_urlencoded::to_string(params).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn form_parameters() {
+        let message = PushoverMessage {
+            message: "Danilo Bargen\n104.37 km".into(),
+            title: Some("🎉"),
+            url: Some("https://www.xcontest.org/"),
+            priority: 1,
+            attachment: Some(b"jpeg"),
+        };
+        assert_eq!(
+            build_form("app", "user", &message),
+            "token=app&user=user&message=Danilo+Bargen%0A104.37+km&priority=1\
+             &title=%F0%9F%8E%89&url=https%3A%2F%2Fwww.xcontest.org%2F\
+             &attachment_base64=anBlZw%3D%3D&attachment_type=image%2Fjpeg"
+        );
+
+        let message = PushoverMessage {
+            message: "Text".into(),
+            priority: -1,
+            ..Default::default()
+        };
+        assert_eq!(
+            build_form("app", "user", &message),
+            "token=app&user=user&message=Text&priority=-1"
+        );
+    }
+}
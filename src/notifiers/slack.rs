@@ -0,0 +1,258 @@
+//! Slack notification channel (Slack app).
+//!
+//! Every Slack channel that follows pilots through the slash command is a user.
+//! Flights are posted there as Block Kit message, the image block links to the
+//! preview served by this server, so it's only added if `public_url` is set.
+
+use futures::future::BoxFuture;
+use reqwest::{header, Client};
+use serde_json::json;
+use sqlx::{Pool, Sqlite};
+use xcontest_client::{Flight, FlightDetails};
+
+use super::{check_response, format, Channel, Error};
+use crate::{
+    config::{SlackConfig, TenantConfig},
+    db::{self, User},
+    messages::{self, Messages},
+};
+
+/// Endpoint of the Slack Web API to post messages.
+const POST_MESSAGE_URL: &str = "https://slack.com/api/chat.postMessage";
+
+pub struct SlackNotifier {
+    client: Client,
+    pool: Pool<Sqlite>,
+    tenant: String,
+    bot_token: String,
+    public_url: Option<String>,
+    messages: &'static Messages,
+    /// Whether flight notifications are marked as test messages
+    simulated: bool,
+}
+
+impl SlackNotifier {
+    pub fn new(
+        tenant: &TenantConfig,
+        config: &SlackConfig,
+        client: Client,
+        pool: Pool<Sqlite>,
+    ) -> Self {
+        Self {
+            client,
+            pool,
+            tenant: tenant.id.clone(),
+            bot_token: config.bot_token.clone(),
+            public_url: config
+                .public_url
+                .as_ref()
+                .map(|url| url.trim_end_matches('/').to_string()),
+            messages: tenant.messages(),
+            simulated: false,
+        }
+    }
+
+    async fn send(&self, message: serde_json::Value, user: &User) -> Result<(), Error> {
+        let context = "Could not post Slack message";
+        let response = self
+            .client
+            .post(POST_MESSAGE_URL)
+            .bearer_auth(&self.bot_token)
+            .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(message.to_string())
+            .send()
+            .await
+            .map_err(|source| Error::Http { context, source })?;
+        let response = check_response(context, response, |status, _| {
+            Error::message(
+                context,
+                format!("Slack rejected the message (HTTP {})", status),
+            )
+        })
+        .await?;
+        let body = response
+            .bytes()
+            .await
+            .map_err(|source| Error::Http { context, source })?;
+        let body: serde_json::Value =
+            serde_json::from_slice(&body).map_err(|e| Error::message(context, e))?;
+        if body["ok"].as_bool() == Some(true) {
+            tracing::debug!("Slack message posted to {}", user.username);
+            return Ok(());
+        }
+        let error = body["error"].as_str().unwrap_or("unknown error");
+        match error {
+            "channel_not_found" | "is_archived" | "not_in_channel" => Err(Error::NotFound {
+                context,
+                recipient: user.username.clone(),
+            }),
+            error => Err(Error::Slack {
+                context,
+                error: error.to_string(),
+            }),
+        }
+    }
+}
+
+impl Channel for SlackNotifier {
+    fn usertype() -> &'static str {
+        "slack"
+    }
+
+    fn section() -> &'static str {
+        "slack"
+    }
+
+    fn set_simulated(&mut self) {
+        self.simulated = true;
+    }
+
+    /// Notify the specified Slack channel about the flight.
+    fn notify<'a>(
+        &'a self,
+        flight: &'a Flight,
+        details: Option<&'a FlightDetails>,
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let marker = if self.simulated {
+                Some(self.messages.simulated_flight)
+            } else if first_of_season {
+                Some(self.messages.first_flight_of_season)
+            } else {
+                None
+            };
+            let flight = match details {
+                Some(details) => flight.clone().with_details(details),
+                None => flight.clone(),
+            };
+            let mut text = format::format_flight(&flight, self.messages, format::MAX_TEXT_CHARS);
+            if let Some(marker) = marker {
+                text = format!("{}\n{}", marker, text);
+            }
+
+            // The preview image is served by our HTTP server, flights that were
+            // not stored (e.g. simulated flights) have none
+            let image_url = match (&self.public_url, details) {
+                (Some(public_url), Some(_)) => {
+                    db::get_flight_by_url(&self.pool, &self.tenant, &flight.url)
+                        .await?
+                        .map(|stored| format!("{}/flights/{}/thumb.jpg", public_url, stored.id))
+                }
+                _ => None,
+            };
+            let message = build_message(&user.username, &text, image_url.as_deref(), &flight.title);
+            self.send(message, user).await
+        })
+    }
+
+    /// Notify the specified Slack channel about several flights of one pilot.
+    fn notify_group<'a>(
+        &'a self,
+        pilot: &'a str,
+        flights: &'a [Flight],
+        first_of_season: bool,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut header = messages::fill(
+                self.messages.group_header,
+                &[("count", &flights.len().to_string()), ("pilot", pilot)],
+            );
+            if first_of_season {
+                header.push('\n');
+                header.push_str(self.messages.first_flight_of_season);
+            }
+            let text =
+                format::format_flights(&header, flights, self.messages, format::MAX_TEXT_CHARS);
+            let message = build_message(&user.username, &text, None, "");
+            self.send(message, user).await
+        })
+    }
+
+    /// Notify the specified Slack channel that the title of the flight
+    /// changed.
+    fn notify_correction<'a>(
+        &'a self,
+        flight: &'a Flight,
+        user: &'a User,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let text = format!(
+                "{}\n{}",
+                self.messages.flight_corrected,
+                format::format_flight(flight, self.messages, format::MAX_TEXT_CHARS)
+            );
+            let message = build_message(&user.username, &text, None, "");
+            self.send(message, user).await
+        })
+    }
+}
+
+/// Build the `chat.postMessage` request for the notification text (using
+/// Threema markdown, which Slack understands): A section with the text and an
+/// image block with the preview image, if available.
+fn build_message(
+    channel: &str,
+    text: &str,
+    image_url: Option<&str>,
+    alt_text: &str,
+) -> serde_json::Value {
+    let text = escape_slack(text);
+    let mut blocks = vec![json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": text },
+    })];
+    if let Some(image_url) = image_url {
+        blocks.push(json!({
+            "type": "image",
+            "image_url": image_url,
+            "alt_text": alt_text,
+        }));
+    }
+    json!({
+        "channel": channel,
+        // Shown in notifications and by clients without Block Kit support
+        "text": text,
+        "blocks": blocks,
+        "unfurl_links": false,
+    })
+}
+
+/// Escape the control characters of Slack message texts.
+fn escape_slack(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_blocks() {
+        let message = build_message(
+            "C0123456",
+            "*Danilo Bargen*\n21.98 km <free flight>",
+            Some("https://xcbot.example.com/flights/1/thumb.jpg"),
+            "09.08.20 [21.98 km :: free_flight] Danilo Bargen",
+        );
+        assert_eq!(message["channel"], "C0123456");
+        assert_eq!(
+            message["text"],
+            "*Danilo Bargen*\n21.98 km &lt;free flight&gt;"
+        );
+        assert_eq!(message["blocks"][0]["text"]["type"], "mrkdwn");
+        assert_eq!(message["blocks"][1]["type"], "image");
+        assert_eq!(
+            message["blocks"][1]["image_url"],
+            "https://xcbot.example.com/flights/1/thumb.jpg"
+        );
+
+        // Without preview image
+        let message = build_message("C0123456", "Text", None, "");
+        assert_eq!(message["blocks"].as_array().unwrap().len(), 1);
+    }
+}
@@ -4,63 +4,95 @@ use std::convert::TryInto;
 
 use anyhow::{Context, Result};
 use reqwest::Client;
-use sqlx::{Pool, Sqlite};
+use sqlx::{Pool, Sqlite, SqliteConnection};
 use threema_gateway::{
     encrypt_file_data, ApiBuilder, E2eApi, FileData, FileMessage, RenderingType,
 };
+use xcontest_client::{Flight, FlightDetails};
 
+use super::{format, Error};
 use crate::{
-    config::ThreemaConfig,
-    db::User,
-    threema,
-    xcontest::{Flight, FlightDetails},
+    config::TenantConfig,
+    db::{self, User},
+    messages::{self, Messages},
+    threema::{self, MessageKind},
 };
 
 pub struct ThreemaNotifier {
     api: E2eApi,
     pool: Pool<Sqlite>,
+    messages: &'static Messages,
+    monthly_cap: Option<u32>,
+    delivery_receipts: bool,
+    /// Whether flight notifications are marked as test messages
+    pub simulated: bool,
 }
 
 impl ThreemaNotifier {
-    pub fn new(config: &ThreemaConfig, client: Client, pool: Pool<Sqlite>) -> Result<Self> {
+    pub fn new(tenant: &TenantConfig, client: Client, pool: Pool<Sqlite>) -> Result<Self> {
+        let config = &tenant.threema;
         let api = ApiBuilder::new(&config.gateway_id, &config.gateway_secret)
             .with_client(client)
             .with_private_key_str(&config.private_key)
             .and_then(|builder| builder.into_e2e())
             .context("Could not create Threema API object")?;
-        Ok(Self { api, pool })
+        Ok(Self {
+            api,
+            pool,
+            messages: tenant.messages(),
+            monthly_cap: config.monthly_notification_cap,
+            delivery_receipts: config.request_delivery_receipts(),
+            simulated: false,
+        })
     }
 
     /// Notify the specified Threema user about the flight.
     pub async fn notify(
         &mut self,
+        conn: &mut SqliteConnection,
         flight: &Flight,
         details: Option<&FlightDetails>,
+        first_of_season: bool,
         user: &User,
-    ) -> Result<()> {
+    ) -> Result<(), Error> {
         tracing::debug!("notify");
 
+        // Enforce monthly notification cap
+        let month = db::current_month();
+        if !self.check_cap(conn, user, &month).await? {
+            return Ok(());
+        }
+
         // Fetch public key of recipient
         let public_key = threema::get_public_key(user, &self.api, &self.pool).await?;
 
-        // Notification text
-        let text = format!("{}\n{}", flight.title, flight.url);
+        // The detail page provides the start time, airtime and triangle stats
+        let with_details;
+        let flight = match details {
+            Some(details) => {
+                with_details = flight.clone().with_details(details);
+                &with_details
+            }
+            None => flight,
+        };
 
         // Depending on whether or not we have details, we'll send a text or image message.
         let msg_id = if let Some(details) = details {
+            let text = self.format_flight(flight, first_of_season, format::MAX_DESCRIPTION_CHARS);
+
             // Encrypt file message contents
             let (encrypted_file_data, key) = encrypt_file_data(&FileData {
                 file: details.thumbnail_large.to_vec(),
                 thumbnail: Some(details.thumbnail_small.to_vec()),
             })
-            .context("Failed to encrypt file data")?;
+            .map_err(|e| Error::message("Failed to encrypt file data", e))?;
 
             // Upload image data
             let file_blob_id = self
                 .api
                 .blob_upload_raw(&encrypted_file_data.file, false)
                 .await
-                .context("Could not upload file blob")?;
+                .map_err(|e| Error::api("Could not upload file blob", e, &user.username))?;
             let thumb_blob_id = self
                 .api
                 .blob_upload_raw(
@@ -70,41 +102,196 @@ impl ThreemaNotifier {
                     false,
                 )
                 .await
-                .context("Could not upload thumbnail blob")?;
+                .map_err(|e| Error::api("Could not upload thumbnail blob", e, &user.username))?;
 
             // Create file message
             let msg = FileMessage::builder(
                 file_blob_id,
                 key,
-                "image/png",
+                details.format.mime_type(),
                 encrypted_file_data.file.len().try_into().unwrap(),
             )
             .thumbnail(thumb_blob_id, "image/jpeg")
             .description(text)
-            .file_name("preview.png")
+            .file_name(format!("preview.{}", details.format.extension()))
             .rendering_type(RenderingType::Media)
-            .animated(false)
+            .animated(details.animated)
             .build()
-            .context("Could not create file message")?;
+            .map_err(|e| Error::message("Could not create file message", e))?;
             let encrypted = self
                 .api
                 .encrypt_file_msg(&msg, &public_key)
-                .context("Failed to encrypt file message")?;
+                .map_err(|e| Error::message("Failed to encrypt file message", e))?;
 
             // Send
-            self.api.send(&user.username, &encrypted, false).await?
+            self.api
+                .send(&user.username, &encrypted, self.delivery_receipts)
+                .await
+                .map_err(|e| Error::api("Could not send file message", e, &user.username))?
         } else {
             // Encrypt simple notification text message
+            let text = self.format_flight(flight, first_of_season, format::MAX_TEXT_CHARS);
             let encrypted = self
                 .api
                 .encrypt_text_msg(&text, &public_key)
-                .context("Failed to encrypt text message")?;
+                .map_err(|e| Error::message("Failed to encrypt text message", e))?;
 
             // Send
-            self.api.send(&user.username, &encrypted, false).await?
+            self.api
+                .send(&user.username, &encrypted, self.delivery_receipts)
+                .await
+                .map_err(|e| Error::api("Could not send text message", e, &user.username))?
         };
 
         tracing::debug!("Notification sent, message id is {}", msg_id);
+        let kind = MessageKind::Notification(&flight.url);
+        if let Err(e) =
+            db::insert_sent_message(&mut *conn, &msg_id, user.id, kind.name(), kind.flight_url())
+                .await
+        {
+            tracing::warn!("Could not store notification message: {}", e);
+        }
+        db::increment_notification_counter(conn, user.id, &month, details.is_some()).await?;
+        Ok(())
+    }
+
+    /// Notify the specified Threema user about several flights of one pilot
+    /// in a single text message.
+    pub async fn notify_group(
+        &mut self,
+        conn: &mut SqliteConnection,
+        pilot: &str,
+        flights: &[Flight],
+        first_of_season: bool,
+        user: &User,
+    ) -> Result<(), Error> {
+        // Enforce monthly notification cap
+        let month = db::current_month();
+        if !self.check_cap(conn, user, &month).await? {
+            return Ok(());
+        }
+
+        // Send text message
+        let mut header = messages::fill(
+            self.messages.group_header,
+            &[("count", &flights.len().to_string()), ("pilot", pilot)],
+        );
+        if first_of_season {
+            header.push('\n');
+            header.push_str(self.messages.first_flight_of_season);
+        }
+        let text = format::format_flights(&header, flights, self.messages, format::MAX_TEXT_CHARS);
+        let msg_id = threema::send_text_message(
+            user,
+            &text,
+            MessageKind::GroupNotification,
+            &self.api,
+            &self.pool,
+            self.delivery_receipts,
+        )
+        .await?;
+
+        tracing::debug!("Group notification sent, message id is {}", msg_id);
+        db::increment_notification_counter(conn, user.id, &month, false).await?;
+        Ok(())
+    }
+
+    /// Notify the specified Threema user that the title of the flight changed.
+    pub async fn notify_correction(
+        &mut self,
+        conn: &mut SqliteConnection,
+        flight: &Flight,
+        user: &User,
+    ) -> Result<(), Error> {
+        // Enforce monthly notification cap
+        let month = db::current_month();
+        if !self.check_cap(conn, user, &month).await? {
+            return Ok(());
+        }
+
+        // Send text message
+        let header = self.messages.flight_corrected;
+        let text = format!(
+            "{}\n{}",
+            header,
+            format::format_flight(
+                flight,
+                self.messages,
+                format::MAX_TEXT_CHARS.saturating_sub(header.chars().count() + 1)
+            )
+        );
+        let msg_id = threema::send_text_message(
+            user,
+            &text,
+            MessageKind::Correction(&flight.url),
+            &self.api,
+            &self.pool,
+            self.delivery_receipts,
+        )
+        .await?;
+
+        tracing::debug!("Correction sent, message id is {}", msg_id);
+        db::increment_notification_counter(conn, user.id, &month, false).await?;
         Ok(())
     }
+
+    /// Format the notification text for a flight, prefixed with the first
+    /// flight of the season marker if applicable.
+    fn format_flight(&self, flight: &Flight, first_of_season: bool, max_chars: usize) -> String {
+        let marker = if self.simulated {
+            self.messages.simulated_flight
+        } else if first_of_season {
+            self.messages.first_flight_of_season
+        } else {
+            return format::format_flight(flight, self.messages, max_chars);
+        };
+        format!(
+            "{}\n{}",
+            marker,
+            format::format_flight(
+                flight,
+                self.messages,
+                max_chars.saturating_sub(marker.chars().count() + 1)
+            )
+        )
+    }
+
+    /// Return whether the user may still be notified this month.
+    ///
+    /// When the monthly cap is reached, the user is told so (once).
+    async fn check_cap(
+        &self,
+        conn: &mut SqliteConnection,
+        user: &User,
+        month: &str,
+    ) -> Result<bool, Error> {
+        let cap = match self.monthly_cap {
+            Some(cap) => cap,
+            None => return Ok(true),
+        };
+        let counter = db::get_notification_counter(&mut *conn, user.id, month).await?;
+        if counter.messages < cap {
+            return Ok(true);
+        }
+        tracing::info!(
+            "Monthly notification cap reached for {}, not notifying",
+            user.username
+        );
+        if !counter.capped {
+            threema::send_text_message(
+                user,
+                &messages::fill(
+                    self.messages.notification_cap_reached,
+                    &[("count", &counter.messages.to_string())],
+                ),
+                MessageKind::CapReached,
+                &self.api,
+                &self.pool,
+                self.delivery_receipts,
+            )
+            .await?;
+            db::set_notification_capped(conn, user.id, month).await?;
+        }
+        Ok(false)
+    }
 }
@@ -0,0 +1,122 @@
+//! Protection against panics.
+//!
+//! A panic (e.g. in a parser, on a weird feed item) must neither take down the
+//! whole process nor go unnoticed. The update loop and the handling of incoming
+//! requests catch panics, count them (shown in the admin stats) and report them
+//! to the admin alert channel.
+
+use std::{
+    any::Any,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::FutureExt;
+
+use crate::{alerts::Alerter, status::BotStatus};
+
+/// Minimum time between two panic alerts, so that a crash loop (e.g. a panic
+/// on every fetch) doesn't flood the admin.
+const ALERT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Return the message of a panic.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(no message)")
+}
+
+/// Run the future, catching panics.
+///
+/// Return the output of the future, or the panic message.
+async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| panic_message(&*payload).to_string())
+}
+
+/// Catches and reports panics.
+#[derive(Clone)]
+pub struct PanicReporter {
+    alerter: Alerter,
+    status: Arc<BotStatus>,
+    /// When the last alert was sent
+    last_alert: Arc<Mutex<Option<Instant>>>,
+}
+
+impl PanicReporter {
+    pub fn new(alerter: Alerter, status: Arc<BotStatus>) -> Self {
+        Self {
+            alerter,
+            status,
+            last_alert: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Run the future, catching panics.
+    ///
+    /// If the future panics, the panic is reported and `None` is returned.
+    /// `location` describes what panicked (e.g. `update loop`).
+    pub async fn catch<F: Future>(&self, location: &str, future: F) -> Option<F::Output> {
+        match catch_panic(future).await {
+            Ok(output) => Some(output),
+            Err(message) => {
+                self.report(location, &message).await;
+                None
+            }
+        }
+    }
+
+    async fn report(&self, location: &str, message: &str) {
+        let count = self.status.record_panic();
+        tracing::error!(
+            location,
+            count,
+            panic = message,
+            "Panic in {}: {}",
+            location,
+            message
+        );
+        let alert = {
+            let mut last_alert = self.last_alert.lock().unwrap();
+            match *last_alert {
+                Some(last) if last.elapsed() < ALERT_INTERVAL => false,
+                _ => {
+                    *last_alert = Some(Instant::now());
+                    true
+                }
+            }
+        };
+        if alert {
+            self.alerter
+                .alert(&format!(
+                    "Panic in {} ({} since start): {}",
+                    location, count, message
+                ))
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn catch() {
+        assert_eq!(catch_panic(async { 42 }).await, Ok(42));
+        assert_eq!(
+            catch_panic(async { panic!("Unexpected feed item") }).await,
+            Err::<(), _>("Unexpected feed item".to_string())
+        );
+        assert_eq!(
+            catch_panic(async { panic!("Item {}", 42) }).await,
+            Err::<(), _>("Item 42".to_string())
+        );
+    }
+}
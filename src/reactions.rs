@@ -0,0 +1,155 @@
+//! Reactions of users to flight notifications.
+//!
+//! Threema users can agree (👍) or disagree (👎) with a message. The bot
+//! receives these reactions as delivery receipts with a special status and the
+//! IDs of the messages. The message IDs of notifications are stored together
+//! with the flight, so that the reactions can be attributed to the flight
+//! (e.g. for the most liked flight of the week in the digest).
+//!
+//! The other delivery receipts (received and read) are recorded with the sent
+//! message, if requested (`request_delivery_receipts`).
+
+use anyhow::{bail, Result};
+use sqlx::{Pool, Sqlite};
+
+use crate::db::{self, User};
+
+/// Delivery receipt status: The message was received
+const STATUS_RECEIVED: u8 = 0x01;
+
+/// Delivery receipt status: The message was read
+const STATUS_READ: u8 = 0x02;
+
+/// Delivery receipt status: The user agreed with the message
+const STATUS_AGREED: u8 = 0x03;
+
+/// Delivery receipt status: The user disagreed with the message
+const STATUS_DISAGREED: u8 = 0x04;
+
+/// Length of a message ID
+const MESSAGE_ID_LENGTH: usize = 8;
+
+/// Sent messages are kept this many days, reactions to older notifications
+/// are ignored.
+pub const NOTIFICATION_MESSAGE_DAYS: u32 = 30;
+
+/// The content of a delivery receipt.
+#[derive(Debug, PartialEq, Eq)]
+enum Receipt {
+    /// The messages were received (or read, if `read` is set)
+    Delivered {
+        read: bool,
+        message_ids: Vec<String>,
+    },
+    /// The user reacted to the messages (1 for agree, -1 for disagree)
+    Reaction {
+        reaction: i32,
+        message_ids: Vec<String>,
+    },
+}
+
+/// Parse the content of a delivery receipt.
+///
+/// Return the receipt with the hex encoded IDs of the messages, or `None` if
+/// the status is unknown.
+fn parse_receipt(data: &[u8]) -> Result<Option<Receipt>> {
+    let (status, message_ids) = match data.split_first() {
+        Some(split) => split,
+        None => bail!("Delivery receipt is empty"),
+    };
+    if !matches!(
+        *status,
+        STATUS_RECEIVED | STATUS_READ | STATUS_AGREED | STATUS_DISAGREED
+    ) {
+        return Ok(None);
+    }
+    if message_ids.is_empty() || message_ids.len() % MESSAGE_ID_LENGTH != 0 {
+        bail!("Invalid message IDs in delivery receipt");
+    }
+    let message_ids = message_ids
+        .chunks(MESSAGE_ID_LENGTH)
+        .map(hex::encode)
+        .collect();
+    Ok(Some(match *status {
+        STATUS_AGREED => Receipt::Reaction {
+            reaction: 1,
+            message_ids,
+        },
+        STATUS_DISAGREED => Receipt::Reaction {
+            reaction: -1,
+            message_ids,
+        },
+        status => Receipt::Delivered {
+            read: status == STATUS_READ,
+            message_ids,
+        },
+    }))
+}
+
+/// Record a delivery receipt of the user: The delivery of the sent messages
+/// or the reactions to them.
+pub async fn record_receipt(pool: &Pool<Sqlite>, user: &User, data: &[u8]) -> Result<()> {
+    match parse_receipt(data)? {
+        Some(Receipt::Delivered { read, message_ids }) => {
+            for message_id in &message_ids {
+                if !db::mark_sent_message(pool, message_id, user.id, read).await? {
+                    tracing::debug!("Ignoring receipt for unknown message {}", message_id);
+                }
+            }
+        }
+        Some(Receipt::Reaction {
+            reaction,
+            message_ids,
+        }) => {
+            for message_id in &message_ids {
+                if !db::set_flight_reaction(pool, message_id, user.id, reaction).await? {
+                    tracing::debug!("Ignoring reaction to unknown message {}", message_id);
+                }
+            }
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receipts() {
+        let mut data = vec![STATUS_AGREED];
+        data.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+        data.extend([0xa, 0xb, 0xc, 0xd, 0xe, 0xf, 0, 1]);
+        let message_ids = vec![
+            "0102030405060708".to_string(),
+            "0a0b0c0d0e0f0001".to_string(),
+        ];
+        assert_eq!(
+            parse_receipt(&data).unwrap(),
+            Some(Receipt::Reaction {
+                reaction: 1,
+                message_ids: message_ids.clone(),
+            })
+        );
+
+        // Read receipts are no reactions
+        data[0] = STATUS_READ;
+        assert_eq!(
+            parse_receipt(&data).unwrap(),
+            Some(Receipt::Delivered {
+                read: true,
+                message_ids,
+            })
+        );
+
+        // Unknown status
+        data[0] = 0x10;
+        assert_eq!(parse_receipt(&data).unwrap(), None);
+
+        // Truncated message ID
+        data[0] = STATUS_DISAGREED;
+        assert!(parse_receipt(&data[..12]).is_err());
+        assert!(parse_receipt(&[]).is_err());
+    }
+}
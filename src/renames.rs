@@ -0,0 +1,108 @@
+//! Detection of pilots that changed their XContest username.
+//!
+//! Subscriptions refer to usernames, so the followers of a renamed pilot would
+//! silently stop getting notifications. When the first flight of a username
+//! shows up, other usernames with the same display name are looked up. Their
+//! followers and the admin are told about the possible rename, together with
+//! the command to move the subscription.
+
+use anyhow::Result;
+use sqlx::{Pool, Sqlite};
+use xcontest_client::ParsedTitle;
+
+use crate::{
+    alerts::Alerter,
+    db, messages,
+    tenants::Tenant,
+    threema::{self, MessageKind},
+};
+
+/// Notify the followers of other usernames with the display name of the pilot
+/// about the possible rename.
+pub async fn detect_rename(
+    pool: &Pool<Sqlite>,
+    alerter: &Alerter,
+    tenant: &Tenant,
+    pilot: &str,
+    pilot_name: &str,
+) -> Result<()> {
+    let messages = tenant.config.messages();
+    for old_pilot in find_previous_usernames(pool, tenant.id(), pilot, pilot_name).await? {
+        let subscribers = db::get_pilot_subscribers(pool, tenant.id(), &old_pilot).await?;
+        if subscribers.is_empty() {
+            continue;
+        }
+        tracing::info!("Possible rename of pilot {} to {}", old_pilot, pilot);
+        let text = messages::fill(
+            messages.rename_detected,
+            &[("old", &old_pilot), ("new", pilot), ("name", pilot_name)],
+        );
+
+        // Failures are not retried, so that nobody gets the hint twice
+        let mut notified = 0;
+        for subscriber in &subscribers {
+            let result = match &*subscriber.usertype {
+                "threema" => threema::send_text_message(
+                    subscriber,
+                    &text,
+                    MessageKind::RenameHint,
+                    &tenant.api,
+                    pool,
+                    tenant.config.threema.request_delivery_receipts(),
+                )
+                .await
+                .map(|_| ()),
+                other => {
+                    tracing::warn!("Unsupported rename hint channel: {}", other);
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) => notified += 1,
+                Err(e) => tracing::error!(
+                    "Could not send rename hint to {}: {}",
+                    subscriber.username,
+                    e
+                ),
+            }
+        }
+        alerter
+            .alert(&format!(
+                "Possible rename of pilot {} to {} ({}) in tenant {}, notified {}/{} followers",
+                old_pilot,
+                pilot,
+                pilot_name,
+                tenant.id(),
+                notified,
+                subscribers.len()
+            ))
+            .await;
+    }
+    Ok(())
+}
+
+/// Return the other usernames of the tenant that uploaded flights with the
+/// same display name as the pilot.
+async fn find_previous_usernames(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    pilot: &str,
+    pilot_name: &str,
+) -> Result<Vec<String>> {
+    let mut usernames: Vec<String> = vec![];
+    for candidate in db::search_pilot_titles(pool, tenant, pilot_name).await? {
+        if candidate.pilot_username.eq_ignore_ascii_case(pilot)
+            || usernames
+                .iter()
+                .any(|username| username.eq_ignore_ascii_case(&candidate.pilot_username))
+        {
+            continue;
+        }
+        let same_name = ParsedTitle::parse(&candidate.title)
+            .is_some_and(|parsed| parsed.pilot_name.to_lowercase() == pilot_name.to_lowercase());
+        if same_name {
+            usernames.push(candidate.pilot_username);
+        }
+    }
+    Ok(usernames)
+}
@@ -0,0 +1,317 @@
+//! Scheduler for periodic tasks (digests, leaderboards, maintenance, backups,
+//! stats snapshots, weekly report, expiry of temporary subscriptions, club
+//! leaderboards).
+//!
+//! Task schedules are configured as cron expressions. When a task is due, a
+//! job is added to the persistent job queue (see [`crate::jobs`]) and the time
+//! of the run is persisted in the same transaction, so that a run missed while
+//! the bot was down is caught up (once) after a restart, and no run is
+//! repeated.
+
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use cron::Schedule;
+use tokio::task::JoinHandle;
+
+use sqlx::{Pool, Sqlite};
+
+use crate::{
+    alerts::Alerter,
+    config::SchedulerConfig,
+    db,
+    jobs::{Job, JobContext},
+    leader::Leadership,
+};
+
+mod tasks;
+
+pub use tasks::prune_data;
+
+/// Upper bound for the time the scheduler sleeps, so that changes of the
+/// system clock are picked up eventually.
+const MAX_SLEEP: chrono::Duration = chrono::Duration::hours(1);
+
+/// A periodic task.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Task {
+    Digest,
+    Leaderboards,
+    Maintenance,
+    Backup,
+    Stats,
+    WeeklyReport,
+    ExpireFollows,
+    ClubLeaderboards,
+}
+
+impl Task {
+    /// The name under which the last run is persisted.
+    fn name(&self) -> &'static str {
+        match self {
+            Task::Digest => "digest",
+            Task::Leaderboards => "leaderboards",
+            Task::Maintenance => "maintenance",
+            Task::Backup => "backup",
+            Task::Stats => "stats",
+            Task::WeeklyReport => "weekly_report",
+            Task::ExpireFollows => "expire_follows",
+            Task::ClubLeaderboards => "club_leaderboards",
+        }
+    }
+
+    /// Return the task with the specified name.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "digest" => Some(Task::Digest),
+            "leaderboards" => Some(Task::Leaderboards),
+            "maintenance" => Some(Task::Maintenance),
+            "backup" => Some(Task::Backup),
+            "stats" => Some(Task::Stats),
+            "weekly_report" => Some(Task::WeeklyReport),
+            "expire_follows" => Some(Task::ExpireFollows),
+            "club_leaderboards" => Some(Task::ClubLeaderboards),
+            _ => None,
+        }
+    }
+
+    async fn run(
+        &self,
+        context: &JobContext,
+        alerter: &Alerter,
+        last_run: DateTime<Utc>,
+    ) -> Result<()> {
+        match self {
+            Task::Digest => tasks::send_digests(context, last_run).await,
+            Task::Leaderboards => tasks::compute_leaderboards(context).await,
+            Task::Maintenance => tasks::run_maintenance(context).await,
+            Task::Backup => tasks::backup_database(context).await,
+            Task::Stats => tasks::record_stats(context).await,
+            Task::WeeklyReport => tasks::send_weekly_report(context, alerter).await,
+            Task::ExpireFollows => tasks::expire_follows(context).await,
+            Task::ClubLeaderboards => tasks::send_club_leaderboards(context).await,
+        }
+    }
+}
+
+/// Run the task with the specified name (called by the job queue worker).
+pub async fn run_task(
+    name: &str,
+    context: &JobContext,
+    alerter: &Alerter,
+    last_run: DateTime<Utc>,
+) -> Result<()> {
+    let task = Task::from_name(name).context(format!("Unknown task: {}", name))?;
+    tracing::info!("Running scheduled task {}", name);
+    task.run(context, alerter, last_run).await
+}
+
+struct ScheduledTask {
+    task: Task,
+    schedule: Schedule,
+}
+
+/// Parse a cron expression.
+///
+/// Both standard five-field expressions (`minute hour day-of-month month
+/// day-of-week`) and expressions with a leading seconds field are supported.
+pub fn parse_schedule(expression: &str) -> Result<Schedule> {
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    };
+    Schedule::from_str(&expression).context(format!("Invalid cron expression: {}", expression))
+}
+
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+    pool: Pool<Sqlite>,
+    leadership: Arc<Leadership>,
+}
+
+impl Scheduler {
+    /// Create a scheduler for all configured tasks. Tasks are only enqueued
+    /// while this instance is the leader.
+    pub fn new(
+        config: Option<&SchedulerConfig>,
+        pool: Pool<Sqlite>,
+        leadership: Arc<Leadership>,
+    ) -> Result<Self> {
+        let default_config = SchedulerConfig::default();
+        let config = config.unwrap_or(&default_config);
+        let mut tasks = vec![
+            (
+                Task::Digest,
+                config.digest.as_deref().unwrap_or("0 20 * * *"),
+            ),
+            (
+                Task::Leaderboards,
+                config.leaderboards.as_deref().unwrap_or("0 3 * * *"),
+            ),
+            (
+                Task::Maintenance,
+                config.maintenance.as_deref().unwrap_or("30 3 * * *"),
+            ),
+            (
+                Task::Stats,
+                config.stats.as_deref().unwrap_or("55 23 * * *"),
+            ),
+            (
+                Task::WeeklyReport,
+                config.weekly_report.as_deref().unwrap_or("0 9 * * 1"),
+            ),
+            (
+                Task::ExpireFollows,
+                config.expire_follows.as_deref().unwrap_or("0 8 * * *"),
+            ),
+            (
+                Task::ClubLeaderboards,
+                config.club_leaderboards.as_deref().unwrap_or("0 18 1 * *"),
+            ),
+        ];
+        if config.backup_dir.is_some() {
+            tasks.push((
+                Task::Backup,
+                config.backup.as_deref().unwrap_or("0 4 * * *"),
+            ));
+        }
+        let tasks = tasks
+            .into_iter()
+            .map(|(task, expression)| {
+                Ok(ScheduledTask {
+                    task,
+                    schedule: parse_schedule(expression)
+                        .context(format!("Invalid schedule for task {}", task.name()))?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            tasks,
+            pool,
+            leadership,
+        })
+    }
+
+    /// Run the scheduler in a background task.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(self) {
+        for scheduled in &self.tasks {
+            tracing::info!(
+                "Scheduled task {} ({})",
+                scheduled.task.name(),
+                scheduled.schedule
+            );
+        }
+        loop {
+            let now = Utc::now();
+            if !self.leadership.is_leader() {
+                tokio::time::sleep(self.leadership.renew_interval()).await;
+                continue;
+            }
+            let mut next_wakeup = now + MAX_SLEEP;
+            for scheduled in &self.tasks {
+                match self.enqueue_if_due(scheduled, now).await {
+                    Ok(Some(next_run)) => next_wakeup = next_wakeup.min(next_run),
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::error!("Could not schedule task {}: {}", scheduled.task.name(), e)
+                    }
+                }
+            }
+            let sleep = (next_wakeup - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+                .max(std::time::Duration::from_secs(1));
+            tokio::time::sleep(sleep).await;
+        }
+    }
+
+    /// Enqueue the task if it is due. Return the time of the next run.
+    async fn enqueue_if_due(
+        &self,
+        scheduled: &ScheduledTask,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let name = scheduled.task.name();
+        let pool = &self.pool;
+
+        // On the very first start, schedule relative to now
+        let last_run = match db::get_last_run(pool, name).await? {
+            Some(last_run) => last_run,
+            None => {
+                db::set_last_run(pool, name, now).await?;
+                now
+            }
+        };
+
+        // Wait until the next run is due
+        match next_run_after(&scheduled.schedule, last_run) {
+            Some(next_run) if next_run > now => return Ok(Some(next_run)),
+            Some(_) => {}
+            None => return Ok(None),
+        }
+
+        // Enqueue task run
+        tracing::debug!("Enqueueing scheduled task {}", name);
+        let job = Job::Task {
+            task: name.to_string(),
+            last_run: db::sql_timestamp(last_run),
+        };
+        db::enqueue_task_run(pool, name, &job.to_payload()?, now).await?;
+        Ok(next_run_after(&scheduled.schedule, now))
+    }
+}
+
+/// Return the first run of the schedule after the specified time (the
+/// schedule is interpreted in local time).
+fn next_run_after(schedule: &Schedule, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule
+        .after(&time.with_timezone(&Local))
+        .next()
+        .map(|next| next.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn parse_five_field_expression() {
+        let schedule = parse_schedule("30 3 * * *").unwrap();
+        let after = Local.with_ymd_and_hms(2026, 10, 17, 12, 0, 0).unwrap();
+        let next = schedule.after(&after).next().unwrap();
+        assert_eq!(
+            next,
+            Local.with_ymd_and_hms(2026, 10, 18, 3, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_invalid_expression() {
+        assert!(parse_schedule("every day").is_err());
+    }
+
+    #[test]
+    fn missed_run_is_due() {
+        let schedule = parse_schedule("0 20 * * *").unwrap();
+        let last_run = Local
+            .with_ymd_and_hms(2026, 10, 16, 20, 0, 5)
+            .unwrap()
+            .with_timezone(&Utc);
+        let next = next_run_after(&schedule, last_run).unwrap();
+        assert_eq!(
+            next,
+            Local
+                .with_ymd_and_hms(2026, 10, 17, 20, 0, 0)
+                .unwrap()
+                .with_timezone(&Utc)
+        );
+    }
+}
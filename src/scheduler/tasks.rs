@@ -0,0 +1,488 @@
+//! The periodic tasks run by the scheduler.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc, Weekday};
+use sqlx::{Pool, Sqlite};
+use xcontest_client::Flight;
+
+use crate::{
+    alerts::Alerter,
+    clubs,
+    config::RetentionConfig,
+    db::{self, LeaderboardEntry, User},
+    jobs::JobContext,
+    messages::{self, Messages},
+    notifiers::format,
+    tenants::Tenant,
+    threema::{self, MessageKind},
+};
+
+/// Send a digest of all flights seen since the last digest to the users that
+/// opted in.
+///
+/// On Sundays, the digest ends with the most liked flight of the week.
+pub async fn send_digests(context: &JobContext, since: DateTime<Utc>) -> Result<()> {
+    if !context.config.features().digests() {
+        tracing::info!("Digests are disabled, not sending any");
+        return Ok(());
+    }
+    let users = db::get_digest_users(&context.pool).await?;
+    let month = db::current_month();
+    let weekly = Local::now().weekday() == Weekday::Sun;
+    let mut most_liked: HashMap<String, Option<String>> = HashMap::new();
+    let mut sent = 0;
+    for user in users {
+        let tenant = match context.tenants.get(&user.tenant) {
+            Some(tenant) => tenant,
+            None => {
+                tracing::warn!("Unknown tenant {} of user {}", user.tenant, user.id);
+                continue;
+            }
+        };
+        let flights = db::get_digest_flights(&context.pool, user.id, since)
+            .await?
+            .iter()
+            .filter_map(|stored| stored.to_flight().ok())
+            .collect::<Vec<_>>();
+        if flights.is_empty() {
+            continue;
+        }
+        let messages = tenant.config.messages();
+        if weekly && !most_liked.contains_key(tenant.id()) {
+            let text = format_most_liked(context, tenant.id(), messages).await?;
+            most_liked.insert(tenant.id().to_string(), text);
+        }
+        let footer = most_liked.get(tenant.id()).cloned().flatten();
+        let mut text = format::format_flights(
+            messages.digest_header,
+            &flights,
+            messages,
+            format::MAX_TEXT_CHARS
+                - footer
+                    .as_ref()
+                    .map_or(0, |footer| footer.chars().count() + 2),
+        );
+        if let Some(footer) = footer {
+            text.push_str("\n\n");
+            text.push_str(&footer);
+        }
+        let result = match &*user.usertype {
+            "threema" => threema::send_text_message(
+                &user,
+                &text,
+                MessageKind::Digest,
+                &tenant.api,
+                &context.pool,
+                tenant.config.threema.request_delivery_receipts(),
+            )
+            .await
+            .map(|_| ()),
+            other => {
+                tracing::warn!("Unsupported digest channel: {}", other);
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => {
+                sent += 1;
+                db::increment_notification_counter(&context.pool, user.id, &month, false).await?;
+            }
+            Err(e) => tracing::error!("Could not send digest to {}: {}", user.username, e),
+        }
+    }
+    tracing::info!("Sent {} digests", sent);
+    Ok(())
+}
+
+/// Format the most liked flight of the tenant in the last week, if any flight
+/// got a like.
+async fn format_most_liked(
+    context: &JobContext,
+    tenant: &str,
+    messages: &Messages,
+) -> Result<Option<String>> {
+    let since = Utc::now() - chrono::Duration::days(7);
+    let (stored, likes) = match db::get_most_liked_flight(&context.pool, tenant, since).await? {
+        Some(most_liked) => most_liked,
+        None => return Ok(None),
+    };
+    let header = messages::fill(messages.digest_most_liked, &[("count", &likes.to_string())]);
+    Ok(Some(format!(
+        "{}\n{}",
+        header,
+        format::format_flight(&stored.to_flight()?, messages, 300)
+    )))
+}
+
+/// Recompute the leaderboards of all tenants in the current month (and in the
+/// previous month, so that its last day is included as well).
+pub async fn compute_leaderboards(context: &JobContext) -> Result<()> {
+    let now = Local::now();
+    let yesterday = now - chrono::Duration::days(1);
+    let mut months = vec![yesterday.format("%Y-%m").to_string()];
+    months.push(now.format("%Y-%m").to_string());
+    months.dedup();
+    for tenant in context.tenants.iter() {
+        for month in &months {
+            let flights = db::get_month_flights(&context.pool, tenant.id(), month)
+                .await?
+                .iter()
+                .filter_map(|stored| stored.to_flight().ok())
+                .collect::<Vec<_>>();
+            let leaderboard = compute_leaderboard(&flights);
+            db::replace_leaderboard(&context.pool, tenant.id(), month, &leaderboard).await?;
+            tracing::info!(
+                "Computed leaderboard of tenant {} for {} ({} pilots)",
+                tenant.id(),
+                month,
+                leaderboard.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Aggregate flights per pilot, ordered by total distance.
+///
+/// Flights without a parseable distance are counted, but don't contribute to
+/// the distance.
+fn compute_leaderboard(flights: &[Flight]) -> Vec<LeaderboardEntry> {
+    let mut entries: HashMap<String, LeaderboardEntry> = HashMap::new();
+    for flight in flights {
+        let entry = entries
+            .entry(flight.pilot_username.to_lowercase())
+            .or_insert_with(|| LeaderboardEntry {
+                pilot_username: flight.pilot_username.clone(),
+                flights: 0,
+                distance_km: 0.0,
+                max_km: 0.0,
+            });
+        let distance = flight
+            .parsed_title
+            .as_ref()
+            .and_then(|parsed| parsed.distance_km)
+            .unwrap_or(0.0);
+        entry.flights += 1;
+        entry.distance_km += distance;
+        entry.max_km = entry.max_km.max(distance);
+    }
+    let mut entries: Vec<LeaderboardEntry> = entries.into_values().collect();
+    entries.sort_by(|a, b| {
+        b.distance_km
+            .total_cmp(&a.distance_km)
+            .then_with(|| a.pilot_username.cmp(&b.pilot_username))
+    });
+    entries
+}
+
+/// Evict expired cache entries and let SQLite optimize the database.
+pub async fn run_maintenance(context: &JobContext) -> Result<()> {
+    let evicted = context.details_cache.evict_expired().await?;
+    tracing::info!("Evicted {} expired flight details", evicted);
+    prune_data(&context.pool, &context.config.retention()).await?;
+    db::optimize(&context.pool).await?;
+    Ok(())
+}
+
+/// Number of rows removed by [`prune_data`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Pruned {
+    pub flights: u64,
+    pub notifications: u64,
+    pub parse_failures: u64,
+}
+
+/// Remove the data older than configured in the `[retention]` section.
+pub async fn prune_data(pool: &Pool<Sqlite>, retention: &RetentionConfig) -> Result<Pruned> {
+    let pruned = Pruned {
+        flights: db::prune_flights(pool, retention.flights_days()).await?,
+        notifications: db::evict_sent_messages(pool, retention.notifications_days()).await?,
+        parse_failures: db::prune_parse_failures(pool, retention.parse_failures_days()).await?,
+    };
+    tracing::info!(
+        "Pruned {} flights, {} sent messages and {} parse failures",
+        pruned.flights,
+        pruned.notifications,
+        pruned.parse_failures
+    );
+    Ok(pruned)
+}
+
+/// End the expired temporary subscriptions and tell the users about it.
+pub async fn expire_follows(context: &JobContext) -> Result<()> {
+    let expired = db::remove_expired_subscriptions(&context.pool).await?;
+    let mut pilots_by_user: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+    for (user_id, pilot) in expired {
+        pilots_by_user.entry(user_id).or_default().push(pilot);
+    }
+    for (user_id, pilots) in &pilots_by_user {
+        let user = match db::get_user(&context.pool, *user_id).await? {
+            Some(user) => user,
+            None => continue,
+        };
+        let tenant = match context.tenants.get(&user.tenant) {
+            Some(tenant) => tenant,
+            None => {
+                tracing::warn!("Unknown tenant {} of user {}", user.tenant, user.id);
+                continue;
+            }
+        };
+        let text = messages::fill(
+            tenant.config.messages().follow_expired,
+            &[("pilots", &pilots.join(", "))],
+        );
+        if let Err(e) = send_text(context, tenant, &user, &text, MessageKind::FollowExpired).await {
+            tracing::error!("Could not send expiry message to {}: {}", user.username, e);
+        }
+    }
+    tracing::info!(
+        "Ended the expired temporary subscriptions of {} users",
+        pilots_by_user.len()
+    );
+    Ok(())
+}
+
+/// Send the leaderboards of the previous month to the subscribers of the
+/// clubs (run at the beginning of the month, after the leaderboards of the
+/// previous month were computed for the last time).
+pub async fn send_club_leaderboards(context: &JobContext) -> Result<()> {
+    let month = (Local::now().with_day(1).unwrap_or_else(Local::now) - Duration::days(1))
+        .format("%Y-%m")
+        .to_string();
+    let mut sent = 0;
+    for tenant in context.tenants.iter() {
+        for club in &tenant.config.clubs {
+            let subscribers =
+                db::get_club_subscribers(&context.pool, tenant.id(), &club.code).await?;
+            if subscribers.is_empty() {
+                continue;
+            }
+            let entries = clubs::leaderboard(&context.pool, tenant.id(), club, &month).await?;
+            let text = clubs::format_leaderboard(club, &month, &entries, tenant.config.messages());
+            for user in &subscribers {
+                match send_text(context, tenant, user, &text, MessageKind::ClubLeaderboard).await {
+                    Ok(()) => sent += 1,
+                    Err(e) => tracing::error!(
+                        "Could not send club leaderboard to {}: {}",
+                        user.username,
+                        e
+                    ),
+                }
+            }
+        }
+    }
+    tracing::info!("Sent {} club leaderboards for {}", sent, month);
+    Ok(())
+}
+
+/// Send a text message of the specified kind to the user.
+async fn send_text(
+    context: &JobContext,
+    tenant: &Tenant,
+    user: &User,
+    text: &str,
+    kind: MessageKind<'_>,
+) -> Result<()> {
+    match &*user.usertype {
+        "threema" => {
+            threema::send_text_message(
+                user,
+                text,
+                kind,
+                &tenant.api,
+                &context.pool,
+                tenant.config.threema.request_delivery_receipts(),
+            )
+            .await?;
+        }
+        other => tracing::warn!("Unsupported channel: {}", other),
+    }
+    Ok(())
+}
+
+/// Store a snapshot of the stats of every tenant, for long-term trends.
+pub async fn record_stats(context: &JobContext) -> Result<()> {
+    for tenant in context.tenants.iter() {
+        db::record_stats_snapshot(&context.pool, tenant.id()).await?;
+    }
+    tracing::info!("Recorded stats snapshots");
+    Ok(())
+}
+
+/// Send the weekly report (activity, notification success rate and top
+/// errors) to the admin.
+pub async fn send_weekly_report(context: &JobContext, alerter: &Alerter) -> Result<()> {
+    let mut text = String::from("📊 Weekly report");
+    for tenant in context.tenants.iter() {
+        let activity = db::get_weekly_activity(&context.pool, tenant.id()).await?;
+        text.push_str(&format!(
+            "\n\n{}:\n- New users: {}\n- Churned users: {}\n- Flights processed: {}",
+            tenant.id(),
+            activity.new_users,
+            activity.churned_users,
+            activity.flights,
+        ));
+
+        // Notification counts are cumulative, compare with the snapshot of a week ago
+        let history = db::get_stats_history(&context.pool, tenant.id(), 14).await?;
+        if let Some((sent, failed)) = weekly_notifications(&history) {
+            let success_rate = if sent == 0 {
+                100.0
+            } else {
+                f64::from(sent.saturating_sub(failed)) * 100.0 / f64::from(sent)
+            };
+            text.push_str(&format!(
+                "\n- Notifications: {} ({} failed, {:.1}% delivered on first attempt)",
+                sent, failed, success_rate,
+            ));
+        }
+    }
+
+    let errors = db::get_top_errors(&context.pool, 5).await?;
+    if errors.is_empty() {
+        text.push_str("\n\nNo errors this week.");
+    } else {
+        text.push_str("\n\nTop errors:");
+        for (error, count) in errors {
+            text.push_str(&format!("\n- {}× {}", count, error));
+        }
+    }
+
+    alerter.report(&text).await;
+    tracing::info!("Sent weekly report");
+    Ok(())
+}
+
+/// Return the number of notifications sent and failed within the last week of
+/// the stats history (oldest first).
+fn weekly_notifications(history: &[db::StatsSnapshot]) -> Option<(u32, u32)> {
+    let last = history.last()?;
+    let week_ago = (NaiveDate::parse_from_str(&last.date, "%Y-%m-%d").ok()? - Duration::days(7))
+        .format("%Y-%m-%d")
+        .to_string();
+    let first = history
+        .iter()
+        .rev()
+        .find(|snapshot| snapshot.date <= week_ago)
+        .or_else(|| history.first())?;
+    Some((
+        last.notifications.saturating_sub(first.notifications),
+        last.notification_failures
+            .saturating_sub(first.notification_failures),
+    ))
+}
+
+/// Back up the database and remove old backups.
+pub async fn backup_database(context: &JobContext) -> Result<()> {
+    let scheduler_config = context.config.scheduler.as_ref();
+    let backup_dir = match scheduler_config.and_then(|scheduler| scheduler.backup_dir.as_ref()) {
+        Some(dir) => Path::new(dir),
+        None => return Ok(()),
+    };
+    let backup_keep = scheduler_config
+        .and_then(|scheduler| scheduler.backup_keep)
+        .unwrap_or(7);
+    std::fs::create_dir_all(backup_dir).context(format!(
+        "Could not create backup directory {:?}",
+        backup_dir
+    ))?;
+
+    // Write backup (VACUUM INTO refuses to overwrite existing files)
+    let path = backup_dir.join(format!(
+        "xc-bot-{}.db",
+        Local::now().format("%Y-%m-%d-%H%M")
+    ));
+    if path.exists() {
+        std::fs::remove_file(&path).context(format!("Could not remove {:?}", path))?;
+    }
+    db::backup(&context.pool, &path.to_string_lossy()).await?;
+    tracing::info!("Backed up database to {:?}", path);
+
+    // Remove old backups (the timestamped names sort chronologically)
+    let mut backups = std::fs::read_dir(backup_dir)
+        .context(format!("Could not list backup directory {:?}", backup_dir))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("xc-bot-") && name.ends_with(".db"))
+        })
+        .collect::<Vec<_>>();
+    backups.sort();
+    let obsolete = backups.len().saturating_sub(backup_keep);
+    for path in &backups[..obsolete] {
+        tracing::info!("Removing old backup {:?}", path);
+        std::fs::remove_file(path).context(format!("Could not remove {:?}", path))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flight(title: &str, pilot: &str) -> Flight {
+        Flight::new(
+            title.to_string(),
+            format!(
+                "https://www.xcontest.org/2020/switzerland/en/flights/detail:{}/9.8.2020/10:45",
+                pilot
+            ),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn leaderboard_ordered_by_distance() {
+        let leaderboard = compute_leaderboard(&[
+            flight("09.08.20 [21.98 km :: free_flight] Danilo Bargen", "dbrgn"),
+            flight("10.08.20 [101.50 km :: fai_triangle] Chrigel M", "chrigel"),
+            flight(
+                "11.08.20 [30.02 km :: flat_triangle] Danilo Bargen",
+                "dbrgn",
+            ),
+            flight("unparseable", "dbrgn"),
+        ]);
+        assert_eq!(leaderboard.len(), 2);
+        assert_eq!(leaderboard[0].pilot_username, "chrigel");
+        assert_eq!(leaderboard[1].pilot_username, "dbrgn");
+        assert_eq!(leaderboard[1].flights, 3);
+        assert!((leaderboard[1].distance_km - 52.0).abs() < 0.001);
+        assert!((leaderboard[1].max_km - 30.02).abs() < 0.001);
+    }
+
+    fn snapshot(date: &str, notifications: u32, notification_failures: u32) -> db::StatsSnapshot {
+        db::StatsSnapshot {
+            date: date.to_string(),
+            users: 0,
+            subscriptions: 0,
+            flights: 0,
+            notifications,
+            failures: 0,
+            notification_failures,
+        }
+    }
+
+    #[test]
+    fn weekly_notifications_counts() {
+        assert_eq!(weekly_notifications(&[]), None);
+
+        // Compared with the snapshot of a week ago
+        let history = vec![
+            snapshot("2026-10-01", 10, 1),
+            snapshot("2026-10-05", 20, 2),
+            snapshot("2026-10-08", 30, 3),
+            snapshot("2026-10-12", 50, 7),
+        ];
+        assert_eq!(weekly_notifications(&history), Some((30, 5)));
+
+        // Fall back to the oldest snapshot
+        let history = vec![snapshot("2026-10-10", 10, 1), snapshot("2026-10-12", 15, 1)];
+        assert_eq!(weekly_notifications(&history), Some((5, 0)));
+    }
+}
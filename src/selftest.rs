@@ -0,0 +1,217 @@
+//! The `selftest` command: Check whether a deployment is ready for
+//! production traffic.
+//!
+//! Every check is run independently (as far as possible) and the results are
+//! printed as a pass/fail report.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use threema_gateway::{RecipientKey, SecretKey};
+use xcontest_client as xcontest;
+
+use crate::{
+    config::{Config, ThreemaConfig},
+    db::{self, PoolSettings},
+    tenants::DEFAULT_TENANT,
+};
+
+/// Run all checks and print a report. Return whether all checks passed.
+pub async fn run(configfile: &Path) -> bool {
+    let mut report = Report::default();
+
+    // Config
+    let config = match Config::load_or_env(configfile) {
+        Ok(config) => {
+            if configfile.exists() {
+                report.pass("config", format!("Loaded {:?}", configfile));
+            } else {
+                report.pass("config", "Loaded from environment".to_string());
+            }
+            config
+        }
+        Err(e) => {
+            report.fail("config", format!("Could not load {:?}: {}", configfile, e));
+            return report.finish();
+        }
+    };
+
+    // Database migrations
+    report.check("database", check_migrations(config.database_path()).await);
+
+    // Threema credentials of all tenants
+    let tenants = config.tenants();
+    for tenant in &tenants {
+        let check = match tenant.id.as_str() {
+            DEFAULT_TENANT => "threema".to_string(),
+            id => format!("threema/{}", id),
+        };
+        report.check(&check, check_threema(&tenant.threema).await);
+    }
+
+    // Message overrides of all tenants
+    for tenant in &tenants {
+        let path = match tenant
+            .messages
+            .as_ref()
+            .and_then(|m| m.override_file.as_ref())
+        {
+            Some(path) => path,
+            None => continue,
+        };
+        let check = match tenant.id.as_str() {
+            DEFAULT_TENANT => "messages".to_string(),
+            id => format!("messages/{}", id),
+        };
+        let result = tenant
+            .clone()
+            .load_messages()
+            .map(|()| format!("Loaded overrides from {:?}", path));
+        report.check(&check, result);
+    }
+
+    // XContest parsers and live feed
+    report.check(
+        "parsers",
+        xcontest::parser_self_test().map(|(feed, detail)| {
+            format!(
+                "Sample payloads match (feed: {}, detail page: {})",
+                feed, detail
+            )
+        }),
+    );
+    let xc = crate::build_http_client().and_then(|client| crate::build_xcontest(&config, client));
+    let mut feed_urls: Vec<&str> = tenants.iter().map(|tenant| tenant.feed_url()).collect();
+    feed_urls.dedup();
+    match &xc {
+        Ok(xc) => {
+            for feed_url in feed_urls {
+                report.check(
+                    "xcontest",
+                    xc.fetch_flights(feed_url)
+                        .await
+                        .map_err(Into::into)
+                        .map(|items| {
+                            format!(
+                                "Fetched {}, {} flights parsed, {} unparseable items",
+                                feed_url,
+                                items.flights.len(),
+                                items.failures.len()
+                            )
+                        }),
+                )
+            }
+        }
+        Err(e) => report.fail("xcontest", format!("{:#}", e)),
+    }
+
+    // Image pipeline
+    report.check(
+        "images",
+        xc.and_then(|xc| Ok(xc.process_preview(Bytes::from_static(xcontest::SAMPLE_THUMBNAIL))?))
+            .map(|details| {
+                format!(
+                    "Processed sample image ({} bytes large, {} bytes small)",
+                    details.thumbnail_large.len(),
+                    details.thumbnail_small.len()
+                )
+            }),
+    );
+
+    report.finish()
+}
+
+/// Apply the migrations to a temporary copy of the database.
+async fn check_migrations(database_path: &str) -> Result<String> {
+    let tempdir = std::env::temp_dir().join(format!("xc-bot-selftest-{}", std::process::id()));
+    std::fs::create_dir_all(&tempdir).context("Could not create temporary directory")?;
+    let copy = tempdir.join("data.db");
+    let result = async {
+        let copied = if Path::new(database_path).exists() {
+            let pool = db::connect(database_path, &PoolSettings::default()).await?;
+            db::backup(&pool, &copy.to_string_lossy()).await?;
+            pool.close().await;
+            true
+        } else {
+            false
+        };
+        let pool = db::connect(&copy.to_string_lossy(), &PoolSettings::default()).await?;
+        db::migrate(&pool).await?;
+        pool.close().await;
+        Ok(if copied {
+            format!("Migrations apply to a copy of {}", database_path)
+        } else {
+            format!(
+                "{} does not exist yet, migrations apply to a new database",
+                database_path
+            )
+        })
+    }
+    .await;
+    if let Err(e) = std::fs::remove_dir_all(&tempdir) {
+        eprintln!("Could not remove temporary directory {:?}: {}", tempdir, e);
+    }
+    result
+}
+
+/// Check the gateway credentials and whether the private key matches the
+/// public key registered for the gateway ID.
+async fn check_threema(config: &ThreemaConfig) -> Result<String> {
+    let api = crate::build_threema_api(config)?;
+    let credits = api
+        .lookup_credits()
+        .await
+        .context("Could not look up credits (invalid gateway ID or secret?)")?;
+    let registered = api
+        .lookup_pubkey(&config.gateway_id)
+        .await
+        .context("Could not look up public key of gateway ID")?;
+    let private_key = hex::decode(&config.private_key).context("Invalid private key")?;
+    let public_key = RecipientKey::from(
+        SecretKey::from_slice(&private_key)
+            .context("Invalid private key")?
+            .public_key(),
+    );
+    if public_key.as_bytes() != registered.as_bytes() {
+        bail!(
+            "The private key does not match the public key registered for {}",
+            config.gateway_id
+        );
+    }
+    Ok(format!("Credentials valid, {} credits left", credits))
+}
+
+/// The results of all checks.
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn pass(&mut self, check: &str, message: String) {
+        println!("[PASS] {:<9} {}", check, message);
+    }
+
+    fn fail(&mut self, check: &str, message: String) {
+        self.failures += 1;
+        println!("[FAIL] {:<9} {}", check, message);
+    }
+
+    fn check(&mut self, check: &str, result: Result<String>) {
+        match result {
+            Ok(message) => self.pass(check, message),
+            Err(e) => self.fail(check, format!("{:#}", e)),
+        }
+    }
+
+    /// Print the summary, return whether all checks passed.
+    fn finish(self) -> bool {
+        if self.failures == 0 {
+            println!("\nAll checks passed.");
+        } else {
+            println!("\n{} check(s) failed.", self.failures);
+        }
+        self.failures == 0
+    }
+}
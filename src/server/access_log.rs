@@ -0,0 +1,92 @@
+//! Access log of the HTTP server.
+//!
+//! Every request is logged with method, path, status, latency and client IP
+//! (with target `access_log`). Query strings and bodies are never logged, and
+//! client IPs are anonymized unless configured otherwise. The full details of
+//! a request are only available in the debug level request span.
+
+use std::{net::IpAddr, time::Instant};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::Response,
+    middleware::Next,
+};
+
+use super::client_ip::ClientIp;
+
+/// Settings of the access log.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessLog {
+    /// Whether client IPs are logged without anonymization
+    pub full_ips: bool,
+}
+
+/// Anonymize an IP address by removing the host part (the last octet of IPv4
+/// addresses, everything after the /48 prefix of IPv6 addresses).
+fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            IpAddr::from([segments[0], segments[1], segments[2], 0, 0, 0, 0, 0])
+        }
+    }
+}
+
+/// Middleware: Log the request once the response is ready.
+pub async fn log_access(
+    State(access_log): State<AccessLog>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let client_ip = request.extensions().get::<ClientIp>().map(|ClientIp(ip)| {
+        if access_log.full_ips {
+            *ip
+        } else {
+            anonymize_ip(*ip)
+        }
+    });
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16();
+    let latency_ms = start.elapsed().as_millis() as u64;
+    tracing::info!(
+        target: "access_log",
+        %method,
+        %path,
+        status,
+        latency_ms,
+        client_ip = ?client_ip,
+        "{} {} {} ({} ms)",
+        method,
+        path,
+        status,
+        latency_ms
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymize() {
+        let ip: IpAddr = "203.0.113.42".parse().unwrap();
+        assert_eq!(anonymize_ip(ip), "203.0.113.0".parse::<IpAddr>().unwrap());
+        let ip: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+        assert_eq!(
+            anonymize_ip(ip),
+            "2001:db8:1234::".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,694 @@
+//! The HTTP API.
+//!
+//! Requests are authenticated with an API token (see [`crate::tokens`]) in the
+//! `Authorization: Bearer <token>` header.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, Response, StatusCode},
+    Json,
+};
+use serde_derive::Deserialize;
+use serde_json::json;
+use xcontest_client::{Flight, ParsedTitle};
+
+use super::{http_500, SharedState};
+use crate::{
+    clubs, db,
+    jobs::{self, Job},
+    notifiers::Channel,
+    status::ParseCoverage,
+    tenants::Tenant,
+    tokens::{self, Scope},
+};
+
+/// Maximum number of flights returned by the flights endpoint.
+const MAX_FLIGHTS: u32 = 500;
+
+/// Maximum number of days returned by the stats history endpoint.
+const MAX_HISTORY_DAYS: u32 = 3650;
+
+fn json_response(status: StatusCode, value: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+fn json_error(status: StatusCode, error: &str) -> Response<Body> {
+    json_response(status, json!({ "error": error }))
+}
+
+/// Check that the request carries a token with the required scope and return
+/// the token.
+async fn authenticate(
+    state: &SharedState,
+    headers: &HeaderMap,
+    required: Scope,
+) -> Result<db::ApiToken, Response<Body>> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let token = match token {
+        Some(token) => token,
+        None => return Err(json_error(StatusCode::UNAUTHORIZED, "missing token")),
+    };
+    match tokens::authenticate(&state.pool, token, required).await {
+        Ok(Some(stored)) => {
+            tracing::debug!("Authenticated API request with token {}", stored.id);
+            Ok(stored)
+        }
+        Ok(None) => Err(json_error(StatusCode::FORBIDDEN, "invalid token or scope")),
+        Err(e) => {
+            tracing::error!("Could not authenticate API request: {}", e);
+            Err(http_500())
+        }
+    }
+}
+
+/// Return the response refusing a state-changing request, if the bot runs in
+/// read-only mode.
+fn refuse_if_read_only(state: &SharedState) -> Option<Response<Body>> {
+    state.read_only.then(|| {
+        json_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "read-only mode, changes are not possible",
+        )
+    })
+}
+
+/// Return the tenant with the specified ID (default: the tenant of the token,
+/// or the default tenant), if the token is valid for it.
+fn get_tenant<'a>(
+    state: &'a SharedState,
+    token: &db::ApiToken,
+    id: Option<&str>,
+) -> Option<&'a Tenant> {
+    let tenant = match id.or(token.tenant.as_deref()) {
+        Some(id) => state.tenants.get(id)?,
+        None => state.tenants.default_tenant(),
+    };
+    match &token.tenant {
+        Some(restricted) if restricted != tenant.id() => None,
+        _ => Some(tenant),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlightsParams {
+    tenant: Option<String>,
+    limit: Option<u32>,
+}
+
+/// Return the most recently seen flights
+pub async fn handle_flights(
+    state: State<Arc<SharedState>>,
+    headers: HeaderMap,
+    Query(params): Query<FlightsParams>,
+) -> Response<Body> {
+    let token = match authenticate(&state, &headers, Scope::Read).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    let tenant = match get_tenant(&state, &token, params.tenant.as_deref()) {
+        Some(tenant) => tenant,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown tenant"),
+    };
+    let limit = params.limit.unwrap_or(50).clamp(1, MAX_FLIGHTS);
+    let flights = match db::get_recent_flights(&state.pool, tenant.id(), limit).await {
+        Ok(flights) => flights,
+        Err(e) => {
+            tracing::error!("Could not fetch flights for API: {}", e);
+            return http_500();
+        }
+    };
+    let flights = flights
+        .iter()
+        .map(|flight| {
+            let parsed = ParsedTitle::parse(&flight.title);
+            json!({
+                "id": flight.id,
+                "url": flight.url,
+                "title": flight.title,
+                "pilot_username": flight.pilot_username,
+                "contest": flight.contest,
+                "start_time": flight.start_time,
+                "airtime_minutes": flight.airtime_minutes,
+                "pilot_name": parsed.as_ref().map(|parsed| &parsed.pilot_name),
+                "date": parsed.as_ref().and_then(|parsed| parsed.date).map(|date| date.to_string()),
+                "distance_km": parsed.as_ref().and_then(|parsed| parsed.distance_km),
+                "flight_type": parsed
+                    .as_ref()
+                    .and_then(|parsed| parsed.flight_type.as_ref())
+                    .map(|flight_type| flight_type.to_string()),
+                "seen_at": flight.seen_at,
+                "card_url": format!("/flights/{}/card.png", flight.id),
+            })
+        })
+        .collect::<Vec<_>>();
+    json_response(StatusCode::OK, json!({ "flights": flights }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitFlight {
+    tenant: Option<String>,
+    /// The XContest URL of the flight
+    url: String,
+    /// The title in the format of the XContest feed (e.g. `09.08.20 [21.98 km
+    /// :: free_flight] Danilo Bargen`), alternatively the fields below
+    title: Option<String>,
+    /// The name of the pilot
+    pilot_name: Option<String>,
+    /// The date of the flight (`YYYY-MM-DD`)
+    date: Option<String>,
+    distance_km: Option<f64>,
+    /// The flight type (e.g. `free_flight`, `flat_triangle` or `fai_triangle`)
+    flight_type: Option<String>,
+}
+
+impl SubmitFlight {
+    /// Return the flight, or an error if the URL is not an XContest flight
+    /// URL or the title lacks the date or distance.
+    fn flight(&self) -> Result<Flight, &'static str> {
+        let url = self.url.trim();
+        if !is_xcontest_url(url) {
+            return Err("invalid url");
+        }
+        let title = self.title()?;
+        match ParsedTitle::parse(&title) {
+            Some(parsed) if parsed.date.is_some() && parsed.distance_km.is_some() => {}
+            _ => return Err("missing date or distance in title"),
+        }
+        Flight::new(title, url.to_string()).map_err(|_| "invalid url")
+    }
+
+    /// Return the flight title, or an error if neither a title nor the flight
+    /// data is valid.
+    fn title(&self) -> Result<String, &'static str> {
+        if let Some(title) = self.title.as_deref().map(str::trim) {
+            if title.is_empty() {
+                return Err("invalid title");
+            }
+            return Ok(title.to_string());
+        }
+        let pilot_name = match self.pilot_name.as_deref().map(str::trim) {
+            Some(pilot_name) if !pilot_name.is_empty() => pilot_name,
+            _ => return Err("missing title or pilot_name"),
+        };
+        let date = match self.date.as_deref() {
+            Some(date) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                Ok(date) => date.format("%d.%m.%y").to_string(),
+                Err(_) => return Err("invalid date"),
+            },
+            None => return Err("missing date"),
+        };
+        let distance = match self.distance_km {
+            Some(distance) if distance.is_finite() && distance >= 0.0 => {
+                format!("{:.2} km", distance)
+            }
+            Some(_) => return Err("invalid distance_km"),
+            None => return Err("missing distance_km"),
+        };
+        let details = match self.flight_type.as_deref().map(str::trim) {
+            Some(flight_type) if !flight_type.is_empty() => {
+                format!("{} :: {}", distance, flight_type)
+            }
+            _ => distance,
+        };
+        Ok(format!("{} [{}] {}", date, details, pilot_name))
+    }
+}
+
+/// Return whether the URL points to XContest (or one of its contest
+/// subdomains).
+fn is_xcontest_url(url: &str) -> bool {
+    match reqwest::Url::parse(url) {
+        Ok(url) => {
+            matches!(url.scheme(), "http" | "https")
+                && url
+                    .host_str()
+                    .is_some_and(|host| host == "xcontest.org" || host.ends_with(".xcontest.org"))
+        }
+        Err(_) => false,
+    }
+}
+
+/// Submit a flight from another source (e.g. the upload form of a club) as
+/// JSON body. The flight is processed in the background like a flight from
+/// the feed: Known flights are ignored, new ones are notified.
+pub async fn handle_submit_flight(
+    state: State<Arc<SharedState>>,
+    headers: HeaderMap,
+    Json(submission): Json<SubmitFlight>,
+) -> Response<Body> {
+    let token = match authenticate(&state, &headers, Scope::Admin).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    if let Some(response) = refuse_if_read_only(&state) {
+        return response;
+    }
+    let tenant = match get_tenant(&state, &token, submission.tenant.as_deref()) {
+        Some(tenant) => tenant,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown tenant"),
+    };
+    let flight = match submission.flight() {
+        Ok(flight) => flight,
+        Err(error) => return json_error(StatusCode::UNPROCESSABLE_ENTITY, error),
+    };
+    let job = Job::SubmitFlight {
+        tenant: tenant.id().to_string(),
+        title: flight.title.clone(),
+        flight_url: flight.url.clone(),
+    };
+    match jobs::enqueue(&state.pool, &job, std::time::Duration::ZERO).await {
+        Ok(()) => json_response(
+            StatusCode::ACCEPTED,
+            json!({
+                "url": flight.url,
+                "title": flight.title,
+                "pilot_username": flight.pilot_username,
+                "parsed": flight.parsed_title.is_some(),
+            }),
+        ),
+        Err(e) => {
+            tracing::error!("Could not enqueue submitted flight: {}", e);
+            http_500()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClubLeaderboardParams {
+    tenant: Option<String>,
+    /// The month (`YYYY-MM`, default: the current month)
+    month: Option<String>,
+}
+
+/// Return the kilometre leaderboard of a club (e.g. for the club website)
+pub async fn handle_club_leaderboard(
+    state: State<Arc<SharedState>>,
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    Query(params): Query<ClubLeaderboardParams>,
+) -> Response<Body> {
+    let token = match authenticate(&state, &headers, Scope::Read).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    let tenant = match get_tenant(&state, &token, params.tenant.as_deref()) {
+        Some(tenant) => tenant,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown tenant"),
+    };
+    let club = match clubs::find(&tenant.config.clubs, &code) {
+        Some(club) => club,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown club"),
+    };
+    let month = params.month.unwrap_or_else(db::current_month);
+    if chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_err() {
+        return json_error(StatusCode::BAD_REQUEST, "invalid month");
+    }
+    let entries = match clubs::leaderboard(&state.pool, tenant.id(), club, &month).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!("Could not compute club leaderboard for API: {:#}", e);
+            return http_500();
+        }
+    };
+    let pilots = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            json!({
+                "rank": i + 1,
+                "pilot_username": entry.pilot_username,
+                "flights": entry.flights,
+                "distance_km": entry.distance_km,
+                "max_km": entry.max_km,
+            })
+        })
+        .collect::<Vec<_>>();
+    json_response(
+        StatusCode::OK,
+        json!({
+            "club": club.code,
+            "name": clubs::name(club),
+            "month": month,
+            "distance_km": entries.iter().map(|entry| entry.distance_km).sum::<f64>(),
+            "flights": entries.iter().map(|entry| entry.flights).sum::<u32>(),
+            "pilots": pilots,
+        }),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsParams {
+    tenant: Option<String>,
+}
+
+/// Return the database stats
+pub async fn handle_stats(
+    state: State<Arc<SharedState>>,
+    headers: HeaderMap,
+    Query(params): Query<StatsParams>,
+) -> Response<Body> {
+    let token = match authenticate(&state, &headers, Scope::Admin).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    let tenant = match get_tenant(&state, &token, params.tenant.as_deref()) {
+        Some(tenant) => tenant,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown tenant"),
+    };
+    let coverage = |coverage: ParseCoverage| {
+        json!({
+            "parsed": coverage.parsed,
+            "failed": coverage.failed,
+        })
+    };
+    let queue = match db::get_queue_depths(&state.pool).await {
+        Ok(depths) => depths
+            .into_iter()
+            .map(|depth| {
+                json!({
+                    "kind": depth.kind,
+                    "channel": depth.channel,
+                    "pending": depth.pending,
+                    "retrying": depth.retrying,
+                    "failed_attempts": depth.failed_attempts,
+                    "oldest_age_seconds": depth.oldest_age_seconds,
+                })
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            tracing::error!("Could not fetch queue depths for API: {}", e);
+            return http_500();
+        }
+    };
+    match db::get_stats(&state.pool, tenant.id()).await {
+        Ok(stats) => json_response(
+            StatusCode::OK,
+            json!({
+                "users": stats.user_count,
+                "subscriptions": stats.subscription_count,
+                "new_subscriptions_30_days": stats.new_subscription_count,
+                "flights": stats.flight_count,
+                "notifications_this_month": stats.notifications_this_month,
+                "images_this_month": stats.images_this_month,
+                "feed_items_last_fetch": state.status.last_parse_coverage().map(coverage),
+                "feed_items_since_start": coverage(state.status.total_parse_coverage()),
+                "notification_latency_seconds": state.status.notification_latency().map(|latency| {
+                    json!({
+                        "samples": latency.samples,
+                        "p50": latency.p50,
+                        "p90": latency.p90,
+                        "p99": latency.p99,
+                    })
+                }),
+                "job_queue": queue,
+                "database_busy_retries": db::busy_retries(),
+                "leader": state.leadership.is_leader(),
+            }),
+        ),
+        Err(e) => {
+            tracing::error!("Could not fetch stats for API: {}", e);
+            http_500()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsHistoryParams {
+    tenant: Option<String>,
+    days: Option<u32>,
+}
+
+/// Return the daily stats snapshots
+pub async fn handle_stats_history(
+    state: State<Arc<SharedState>>,
+    headers: HeaderMap,
+    Query(params): Query<StatsHistoryParams>,
+) -> Response<Body> {
+    let token = match authenticate(&state, &headers, Scope::Admin).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    let tenant = match get_tenant(&state, &token, params.tenant.as_deref()) {
+        Some(tenant) => tenant,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown tenant"),
+    };
+    let days = params
+        .days
+        .unwrap_or(db::STATS_TREND_DAYS)
+        .clamp(1, MAX_HISTORY_DAYS);
+    match db::get_stats_history(&state.pool, tenant.id(), days).await {
+        Ok(history) => {
+            let snapshots = history
+                .iter()
+                .map(|snapshot| {
+                    json!({
+                        "date": snapshot.date,
+                        "users": snapshot.users,
+                        "subscriptions": snapshot.subscriptions,
+                        "flights": snapshot.flights,
+                        "notifications": snapshot.notifications,
+                        "failures": snapshot.failures,
+                        "notification_failures": snapshot.notification_failures,
+                    })
+                })
+                .collect::<Vec<_>>();
+            json_response(StatusCode::OK, json!({ "snapshots": snapshots }))
+        }
+        Err(e) => {
+            tracing::error!("Could not fetch stats history for API: {}", e);
+            http_500()
+        }
+    }
+}
+
+/// A subscription of a user of a channel to a pilot (sent as JSON body, since
+/// the setting may be a secret).
+#[derive(Debug, Deserialize)]
+pub struct ChannelSubscription {
+    tenant: Option<String>,
+    /// The name of the user (the address of e-mail users)
+    #[serde(alias = "address")]
+    name: String,
+    /// The XContest username of the pilot
+    pilot: String,
+    /// The setting of the user: The ntfy topic, the Pushover user key or the
+    /// Gotify application token
+    #[serde(alias = "topic", alias = "user_key", alias = "app_token")]
+    setting: Option<String>,
+}
+
+/// The subscription of a user of a channel to a pilot to remove.
+#[derive(Debug, Deserialize)]
+pub struct ChannelUnsubscription {
+    tenant: Option<String>,
+    /// The name of the user (the address of e-mail users)
+    #[serde(alias = "address")]
+    name: String,
+    /// The XContest username of the pilot
+    pilot: String,
+}
+
+/// Return the normalized name, pilot and setting of a subscription, or an
+/// error if they are invalid.
+fn validate_subscription<C: Channel>(
+    name: &str,
+    pilot: &str,
+    setting: Option<&str>,
+) -> Result<(String, String, Option<String>), &'static str> {
+    let (name, setting) = C::validate(name, setting)?;
+    let pilot = pilot.trim();
+    if pilot.is_empty() || pilot.contains(char::is_whitespace) {
+        return Err("invalid pilot");
+    }
+    Ok((name, pilot.to_string(), setting))
+}
+
+/// Subscribe a user of the channel to the flights of a pilot
+pub async fn handle_add_subscription<C: Channel + 'static>(
+    state: State<Arc<SharedState>>,
+    headers: HeaderMap,
+    Json(subscription): Json<ChannelSubscription>,
+) -> Response<Body> {
+    let token = match authenticate(&state, &headers, Scope::Admin).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    if let Some(response) = refuse_if_read_only(&state) {
+        return response;
+    }
+    let tenant = match get_tenant(&state, &token, subscription.tenant.as_deref()) {
+        Some(tenant) => tenant,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown tenant"),
+    };
+    let (name, pilot, setting) = match validate_subscription::<C>(
+        &subscription.name,
+        &subscription.pilot,
+        subscription.setting.as_deref(),
+    ) {
+        Ok(validated) => validated,
+        Err(error) => return json_error(StatusCode::BAD_REQUEST, error),
+    };
+    let result = async {
+        let user = db::get_or_create_user(&state.pool, tenant.id(), &name, C::usertype()).await?;
+        if let Err(error) = C::store_setting(&state.pool, &user, setting.as_deref()).await? {
+            return Ok(Err(error));
+        }
+        db::add_subscription(&state.pool, user.id, &pilot, None, None).await?;
+        anyhow::Ok(Ok(()))
+    };
+    match result.await {
+        Ok(Ok(())) => json_response(
+            StatusCode::OK,
+            json!({ "name": name, "pilot": pilot, "subscribed": true }),
+        ),
+        Ok(Err(error)) => json_error(StatusCode::BAD_REQUEST, error),
+        Err(e) => {
+            tracing::error!("Could not add {} subscription: {}", C::usertype(), e);
+            http_500()
+        }
+    }
+}
+
+/// Unsubscribe a user of the channel from the flights of a pilot
+pub async fn handle_remove_subscription<C: Channel + 'static>(
+    state: State<Arc<SharedState>>,
+    headers: HeaderMap,
+    Query(unsubscription): Query<ChannelUnsubscription>,
+) -> Response<Body> {
+    let token = match authenticate(&state, &headers, Scope::Admin).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    if let Some(response) = refuse_if_read_only(&state) {
+        return response;
+    }
+    let tenant = match get_tenant(&state, &token, unsubscription.tenant.as_deref()) {
+        Some(tenant) => tenant,
+        None => return json_error(StatusCode::NOT_FOUND, "unknown tenant"),
+    };
+    let (name, pilot, _) =
+        match validate_subscription::<C>(&unsubscription.name, &unsubscription.pilot, None) {
+            Ok(validated) => validated,
+            Err(error) => return json_error(StatusCode::BAD_REQUEST, error),
+        };
+    let result = async {
+        match db::find_user(&state.pool, tenant.id(), &name).await? {
+            Some(user) if user.usertype == C::usertype() => {
+                db::remove_subscription(&state.pool, user.id, &pilot).await
+            }
+            _ => Ok(false),
+        }
+    };
+    match result.await {
+        Ok(removed) => json_response(
+            StatusCode::OK,
+            json!({ "name": name, "pilot": pilot, "subscribed": false, "removed": removed }),
+        ),
+        Err(e) => {
+            tracing::error!("Could not remove {} subscription: {}", C::usertype(), e);
+            http_500()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(title: Option<&str>) -> SubmitFlight {
+        SubmitFlight {
+            tenant: None,
+            url: "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+                .into(),
+            title: title.map(Into::into),
+            pilot_name: None,
+            date: None,
+            distance_km: None,
+            flight_type: None,
+        }
+    }
+
+    #[test]
+    fn submitted_title() {
+        let title = "09.08.20 [21.98 km :: free_flight] Danilo Bargen";
+        assert_eq!(submission(Some(title)).title().unwrap(), title);
+        assert!(submission(Some(" ")).title().is_err());
+        assert!(submission(None).title().is_err());
+
+        // Built from the flight data
+        let structured = SubmitFlight {
+            pilot_name: Some("Danilo Bargen".into()),
+            date: Some("2020-08-09".into()),
+            distance_km: Some(21.98),
+            flight_type: Some("free_flight".into()),
+            ..submission(None)
+        };
+        let built = structured.title().unwrap();
+        assert_eq!(built, title);
+        let parsed = ParsedTitle::parse(&built).unwrap();
+        assert_eq!(parsed.pilot_name, "Danilo Bargen");
+        assert_eq!(parsed.distance_km, Some(21.98));
+        assert_eq!(
+            SubmitFlight {
+                pilot_name: Some("Danilo Bargen".into()),
+                ..submission(None)
+            }
+            .title(),
+            Err("missing date")
+        );
+        assert_eq!(
+            SubmitFlight {
+                pilot_name: Some("Danilo Bargen".into()),
+                date: Some("2020-08-09".into()),
+                ..submission(None)
+            }
+            .title(),
+            Err("missing distance_km")
+        );
+        assert_eq!(
+            SubmitFlight {
+                date: Some("9.8.2020".into()),
+                ..structured
+            }
+            .title(),
+            Err("invalid date")
+        );
+    }
+
+    #[test]
+    fn submitted_flight() {
+        let title = "09.08.20 [21.98 km :: free_flight] Danilo Bargen";
+        let flight = submission(Some(title)).flight().unwrap();
+        assert_eq!(flight.pilot_username, "dbrgn");
+
+        // Titles without date or distance are rejected
+        assert_eq!(
+            submission(Some("Danilo Bargen")).flight().unwrap_err(),
+            "missing date or distance in title"
+        );
+
+        // Only XContest flight URLs are accepted
+        for url in [
+            "https://example.com/?xcontest.org/detail:dbrgn/9.8.2020/10:45",
+            "https://www.xcontest.org/switzerland/en/",
+            "ftp://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45",
+        ] {
+            let invalid = SubmitFlight {
+                url: url.into(),
+                ..submission(Some(title))
+            };
+            assert_eq!(invalid.flight().unwrap_err(), "invalid url");
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Backpressure on the Threema callback routes.
+//!
+//! After downtime, the gateway redelivers all pending messages at once. To not
+//! exhaust the database pool, only a few callbacks are processed concurrently
+//! and a bounded number waits for its turn. Further callbacks are rejected with
+//! HTTP 429 and a `Retry-After` header, the gateway delivers them again later.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, Response, StatusCode},
+    middleware::Next,
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::config::ServerConfig;
+
+/// Default number of callbacks processed concurrently (the database pool has
+/// five connections by default, some are left for the fetch loop and the job
+/// worker)
+const DEFAULT_CONCURRENCY: usize = 3;
+
+/// Default number of callbacks waiting to be processed
+const DEFAULT_QUEUE: usize = 20;
+
+/// Seconds after which a rejected callback should be retried
+const RETRY_AFTER_SECONDS: u32 = 30;
+
+/// Limits the number of concurrently processed and waiting callbacks.
+#[derive(Debug)]
+pub struct Backpressure {
+    permits: Semaphore,
+    /// Number of callbacks waiting for a permit
+    queued: AtomicUsize,
+    max_queued: usize,
+}
+
+/// A place in the queue, freed when dropped (also if the request is aborted
+/// while waiting).
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Backpressure {
+    pub fn new(concurrency: usize, max_queued: usize) -> Self {
+        Self {
+            permits: Semaphore::new(concurrency),
+            queued: AtomicUsize::new(0),
+            max_queued,
+        }
+    }
+
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self::new(
+            config.webhook_concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+            config.webhook_queue.unwrap_or(DEFAULT_QUEUE),
+        )
+    }
+
+    /// Wait for a permit to process a callback.
+    ///
+    /// Return `None` if the queue is full.
+    async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        if let Ok(permit) = self.permits.try_acquire() {
+            return Some(permit);
+        }
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        let _slot = QueueSlot(&self.queued);
+        self.permits.acquire().await.ok()
+    }
+}
+
+/// Middleware: Limit the number of concurrently processed callbacks.
+pub async fn limit_callbacks(
+    State(backpressure): State<Arc<Backpressure>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let _permit = match backpressure.acquire().await {
+        Some(permit) => permit,
+        None => {
+            tracing::warn!("Too many Threema callbacks, asking the gateway to retry later");
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(header::RETRY_AFTER, RETRY_AFTER_SECONDS)
+                .body(Body::from("too many requests"))
+                .unwrap();
+        }
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn bounded_queue() {
+        let backpressure = Backpressure::new(1, 1);
+        let first = backpressure.acquire().await.unwrap();
+
+        // The second callback waits, the third is rejected
+        let mut second = Box::pin(backpressure.acquire());
+        assert!((&mut second).now_or_never().is_none());
+        assert!(matches!(backpressure.acquire().now_or_never(), Some(None)));
+
+        // Once the first callback is done, the second one is processed
+        drop(first);
+        let second = second.await.unwrap();
+        assert_eq!(backpressure.queued.load(Ordering::SeqCst), 0);
+
+        // An aborted callback frees its place in the queue
+        let waiting = Box::pin(backpressure.acquire());
+        drop(waiting);
+        let mut third = Box::pin(backpressure.acquire());
+        assert!((&mut third).now_or_never().is_none());
+        drop(second);
+        assert!(third.await.is_some());
+    }
+}
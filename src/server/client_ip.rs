@@ -0,0 +1,184 @@
+//! Client IP extraction and the allowlist of the Threema callback routes.
+//!
+//! Behind a reverse proxy, the peer address of every request is the proxy.
+//! The real client IP is then taken from the `X-Forwarded-For` header, but
+//! only if the request comes from a trusted proxy, since the header can be
+//! set by anybody.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, Response, StatusCode},
+    middleware::Next,
+};
+use ipnet::IpNet;
+
+use crate::config::ServerConfig;
+
+/// The IP address of the client that sent a request (stored as request
+/// extension).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Parse an IP address or network (e.g. `10.0.0.1` or `10.0.0.0/8`).
+fn parse_network(value: &str) -> Result<IpNet> {
+    IpNet::from_str(value)
+        .or_else(|_| IpAddr::from_str(value).map(IpNet::from))
+        .context(format!("Invalid IP address or network: {}", value))
+}
+
+fn parse_networks(values: &[String]) -> Result<Vec<IpNet>> {
+    values.iter().map(|value| parse_network(value)).collect()
+}
+
+/// Trusted proxies and allowed Threema callback senders.
+#[derive(Debug, Clone, Default)]
+pub struct ClientIpConfig {
+    trusted_proxies: Vec<IpNet>,
+    threema_allowlist: Option<Vec<IpNet>>,
+}
+
+impl ClientIpConfig {
+    pub fn from_config(config: &ServerConfig) -> Result<Self> {
+        Ok(Self {
+            trusted_proxies: parse_networks(config.trusted_proxies.as_deref().unwrap_or(&[]))?,
+            threema_allowlist: config
+                .threema_allowlist
+                .as_deref()
+                .map(parse_networks)
+                .transpose()?,
+        })
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|network| network.contains(&ip))
+    }
+
+    /// Return the IP of the client, given the peer address of the connection
+    /// and the request headers.
+    ///
+    /// The `X-Forwarded-For` entries are processed from right to left (i.e.
+    /// starting with the proxy closest to the bot), skipping trusted proxies.
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut ip = peer;
+        if !self.is_trusted_proxy(peer) {
+            return ip;
+        }
+        let forwarded_for = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        for entry in forwarded_for.iter().rev() {
+            match IpAddr::from_str(entry.trim()) {
+                Ok(forwarded) => ip = forwarded,
+                Err(_) => break,
+            }
+            if !self.is_trusted_proxy(ip) {
+                break;
+            }
+        }
+        ip
+    }
+}
+
+/// Middleware: Determine the client IP and store it as request extension.
+pub async fn extract_client_ip(
+    State(config): State<Arc<ClientIpConfig>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response<Body> {
+    let ip = config.client_ip(peer.ip(), request.headers());
+    request.extensions_mut().insert(ClientIp(ip));
+    next.run(request).await
+}
+
+/// Middleware: Reject requests from clients that are not in the allowlist of
+/// the Threema callback routes (if configured).
+pub async fn check_threema_allowlist(
+    State(config): State<Arc<ClientIpConfig>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    if let Some(allowlist) = &config.threema_allowlist {
+        let allowed = request
+            .extensions()
+            .get::<ClientIp>()
+            .is_some_and(|ClientIp(ip)| allowlist.iter().any(|network| network.contains(ip)));
+        if !allowed {
+            tracing::warn!(
+                "Rejecting Threema callback from {:?}, not in allowlist",
+                request.extensions().get::<ClientIp>()
+            );
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("forbidden"))
+                .unwrap();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(trusted_proxies: &[&str]) -> ClientIpConfig {
+        ClientIpConfig {
+            trusted_proxies: trusted_proxies
+                .iter()
+                .map(|proxy| parse_network(proxy).unwrap())
+                .collect(),
+            threema_allowlist: None,
+        }
+    }
+
+    fn headers(forwarded_for: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn forwarded_for_only_from_trusted_proxies() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        let headers = headers("203.0.113.7, 198.51.100.1, 10.0.0.1");
+
+        // Untrusted peer: Header is ignored
+        assert_eq!(config(&[]).client_ip(peer, &headers), peer);
+
+        // Trusted peer: The first untrusted entry from the right is the client
+        assert_eq!(
+            config(&["10.0.0.0/8"]).client_ip(peer, &headers),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            config(&["10.0.0.0/8", "198.51.100.1"]).client_ip(peer, &headers),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+
+        // Without header, the peer is the client
+        assert_eq!(
+            config(&["10.0.0.0/8"]).client_ip(peer, &HeaderMap::new()),
+            peer
+        );
+    }
+
+    #[test]
+    fn invalid_network() {
+        assert!(parse_network("10.0.0.0/33").is_err());
+        assert!(parse_network("localhost").is_err());
+        assert!(parse_network("::1").is_ok());
+    }
+}
@@ -0,0 +1,122 @@
+//! Public landing page of the bot.
+//!
+//! The page describes the bot of the default tenant, links to its Threema ID
+//! and shows the number of users and tracked flights, so that no separate web
+//! hosting is needed.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, Response, StatusCode},
+};
+
+use super::{http_500, SharedState};
+use crate::{db, messages};
+
+/// Maximum number of landing page requests per minute. Every request queries
+/// the database, so this protects the bot against floods of requests.
+const MAX_REQUESTS_PER_MINUTE: usize = 60;
+
+/// Rate limiting state of the landing page.
+pub struct LandingPage {
+    requests: Mutex<VecDeque<Instant>>,
+}
+
+impl LandingPage {
+    pub fn new() -> Self {
+        Self {
+            requests: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Record a request, unless the rate limit is exceeded.
+    fn take_request(&self) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let now = Instant::now();
+        while let Some(oldest) = requests.front() {
+            if now.duration_since(*oldest) >= Duration::from_secs(60) {
+                requests.pop_front();
+            } else {
+                break;
+            }
+        }
+        if requests.len() >= MAX_REQUESTS_PER_MINUTE {
+            return false;
+        }
+        requests.push_back(now);
+        true
+    }
+}
+
+/// Escape text for use in HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Serve the landing page
+pub async fn handle_landing_page(state: State<Arc<SharedState>>) -> Response<Body> {
+    let allowed = state
+        .landing_page
+        .as_ref()
+        .is_some_and(|landing_page| landing_page.take_request());
+    if !allowed {
+        return Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(header::RETRY_AFTER, "60")
+            .body(Body::from("too many requests"))
+            .unwrap();
+    }
+
+    let tenant = state.tenants.default_tenant();
+    let stats = match db::get_stats(&state.pool, tenant.id()).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            tracing::error!("Could not fetch stats for landing page: {}", e);
+            return http_500();
+        }
+    };
+    let messages = tenant.config.messages();
+    let gateway_id = &tenant.config.threema.gateway_id;
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>XC Bot</title>
+</head>
+<body>
+<h1>XC Bot 🪂</h1>
+<p>{description}</p>
+<p><a href="https://threema.id/{gateway_id}">{link}</a> ({gateway_id})</p>
+<p>{stats}</p>
+</body>
+</html>
+"#,
+        description = escape_html(messages.landing_description),
+        gateway_id = escape_html(gateway_id),
+        link = escape_html(messages.landing_link),
+        stats = escape_html(&messages::fill(
+            messages.landing_stats,
+            &[
+                ("users", &stats.user_count.to_string()),
+                ("flights", &stats.flight_count.to_string()),
+            ],
+        )),
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "public, max-age=300")
+        .body(Body::from(body))
+        .unwrap()
+}
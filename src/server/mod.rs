@@ -1,20 +1,56 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
+use anyhow::{Context, Result};
 use axum::{
     body::Body,
-    extract::State,
-    http::{Response, StatusCode},
-    routing::post,
+    extract::{Path, Request, State},
+    http::{header, HeaderValue, Method, Response, StatusCode},
+    middleware,
+    routing::{get, post},
 };
 use bytes::Bytes;
-use command_handlers::HandleResult;
 use sqlx::{Pool, Sqlite};
-use threema_gateway::E2eApi;
-use tower_http::trace::TraceLayer;
+use threema_gateway::{IncomingMessage, RecipientKey};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
+use tracing::Instrument;
+use xcontest_client::XContest;
+
+mod access_log;
+mod api;
+mod backpressure;
+mod client_ip;
+mod landing;
+mod slack;
+mod thumbnails;
 
-mod command_handlers;
+pub use landing::LandingPage;
 
-use crate::{config::Config, db, threema};
+use access_log::AccessLog;
+use backpressure::Backpressure;
+use client_ip::ClientIpConfig;
+
+use crate::{
+    alerts::Alerter,
+    cache::DetailsCache,
+    card,
+    commands::{self, IncomingCommand, OutgoingReply},
+    config::{FeaturesConfig, ServerConfig},
+    db::{self, User},
+    jobs::{self, Job},
+    messages,
+    notifiers::{format, EmailNotifier, GotifyNotifier, NtfyNotifier, PushoverNotifier},
+    panics::PanicReporter,
+    reactions,
+    status::BotStatus,
+    surveys,
+    tenants::{Tenant, Tenants},
+    threema::{self, MessageKind},
+};
 
 fn http_200() -> Response<Body> {
     Response::builder()
@@ -23,6 +59,13 @@ fn http_200() -> Response<Body> {
         .unwrap()
 }
 
+fn http_404() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("not found"))
+        .unwrap()
+}
+
 fn http_500() -> Response<Body> {
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -30,29 +73,90 @@ fn http_500() -> Response<Body> {
         .unwrap()
 }
 
-/// Handle a Threema message HTTP request
+/// Handle a Threema message HTTP request for the default tenant
 async fn handle_threema_request(state: State<Arc<SharedState>>, bytes: Bytes) -> Response<Body> {
-    let api = &state.api;
-    let pool = &state.pool;
-    let config = &state.config;
+    process_threema_request(&state, state.tenants.default_tenant(), bytes).await
+}
 
+/// Handle a Threema message HTTP request for the specified tenant
+async fn handle_tenant_threema_request(
+    state: State<Arc<SharedState>>,
+    Path(tenant): Path<String>,
+    bytes: Bytes,
+) -> Response<Body> {
+    match state.tenants.get(&tenant) {
+        Some(tenant) => process_threema_request(&state, tenant, bytes).await,
+        None => http_404(),
+    }
+}
+
+/// Process an incoming Threema message
+async fn process_threema_request(
+    state: &SharedState,
+    tenant: &Tenant,
+    bytes: Bytes,
+) -> Response<Body> {
     // Parse body
-    let msg = match state.api.decode_incoming_message(bytes) {
+    let msg = match tracing::debug_span!("decode")
+        .in_scope(|| tenant.api.decode_incoming_message(bytes))
+    {
         Ok(data) => data,
         Err(e) => {
             tracing::error!("Could not decode incoming Threema message: {}", e);
             return http_500();
         }
     };
-    let span = tracing::debug_span!("incoming_message", from = &*msg.from, id = &*msg.message_id);
-    let _enter = span.enter();
+
+    // The span must not be entered with a guard, since that would be held
+    // across await points (and attribute other requests to this message).
+    let span = tracing::debug_span!(
+        "incoming_message",
+        tenant = tenant.id(),
+        from = &*msg.from,
+        id = &*msg.message_id
+    );
+    // A panic while processing the message must not go unnoticed. The
+    // gateway delivers the message again later.
+    state
+        .panic_reporter
+        .catch(
+            "incoming message",
+            process_incoming_message(state, tenant, msg).instrument(span),
+        )
+        .await
+        .unwrap_or_else(http_500)
+}
+
+/// Process a decoded incoming Threema message
+async fn process_incoming_message(
+    state: &SharedState,
+    tenant: &Tenant,
+    msg: IncomingMessage,
+) -> Response<Body> {
+    let api = &tenant.api;
+    let pool = &state.pool;
+    let config = &tenant.config;
+
     tracing::trace!("Incoming message from {}", msg.from);
     tracing::trace!("Raw message: {:?}", msg);
 
     // Fetch user
-    let user = match db::get_or_create_user(pool, &msg.from, "threema").await {
-        Ok(user) => {
+    let user = match db::get_or_register_user(pool, tenant.id(), &msg.from, "threema").await {
+        Ok((user, created)) => {
             tracing::debug!("User ID: {}", user.id);
+            if created {
+                // Inform the admin in the background, to not delay the reply
+                let alerter = state.alerter.clone();
+                let pool = pool.clone();
+                let tenant = tenant.id().to_string();
+                let identity = msg.from.clone();
+                let nickname = msg.nickname.clone();
+                tokio::spawn(async move {
+                    alerter
+                        .new_user(&pool, &tenant, &identity, nickname.as_deref())
+                        .await
+                });
+            }
             user
         }
         Err(e) => {
@@ -71,7 +175,9 @@ async fn handle_threema_request(state: State<Arc<SharedState>>, bytes: Bytes) ->
     };
 
     // Decrypt
-    let data = match api.decrypt_incoming_message(&msg, &public_key) {
+    let data = match tracing::debug_span!("decrypt")
+        .in_scope(|| api.decrypt_incoming_message(&msg, &public_key))
+    {
         Ok(key) => key,
         Err(e) => {
             tracing::error!("Could not fetch public key for {}: {}", &msg.from, e);
@@ -80,6 +186,17 @@ async fn handle_threema_request(state: State<Arc<SharedState>>, bytes: Bytes) ->
     };
     tracing::debug!("Decrypted data: {:?}", data);
 
+    // Send read receipt, unless the incoming message is a delivery receipt itself
+    if config.threema.send_read_receipts.unwrap_or(false) && data.first() != Some(&0x80) {
+        match threema::send_read_receipt(&msg.from, &msg.message_id, &public_key, api).await {
+            Ok(receipt_id) => {
+                threema::record_sent_message(pool, user.id, &receipt_id, MessageKind::ReadReceipt)
+                    .await
+            }
+            Err(e) => tracing::warn!("Could not send read receipt: {}", e),
+        }
+    }
+
     // Handle depending on type
     match data.first() {
         Some(0x01) => {
@@ -92,41 +209,89 @@ async fn handle_threema_request(state: State<Arc<SharedState>>, bytes: Bytes) ->
                 }
             };
 
+            // A follower leaves the command to the leader
+            if !state.leadership.is_leader() {
+                let job = Job::HandleCommand {
+                    user_id: user.id,
+                    text: text.to_string(),
+                    nickname: msg.nickname.clone(),
+                };
+                return match jobs::enqueue(pool, &job, Duration::ZERO).await {
+                    Ok(()) => {
+                        tracing::debug!("Not the leader, queued command of {}", msg.from);
+                        http_200()
+                    }
+                    Err(e) => {
+                        tracing::error!("Could not queue command of {}: {}", msg.from, e);
+                        http_500()
+                    }
+                };
+            }
+
             // Process text message
-            match command_handlers::handle_threema_text_message(
+            let incoming = IncomingCommand {
                 text,
-                &msg.from,
-                msg.nickname.as_deref(),
-                config.threema.admin_id.as_deref(),
+                sender: &msg.from,
+                sender_nickname: msg.nickname.as_deref(),
+                is_admin: Some(&*msg.from) == config.threema.admin_id.as_deref(),
+            };
+            match commands::handle_command(
+                &incoming,
+                config,
                 &user,
                 pool,
+                &state.status,
+                &state.middleware,
             )
+            .instrument(tracing::debug_span!("handle"))
             .await
             {
-                HandleResult::Reply(text) => {
-                    match api.encrypt_text_msg(text.as_ref(), &public_key) {
-                        Ok(reply) => match api.send(&msg.from, &reply, false).await {
-                            Ok(msgid) => tracing::debug!("Reply sent (msgid={})", msgid),
-                            Err(e) => tracing::error!("Could not send reply: {}", e),
-                        },
-                        Err(e) => tracing::error!("Could not encrypt reply: {}", e),
-                    }
+                OutgoingReply::Text(text) => {
+                    send_reply(tenant, &user, &public_key, &text, pool)
+                        .instrument(tracing::debug_span!("reply"))
+                        .await
                 }
-                HandleResult::NoOp => {}
-                HandleResult::ServerError => return http_500(),
+                OutgoingReply::Nothing => {}
+                OutgoingReply::Error => return http_500(),
             };
 
             // Done processing, confirm message
             http_200()
         }
+        Some(&threema::MESSAGE_TYPE_POLL_VOTE) => {
+            // Poll vote, record the response to the survey
+            if let Err(e) = surveys::record_vote(pool, config, &user, &data[1..]).await {
+                tracing::warn!("Could not record poll vote: {:#}", e);
+            }
+            http_200()
+        }
         Some(0x80) => {
-            // Delivery receipt, ignore
-            tracing::info!("Ignoring delivery receipt");
+            // Delivery receipt, records the delivery or reaction
+            if let Err(e) = reactions::record_receipt(pool, &user, &data[1..]).await {
+                tracing::warn!("Could not record delivery receipt: {:#}", e);
+            }
             http_200()
         }
-        Some(other) => {
-            // Unsupported message type, ignore
-            tracing::warn!("Ignoring unsupported message type: {}", other);
+        Some(&other) => {
+            // Media messages are answered with a hint that only text commands
+            // are understood, other message types are ignored
+            let messages = config.messages();
+            let kind = match unsupported_media(other, &data[1..]) {
+                Some(Media::Image) => messages.media_image,
+                Some(Media::Video) => messages.media_video,
+                Some(Media::Voice) => messages.media_voice,
+                Some(Media::File) => messages.media_file,
+                Some(Media::Location) => messages.media_location,
+                None => {
+                    tracing::warn!("Ignoring unsupported message type: {}", other);
+                    return http_200();
+                }
+            };
+            tracing::debug!("Received unsupported media message (type {})", other);
+            let text = messages::fill(messages.unsupported_media, &[("kind", kind)]);
+            send_reply(tenant, &user, &public_key, &text, pool)
+                .instrument(tracing::debug_span!("reply"))
+                .await;
             http_200()
         }
         None => {
@@ -137,28 +302,429 @@ async fn handle_threema_request(state: State<Arc<SharedState>>, bytes: Bytes) ->
     }
 }
 
+/// Send a text reply to an incoming message of the user. Long replies are sent
+/// as several messages.
+async fn send_reply(
+    tenant: &Tenant,
+    user: &User,
+    public_key: &RecipientKey,
+    text: &str,
+    pool: &Pool<Sqlite>,
+) {
+    let api = &tenant.api;
+    for part in format::split_text(text, format::MAX_TEXT_CHARS) {
+        match api.encrypt_text_msg(&part, public_key) {
+            Ok(reply) => match api
+                .send(
+                    &user.username,
+                    &reply,
+                    tenant.config.threema.request_delivery_receipts(),
+                )
+                .await
+            {
+                Ok(msgid) => {
+                    tracing::debug!("Reply sent (msgid={})", msgid);
+                    threema::record_sent_message(pool, user.id, &msgid, MessageKind::Reply).await;
+                }
+                Err(e) => {
+                    tracing::error!("Could not send reply: {}", e);
+                    break;
+                }
+            },
+            Err(e) => {
+                tracing::error!("Could not encrypt reply: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Kinds of incoming media messages the bot can't handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Media {
+    Image,
+    Video,
+    Voice,
+    File,
+    Location,
+}
+
+/// Return the kind of media of an incoming message, if it's a media message.
+///
+/// File messages are classified by the media type of the file (e.g. voice
+/// messages are audio files).
+fn unsupported_media(msgtype: u8, data: &[u8]) -> Option<Media> {
+    match msgtype {
+        0x02 => Some(Media::Image),
+        0x10 => Some(Media::Location),
+        0x13 => Some(Media::Video),
+        0x14 => Some(Media::Voice),
+        0x17 => {
+            let media_type = serde_json::from_slice::<serde_json::Value>(data)
+                .ok()
+                .and_then(|file| file["m"].as_str().map(str::to_string))
+                .unwrap_or_default();
+            Some(match media_type.split('/').next() {
+                Some("image") => Media::Image,
+                Some("video") => Media::Video,
+                Some("audio") => Media::Voice,
+                _ => Media::File,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Handle a health check request
+async fn handle_healthz(state: State<Arc<SharedState>>) -> Response<Body> {
+    let body = match state.status.throttling() {
+        Some(throttling) => format!(
+            "degraded: throttled by XContest (HTTP {}) since {}, next attempt at {}",
+            throttling.status.as_u16(),
+            throttling.since.to_rfc3339(),
+            throttling.until.to_rfc3339(),
+        ),
+        None => "ok".into(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Serve a shareable card image for a stored flight
+async fn handle_flight_card(state: State<Arc<SharedState>>, Path(id): Path<i64>) -> Response<Body> {
+    let flight = match db::get_flight(&state.pool, id).await {
+        Ok(Some(stored)) => match stored.to_flight() {
+            Ok(flight) => flight,
+            Err(e) => {
+                tracing::error!("Could not parse stored flight {}: {}", id, e);
+                return http_500();
+            }
+        },
+        Ok(None) => return http_404(),
+        Err(e) => {
+            tracing::error!("Could not fetch flight {}: {}", id, e);
+            return http_500();
+        }
+    };
+
+    // Fetch details for the mini map (the card is rendered without it on failure)
+    let details = match state.details_cache.get_or_fetch(&state.xc, &flight).await {
+        Ok(details) => Some(details),
+        Err(e) => {
+            tracing::warn!("Could not fetch details for card of flight {}: {}", id, e);
+            None
+        }
+    };
+
+    // Render card in a blocking task, since image processing is CPU bound
+    let rendered =
+        tokio::task::spawn_blocking(move || card::render_card(&flight, details.as_ref())).await;
+    match rendered {
+        Ok(Ok(png)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "image/png")
+            .header(header::CACHE_CONTROL, "public, max-age=86400")
+            .body(Body::from(png))
+            .unwrap(),
+        Ok(Err(e)) => {
+            tracing::error!("Could not render card for flight {}: {}", id, e);
+            http_500()
+        }
+        Err(e) => {
+            tracing::error!("Card rendering task failed: {}", e);
+            http_500()
+        }
+    }
+}
+
 pub struct SharedState {
-    pub api: E2eApi,
+    pub tenants: Tenants,
     pub pool: Pool<Sqlite>,
-    pub config: Config,
+    pub status: Arc<BotStatus>,
+    pub xc: Arc<XContest>,
+    pub details_cache: DetailsCache,
+    /// The public landing page, if enabled
+    pub landing_page: Option<LandingPage>,
+    /// The middleware run around every command
+    pub middleware: Arc<crate::middleware::Chain>,
+    /// Whether state-changing API requests are refused
+    pub read_only: bool,
+    /// Whether this instance is the leader (followers queue the commands)
+    pub leadership: Arc<crate::leader::Leadership>,
+    /// Alerts and adoption messages to the admin
+    pub alerter: Alerter,
+    /// Reports panics while handling requests
+    pub panic_reporter: PanicReporter,
+}
+
+/// Bind the HTTP server to the specified address.
+pub async fn bind(listen_addr: SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(listen_addr)
+        .await
+        .context(format!("Could not bind HTTP server to {}", listen_addr))
+}
+
+/// Settings of the HTTP server.
+pub struct ServerOptions {
+    /// CORS layer of the API routes
+    cors: Option<CorsLayer>,
+    client_ip: Arc<ClientIpConfig>,
+    /// Limits of the Threema callback routes
+    backpressure: Arc<Backpressure>,
+    /// Whether responses are compressed
+    compression: bool,
+    /// Access log settings, if enabled
+    access_log: Option<AccessLog>,
+    /// Whether the HTTP API is served
+    api: bool,
+}
+
+impl ServerOptions {
+    pub fn from_config(config: &ServerConfig, features: &FeaturesConfig) -> Result<Self> {
+        Ok(Self {
+            cors: cors_layer(config)?,
+            client_ip: Arc::new(ClientIpConfig::from_config(config)?),
+            backpressure: Arc::new(Backpressure::from_config(config)),
+            compression: config.compression.unwrap_or(true),
+            access_log: config.access_log.unwrap_or(true).then(|| AccessLog {
+                full_ips: config.access_log_full_ips.unwrap_or(false),
+            }),
+            api: features.api(),
+        })
+    }
+}
+
+/// Create the CORS layer of the API routes, if any origins are allowed.
+fn cors_layer(config: &ServerConfig) -> Result<Option<CorsLayer>> {
+    let origins = match &config.cors_allowed_origins {
+        Some(origins) if !origins.is_empty() => origins,
+        _ => return Ok(None),
+    };
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            origins
+                .iter()
+                .map(|origin| {
+                    HeaderValue::from_str(origin)
+                        .context(format!("Invalid CORS origin: {}", origin))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )
+    };
+    let methods = match &config.cors_allowed_methods {
+        Some(methods) => methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .context(format!("Invalid CORS method: {}", method))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![Method::GET],
+    };
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(methods)
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]),
+    ))
 }
 
-/// Bind to `listen_addr` and serve forever.
+/// The admin API routes, served by the internal server if configured, and by
+/// the public server otherwise.
+fn admin_routes() -> axum::Router<Arc<SharedState>> {
+    axum::Router::new()
+        .route("/api/v1/stats", get(api::handle_stats))
+        .route("/api/v1/stats/history", get(api::handle_stats_history))
+        .route(
+            "/api/v1/email/subscriptions",
+            post(api::handle_add_subscription::<EmailNotifier>)
+                .delete(api::handle_remove_subscription::<EmailNotifier>),
+        )
+        .route(
+            "/api/v1/ntfy/subscriptions",
+            post(api::handle_add_subscription::<NtfyNotifier>)
+                .delete(api::handle_remove_subscription::<NtfyNotifier>),
+        )
+        .route(
+            "/api/v1/pushover/subscriptions",
+            post(api::handle_add_subscription::<PushoverNotifier>)
+                .delete(api::handle_remove_subscription::<PushoverNotifier>),
+        )
+        .route(
+            "/api/v1/gotify/subscriptions",
+            post(api::handle_add_subscription::<GotifyNotifier>)
+                .delete(api::handle_remove_subscription::<GotifyNotifier>),
+        )
+}
+
+/// Add the shared state and the compression, tracing, access log and client IP
+/// layers to the router.
+fn finish_router(
+    router: axum::Router<Arc<SharedState>>,
+    state: Arc<SharedState>,
+    options: &ServerOptions,
+) -> axum::Router {
+    let mut router = router.with_state(state);
+    if options.compression {
+        // Images and tiny responses are not compressed
+        router = router.layer(CompressionLayer::new());
+    }
+    router = router.layer(
+        TraceLayer::new_for_http().make_span_with(|request: &Request| {
+            let client_ip = request
+                .extensions()
+                .get::<client_ip::ClientIp>()
+                .map(|client_ip| client_ip.0);
+            tracing::debug_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                version = ?request.version(),
+                client_ip = ?client_ip,
+            )
+        }),
+    );
+    if let Some(access_log) = options.access_log {
+        router = router.layer(middleware::from_fn_with_state(
+            access_log,
+            access_log::log_access,
+        ));
+    }
+    router.layer(middleware::from_fn_with_state(
+        options.client_ip.clone(),
+        client_ip::extract_client_ip,
+    ))
+}
+
+/// Serve the router on the listener until the server stops.
+async fn serve_router(name: &str, listener: TcpListener, router: axum::Router) -> Result<()> {
+    tracing::info!("Starting {} on {}", name, listener.local_addr()?);
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .context(format!("{} error", name))
+}
+
+/// Serve HTTP requests on the listener in a background task.
+///
+/// If there is an internal listener, the health check and the admin API are
+/// only served on that one.
 ///
-/// The async call will return once the server task has been spawned.
-pub async fn serve(state: SharedState, listen_addr: SocketAddr) {
-    // Set up routing and shared state
-    let app = axum::Router::new()
+/// The task only returns if a server stops.
+pub fn serve(
+    state: SharedState,
+    options: ServerOptions,
+    listener: TcpListener,
+    internal_listener: Option<TcpListener>,
+) -> JoinHandle<Result<()>> {
+    let state = Arc::new(state);
+
+    // Threema callback routes
+    let threema = axum::Router::new()
         .route("/receive/threema/", post(handle_threema_request))
-        .with_state(Arc::new(state))
-        .layer(TraceLayer::new_for_http());
+        .route(
+            "/receive/threema/:tenant/",
+            post(handle_tenant_threema_request),
+        )
+        .layer(middleware::from_fn_with_state(
+            options.backpressure.clone(),
+            backpressure::limit_callbacks,
+        ))
+        .layer(middleware::from_fn_with_state(
+            options.client_ip.clone(),
+            client_ip::check_threema_allowlist,
+        ));
+
+    // Public routes
+    let mut app = axum::Router::new();
+    if state.landing_page.is_some() {
+        app = app.route("/", get(landing::handle_landing_page));
+    }
+    let mut app = app
+        .route("/flights/:id/card.png", get(handle_flight_card))
+        .route(
+            "/flights/:id/thumb.jpg",
+            get(thumbnails::handle_flight_thumbnail),
+        )
+        .route("/receive/slack/", post(slack::handle_slash_command))
+        .route(
+            "/receive/slack/:tenant/",
+            post(slack::handle_tenant_slash_command),
+        )
+        .merge(threema);
+    let mut api = axum::Router::new();
+    if options.api {
+        api = api
+            .route(
+                "/api/v1/flights",
+                get(api::handle_flights).post(api::handle_submit_flight),
+            )
+            .route(
+                "/api/v1/clubs/:code/leaderboard",
+                get(api::handle_club_leaderboard),
+            );
+    }
+
+    // Internal routes
+    let mut internal = axum::Router::new().route("/healthz", get(handle_healthz));
+    if options.api {
+        internal = internal.merge(admin_routes());
+    }
+    let internal = match internal_listener {
+        Some(internal_listener) => Some((internal_listener, internal)),
+        None => {
+            app = app.route("/healthz", get(handle_healthz));
+            if options.api {
+                api = api.merge(admin_routes());
+            }
+            None
+        }
+    };
 
-    // Then bind and serve...
-    let listener = tokio::net::TcpListener::bind(listen_addr).await.unwrap();
+    if let Some(cors) = &options.cors {
+        api = api.layer(cors.clone());
+    }
+    let app = finish_router(app.merge(api), state.clone(), &options);
+
+    // Then serve...
     tokio::spawn(async move {
-        tracing::info!("Starting HTTP server on {}", listen_addr);
-        if let Err(e) = axum::serve(listener, app).await {
-            tracing::error!("Server error: {}", e);
+        let public = serve_router("HTTP server", listener, app);
+        match internal {
+            Some((internal_listener, internal)) => {
+                let internal = finish_router(internal, state, &options);
+                tokio::try_join!(
+                    public,
+                    serve_router("internal HTTP server", internal_listener, internal)
+                )?;
+                Ok(())
+            }
+            None => public.await,
         }
-    });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_kinds() {
+        assert_eq!(unsupported_media(0x02, b""), Some(Media::Image));
+        assert_eq!(
+            unsupported_media(0x17, br#"{"b":"1234","m":"audio/aac"}"#),
+            Some(Media::Voice)
+        );
+        assert_eq!(
+            unsupported_media(0x17, br#"{"m":"application/pdf"}"#),
+            Some(Media::File)
+        );
+        assert_eq!(unsupported_media(0x17, b"garbage"), Some(Media::File));
+        assert_eq!(unsupported_media(0x15, b""), None);
+    }
 }
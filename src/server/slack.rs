@@ -0,0 +1,212 @@
+//! Slash commands of the Slack app.
+//!
+//! Slack channels manage their subscriptions with the same commands as
+//! Threema users (e.g. `/xcbot folge dbrgn`). The subscriptions belong to the
+//! channel, so everyone in it follows the same pilots.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, Response, StatusCode},
+};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde_derive::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+
+use super::{http_200, http_404, http_500, SharedState};
+use crate::{
+    commands::{self, IncomingCommand, OutgoingReply},
+    db,
+    tenants::Tenant,
+};
+
+/// Requests signed longer ago than this are rejected (replay protection).
+const MAX_REQUEST_AGE_SECONDS: i64 = 300;
+
+#[derive(Debug, Deserialize)]
+struct SlashCommand {
+    /// The channel the command was sent in
+    channel_id: String,
+    /// The Slack username of the sender
+    user_name: Option<String>,
+    /// The text after the command name
+    #[serde(default)]
+    text: String,
+}
+
+fn http_401() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .body(Body::from("invalid signature"))
+        .unwrap()
+}
+
+/// Verify the signature of a request from Slack (`v0=` followed by the hex
+/// encoded HMAC-SHA256 of `v0:<timestamp>:<body>`).
+fn verify_signature(secret: &str, timestamp: &str, body: &[u8], signature: &str, now: i64) -> bool {
+    match timestamp.parse::<i64>() {
+        Ok(timestamp) if (now - timestamp).abs() <= MAX_REQUEST_AGE_SECONDS => {}
+        _ => return false,
+    }
+    let signature = match signature
+        .strip_prefix("v0=")
+        .and_then(|hex_signature| hex::decode(hex_signature).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(format!("v0:{}:", timestamp).as_bytes());
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Handle a slash command for the default tenant
+pub async fn handle_slash_command(
+    state: State<Arc<SharedState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    process_slash_command(&state, state.tenants.default_tenant(), &headers, &body).await
+}
+
+/// Handle a slash command for the specified tenant
+pub async fn handle_tenant_slash_command(
+    state: State<Arc<SharedState>>,
+    Path(tenant): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    match state.tenants.get(&tenant) {
+        Some(tenant) => process_slash_command(&state, tenant, &headers, &body).await,
+        None => http_404(),
+    }
+}
+
+/// Verify and process a slash command, the reply is only shown to the sender.
+async fn process_slash_command(
+    state: &SharedState,
+    tenant: &Tenant,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Response<Body> {
+    let config = &tenant.config;
+    let slack = match &config.slack {
+        Some(slack) => slack,
+        None => return http_404(),
+    };
+
+    // Verify signature
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    if !verify_signature(
+        &slack.signing_secret,
+        header("X-Slack-Request-Timestamp"),
+        body,
+        header("X-Slack-Signature"),
+        chrono::Utc::now().timestamp(),
+    ) {
+        tracing::warn!("Rejected Slack request with invalid signature");
+        return http_401();
+    }
+
+    // Parse command
+    let command: SlashCommand = match serde_urlencoded::from_bytes(body) {
+        Ok(command) => command,
+        Err(e) => {
+            tracing::warn!("Could not parse Slack slash command: {}", e);
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("invalid command"))
+                .unwrap();
+        }
+    };
+
+    // The channel is the user
+    let user = match db::get_or_create_user(&state.pool, tenant.id(), &command.channel_id, "slack")
+        .await
+    {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::error!("Error in get_or_create_user: {}", e);
+            return http_500();
+        }
+    };
+    let incoming = IncomingCommand {
+        text: command.text.trim(),
+        sender: &command.channel_id,
+        sender_nickname: command.user_name.as_deref(),
+        is_admin: false,
+    };
+    match commands::handle_command(
+        &incoming,
+        config,
+        &user,
+        &state.pool,
+        &state.status,
+        &state.middleware,
+    )
+    .await
+    {
+        OutgoingReply::Text(text) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "response_type": "ephemeral", "text": text }).to_string(),
+            ))
+            .unwrap(),
+        OutgoingReply::Nothing => http_200(),
+        OutgoingReply::Error => http_500(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_signature() {
+        // Example from the Slack documentation
+        let secret = "8f742231b10e8888abcd99yyyzzz85a5";
+        let timestamp = "1531420618";
+        let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let signature = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+        let now = 1531420618;
+        assert!(verify_signature(secret, timestamp, body, signature, now));
+
+        // Tampered body, wrong secret, old timestamp
+        assert!(!verify_signature(
+            secret,
+            timestamp,
+            b"text=unfollow",
+            signature,
+            now
+        ));
+        assert!(!verify_signature("secret", timestamp, body, signature, now));
+        assert!(!verify_signature(
+            secret,
+            timestamp,
+            body,
+            signature,
+            now + MAX_REQUEST_AGE_SECONDS + 1
+        ));
+        assert!(!verify_signature(secret, timestamp, body, "invalid", now));
+
+        // The command is parsed from the form body
+        let command: SlashCommand = serde_urlencoded::from_bytes(body).unwrap();
+        assert_eq!(command.channel_id, "G8PSS9T3V");
+        assert_eq!(command.user_name.as_deref(), Some("roadrunner"));
+        assert_eq!(command.text, "");
+    }
+}
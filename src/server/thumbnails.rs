@@ -0,0 +1,185 @@
+//! Flight thumbnails served as JPEG.
+//!
+//! Channels that can't send blobs (e-mail, webhooks, RSS re-exports,
+//! dashboards) reference these URLs instead of embedding the preview image.
+
+use std::{io::Cursor, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, Response, StatusCode},
+};
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, ImageReader};
+use serde_derive::Deserialize;
+use xcontest_client::FlightDetails;
+
+use super::{http_404, http_500, SharedState};
+use crate::db;
+
+/// Thumbnails of a flight never change, so they may be cached for a day.
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// Size variant of a thumbnail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailSize {
+    /// Max 128x128px
+    Small,
+    /// Max 512x512px (the cached small thumbnail)
+    Medium,
+    /// Original size of the preview
+    Large,
+}
+
+impl ThumbnailSize {
+    fn name(&self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "small",
+            ThumbnailSize::Medium => "medium",
+            ThumbnailSize::Large => "large",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailParams {
+    /// Size variant (default: medium)
+    size: Option<ThumbnailSize>,
+}
+
+/// Return the thumbnail of the specified size as JPEG.
+fn render_thumbnail(details: &FlightDetails, size: ThumbnailSize) -> Result<Vec<u8>> {
+    let image = match size {
+        ThumbnailSize::Medium => return Ok(details.thumbnail_small.to_vec()),
+        ThumbnailSize::Small => ImageReader::with_format(
+            Cursor::new(&details.thumbnail_small),
+            image::ImageFormat::Jpeg,
+        )
+        .decode()
+        .context("Could not decode thumbnail")?
+        .resize(128, 128, FilterType::CatmullRom),
+        ThumbnailSize::Large => {
+            ImageReader::with_format(Cursor::new(&details.thumbnail_large), details.format.into())
+                .decode()
+                .context("Could not decode flight preview")?
+        }
+    };
+    let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    image
+        .to_rgb8()
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 80))
+        .context("Could not encode thumbnail")?;
+    Ok(bytes.into_inner())
+}
+
+/// Serve the thumbnail of a stored flight
+pub async fn handle_flight_thumbnail(
+    state: State<Arc<SharedState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<ThumbnailParams>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let size = params.size.unwrap_or(ThumbnailSize::Medium);
+    let etag = format!("\"{}-{}\"", id, size.name());
+
+    // Thumbnails never change, so a matching ETag means the client is up to date
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == etag);
+    if not_modified {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, etag)
+            .header(header::CACHE_CONTROL, CACHE_CONTROL)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let flight = match db::get_flight(&state.pool, id).await {
+        Ok(Some(stored)) => match stored.to_flight() {
+            Ok(flight) => flight,
+            Err(e) => {
+                tracing::error!("Could not parse stored flight {}: {}", id, e);
+                return http_500();
+            }
+        },
+        Ok(None) => return http_404(),
+        Err(e) => {
+            tracing::error!("Could not fetch flight {}: {}", id, e);
+            return http_500();
+        }
+    };
+
+    // Fetch details (usually cached, since the subscribers were notified)
+    let details = match state.details_cache.get_or_fetch(&state.xc, &flight).await {
+        Ok(details) => details,
+        Err(xcontest_client::Error::NotFound(_)) => return http_404(),
+        Err(e) => {
+            tracing::warn!(
+                "Could not fetch details for thumbnail of flight {}: {}",
+                id,
+                e
+            );
+            return http_500();
+        }
+    };
+
+    // Resize in a blocking task, since image processing is CPU bound
+    let rendered = tokio::task::spawn_blocking(move || render_thumbnail(&details, size)).await;
+    match rendered {
+        Ok(Ok(jpeg)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "image/jpeg")
+            .header(header::CACHE_CONTROL, CACHE_CONTROL)
+            .header(header::ETAG, etag)
+            .body(Body::from(jpeg))
+            .unwrap(),
+        Ok(Err(e)) => {
+            tracing::error!("Could not render thumbnail for flight {}: {}", id, e);
+            http_500()
+        }
+        Err(e) => {
+            tracing::error!("Thumbnail rendering task failed: {}", e);
+            http_500()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use image::{GenericImageView, RgbImage};
+    use xcontest_client::PreviewFormat;
+
+    use super::*;
+
+    fn encode(image: &RgbImage, format: image::ImageFormat) -> Bytes {
+        let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, format).unwrap();
+        Bytes::from(bytes.into_inner())
+    }
+
+    #[test]
+    fn thumbnail_sizes() {
+        let details = FlightDetails {
+            thumbnail_large: encode(&RgbImage::new(800, 600), image::ImageFormat::Png),
+            thumbnail_small: encode(&RgbImage::new(512, 384), image::ImageFormat::Jpeg),
+            format: PreviewFormat::Png,
+            animated: false,
+            times: Default::default(),
+            triangle: Default::default(),
+        };
+        let dimensions = |size| {
+            let jpeg = render_thumbnail(&details, size).unwrap();
+            image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg)
+                .unwrap()
+                .dimensions()
+        };
+        assert_eq!(dimensions(ThumbnailSize::Small), (128, 96));
+        assert_eq!(dimensions(ThumbnailSize::Medium), (512, 384));
+        assert_eq!(dimensions(ThumbnailSize::Large), (800, 600));
+    }
+}
@@ -0,0 +1,292 @@
+//! Runtime status of the bot, shared between the fetch loop and the server.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+use chrono::{DateTime, Local};
+use reqwest::StatusCode;
+
+/// XContest is currently throttling or blocking our requests.
+#[derive(Debug, Clone)]
+pub struct Throttling {
+    /// The HTTP status code returned by XContest
+    pub status: StatusCode,
+    /// When throttling was first detected
+    pub since: DateTime<Local>,
+    /// When the next fetch will be attempted
+    pub until: DateTime<Local>,
+}
+
+/// Number of parsed and unparseable feed items.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseCoverage {
+    pub parsed: u64,
+    pub failed: u64,
+}
+
+impl ParseCoverage {
+    /// Return the percentage of feed items that could not be parsed.
+    pub fn failure_percent(&self) -> f64 {
+        let total = self.parsed + self.failed;
+        if total == 0 {
+            return 0.0;
+        }
+        self.failed as f64 * 100.0 / total as f64
+    }
+
+    /// Return whether more than `percent` percent of the feed items could not
+    /// be parsed.
+    pub fn exceeds(&self, percent: u32) -> bool {
+        self.failed > 0 && self.failure_percent() > f64::from(percent)
+    }
+}
+
+/// Number of recent notification latencies the percentiles are based on.
+const LATENCY_SAMPLES: usize = 100;
+
+/// Percentiles of the latency between the publication of flights in the feed
+/// and their notification, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    /// Number of notified flights the percentiles are based on
+    pub samples: usize,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+impl LatencyPercentiles {
+    /// Calculate the percentiles (nearest rank) of the latencies.
+    fn calculate(latencies: &VecDeque<u64>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<u64> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[(p * sorted.len()).div_ceil(100).max(1) - 1];
+        Some(Self {
+            samples: sorted.len(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct BotStatus {
+    /// When the bot was started
+    started_at: DateTime<Local>,
+    /// When the feed was last fetched successfully
+    last_fetch: RwLock<Option<DateTime<Local>>>,
+    /// Parse coverage of the last fetch cycle
+    last_parse_coverage: RwLock<Option<ParseCoverage>>,
+    /// Parse coverage of all fetch cycles since the start
+    total_parse_coverage: Mutex<ParseCoverage>,
+    throttling: RwLock<Option<Throttling>>,
+    /// Number of handled and failed commands since the start, per command
+    commands: Mutex<BTreeMap<&'static str, (u64, u64)>>,
+    /// Number of caught panics since the start
+    panics: AtomicU64,
+    /// Latencies of the last notified flights in seconds, oldest first
+    notification_latencies: Mutex<VecDeque<u64>>,
+}
+
+impl Default for BotStatus {
+    fn default() -> Self {
+        Self {
+            started_at: Local::now(),
+            last_fetch: RwLock::default(),
+            last_parse_coverage: RwLock::default(),
+            total_parse_coverage: Mutex::default(),
+            throttling: RwLock::default(),
+            commands: Mutex::default(),
+            panics: AtomicU64::default(),
+            notification_latencies: Mutex::default(),
+        }
+    }
+}
+
+impl BotStatus {
+    /// Return when the bot was started.
+    pub fn started_at(&self) -> DateTime<Local> {
+        self.started_at
+    }
+
+    /// Return when the feed was last fetched successfully.
+    pub fn last_fetch(&self) -> Option<DateTime<Local>> {
+        *self.last_fetch.read().unwrap()
+    }
+
+    /// Record a successful fetch of the feed.
+    pub fn record_fetch(&self) {
+        *self.last_fetch.write().unwrap() = Some(Local::now());
+    }
+
+    /// Record the parse coverage of a fetch cycle.
+    pub fn record_parse_coverage(&self, coverage: ParseCoverage) {
+        *self.last_parse_coverage.write().unwrap() = Some(coverage);
+        let mut total = self.total_parse_coverage.lock().unwrap();
+        total.parsed += coverage.parsed;
+        total.failed += coverage.failed;
+    }
+
+    /// Return the parse coverage of the last fetch cycle.
+    pub fn last_parse_coverage(&self) -> Option<ParseCoverage> {
+        *self.last_parse_coverage.read().unwrap()
+    }
+
+    /// Return the parse coverage of all fetch cycles since the start.
+    pub fn total_parse_coverage(&self) -> ParseCoverage {
+        *self.total_parse_coverage.lock().unwrap()
+    }
+
+    /// Return the current throttling state, if XContest is throttling us.
+    pub fn throttling(&self) -> Option<Throttling> {
+        self.throttling.read().unwrap().clone()
+    }
+
+    /// Mark XContest as throttling until `until`.
+    ///
+    /// Return `true` if we were not throttled before.
+    pub fn set_throttled(&self, status: StatusCode, until: DateTime<Local>) -> bool {
+        let mut throttling = self.throttling.write().unwrap();
+        let since = throttling.as_ref().map(|t| t.since);
+        *throttling = Some(Throttling {
+            status,
+            since: since.unwrap_or_else(Local::now),
+            until,
+        });
+        since.is_none()
+    }
+
+    /// Clear the throttling state.
+    ///
+    /// Return `true` if we were throttled before.
+    pub fn clear_throttled(&self) -> bool {
+        self.throttling.write().unwrap().take().is_some()
+    }
+
+    /// Count a handled command.
+    pub fn record_command(&self, name: &'static str, failed: bool) {
+        let mut commands = self.commands.lock().unwrap();
+        let (handled, failures) = commands.entry(name).or_default();
+        *handled += 1;
+        if failed {
+            *failures += 1;
+        }
+    }
+
+    /// Return the number of handled and failed commands since the start, per
+    /// command.
+    pub fn command_counts(&self) -> Vec<(String, u64, u64)> {
+        self.commands
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, (handled, failures))| (name.to_string(), *handled, *failures))
+            .collect()
+    }
+
+    /// Count a caught panic and return the number of panics since the start.
+    pub fn record_panic(&self) -> u64 {
+        self.panics.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Return the number of caught panics since the start.
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Record the seconds between the publication of a flight in the feed and
+    /// its notification.
+    pub fn record_notification_latency(&self, seconds: u64) {
+        let mut latencies = self.notification_latencies.lock().unwrap();
+        if latencies.len() == LATENCY_SAMPLES {
+            latencies.pop_front();
+        }
+        latencies.push_back(seconds);
+    }
+
+    /// Return the latency percentiles of the last notified flights, if any.
+    pub fn notification_latency(&self) -> Option<LatencyPercentiles> {
+        LatencyPercentiles::calculate(&self.notification_latencies.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_coverage() {
+        let status = BotStatus::default();
+        assert_eq!(status.last_parse_coverage(), None);
+
+        let coverage = ParseCoverage {
+            parsed: 18,
+            failed: 2,
+        };
+        assert_eq!(coverage.failure_percent(), 10.0);
+        assert!(!coverage.exceeds(10));
+        assert!(coverage.exceeds(5));
+        assert!(coverage.exceeds(0));
+        assert!(!ParseCoverage::default().exceeds(0));
+
+        status.record_parse_coverage(coverage);
+        status.record_parse_coverage(ParseCoverage {
+            parsed: 20,
+            failed: 0,
+        });
+        assert_eq!(
+            status.last_parse_coverage(),
+            Some(ParseCoverage {
+                parsed: 20,
+                failed: 0
+            })
+        );
+        assert_eq!(
+            status.total_parse_coverage(),
+            ParseCoverage {
+                parsed: 38,
+                failed: 2
+            }
+        );
+    }
+
+    #[test]
+    fn notification_latency() {
+        let status = BotStatus::default();
+        assert_eq!(status.notification_latency(), None);
+
+        status.record_notification_latency(42);
+        assert_eq!(
+            status.notification_latency(),
+            Some(LatencyPercentiles {
+                samples: 1,
+                p50: 42,
+                p90: 42,
+                p99: 42,
+            })
+        );
+
+        // Only the last samples are kept
+        for seconds in 1..=(LATENCY_SAMPLES as u64) {
+            status.record_notification_latency(seconds);
+        }
+        assert_eq!(
+            status.notification_latency(),
+            Some(LatencyPercentiles {
+                samples: 100,
+                p50: 50,
+                p90: 90,
+                p99: 99,
+            })
+        );
+    }
+}
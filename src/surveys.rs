@@ -0,0 +1,214 @@
+//! Surveys (e.g. yearly feedback surveys) sent to users as Threema polls.
+//!
+//! The Threema Gateway has no dedicated API for polls, so they are sent as
+//! end-to-end encrypted messages using the poll (ballot) message types of the
+//! Threema protocol. A poll setup message consists of the 8 byte ballot ID and
+//! the poll as JSON. Votes come back through the webhook as messages with the
+//! identity of the poll creator, the ballot ID and the selected choices.
+
+use anyhow::{bail, Context, Result};
+use crypto_box::aead::{rand_core::RngCore, OsRng};
+use serde_json::json;
+use sqlx::{Pool, Sqlite};
+
+use crate::{
+    config::TenantConfig,
+    db::{self, StoredSurvey, User},
+    jobs::{self, Job},
+    tenants::Tenant,
+    threema,
+};
+
+/// Length of a Threema identity
+const IDENTITY_LENGTH: usize = 8;
+
+/// Length of a ballot ID
+const BALLOT_ID_LENGTH: usize = 8;
+
+/// Create a survey and queue a poll message for every recipient (all Threema
+/// users of the tenant, or a random sample of them).
+///
+/// Return the survey ID and the number of recipients.
+pub async fn create(
+    pool: &Pool<Sqlite>,
+    tenant: &str,
+    question: &str,
+    options: &[String],
+    sample: Option<u32>,
+) -> Result<(i64, usize)> {
+    let mut ballot_id = [0u8; BALLOT_ID_LENGTH];
+    OsRng.fill_bytes(&mut ballot_id);
+    let recipients = db::get_survey_recipients(pool, tenant, sample).await?;
+    let survey_id = db::insert_survey(
+        pool,
+        tenant,
+        question,
+        options,
+        &hex::encode(ballot_id),
+        recipients.len() as u32,
+    )
+    .await?;
+    for user in &recipients {
+        let job = Job::SendSurvey {
+            survey_id,
+            user_id: user.id,
+        };
+        jobs::enqueue(pool, &job, std::time::Duration::ZERO).await?;
+    }
+    Ok((survey_id, recipients.len()))
+}
+
+/// Return the content of the poll setup message of the survey.
+fn poll_data(survey: &StoredSurvey) -> Result<Vec<u8>> {
+    let choices = survey
+        .options()?
+        .iter()
+        .enumerate()
+        .map(|(i, option)| json!({ "i": i, "n": option, "o": i, "r": [] }))
+        .collect::<Vec<_>>();
+    let poll = json!({
+        // Description
+        "d": survey.question,
+        // State: Open
+        "s": 0,
+        // Assessment: Single choice
+        "a": 0,
+        // Type: Results are shown when the poll is closed
+        "t": 0,
+        // Choice type: Text
+        "o": 0,
+        "c": choices,
+        "p": [],
+    });
+    let mut data = hex::decode(&survey.ballot_id).context("Invalid ballot ID")?;
+    data.extend(serde_json::to_vec(&poll).context("Could not serialize poll")?);
+    Ok(data)
+}
+
+/// Send the poll of the survey to the user.
+pub async fn send(pool: &Pool<Sqlite>, tenant: &Tenant, survey_id: i64, user: &User) -> Result<()> {
+    let survey = db::get_survey(pool, survey_id)
+        .await?
+        .context(format!("Survey {} does not exist", survey_id))?;
+    threema::send_poll_message(
+        user,
+        &poll_data(&survey)?,
+        &tenant.api,
+        pool,
+        tenant.config.threema.request_delivery_receipts(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// A vote parsed from a poll vote message.
+#[derive(Debug, PartialEq, Eq)]
+struct Vote {
+    /// Identity of the poll creator
+    creator: String,
+    /// Hex encoded ballot ID
+    ballot_id: String,
+    /// The selected choice (`None` if the vote was withdrawn)
+    choice: Option<u32>,
+}
+
+/// Parse the content of a poll vote message.
+fn parse_vote(data: &[u8]) -> Result<Vote> {
+    if data.len() < IDENTITY_LENGTH + BALLOT_ID_LENGTH {
+        bail!("Poll vote is too short ({} bytes)", data.len());
+    }
+    let (creator, rest) = data.split_at(IDENTITY_LENGTH);
+    let (ballot_id, votes) = rest.split_at(BALLOT_ID_LENGTH);
+    let creator = std::str::from_utf8(creator).context("Invalid poll creator identity")?;
+
+    // The votes are a list of [choice, selected] pairs
+    let votes: Vec<(u32, u8)> = serde_json::from_slice(votes).context("Invalid poll votes")?;
+    let choice = votes
+        .iter()
+        .find(|(_, selected)| *selected != 0)
+        .map(|(choice, _)| *choice);
+    Ok(Vote {
+        creator: creator.to_string(),
+        ballot_id: hex::encode(ballot_id),
+        choice,
+    })
+}
+
+/// Record the vote of a user contained in a poll vote message.
+pub async fn record_vote(
+    pool: &Pool<Sqlite>,
+    tenant: &TenantConfig,
+    user: &User,
+    data: &[u8],
+) -> Result<()> {
+    let vote = parse_vote(data)?;
+    if vote.creator != tenant.threema.gateway_id {
+        bail!("Vote for poll of {} ignored", vote.creator);
+    }
+    let survey = db::get_survey_by_ballot_id(pool, &tenant.id, &vote.ballot_id)
+        .await?
+        .context(format!("No survey with ballot ID {}", vote.ballot_id))?;
+    if let Some(choice) = vote.choice {
+        if choice as usize >= survey.options()?.len() {
+            bail!("Invalid choice {} for survey {}", choice, survey.id);
+        }
+    }
+    Ok(db::set_survey_response(pool, survey.id, user.id, vote.choice).await?)
+}
+
+/// Format the results of a survey for the admin.
+pub async fn format_results(pool: &Pool<Sqlite>, survey: &StoredSurvey) -> Result<String> {
+    let results = db::get_survey_results(pool, survey.id).await?;
+    let total: u32 = results.iter().map(|(_, count)| count).sum();
+    let mut text = format!(
+        "Survey {} ({}): {}\n\nSent to {} users, {} responses:\n",
+        survey.id, survey.created_at, survey.question, survey.recipients, total
+    );
+    for (i, option) in survey.options()?.iter().enumerate() {
+        let count = results
+            .iter()
+            .find(|(choice, _)| *choice as usize == i)
+            .map_or(0, |(_, count)| *count);
+        let percent = (count * 100).checked_div(total).unwrap_or(0);
+        text.push_str(&format!("\n- {}: {} ({}%)", option, count, percent));
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn poll_and_vote() {
+        let survey = StoredSurvey {
+            id: 1,
+            tenant: "default".into(),
+            question: "Wie gefällt dir der Bot?".into(),
+            options: r#"["Gut","Schlecht"]"#.into(),
+            ballot_id: "0102030405060708".into(),
+            recipients: 2,
+            created_at: "2026-10-17 12:00:00".into(),
+        };
+        let data = poll_data(&survey).unwrap();
+        assert_eq!(&data[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let poll: Value = serde_json::from_slice(&data[8..]).unwrap();
+        assert_eq!(poll["d"], "Wie gefällt dir der Bot?");
+        assert_eq!(poll["c"][1]["n"], "Schlecht");
+
+        let mut vote = b"*XCBOTXX".to_vec();
+        vote.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+        vote.extend(b"[[0,0],[1,1]]");
+        assert_eq!(
+            parse_vote(&vote).unwrap(),
+            Vote {
+                creator: "*XCBOTXX".into(),
+                ballot_id: "0102030405060708".into(),
+                choice: Some(1),
+            }
+        );
+        assert!(parse_vote(&vote[..10]).is_err());
+    }
+}
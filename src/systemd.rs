@@ -0,0 +1,105 @@
+//! Minimal systemd integration: Readiness and watchdog notifications
+//! (`sd_notify`) and socket activation.
+//!
+//! All functions are no-ops if the bot is not started by systemd.
+
+use std::{env, time::Duration};
+
+use anyhow::{Context, Result};
+use tokio::net::TcpListener;
+
+/// The first file descriptor passed by systemd (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Send a notification (e.g. `READY=1` or `WATCHDOG=1`) to systemd.
+///
+/// Errors are logged, since they must not affect the bot itself.
+pub fn notify(state: &str) {
+    if let Err(e) = try_notify(state) {
+        tracing::warn!("Could not send notification {:?} to systemd: {}", state, e);
+    }
+}
+
+#[cfg(unix)]
+fn try_notify(state: &str) -> Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let socket = UnixDatagram::unbound().context("Could not create socket")?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let addr = SocketAddr::from_abstract_name(name.as_bytes())
+                .context("Invalid abstract socket name")?;
+            socket.send_to_addr(state.as_bytes(), &addr)
+        }
+        _ => socket.send_to(state.as_bytes(), &*path),
+    }
+    .context(format!("Could not send to {}", path))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn try_notify(_state: &str) -> Result<()> {
+    Ok(())
+}
+
+/// Return the interval in which systemd expects watchdog pings, if the
+/// watchdog is enabled for this process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse()
+        .ok()
+        .map(Duration::from_micros)
+}
+
+/// Return the listening socket passed by systemd (socket activation), if
+/// any.
+#[cfg(unix)]
+pub fn take_listener() -> Result<Option<TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse().ok());
+    let fds: u32 = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse().ok())
+        .unwrap_or(0);
+    if pid != Some(std::process::id()) || fds == 0 {
+        return Ok(None);
+    }
+    if fds > 1 {
+        tracing::warn!("Got {} sockets from systemd, using the first one", fds);
+    }
+
+    // Don't pass the sockets on to child processes
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // Safety: systemd passes the sockets as file descriptors starting at
+    // SD_LISTEN_FDS_START, and they are only taken once.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener
+        .set_nonblocking(true)
+        .context("Could not configure socket passed by systemd")?;
+    Ok(Some(
+        TcpListener::from_std(listener).context("Invalid socket passed by systemd")?,
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn take_listener() -> Result<Option<TcpListener>> {
+    Ok(None)
+}
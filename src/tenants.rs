@@ -0,0 +1,76 @@
+//! Tenants: Several logical bots running in one process.
+//!
+//! Every tenant has its own Threema Gateway ID, feed and texts. Users,
+//! flights and leaderboards are scoped by tenant ID in the database.
+
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::{bail, Result};
+use threema_gateway::E2eApi;
+
+use crate::config::{Config, TenantConfig};
+
+/// ID of the tenant configured by the top-level settings.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// A tenant with its Threema Gateway API client.
+pub struct Tenant {
+    pub config: TenantConfig,
+    pub api: E2eApi,
+}
+
+impl Tenant {
+    pub fn id(&self) -> &str {
+        &self.config.id
+    }
+}
+
+/// All configured tenants.
+#[derive(Clone)]
+pub struct Tenants {
+    tenants: Arc<Vec<Tenant>>,
+}
+
+impl Tenants {
+    /// Create the tenants configured in the config, including the default
+    /// tenant.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut ids = HashSet::new();
+        let mut tenants = vec![];
+        for mut tenant_config in config.tenants() {
+            if tenant_config.id.is_empty()
+                || !tenant_config
+                    .id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!("Invalid tenant ID: {:?}", tenant_config.id);
+            }
+            if !ids.insert(tenant_config.id.clone()) {
+                bail!("Duplicate tenant ID: {}", tenant_config.id);
+            }
+            tenant_config.load_messages()?;
+            tenants.push(Tenant {
+                api: crate::build_threema_api(&tenant_config.threema)?,
+                config: tenant_config,
+            });
+        }
+        Ok(Self {
+            tenants: Arc::new(tenants),
+        })
+    }
+
+    /// Return the tenant with the specified ID.
+    pub fn get(&self, id: &str) -> Option<&Tenant> {
+        self.tenants.iter().find(|tenant| tenant.id() == id)
+    }
+
+    /// Return the default tenant.
+    pub fn default_tenant(&self) -> &Tenant {
+        &self.tenants[0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tenant> {
+        self.tenants.iter()
+    }
+}
@@ -1,15 +1,91 @@
 use anyhow::{Context, Result};
 use sqlx::{Pool, Sqlite};
-use threema_gateway::{E2eApi, RecipientKey};
+use threema_gateway::{E2eApi, MessageType, RecipientKey};
 
-use crate::db::{cache_public_key, User};
+use crate::{
+    db::{self, cache_public_key, User},
+    notifiers::Error as SendError,
+};
+
+/// What an outbound message is about. It is stored together with the message
+/// ID returned by the gateway, to map the ID back to the user and flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind<'a> {
+    /// Notification about the flight with this URL
+    Notification(&'a str),
+    /// Notification about several flights of a pilot
+    GroupNotification,
+    /// Correction of the flight with this URL
+    Correction(&'a str),
+    /// Hint that the monthly notification cap is reached
+    CapReached,
+    /// Reply to a command or message of the user
+    Reply,
+    Digest,
+    ClubLeaderboard,
+    /// Note about a club member seen in the feed for the first time
+    ClubMember,
+    /// Hint that temporary subscriptions ended
+    FollowExpired,
+    /// Hint that a followed pilot was possibly renamed
+    RenameHint,
+    Survey,
+    /// Alert to the admin
+    Alert,
+    /// Read receipt for an incoming message
+    ReadReceipt,
+}
+
+impl MessageKind<'_> {
+    /// Return the name stored in the database.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Notification(_) => "notification",
+            Self::GroupNotification => "group_notification",
+            Self::Correction(_) => "correction",
+            Self::CapReached => "cap_reached",
+            Self::Reply => "reply",
+            Self::Digest => "digest",
+            Self::ClubLeaderboard => "club_leaderboard",
+            Self::ClubMember => "club_member",
+            Self::FollowExpired => "follow_expired",
+            Self::RenameHint => "rename_hint",
+            Self::Survey => "survey",
+            Self::Alert => "alert",
+            Self::ReadReceipt => "read_receipt",
+        }
+    }
+
+    /// Return the URL of the flight the message is about.
+    pub fn flight_url(&self) -> Option<&str> {
+        match self {
+            Self::Notification(url) | Self::Correction(url) => Some(url),
+            _ => None,
+        }
+    }
+}
+
+/// Remember the message ID of a message sent to the user. Failures are only
+/// logged, since the message was sent anyway.
+pub async fn record_sent_message(
+    pool: &Pool<Sqlite>,
+    user_id: i32,
+    message_id: &str,
+    kind: MessageKind<'_>,
+) {
+    let result =
+        db::insert_sent_message(pool, message_id, user_id, kind.name(), kind.flight_url()).await;
+    if let Err(e) = result {
+        tracing::warn!("Could not store sent message {}: {}", message_id, e);
+    }
+}
 
 /// Return the public key of this user. If it isn't known yet, fetch and cache it.
 pub async fn get_public_key(
     user: &User,
     api: &E2eApi,
     pool: &Pool<Sqlite>,
-) -> Result<RecipientKey> {
+) -> Result<RecipientKey, SendError> {
     Ok(match user.threema_public_key.as_ref() {
         Some(pubkey) => {
             tracing::info!("Using cached public key for {}", user.username);
@@ -22,10 +98,9 @@ pub async fn get_public_key(
             );
 
             // Fetch public key from API
-            let pubkey = api
-                .lookup_pubkey(&user.username)
-                .await
-                .context("Could not look up recipient public key")?;
+            let pubkey = api.lookup_pubkey(&user.username).await.map_err(|e| {
+                SendError::api("Could not look up recipient public key", e, &user.username)
+            })?;
 
             // Cache public key
             let pool_clone = pool.clone();
@@ -45,3 +120,82 @@ pub async fn get_public_key(
         }
     })
 }
+
+/// Send a text message of the specified kind to the specified user.
+///
+/// Return the message ID.
+pub async fn send_text_message(
+    user: &User,
+    text: &str,
+    kind: MessageKind<'_>,
+    api: &E2eApi,
+    pool: &Pool<Sqlite>,
+    delivery_receipts: bool,
+) -> Result<String, SendError> {
+    let public_key = get_public_key(user, api, pool).await?;
+    let encrypted = api
+        .encrypt_text_msg(text, &public_key)
+        .map_err(|e| SendError::message("Failed to encrypt text message", e))?;
+    let msg_id = api
+        .send(&user.username, &encrypted, delivery_receipts)
+        .await
+        .map_err(|e| SendError::api("Could not send text message", e, &user.username))?;
+    record_sent_message(pool, user.id, &msg_id, kind).await;
+    Ok(msg_id)
+}
+
+/// Message type of a poll (called ballot in the Threema protocol) setup
+const MESSAGE_TYPE_POLL_SETUP: u8 = 0x15;
+
+/// Message type of a poll vote
+pub const MESSAGE_TYPE_POLL_VOTE: u8 = 0x16;
+
+/// Send a poll message with the specified data (ballot ID followed by the JSON
+/// encoded poll) to the specified user.
+///
+/// Return the message ID.
+pub async fn send_poll_message(
+    user: &User,
+    data: &[u8],
+    api: &E2eApi,
+    pool: &Pool<Sqlite>,
+    delivery_receipts: bool,
+) -> Result<String, SendError> {
+    let public_key = get_public_key(user, api, pool).await?;
+    let encrypted = api
+        .encrypt(
+            data,
+            MessageType::Other(MESSAGE_TYPE_POLL_SETUP),
+            &public_key,
+        )
+        .map_err(|e| SendError::message("Failed to encrypt poll message", e))?;
+    let msg_id = api
+        .send(&user.username, &encrypted, delivery_receipts)
+        .await
+        .map_err(|e| SendError::api("Could not send poll message", e, &user.username))?;
+    record_sent_message(pool, user.id, &msg_id, MessageKind::Survey).await;
+    Ok(msg_id)
+}
+
+/// Delivery receipt status: The message was read
+const DELIVERY_RECEIPT_READ: u8 = 0x02;
+
+/// Send a read receipt for the incoming message with the specified (hex
+/// encoded) message ID.
+///
+/// Return the message ID of the receipt.
+pub async fn send_read_receipt(
+    to: &str,
+    message_id: &str,
+    public_key: &RecipientKey,
+    api: &E2eApi,
+) -> Result<String> {
+    let mut data = vec![DELIVERY_RECEIPT_READ];
+    data.extend(hex::decode(message_id).context("Invalid message ID")?);
+    let encrypted = api
+        .encrypt(&data, MessageType::DeliveryReceipt, public_key)
+        .context("Failed to encrypt delivery receipt")?;
+    api.send(to, &encrypted, false)
+        .await
+        .context("Could not send delivery receipt")
+}
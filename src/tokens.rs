@@ -0,0 +1,158 @@
+//! Tokens for the HTTP API.
+//!
+//! Tokens are random strings handed out once. Only their SHA-256 hash is
+//! stored, so a leaked database doesn't leak usable tokens. Every token has a
+//! scope: `read` tokens can query data, `admin` tokens can do everything.
+//! Tokens created by a tenant admin are restricted to the tenant, tokens
+//! created with the CLI are valid for all tenants.
+
+use std::{fmt, path::Path, str::FromStr};
+
+use anyhow::{bail, Result};
+use crypto_box::aead::{rand_core::RngCore, OsRng};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite};
+
+use crate::{cli::TokenAction, config::Config, db};
+
+/// Prefix of all tokens, to make them recognizable (e.g. by secret scanners).
+const TOKEN_PREFIX: &str = "xcb_";
+
+/// The permissions of an API token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Query data
+    Read,
+    /// Query data and use the admin endpoints
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Whether this scope includes the permissions of the `required` scope.
+    pub fn allows(self, required: Scope) -> bool {
+        self >= required
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "admin" => Ok(Scope::Admin),
+            other => bail!("Unknown token scope: {} (expected read or admin)", other),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Return the hex-encoded SHA-256 hash of the token.
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Create a new token (restricted to the tenant, if specified) and return its
+/// ID and the token itself (which can't be recovered later).
+pub async fn create(
+    pool: &Pool<Sqlite>,
+    tenant: Option<&str>,
+    name: &str,
+    scope: Scope,
+) -> Result<(i64, String)> {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    let token = format!("{}{}", TOKEN_PREFIX, hex::encode(bytes));
+    let id = db::insert_api_token(pool, tenant, name, &hash(&token), scope.as_str()).await?;
+    Ok((id, token))
+}
+
+/// Return the token, if it exists and has the required scope.
+pub async fn authenticate(
+    pool: &Pool<Sqlite>,
+    token: &str,
+    required: Scope,
+) -> Result<Option<db::ApiToken>> {
+    let stored = match db::use_api_token(pool, &hash(token)).await? {
+        Some(stored) => stored,
+        None => return Ok(None),
+    };
+    let allowed = stored
+        .scope
+        .parse::<Scope>()
+        .is_ok_and(|scope| scope.allows(required));
+    Ok(allowed.then_some(stored))
+}
+
+/// Format a token for listings.
+pub fn format_token(token: &db::ApiToken) -> String {
+    format!(
+        "{} {} ({}, {}), created {}, last used {}",
+        token.id,
+        token.name,
+        token.scope,
+        token.tenant.as_deref().unwrap_or("all tenants"),
+        token.created_at,
+        token.last_used_at.as_deref().unwrap_or("never"),
+    )
+}
+
+/// Run the `token` CLI command against the configured database.
+pub async fn run(configfile: &Path, action: TokenAction) -> Result<()> {
+    let config = Config::load_or_env(configfile)
+        .map_err(|e| anyhow::anyhow!("Could not load config file {:?}: {}", configfile, e))?;
+    let pool = db::connect(config.database_path(), &config.pool_settings()).await?;
+    db::migrate(&pool).await?;
+
+    match action {
+        TokenAction::Create { name, scope } => {
+            let (id, token) = create(&pool, None, &name, scope.parse()?).await?;
+            println!("Created token {} ({}):\n\n{}\n", id, name, token);
+            println!("Store the token now, it can't be shown again.");
+        }
+        TokenAction::List => {
+            let tokens = db::get_api_tokens(&pool, None).await?;
+            for token in &tokens {
+                println!("{}", format_token(token));
+            }
+            println!("\n{} token(s).", tokens.len());
+        }
+        TokenAction::Revoke { id } => {
+            if db::delete_api_token(&pool, None, id).await? {
+                println!("Revoked token {}.", id);
+            } else {
+                println!("Token {} does not exist.", id);
+            }
+        }
+    }
+
+    pool.close().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes() {
+        assert!(Scope::Admin.allows(Scope::Read));
+        assert!(Scope::Admin.allows(Scope::Admin));
+        assert!(Scope::Read.allows(Scope::Read));
+        assert!(!Scope::Read.allows(Scope::Admin));
+        assert_eq!("admin".parse::<Scope>().unwrap(), Scope::Admin);
+        assert!("write".parse::<Scope>().is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Persistent HTTP response cache for XContest requests.
+//!
+//! Responses are stored on disk (body and metadata per URL) and reused
+//! according to their `Cache-Control` header: Fresh responses are served
+//! without a request, responses within the `stale-while-revalidate` window are
+//! served while they are revalidated in the background, and older responses
+//! are still served if XContest cannot be reached (`stale-if-error`, or the
+//! configured maximum staleness).
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// The caching directives of a response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct CachePolicy {
+    /// The response must not be stored
+    pub no_store: bool,
+    /// Seconds the response is fresh
+    pub max_age: u64,
+    /// Seconds after `max_age` the response may be served while it's
+    /// revalidated
+    pub stale_while_revalidate: u64,
+    /// Seconds after `max_age` the response may be served if the request
+    /// fails (default: the maximum staleness of the cache)
+    pub stale_if_error: Option<u64>,
+}
+
+impl CachePolicy {
+    /// Parse the `Cache-Control` header of a response.
+    pub fn parse(cache_control: Option<&str>) -> Self {
+        let mut policy = CachePolicy::default();
+        for directive in cache_control.unwrap_or_default().split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().trim_matches('"').parse().ok()),
+                None => (directive.trim(), None),
+            };
+            match (&*name.to_ascii_lowercase(), value) {
+                ("no-store", _) => policy.no_store = true,
+                ("no-cache", _) => policy.max_age = 0,
+                ("max-age", Some(seconds)) => policy.max_age = seconds,
+                ("stale-while-revalidate", Some(seconds)) => {
+                    policy.stale_while_revalidate = seconds
+                }
+                ("stale-if-error", Some(seconds)) => policy.stale_if_error = Some(seconds),
+                _ => {}
+            }
+        }
+        policy
+    }
+}
+
+/// A response stored in the cache.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedResponse {
+    pub body: Bytes,
+    /// Seconds since the epoch when the response was stored (or revalidated)
+    pub stored_at: u64,
+    pub policy: CachePolicy,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CachedResponse {
+    fn age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.stored_at)
+    }
+
+    /// Whether the response may be served without a request.
+    pub fn is_fresh(&self, now: u64) -> bool {
+        self.age(now) < self.policy.max_age
+    }
+
+    /// Whether the response may be served while it's revalidated.
+    pub fn may_revalidate_later(&self, now: u64) -> bool {
+        self.age(now) < self.policy.max_age + self.policy.stale_while_revalidate
+    }
+
+    /// Whether the response may be served if the request fails.
+    pub fn may_serve_on_error(&self, now: u64, max_stale: Duration) -> bool {
+        let stale_if_error = self.policy.stale_if_error.unwrap_or(max_stale.as_secs());
+        self.age(now) <= self.policy.max_age + stale_if_error
+    }
+}
+
+/// Return the current time in seconds since the epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// An on-disk HTTP response cache.
+#[derive(Debug)]
+pub struct HttpCache {
+    dir: PathBuf,
+    /// How long responses without `stale-if-error` are served when requests
+    /// fail
+    pub(crate) max_stale: Duration,
+}
+
+impl HttpCache {
+    /// Use (and create) the cache directory `dir`. If requests fail, cached
+    /// responses up to `max_stale` past their freshness are served.
+    pub fn new(dir: impl AsRef<Path>, max_stale: Duration) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).context(format!(
+            "Could not create HTTP cache directory {}",
+            dir.display()
+        ))?;
+        Ok(Self { dir, max_stale })
+    }
+
+    /// Return the paths of the body and metadata files of the URL.
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = hex::encode(Sha256::digest(url.as_bytes()));
+        (
+            self.dir.join(format!("{}.body", key)),
+            self.dir.join(format!("{}.json", key)),
+        )
+    }
+
+    /// Return the cached response of the URL, if any.
+    pub(crate) fn load(&self, url: &str) -> Option<CachedResponse> {
+        let (body_path, meta_path) = self.paths(url);
+        let meta: serde_json::Value = serde_json::from_slice(&fs::read(meta_path).ok()?).ok()?;
+        if meta["url"].as_str() != Some(url) {
+            return None;
+        }
+        let string = |name: &str| meta[name].as_str().map(str::to_string);
+        Some(CachedResponse {
+            body: Bytes::from(fs::read(body_path).ok()?),
+            stored_at: meta["stored_at"].as_u64()?,
+            policy: CachePolicy {
+                no_store: false,
+                max_age: meta["max_age"].as_u64().unwrap_or_default(),
+                stale_while_revalidate: meta["stale_while_revalidate"].as_u64().unwrap_or_default(),
+                stale_if_error: meta["stale_if_error"].as_u64(),
+            },
+            etag: string("etag"),
+            last_modified: string("last_modified"),
+        })
+    }
+
+    /// Store the response of the URL. Errors are only logged, the cache is
+    /// an optimization.
+    pub(crate) fn store(&self, url: &str, response: &CachedResponse) {
+        let (body_path, meta_path) = self.paths(url);
+        let meta = json!({
+            "url": url,
+            "stored_at": response.stored_at,
+            "max_age": response.policy.max_age,
+            "stale_while_revalidate": response.policy.stale_while_revalidate,
+            "stale_if_error": response.policy.stale_if_error,
+            "etag": response.etag,
+            "last_modified": response.last_modified,
+        });
+        // The metadata is written last, so that it never refers to a
+        // partially written body
+        let result = fs::write(&body_path, &response.body)
+            .and_then(|()| fs::write(&meta_path, meta.to_string()));
+        if let Err(e) = result {
+            tracing::warn!("Could not store {} in the HTTP cache: {}", url, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cache_control() {
+        assert_eq!(CachePolicy::parse(None), CachePolicy::default());
+        assert_eq!(
+            CachePolicy::parse(Some(
+                "public, max-age=600, stale-while-revalidate=60, stale-if-error=86400"
+            )),
+            CachePolicy {
+                no_store: false,
+                max_age: 600,
+                stale_while_revalidate: 60,
+                stale_if_error: Some(86400),
+            }
+        );
+        assert!(CachePolicy::parse(Some("no-store")).no_store);
+        assert_eq!(CachePolicy::parse(Some("max-age=60, no-cache")).max_age, 0);
+    }
+
+    #[test]
+    fn stale_windows() {
+        let response = CachedResponse {
+            body: Bytes::from_static(b"<html></html>"),
+            stored_at: 1000,
+            policy: CachePolicy::parse(Some("max-age=60, stale-while-revalidate=30")),
+            etag: None,
+            last_modified: None,
+        };
+        let max_stale = Duration::from_secs(3600);
+        assert!(response.is_fresh(1059));
+        assert!(!response.is_fresh(1060));
+        assert!(response.may_revalidate_later(1089));
+        assert!(!response.may_revalidate_later(1090));
+        assert!(response.may_serve_on_error(1000 + 60 + 3600, max_stale));
+        assert!(!response.may_serve_on_error(1000 + 60 + 3601, max_stale));
+    }
+
+    #[test]
+    fn store_and_load() {
+        let dir = std::env::temp_dir().join(format!("xc-http-cache-{}", std::process::id()));
+        let cache = HttpCache::new(&dir, Duration::from_secs(60)).unwrap();
+        let url =
+            "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45";
+        assert!(cache.load(url).is_none());
+        let response = CachedResponse {
+            body: Bytes::from_static(b"<html></html>"),
+            stored_at: 1000,
+            policy: CachePolicy::parse(Some("max-age=60, stale-if-error=600")),
+            etag: Some("\"abc\"".into()),
+            last_modified: None,
+        };
+        cache.store(url, &response);
+        let loaded = cache.load(url).unwrap();
+        assert_eq!(loaded.body, response.body);
+        assert_eq!(loaded.stored_at, 1000);
+        assert_eq!(loaded.policy, response.policy);
+        assert_eq!(loaded.etag, response.etag);
+        assert!(cache.load("https://www.xcontest.org/").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1405 @@
+//! Client for XContest: Fetches the flights of an RSS feed and the details
+//! (preview image) of a flight.
+//!
+//! The feed items and detail pages are parsed by versioned parsers, payloads
+//! that no parser understands are returned as [`ParseFailure`]s.
+//!
+//! Errors are returned as [`Error`], so that callers can distinguish network
+//! errors, missing pages, throttling and parse errors.
+//!
+//! Detail pages and preview images can be cached on disk (see [`HttpCache`]).
+
+use std::{
+    collections::VecDeque,
+    io::Cursor,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use image::{
+    codecs::{gif::GifDecoder, jpeg::JpegEncoder, webp::WebPDecoder},
+    error::{ImageFormatHint, UnsupportedError},
+    imageops::FilterType,
+    AnimationDecoder, DynamicImage, ImageError, ImageFormat, ImageReader,
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::{header::HeaderMap, Client, RequestBuilder, Response, StatusCode};
+
+mod cache;
+mod parsers;
+
+use cache::{CachePolicy, CachedResponse};
+use parsers::DetailParser;
+
+pub use cache::HttpCache;
+pub use parsers::{self_test as parser_self_test, NoMatchingParser};
+
+/// The RSS feed of the CCC (XContest Switzerland).
+pub const DEFAULT_FEED_URL: &str = "https://www.xcontest.org/rss/flights/?ccc";
+
+lazy_static! {
+    /// The built-in patterns of flight URLs, most specific first.
+    static ref DEFAULT_URL_PATTERNS: Vec<Regex> = vec![
+        // The contest as subdomain or path, an optional season, the language
+        // and the localized flights path, e.g.
+        // `https://www.xcontest.org/2020/switzerland/en/flights/detail:...`,
+        // `https://www.xcontest.org/czech/cs/lety/detail:...` or
+        // `https://poland.xcontest.org/pl/loty/detail:...`
+        Regex::new(
+            r"(?x)
+            ^https?://(?:www\.|(?P<subdomain>[a-z-]+)\.)?xcontest\.org
+            (?:/[0-9]{4})?
+            (?:/(?P<contest>[a-z][a-z-]+[a-z]))?
+            (?:/[a-z]{2})?
+            /[^/]+
+            /detail:(?P<pilot>[^/]*)
+            /(?P<date>[^/]*)
+            /(?P<time>[0-2][0-9]:[0-6][0-9])
+        "
+        )
+        .unwrap(),
+        // Any other flight detail URL on XContest
+        Regex::new(
+            r"(?x)
+            http.*xcontest\.org.*
+            /detail:(?P<pilot>[^/]*)
+            /(?P<date>[^/]*)
+            /(?P<time>[0-2][0-9]:[0-6][0-9])
+        "
+        )
+        .unwrap(),
+    ];
+}
+
+/// The pattern of flight URLs set with [`set_url_pattern`].
+static URL_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+/// Compile a configured pattern, which must contain the capture group
+/// `group`.
+fn compile_pattern(pattern: &str, group: &str) -> anyhow::Result<Regex> {
+    let regex = Regex::new(pattern).context(format!("Invalid pattern: {}", pattern))?;
+    if !regex.capture_names().any(|name| name == Some(group)) {
+        anyhow::bail!(
+            "The pattern {} has no capture group named {}",
+            pattern,
+            group
+        );
+    }
+    Ok(regex)
+}
+
+/// Replace the built-in patterns of flight URLs, so that parsing can be fixed
+/// without a release if XContest changes its URL format. The pilot username
+/// is taken from the capture group `pilot`, the contest from the optional
+/// capture group `contest`.
+///
+/// The pattern applies to all flights parsed by this process and can only be
+/// set once.
+pub fn set_url_pattern(pattern: &str) -> anyhow::Result<()> {
+    let regex = compile_pattern(pattern, "pilot")?;
+    if let Err(regex) = URL_PATTERN.set(regex) {
+        if URL_PATTERN.get().map(Regex::as_str) != Some(regex.as_str()) {
+            anyhow::bail!("A different flight URL pattern was already set");
+        }
+    }
+    Ok(())
+}
+
+/// Sends the requests to XContest. Cloned into background revalidations, so
+/// that they respect the delay between requests as well.
+#[derive(Clone)]
+struct Requester {
+    client: Client,
+    /// Additional headers sent with every request to XContest
+    headers: HeaderMap,
+    /// Minimum delay between two requests to XContest
+    min_request_delay: Duration,
+    /// Time of the last request to XContest
+    last_request: Arc<tokio::sync::Mutex<Option<Instant>>>,
+}
+
+impl Requester {
+    /// Send a request, respecting the minimum delay between requests.
+    async fn send_politely(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let mut last_request = self.last_request.lock().await;
+        if let Some(last) = *last_request {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_request_delay {
+                tokio::time::sleep(self.min_request_delay - elapsed).await;
+            }
+        }
+        *last_request = Some(Instant::now());
+        drop(last_request);
+        request.headers(self.headers.clone()).send().await
+    }
+
+    /// Fetch `url` (revalidating the `cached` response, if any) and store the
+    /// response in the cache.
+    async fn fetch(
+        &self,
+        url: &str,
+        cache: Option<&HttpCache>,
+        cached: Option<&CachedResponse>,
+    ) -> Result<Bytes> {
+        use reqwest::header::{
+            CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        };
+
+        let mut request = self.client.get(url);
+        if let Some(cached) = cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = self.send_politely(request).await?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let policy = CachePolicy::parse(header(CACHE_CONTROL).as_deref());
+        if let (StatusCode::NOT_MODIFIED, Some(cache), Some(cached)) =
+            (response.status(), cache, cached)
+        {
+            let revalidated = CachedResponse {
+                stored_at: cache::now(),
+                policy,
+                ..cached.clone()
+            };
+            cache.store(url, &revalidated);
+            return Ok(revalidated.body);
+        }
+        check_response(&response, url)?;
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let body = response.bytes().await?;
+        if let Some(cache) = cache.filter(|_| !policy.no_store) {
+            cache.store(
+                url,
+                &CachedResponse {
+                    body: body.clone(),
+                    stored_at: cache::now(),
+                    policy,
+                    etag,
+                    last_modified,
+                },
+            );
+        }
+        Ok(body)
+    }
+}
+
+pub struct XContest {
+    requester: Requester,
+    /// Whether animated previews should be passed through as-is
+    animated_previews: bool,
+    /// Maximum number of detail page fetches per hour
+    detail_budget_per_hour: Option<u32>,
+    /// Cache of detail pages and preview images, if enabled
+    http_cache: Option<Arc<HttpCache>>,
+    /// Times of the detail page fetches within the last hour
+    detail_fetches: Mutex<VecDeque<Instant>>,
+    /// Configured pattern of the preview image URL, tried before the built-in
+    /// detail page parsers
+    thumbnail_pattern: Option<parsers::ConfiguredThumbnail>,
+}
+
+/// Errors of the XContest client.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// XContest could not be reached or answered with an error status
+    #[error("Request to XContest failed: {0}")]
+    Network(#[from] reqwest::Error),
+    /// The requested page does not exist (anymore), e.g. a deleted flight
+    #[error("Not found on XContest: {0}")]
+    NotFound(String),
+    /// XContest is throttling or blocking our requests
+    #[error(transparent)]
+    RateLimited(#[from] Throttled),
+    /// The hourly detail page fetch budget is exhausted
+    #[error(transparent)]
+    BudgetExhausted(#[from] DetailBudgetExhausted),
+    /// The feed is not valid RSS
+    #[error("Could not read XContest feed: {0}")]
+    InvalidFeed(#[from] rss::Error),
+    /// The feed does not match any known parser
+    #[error(transparent)]
+    NoMatchingParser(#[from] NoMatchingParser),
+    /// A detail page could not be parsed
+    #[error(transparent)]
+    Parse(#[from] ParseFailure),
+    /// The preview image could not be decoded or encoded
+    #[error("Could not process preview image: {0}")]
+    Image(#[from] ImageError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The hourly detail page fetch budget is exhausted.
+#[derive(Debug)]
+pub struct DetailBudgetExhausted;
+
+impl std::fmt::Display for DetailBudgetExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Hourly detail page fetch budget exhausted")
+    }
+}
+
+impl std::error::Error for DetailBudgetExhausted {}
+
+/// XContest is throttling or blocking our requests (HTTP 429 or 403).
+#[derive(Debug)]
+pub struct Throttled {
+    /// The HTTP status code returned by XContest
+    pub status: StatusCode,
+    /// The delay requested through the `Retry-After` header, if any
+    pub retry_after: Option<Duration>,
+}
+
+impl Throttled {
+    /// Return an error if the response indicates throttling or blocking.
+    fn check(response: &Response) -> std::result::Result<(), Throttled> {
+        match response.status() {
+            status @ (StatusCode::TOO_MANY_REQUESTS | StatusCode::FORBIDDEN) => Err(Throttled {
+                status,
+                retry_after: response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "XContest is throttling requests (HTTP {})", self.status)?;
+        if let Some(retry_after) = self.retry_after {
+            write!(f, ", retry after {:?}", retry_after)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+impl Error {
+    /// Whether the error may go away by itself (e.g. XContest is down), so
+    /// that cached data may be served instead.
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Network(_) | Error::RateLimited(_) | Error::BudgetExhausted(_)
+        )
+    }
+}
+
+/// Return an error if the request for `url` failed: XContest is throttling
+/// us, the page does not exist or the status indicates another error.
+fn check_response(response: &Response, url: &str) -> Result<()> {
+    Throttled::check(response)?;
+    if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+        return Err(Error::NotFound(url.to_string()));
+    }
+    response.error_for_status_ref()?;
+    Ok(())
+}
+
+/// The kind of a payload fetched from XContest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    /// A single RSS feed item (serialized as JSON)
+    FeedItem,
+    /// A flight detail page (HTML)
+    DetailPage,
+    /// A row of the flight list page (HTML)
+    ListRow,
+}
+
+impl PayloadKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadKind::FeedItem => "feed_item",
+            PayloadKind::DetailPage => "detail_page",
+            PayloadKind::ListRow => "list_row",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "feed_item" => Some(PayloadKind::FeedItem),
+            "detail_page" => Some(PayloadKind::DetailPage),
+            "list_row" => Some(PayloadKind::ListRow),
+            _ => None,
+        }
+    }
+}
+
+/// A payload that could not be parsed.
+#[derive(Debug, Clone)]
+pub struct ParseFailure {
+    /// The kind of payload
+    pub kind: PayloadKind,
+    /// Where the payload came from (usually a URL)
+    pub source: String,
+    /// The raw payload
+    pub payload: String,
+    /// The parse error
+    pub error: String,
+}
+
+impl std::fmt::Display for ParseFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Could not parse {} {}: {}",
+            self.kind.as_str(),
+            self.source,
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for ParseFailure {}
+
+/// The result of parsing the RSS feed (or the flight list).
+#[derive(Debug, Default)]
+pub struct FeedItems {
+    /// Successfully parsed flights
+    pub flights: Vec<Flight>,
+    /// Items that could not be parsed
+    pub failures: Vec<ParseFailure>,
+}
+
+/// Parse a previously failed payload again (e.g. after a parser fix).
+///
+/// Return a short description of the parse result.
+pub fn reparse(kind: PayloadKind, payload: &str) -> anyhow::Result<String> {
+    match kind {
+        PayloadKind::FeedItem => {
+            let item: rss::Item =
+                serde_json::from_str(payload).context("Could not deserialize feed item")?;
+            let mut last_error = None;
+            for parser in parsers::feed_parsers() {
+                match parser.parse_item(&item) {
+                    Ok(flight) => {
+                        return Ok(format!(
+                            "Flight {} by {} (parser {})",
+                            flight.url,
+                            flight.pilot_username,
+                            parser.version()
+                        ))
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            Err(last_error.unwrap_or_else(|| NoMatchingParser { kind: "feed item" }.into()))
+        }
+        PayloadKind::ListRow => {
+            // The base URL of the list is not stored, relative links are
+            // resolved against the main site.
+            let base = reqwest::Url::parse("https://www.xcontest.org/").unwrap();
+            let mut last_error = None;
+            for parser in parsers::list_parsers() {
+                match parser.parse_row(payload, &base) {
+                    Ok(flight) => {
+                        return Ok(format!(
+                            "Flight {} by {} (parser {})",
+                            flight.url,
+                            flight.pilot_username,
+                            parser.version()
+                        ))
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            Err(last_error.unwrap_or_else(|| NoMatchingParser { kind: "list row" }.into()))
+        }
+        PayloadKind::DetailPage => parsers::detail_parsers()
+            .iter()
+            .find_map(|parser| {
+                parser
+                    .thumbnail_url(payload)
+                    .map(|url| format!("Preview image {} (parser {})", url, parser.version()))
+            })
+            .ok_or_else(|| {
+                NoMatchingParser {
+                    kind: "detail page",
+                }
+                .into()
+            }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flight {
+    /// Flight title
+    pub title: String,
+    /// Flight URL
+    pub url: String,
+    /// Username of the pilot
+    pub pilot_username: String,
+    /// The structured information contained in the title, if it could be parsed
+    pub parsed_title: Option<ParsedTitle>,
+    /// The GUID of the RSS item, if present
+    pub guid: Option<String>,
+    /// The contest (e.g. `switzerland` or `world`), if contained in the URL
+    pub contest: Option<String>,
+    /// Start time and airtime, if known from the detail page
+    pub times: FlightTimes,
+    /// Closing and speed of a triangle, if known from the detail page
+    pub triangle: TriangleStats,
+    /// When the flight was published in the feed, if known
+    pub published: Option<DateTime<Utc>>,
+}
+
+/// Start time and airtime of a flight, parsed from the detail page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlightTimes {
+    /// Local start time
+    pub start: Option<NaiveTime>,
+    /// Time in the air, in minutes
+    pub airtime_minutes: Option<u32>,
+}
+
+impl FlightTimes {
+    /// Return whether neither the start time nor the airtime is known.
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.airtime_minutes.is_none()
+    }
+}
+
+/// How well a triangle was closed and how fast it was flown, parsed from the
+/// detail page.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TriangleStats {
+    /// Closing of the triangle, in percent (100% means the start and end
+    /// points coincide)
+    pub closing_percent: Option<f64>,
+    /// Average speed on the triangle, in km/h
+    pub speed_kmh: Option<f64>,
+}
+
+impl TriangleStats {
+    /// Return whether neither the closing nor the speed is known.
+    pub fn is_empty(&self) -> bool {
+        self.closing_percent.is_none() && self.speed_kmh.is_none()
+    }
+}
+
+/// The structured information contained in an RSS item title, e.g.
+/// `09.08.20 [21.98 km :: free_flight] Firstname Lastname`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedTitle {
+    /// Date of the flight
+    pub date: Option<NaiveDate>,
+    /// Scored distance in kilometers
+    pub distance_km: Option<f64>,
+    /// Type of the flight
+    pub flight_type: Option<FlightType>,
+    /// Display name of the pilot
+    pub pilot_name: String,
+}
+
+/// The type of a flight, as scored by XContest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlightType {
+    FreeFlight,
+    FlatTriangle,
+    FaiTriangle,
+    Other(String),
+}
+
+impl FlightType {
+    fn parse(value: &str) -> Option<Self> {
+        let normalized = value.trim().to_ascii_lowercase().replace([' ', '-'], "_");
+        match &*normalized {
+            "" => None,
+            "free_flight" | "free" => Some(FlightType::FreeFlight),
+            "flat_triangle" => Some(FlightType::FlatTriangle),
+            "fai_triangle" => Some(FlightType::FaiTriangle),
+            _ => Some(FlightType::Other(value.trim().to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for FlightType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlightType::FreeFlight => write!(f, "free flight"),
+            FlightType::FlatTriangle => write!(f, "flat triangle"),
+            FlightType::FaiTriangle => write!(f, "FAI triangle"),
+            FlightType::Other(other) => write!(f, "{}", other),
+        }
+    }
+}
+
+impl ParsedTitle {
+    /// Parse an RSS item title.
+    ///
+    /// The parser is lenient regarding date formats (`09.08.20`, `9.8.2020`,
+    /// `09/08/2020`, `2020-08-09`), decimal and thousands separators
+    /// (`1'234.5`, `21,98`) and distance units (`km`, `mi`).
+    pub fn parse(title: &str) -> Option<Self> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(
+                r"(?xi)
+                ^\s*(?P<date>[^\[\s]*)
+                \s*\[\s*
+                (?P<distance>[0-9][0-9.,'’\x20]*?)?
+                \s*(?P<unit>km|miles|mi)?
+                \s*(?:::\s*(?P<type>[^\]]*?))?
+                \s*\]
+                \s*(?P<pilot>.*?)\s*$
+            "
+            )
+            .unwrap();
+        }
+        let caps = RE.captures(title)?;
+        let factor = match caps.name("unit").map(|m| m.as_str().to_ascii_lowercase()) {
+            Some(unit) if unit.starts_with("mi") => 1.609_344,
+            _ => 1.0,
+        };
+        Some(Self {
+            date: caps.name("date").and_then(|m| parse_date(m.as_str())),
+            distance_km: caps
+                .name("distance")
+                .and_then(|m| parse_number(m.as_str()))
+                .map(|distance| distance * factor),
+            flight_type: caps
+                .name("type")
+                .and_then(|m| FlightType::parse(m.as_str())),
+            pilot_name: caps.name("pilot").map_or("", |m| m.as_str()).to_string(),
+        })
+    }
+}
+
+/// Parse a date in day-month-year order (with two or four digit year), or in
+/// ISO 8601 format.
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    let parts: Vec<&str> = value.split(['.', '/', '-']).collect();
+    let numbers: Vec<u32> = parts
+        .iter()
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (year, month, day) = match (&parts[..], &numbers[..]) {
+        ([y, _, _], &[year, month, day]) if y.len() == 4 => (year, month, day),
+        ([_, _, y], &[day, month, year]) if y.len() == 2 => (2000 + year, month, day),
+        ([_, _, y], &[day, month, year]) if y.len() == 4 => (year, month, day),
+        _ => return None,
+    };
+    // The year has at most four digits, so it always fits into an i32
+    NaiveDate::from_ymd_opt(year as i32, month, day)
+}
+
+/// Parse a decimal number, tolerating different decimal and thousands
+/// separators.
+fn parse_number(value: &str) -> Option<f64> {
+    let cleaned: String = value
+        .chars()
+        .filter(|c| !matches!(c, '\'' | '’' | ' '))
+        .collect();
+    let normalized = match (cleaned.rfind('.'), cleaned.rfind(',')) {
+        // Both separators present: The last one is the decimal separator
+        (Some(dot), Some(comma)) if comma > dot => cleaned.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => cleaned.replace(',', ""),
+        // Only a comma: Decimal separator
+        (None, Some(_)) => cleaned.replace(',', "."),
+        _ => cleaned,
+    };
+    normalized
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+#[derive(Debug, Clone)]
+pub struct FlightDetails {
+    /// Flight thumbnail (PNG data, or GIF/WebP data if animated)
+    pub thumbnail_large: Bytes,
+    /// Flight thumbnail (max 512x512px, JPEG data)
+    pub thumbnail_small: Bytes,
+    /// Format of the large thumbnail
+    pub format: PreviewFormat,
+    /// Whether the large thumbnail is animated
+    pub animated: bool,
+    /// Start time and airtime from the detail page
+    pub times: FlightTimes,
+    /// Closing and speed of a triangle from the detail page
+    pub triangle: TriangleStats,
+}
+
+/// Image format of a flight preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewFormat {
+    Png,
+    Gif,
+    WebP,
+}
+
+impl PreviewFormat {
+    /// Return the MIME type of this format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            PreviewFormat::Png => "image/png",
+            PreviewFormat::Gif => "image/gif",
+            PreviewFormat::WebP => "image/webp",
+        }
+    }
+
+    /// Return the file extension of this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PreviewFormat::Png => "png",
+            PreviewFormat::Gif => "gif",
+            PreviewFormat::WebP => "webp",
+        }
+    }
+
+    /// Parse a format from its file extension.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "png" => Some(PreviewFormat::Png),
+            "gif" => Some(PreviewFormat::Gif),
+            "webp" => Some(PreviewFormat::WebP),
+            _ => None,
+        }
+    }
+}
+
+impl Flight {
+    /// Create a flight, parsing the pilot username and the contest from the
+    /// URL with the configured (or built-in) URL patterns.
+    pub fn new(title: String, url: String) -> anyhow::Result<Self> {
+        match URL_PATTERN.get() {
+            Some(pattern) => Self::with_url_patterns(title, url, std::slice::from_ref(pattern)),
+            None => Self::with_url_patterns(title, url, &DEFAULT_URL_PATTERNS),
+        }
+    }
+
+    /// Create a flight, parsing the URL with the first matching pattern.
+    fn with_url_patterns(title: String, url: String, patterns: &[Regex]) -> anyhow::Result<Self> {
+        let caps = patterns
+            .iter()
+            .find_map(|pattern| pattern.captures(&url))
+            .context(format!("Regex did not match XContest URL ({})", &url))?;
+        let pilot_username = caps.name("pilot").unwrap().as_str().to_string();
+        let contest = caps
+            .name("subdomain")
+            .or_else(|| caps.name("contest"))
+            .map(|contest| contest.as_str().to_string());
+        let parsed_title = ParsedTitle::parse(&title);
+        Ok(Self {
+            title,
+            url,
+            pilot_username,
+            parsed_title,
+            guid: None,
+            contest,
+            times: FlightTimes::default(),
+            triangle: TriangleStats::default(),
+            published: None,
+        })
+    }
+
+    /// Set the GUID of the RSS item this flight was parsed from.
+    pub fn with_guid(mut self, guid: Option<String>) -> Self {
+        self.guid = guid;
+        self
+    }
+
+    /// Set the publication time of the RSS item this flight was parsed from.
+    pub fn with_published(mut self, published: Option<DateTime<Utc>>) -> Self {
+        self.published = published;
+        self
+    }
+
+    /// Set the start time and airtime parsed from the detail page.
+    pub fn with_times(mut self, times: FlightTimes) -> Self {
+        self.times = times;
+        self
+    }
+
+    /// Set the information parsed from the detail page, unless already known.
+    pub fn with_details(mut self, details: &FlightDetails) -> Self {
+        if self.times.is_empty() {
+            self.times = details.times;
+        }
+        if self.triangle.is_empty() {
+            self.triangle = details.triangle;
+        }
+        self
+    }
+
+    /// Return the key used to detect duplicate flights: The GUID if present,
+    /// the URL otherwise.
+    pub fn dedup_key(&self) -> &str {
+        self.guid.as_deref().unwrap_or(&self.url)
+    }
+}
+
+impl XContest {
+    pub fn new(client: Client) -> Self {
+        Self {
+            requester: Requester {
+                client,
+                headers: HeaderMap::new(),
+                min_request_delay: Duration::ZERO,
+                last_request: Arc::new(tokio::sync::Mutex::new(None)),
+            },
+            animated_previews: false,
+            detail_budget_per_hour: None,
+            http_cache: None,
+            detail_fetches: Mutex::new(VecDeque::new()),
+            thumbnail_pattern: None,
+        }
+    }
+
+    /// Send these headers with every request to XContest, overriding the
+    /// defaults of the HTTP client (e.g. the `User-Agent`).
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.requester.headers = headers;
+        self
+    }
+
+    /// Wait at least `delay` between two consecutive requests to XContest.
+    pub fn with_min_request_delay(mut self, delay: Duration) -> Self {
+        self.requester.min_request_delay = delay;
+        self
+    }
+
+    /// Limit the number of detail page fetches per hour. Once the budget is
+    /// exhausted, [`fetch_details`](Self::fetch_details) fails
+    /// with [`Error::BudgetExhausted`] (unless a cached detail page can be
+    /// served).
+    pub fn with_detail_budget(mut self, fetches_per_hour: Option<u32>) -> Self {
+        self.detail_budget_per_hour = fetches_per_hour;
+        self
+    }
+
+    /// Extract the preview image URL of detail pages with this pattern (from
+    /// the capture group `url`) before trying the built-in parsers, so that
+    /// parsing can be fixed without a release if XContest changes its pages.
+    pub fn with_thumbnail_pattern(mut self, pattern: &str) -> anyhow::Result<Self> {
+        self.thumbnail_pattern = Some(parsers::ConfiguredThumbnail(compile_pattern(
+            pattern, "url",
+        )?));
+        Ok(self)
+    }
+
+    /// Cache detail pages and preview images in `cache`. The RSS feed and the
+    /// flight list are never cached, since they must be current.
+    pub fn with_http_cache(mut self, cache: HttpCache) -> Self {
+        self.http_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Send a request, respecting the minimum delay between requests.
+    async fn send_politely(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        self.requester.send_politely(request).await
+    }
+
+    /// Fetch `url` through the HTTP cache (if enabled): Fresh responses are
+    /// served from the cache, stale ones within the `stale-while-revalidate`
+    /// window as well while they are revalidated in the background. If the
+    /// request fails for a transient reason, a stale response is served.
+    ///
+    /// Detail page requests (`detail_page`) consume the hourly budget.
+    async fn get_cached(&self, url: &str, detail_page: bool) -> Result<Bytes> {
+        let cache = self.http_cache.as_deref();
+        let cached = cache.and_then(|cache| cache.load(url));
+        let now = cache::now();
+        if let Some(cached) = &cached {
+            if cached.is_fresh(now) {
+                tracing::debug!("Serving {} from the HTTP cache", url);
+                return Ok(cached.body.clone());
+            }
+            if cached.may_revalidate_later(now) {
+                tracing::debug!("Serving stale {} from the HTTP cache, revalidating", url);
+                let requester = self.requester.clone();
+                let cache = self.http_cache.clone();
+                let url = url.to_string();
+                let stale = cached.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = requester.fetch(&url, cache.as_deref(), Some(&stale)).await {
+                        tracing::warn!("Could not revalidate {}: {}", url, e);
+                    }
+                });
+                return Ok(cached.body.clone());
+            }
+        }
+        let result = match detail_page.then(|| self.take_detail_budget()) {
+            Some(Err(e)) => Err(e.into()),
+            _ => self.requester.fetch(url, cache, cached.as_ref()).await,
+        };
+        match (result, cache, cached) {
+            (Err(e), Some(cache), Some(cached))
+                if e.is_transient() && cached.may_serve_on_error(now, cache.max_stale) =>
+            {
+                tracing::warn!("Serving stale {} from the HTTP cache: {}", url, e);
+                Ok(cached.body)
+            }
+            (result, _, _) => result,
+        }
+    }
+
+    /// Consume one detail page fetch from the hourly budget.
+    fn take_detail_budget(&self) -> std::result::Result<(), DetailBudgetExhausted> {
+        let budget = match self.detail_budget_per_hour {
+            Some(budget) => budget as usize,
+            None => return Ok(()),
+        };
+        let mut fetches = self.detail_fetches.lock().unwrap();
+        let now = Instant::now();
+        while let Some(oldest) = fetches.front() {
+            if now.duration_since(*oldest) >= Duration::from_secs(3600) {
+                fetches.pop_front();
+            } else {
+                break;
+            }
+        }
+        if fetches.len() >= budget {
+            return Err(DetailBudgetExhausted);
+        }
+        fetches.push_back(now);
+        Ok(())
+    }
+
+    /// Pass through animated (GIF/WebP) previews instead of reducing them to
+    /// a static PNG of the first frame. This is quite bandwidth-heavy.
+    pub fn with_animated_previews(mut self, enabled: bool) -> Self {
+        self.animated_previews = enabled;
+        self
+    }
+
+    /// Fetch the latest RSS feed and parse it into a `Channel`.
+    async fn fetch_feed(&self, feed_url: &str) -> Result<rss::Channel> {
+        let feed_resp = self
+            .send_politely(self.requester.client.get(feed_url))
+            .await?;
+        check_response(&feed_resp, feed_url)?;
+        let feed_bytes = feed_resp.bytes().await?;
+        let channel = rss::Channel::read_from(&feed_bytes[..])?;
+        Ok(channel)
+    }
+
+    /// Fetch and parse the flights in the RSS feed at `feed_url`.
+    ///
+    /// Feed items that cannot be parsed are returned as failures.
+    pub async fn fetch_flights(&self, feed_url: &str) -> Result<FeedItems> {
+        let channel = self.fetch_feed(feed_url).await?;
+        if channel.items().is_empty() {
+            return Ok(FeedItems::default());
+        }
+
+        // Use the first parser that can parse at least one item
+        for parser in parsers::feed_parsers() {
+            let results: Vec<anyhow::Result<Flight>> = channel
+                .items()
+                .iter()
+                .map(|item| parser.parse_item(item))
+                .collect();
+            if results.iter().all(anyhow::Result::is_err) {
+                tracing::debug!("Feed does not match parser {}", parser.version());
+                continue;
+            }
+            tracing::debug!("Parsing feed with parser {}", parser.version());
+            let mut feed_items = FeedItems::default();
+            for (result, item) in results.into_iter().zip(channel.items()) {
+                match result {
+                    Ok(flight) => feed_items.flights.push(flight),
+                    Err(e) => {
+                        tracing::warn!("Could not parse flight URL: {}", e);
+                        feed_items.failures.push(ParseFailure {
+                            kind: PayloadKind::FeedItem,
+                            source: item
+                                .link
+                                .clone()
+                                .or_else(|| item.title.clone())
+                                .unwrap_or_default(),
+                            // Cannot fail, the item was deserialized before
+                            payload: serde_json::to_string(item).unwrap_or_default(),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+            return Ok(feed_items);
+        }
+        Err(NoMatchingParser { kind: "feed" }.into())
+    }
+
+    /// Fetch and parse the flights on the flight list page at `list_url` (the
+    /// flight search of a contest), as a fallback for the RSS feed.
+    ///
+    /// Rows that cannot be parsed are returned as failures.
+    pub async fn fetch_flight_list(&self, list_url: &str) -> Result<FeedItems> {
+        let list_resp = self
+            .send_politely(self.requester.client.get(list_url))
+            .await?;
+        check_response(&list_resp, list_url)?;
+        // Relative links are resolved against the URL after redirects
+        let base = list_resp.url().clone();
+        let html = list_resp.text().await?;
+
+        // Use the first parser that can parse at least one row
+        for parser in parsers::list_parsers() {
+            let rows = parser.rows(&html);
+            let results: Vec<anyhow::Result<Flight>> = rows
+                .iter()
+                .map(|row| parser.parse_row(row, &base))
+                .collect();
+            if results.iter().all(anyhow::Result::is_err) {
+                tracing::debug!("Flight list does not match parser {}", parser.version());
+                continue;
+            }
+            tracing::debug!("Parsing flight list with parser {}", parser.version());
+            let mut list_items = FeedItems::default();
+            for (result, row) in results.into_iter().zip(rows) {
+                match result {
+                    Ok(flight) => list_items.flights.push(flight),
+                    Err(e) => {
+                        tracing::warn!("Could not parse flight list row: {}", e);
+                        list_items.failures.push(ParseFailure {
+                            kind: PayloadKind::ListRow,
+                            source: list_url.to_string(),
+                            payload: row.to_string(),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+            return Ok(list_items);
+        }
+        Err(NoMatchingParser {
+            kind: "flight list",
+        }
+        .into())
+    }
+
+    /// Fetch additional details for this flight.
+    pub async fn fetch_details(&self, flight: &Flight) -> Result<FlightDetails> {
+        // Fetch flight details HTML
+        let html = self.get_cached(&flight.url, true).await?;
+        let html = String::from_utf8_lossy(&html).into_owned();
+
+        // Extract thumbnail URL
+        let thumbnail_url = match self
+            .thumbnail_pattern
+            .as_ref()
+            .and_then(|parser| parser.thumbnail_url(&html))
+            .or_else(|| {
+                parsers::detail_parsers()
+                    .iter()
+                    .find_map(|parser| parser.thumbnail_url(&html))
+            }) {
+            Some(url) => url,
+            None => {
+                return Err(ParseFailure {
+                    kind: PayloadKind::DetailPage,
+                    source: flight.url.clone(),
+                    error: NoMatchingParser {
+                        kind: "detail page",
+                    }
+                    .to_string(),
+                    payload: html,
+                }
+                .into())
+            }
+        };
+
+        // Fetch thumbnail
+        let thumbnail_bytes = self.get_cached(&thumbnail_url, false).await?;
+        let mut details = self.process_preview(thumbnail_bytes)?;
+
+        // Extract start time and airtime, if the page contains them
+        details.times = parsers::detail_parsers()
+            .iter()
+            .map(|parser| parser.times(&html))
+            .find(|times| !times.is_empty())
+            .unwrap_or_default();
+        details.triangle = parsers::detail_parsers()
+            .iter()
+            .map(|parser| parser.triangle(&html))
+            .find(|triangle| !triangle.is_empty())
+            .unwrap_or_default();
+        Ok(details)
+    }
+
+    /// Turn a downloaded preview image into flight details: The large
+    /// thumbnail (reduced to a static PNG, unless animated previews are
+    /// enabled) and a small JPEG thumbnail.
+    pub fn process_preview(&self, thumbnail_bytes: Bytes) -> Result<FlightDetails> {
+        // Decode thumbnail (first frame only, in case of an animation)
+        let (format, animated) = sniff_preview(&thumbnail_bytes)?;
+        let first_frame =
+            ImageReader::with_format(Cursor::new(&thumbnail_bytes), format.into()).decode()?;
+
+        // Unless enabled, reduce animated previews to a static PNG
+        let (thumbnail_large, format, animated) =
+            if format == PreviewFormat::Png || (animated && self.animated_previews) {
+                (thumbnail_bytes, format, animated)
+            } else {
+                (encode_png(&first_frame)?, PreviewFormat::Png, false)
+            };
+
+        // Convert thumbnail to JPEG max 512x512
+        let thumbnail_resized = first_frame.resize(512, 512, FilterType::CatmullRom);
+        let mut thumbnail_resized_bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let encoder = JpegEncoder::new_with_quality(&mut thumbnail_resized_bytes, 80);
+        thumbnail_resized.write_with_encoder(encoder)?;
+
+        Ok(FlightDetails {
+            thumbnail_large,
+            thumbnail_small: Bytes::from(thumbnail_resized_bytes.into_inner()),
+            format,
+            animated,
+            times: FlightTimes::default(),
+            triangle: TriangleStats::default(),
+        })
+    }
+}
+
+impl From<PreviewFormat> for ImageFormat {
+    fn from(format: PreviewFormat) -> Self {
+        match format {
+            PreviewFormat::Png => ImageFormat::Png,
+            PreviewFormat::Gif => ImageFormat::Gif,
+            PreviewFormat::WebP => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Sample preview image, used for testing the image pipeline.
+pub const SAMPLE_THUMBNAIL: &[u8] = include_bytes!("../samples/thumbnail.png");
+
+/// Determine the format of the preview image, and whether it is animated.
+fn sniff_preview(bytes: &[u8]) -> Result<(PreviewFormat, bool)> {
+    match image::guess_format(bytes)? {
+        ImageFormat::Png => Ok((PreviewFormat::Png, false)),
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(Cursor::new(bytes))?;
+            let animated = decoder.into_frames().take(2).count() > 1;
+            Ok((PreviewFormat::Gif, animated))
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            Ok((PreviewFormat::WebP, decoder.has_animation()))
+        }
+        other => Err(
+            ImageError::Unsupported(UnsupportedError::from(ImageFormatHint::Exact(other))).into(),
+        ),
+    }
+}
+
+/// Encode an image as PNG.
+fn encode_png(image: &DynamicImage) -> Result<Bytes> {
+    let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, ImageFormat::Png)?;
+    Ok(Bytes::from(bytes.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        let title = "09.08.20 [21.98 km :: free_flight] Firstname Lastname".to_string();
+        let url =
+            "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+                .to_string();
+        let flight = Flight::new(title.clone(), url.clone()).unwrap();
+        assert_eq!(flight.title, title);
+        assert_eq!(flight.url, url);
+        assert_eq!(flight.pilot_username, "dbrgn");
+        assert_eq!(
+            flight.parsed_title,
+            Some(ParsedTitle {
+                date: NaiveDate::from_ymd_opt(2020, 8, 9),
+                distance_km: Some(21.98),
+                flight_type: Some(FlightType::FreeFlight),
+                pilot_name: "Firstname Lastname".into(),
+            })
+        );
+    }
+
+    /// Return the pilot username and the contest of the flight URL.
+    fn parse_variant(url: &str) -> (String, Option<String>) {
+        let flight = Flight::new(String::new(), url.to_string()).unwrap();
+        (flight.pilot_username, flight.contest)
+    }
+
+    #[test]
+    fn parse_url_national_contest() {
+        assert_eq!(
+            parse_variant(
+                "https://www.xcontest.org/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+            ),
+            ("dbrgn".into(), Some("switzerland".into()))
+        );
+    }
+
+    #[test]
+    fn parse_url_season() {
+        assert_eq!(
+            parse_variant(
+                "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+            ),
+            ("dbrgn".into(), Some("switzerland".into()))
+        );
+    }
+
+    #[test]
+    fn parse_url_world() {
+        assert_eq!(
+            parse_variant(
+                "https://www.xcontest.org/world/en/flights/detail:chrigel/1.7.2021/09:12"
+            ),
+            ("chrigel".into(), Some("world".into()))
+        );
+    }
+
+    #[test]
+    fn parse_url_localized() {
+        assert_eq!(
+            parse_variant("https://www.xcontest.org/czech/cs/lety/detail:pavel/3.5.2023/11:02"),
+            ("pavel".into(), Some("czech".into()))
+        );
+        assert_eq!(
+            parse_variant(
+                "https://www.xcontest.org/2022/slovenija/sl/preleti/detail:ana/12.6.2022/08:30"
+            ),
+            ("ana".into(), Some("slovenija".into()))
+        );
+    }
+
+    #[test]
+    fn parse_url_subdomain() {
+        assert_eq!(
+            parse_variant("https://poland.xcontest.org/2023/pl/loty/detail:jan/20.4.2023/12:00"),
+            ("jan".into(), Some("poland".into()))
+        );
+    }
+
+    #[test]
+    fn parse_url_without_contest() {
+        assert_eq!(
+            parse_variant("https://www.xcontest.org/en/flights/detail:dbrgn/9.8.2020/10:45"),
+            ("dbrgn".into(), None)
+        );
+        assert_eq!(
+            parse_variant("http://xcontest.org/some/other/path/detail:dbrgn/9.8.2020/10:45"),
+            ("dbrgn".into(), None)
+        );
+    }
+
+    #[test]
+    fn parse_url_pattern() {
+        let url = "https://www.xcontest.org/world/en/flights/detail/dbrgn/9.8.2020/10:45";
+        assert!(Flight::new(String::new(), url.to_string()).is_err());
+
+        let pattern =
+            compile_pattern(r"xcontest\.org/.*/detail/(?P<pilot>[^/]*)/", "pilot").unwrap();
+        let flight = Flight::with_url_patterns(String::new(), url.to_string(), &[pattern]).unwrap();
+        assert_eq!(flight.pilot_username, "dbrgn");
+
+        assert!(compile_pattern(r"detail/([^/]*)/", "pilot").is_err());
+        assert!(compile_pattern(r"detail/(?P<pilot>[^/]*", "pilot").is_err());
+    }
+
+    #[test]
+    fn parse_title_variants() {
+        let cases = [
+            (
+                "9.8.2020 [21,98 km :: flat_triangle] Hans Müller",
+                (2020, 8, 9),
+                21.98,
+                FlightType::FlatTriangle,
+                "Hans Müller",
+            ),
+            (
+                "09/08/20 [1'234.5km :: FAI_triangle] Jane Doe",
+                (2020, 8, 9),
+                1234.5,
+                FlightType::FaiTriangle,
+                "Jane Doe",
+            ),
+            (
+                "2020-08-09 [10 mi :: free flight]   Joe  ",
+                (2020, 8, 9),
+                16.09344,
+                FlightType::FreeFlight,
+                "Joe",
+            ),
+            (
+                "09.08.20 [1.234,5 km :: hike_and_fly] Jane Doe",
+                (2020, 8, 9),
+                1234.5,
+                FlightType::Other("hike_and_fly".into()),
+                "Jane Doe",
+            ),
+        ];
+        for (title, (y, m, d), distance, flight_type, pilot) in cases {
+            let parsed = ParsedTitle::parse(title).unwrap();
+            assert_eq!(parsed.date, NaiveDate::from_ymd_opt(y, m, d), "{}", title);
+            assert!(
+                (parsed.distance_km.unwrap() - distance).abs() < 1e-6,
+                "{}",
+                title
+            );
+            assert_eq!(parsed.flight_type, Some(flight_type), "{}", title);
+            assert_eq!(parsed.pilot_name, pilot, "{}", title);
+        }
+    }
+
+    #[test]
+    fn parse_title_partial() {
+        let parsed = ParsedTitle::parse("32.13.20 [km] Jane").unwrap();
+        assert_eq!(parsed.date, None);
+        assert_eq!(parsed.distance_km, None);
+        assert_eq!(parsed.flight_type, None);
+        assert_eq!(parsed.pilot_name, "Jane");
+
+        assert_eq!(ParsedTitle::parse("no brackets at all"), None);
+    }
+
+    /// Feed pseudo-random garbage into the title parser. It must never panic
+    /// and never return non-finite or negative distances.
+    #[test]
+    fn parse_title_fuzz() {
+        const ALPHABET: &[char] = &[
+            '0', '1', '9', '.', ',', '\'', '’', '/', '-', ' ', '[', ']', ':', 'k', 'm', 'i', 'ü',
+            'x', 'e', '_',
+        ];
+        let mut seed: u64 = 0x5eed;
+        for _ in 0..10_000 {
+            let len = (seed % 40) as usize;
+            let title: String = (0..len)
+                .map(|_| {
+                    // Xorshift PRNG
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    ALPHABET[(seed % ALPHABET.len() as u64) as usize]
+                })
+                .collect();
+            if let Some(parsed) = ParsedTitle::parse(&title) {
+                if let Some(distance) = parsed.distance_km {
+                    assert!(distance.is_finite() && distance >= 0.0, "{:?}", title);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reparse_payloads() {
+        let item = rss::Item {
+            title: Some("09.08.20 [21.98 km :: free_flight] Firstname Lastname".into()),
+            link: Some(
+                "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+                    .into(),
+            ),
+            ..Default::default()
+        };
+        let payload = serde_json::to_string(&item).unwrap();
+        assert!(reparse(PayloadKind::FeedItem, &payload)
+            .unwrap()
+            .contains("by dbrgn"));
+
+        let item = rss::Item {
+            link: Some("https://example.com/".into()),
+            ..item
+        };
+        let payload = serde_json::to_string(&item).unwrap();
+        assert!(reparse(PayloadKind::FeedItem, &payload).is_err());
+
+        assert!(reparse(PayloadKind::DetailPage, "<html></html>").is_err());
+    }
+
+    #[test]
+    fn detail_budget() {
+        let xc = XContest::new(Client::new()).with_detail_budget(Some(2));
+        assert!(xc.take_detail_budget().is_ok());
+        assert!(xc.take_detail_budget().is_ok());
+        assert!(xc.take_detail_budget().is_err());
+    }
+
+    #[test]
+    fn sniff_animated_gif() {
+        use image::{codecs::gif::GifEncoder, Frame, RgbaImage};
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            encoder
+                .encode_frames(vec![
+                    Frame::new(RgbaImage::new(2, 2)),
+                    Frame::new(RgbaImage::from_pixel(2, 2, [255, 0, 0, 255].into())),
+                ])
+                .unwrap();
+        }
+        assert_eq!(sniff_preview(&bytes).unwrap(), (PreviewFormat::Gif, true));
+    }
+
+    #[test]
+    fn process_sample_thumbnail() {
+        let xc = XContest::new(Client::new());
+        let details = xc
+            .process_preview(Bytes::from_static(SAMPLE_THUMBNAIL))
+            .unwrap();
+        assert_eq!(details.format, PreviewFormat::Png);
+        assert!(!details.animated);
+        assert_eq!(
+            image::guess_format(&details.thumbnail_small).unwrap(),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn sniff_static_png() {
+        let bytes = encode_png(&DynamicImage::new_rgb8(2, 2)).unwrap();
+        assert_eq!(sniff_preview(&bytes).unwrap(), (PreviewFormat::Png, false));
+    }
+
+    #[test]
+    fn response_errors() {
+        let response = |status: u16, retry_after: Option<&str>| {
+            let mut builder = http::Response::builder().status(status);
+            if let Some(retry_after) = retry_after {
+                builder = builder.header("retry-after", retry_after);
+            }
+            Response::from(builder.body("").unwrap())
+        };
+        let url = "https://www.xcontest.org/flights/detail:dbrgn/9.8.2020/10:45";
+
+        assert!(check_response(&response(200, None), url).is_ok());
+        assert!(matches!(
+            check_response(&response(429, Some("120")), url),
+            Err(Error::RateLimited(Throttled {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                retry_after: Some(delay),
+            })) if delay == Duration::from_secs(120)
+        ));
+        assert!(matches!(
+            check_response(&response(404, None), url),
+            Err(Error::NotFound(not_found)) if not_found == url
+        ));
+        assert!(matches!(
+            check_response(&response(502, None), url),
+            Err(Error::Network(_))
+        ));
+    }
+}
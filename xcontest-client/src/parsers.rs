@@ -0,0 +1,430 @@
+//! Versioned parsers for the XContest RSS feed, the flight list and flight
+//! detail pages.
+//!
+//! When XContest changes its feed or page format, add a new parser version
+//! instead of modifying the existing one. The parsers are tried in order, the
+//! first one that matches wins.
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use reqwest::Url;
+
+use super::{Flight, FlightTimes, TriangleStats};
+
+/// Sample RSS feed payload, used for the parser self-test.
+const SAMPLE_FEED: &str = include_str!("../samples/feed.xml");
+
+/// Sample flight detail page payload, used for the parser self-test.
+const SAMPLE_DETAIL: &str = include_str!("../samples/detail.html");
+
+/// Sample flight list page payload.
+#[cfg(test)]
+const SAMPLE_LIST: &str = include_str!("../samples/flights.html");
+
+/// A parser for items of the XContest RSS feed.
+pub trait FeedParser: Send + Sync {
+    /// Version identifier of this parser.
+    fn version(&self) -> &'static str;
+
+    /// Parse a single feed item into a flight.
+    fn parse_item(&self, item: &rss::Item) -> Result<Flight>;
+}
+
+/// A parser for the XContest flight list (the flight search page), used when
+/// the RSS feed is unavailable.
+pub trait ListParser: Send + Sync {
+    /// Version identifier of this parser.
+    fn version(&self) -> &'static str;
+
+    /// Split the page HTML into the rows of the flight list.
+    fn rows<'a>(&self, html: &'a str) -> Vec<&'a str>;
+
+    /// Parse a single row into a flight. Relative links are resolved against
+    /// `base`.
+    fn parse_row(&self, row: &str, base: &Url) -> Result<Flight>;
+}
+
+/// A parser for XContest flight detail pages.
+pub trait DetailParser: Send + Sync {
+    /// Version identifier of this parser.
+    fn version(&self) -> &'static str;
+
+    /// Extract the preview image URL from the detail page HTML.
+    fn thumbnail_url(&self, html: &str) -> Option<String>;
+
+    /// Extract the start time and airtime from the detail page HTML.
+    fn times(&self, _html: &str) -> FlightTimes {
+        FlightTimes::default()
+    }
+
+    /// Extract the closing and speed of a triangle from the detail page HTML.
+    fn triangle(&self, _html: &str) -> TriangleStats {
+        TriangleStats::default()
+    }
+}
+
+/// The RSS feed format as of 2021: Title and link, the pilot username is
+/// contained in the link.
+pub struct RssV1;
+
+impl FeedParser for RssV1 {
+    fn version(&self) -> &'static str {
+        "rss-v1"
+    }
+
+    fn parse_item(&self, item: &rss::Item) -> Result<Flight> {
+        let title = item.title.clone().context("Feed item has no title")?;
+        let link = item.link.clone().context("Feed item has no link")?;
+        // The publication date is optional (RFC 2822), invalid dates are ignored
+        let published = item
+            .pub_date
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.with_timezone(&Utc));
+        Ok(Flight::new(title, link)?
+            .with_guid(item.guid.as_ref().map(|guid| guid.value.clone()))
+            .with_published(published))
+    }
+}
+
+/// The flight list format as of 2021: A table row per flight, with the date,
+/// the pilot name, the flight type (as CSS class), the distance and the link
+/// to the detail page. The title is rebuilt in the format of the feed.
+pub struct TableV1;
+
+impl ListParser for TableV1 {
+    fn version(&self) -> &'static str {
+        "table-v1"
+    }
+
+    fn rows<'a>(&self, html: &'a str) -> Vec<&'a str> {
+        lazy_static! {
+            static ref ROW_RE: Regex = Regex::new(r"(?s)<tr\b[^>]*>(?P<row>.*?)</tr>").unwrap();
+        }
+        ROW_RE
+            .captures_iter(html)
+            .map(|caps| caps.name("row").unwrap().as_str())
+            // Skip header rows
+            .filter(|row| row.contains("<td"))
+            .collect()
+    }
+
+    fn parse_row(&self, row: &str, base: &Url) -> Result<Flight> {
+        lazy_static! {
+            static ref LINK_RE: Regex =
+                Regex::new(r#"<a\s+class="detail"\s+href="(?P<href>[^"]*)""#).unwrap();
+            static ref PILOT_RE: Regex =
+                Regex::new(r#"<a\s+class="plt"[^>]*>(?P<name>[^<]*)</a>"#).unwrap();
+            static ref DATE_RE: Regex =
+                Regex::new(r#"<div\s+class="full">\s*(?P<date>[0-9]{2}\.[0-9]{2}\.[0-9]{2})"#)
+                    .unwrap();
+            static ref TYPE_RE: Regex = Regex::new(r#"class="cat-(?P<type>[a-z_]+)""#).unwrap();
+            static ref DISTANCE_RE: Regex =
+                Regex::new(r#"<td\s+class="km">\s*<strong>(?P<km>[0-9.]+)</strong>"#).unwrap();
+        }
+        let href = LINK_RE
+            .captures(row)
+            .map(|caps| unescape_html(&caps["href"]))
+            .context("Row has no flight link")?;
+        let url = base
+            .join(&href)
+            .context(format!("Invalid flight link: {}", href))?;
+        let pilot_name = PILOT_RE
+            .captures(row)
+            .map(|caps| unescape_html(caps["name"].trim()))
+            .context("Row has no pilot name")?;
+        let date = DATE_RE
+            .captures(row)
+            .map(|caps| caps["date"].to_string())
+            .unwrap_or_default();
+        let distance = DISTANCE_RE
+            .captures(row)
+            .map(|caps| format!("{} km", &caps["km"]))
+            .unwrap_or_default();
+        let details = match TYPE_RE.captures(row) {
+            Some(caps) => format!("{} :: {}", distance, &caps["type"]),
+            None => distance,
+        };
+        let title = format!("{} [{}] {}", date, details, pilot_name);
+        Flight::new(title.trim().to_string(), url.to_string())
+    }
+}
+
+/// Decode the HTML entities XContest uses in names and links.
+fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The detail page format as of 2021: The preview image is referenced in the
+/// `og:image` meta tag, the start time, airtime and the closing and speed of
+/// triangles are listed in the `XCinfo` table.
+pub struct OgImageV1;
+
+impl DetailParser for OgImageV1 {
+    fn version(&self) -> &'static str {
+        "og-image-v1"
+    }
+
+    fn thumbnail_url(&self, html: &str) -> Option<String> {
+        lazy_static! {
+            static ref THUMBNAIL_RE: Regex =
+                Regex::new(r#"<meta\s*property="og:image"\s*content="(?P<url>[^"]*)"\s*/>"#)
+                    .unwrap();
+        }
+        THUMBNAIL_RE
+            .captures(html)
+            .map(|caps| caps.name("url").unwrap().as_str().to_string())
+    }
+
+    fn times(&self, html: &str) -> FlightTimes {
+        lazy_static! {
+            static ref START_RE: Regex =
+                Regex::new(r"<th>start/landing</th>\s*<td>\s*(?P<start>[0-9]{1,2}:[0-9]{2})")
+                    .unwrap();
+            static ref AIRTIME_RE: Regex = Regex::new(
+                r"<th>airtime</th>\s*<td>\s*(?P<hours>[0-9]{1,2}):(?P<minutes>[0-5][0-9])"
+            )
+            .unwrap();
+        }
+        FlightTimes {
+            start: START_RE
+                .captures(html)
+                .and_then(|caps| NaiveTime::parse_from_str(&caps["start"], "%H:%M").ok()),
+            airtime_minutes: AIRTIME_RE.captures(html).and_then(|caps| {
+                let hours: u32 = caps["hours"].parse().ok()?;
+                let minutes: u32 = caps["minutes"].parse().ok()?;
+                Some(hours * 60 + minutes)
+            }),
+        }
+    }
+
+    fn triangle(&self, html: &str) -> TriangleStats {
+        lazy_static! {
+            static ref CLOSING_RE: Regex =
+                Regex::new(r"<th>closing</th>\s*<td>\s*(?P<value>[0-9]+(?:[.,][0-9]+)?)\s*%")
+                    .unwrap();
+            static ref SPEED_RE: Regex = Regex::new(
+                r"<th>(?:avg\.?\s*)?speed</th>\s*<td>\s*(?P<value>[0-9]+(?:[.,][0-9]+)?)\s*km/h"
+            )
+            .unwrap();
+        }
+        let value = |re: &Regex| {
+            re.captures(html)
+                .and_then(|caps| caps["value"].replace(',', ".").parse::<f64>().ok())
+        };
+        TriangleStats {
+            closing_percent: value(&CLOSING_RE),
+            speed_kmh: value(&SPEED_RE),
+        }
+    }
+}
+
+/// A preview image URL pattern configured by the operator, with the capture
+/// group `url`.
+pub struct ConfiguredThumbnail(pub Regex);
+
+impl DetailParser for ConfiguredThumbnail {
+    fn version(&self) -> &'static str {
+        "configured"
+    }
+
+    fn thumbnail_url(&self, html: &str) -> Option<String> {
+        self.0
+            .captures(html)
+            .and_then(|caps| caps.name("url"))
+            .map(|url| url.as_str().to_string())
+    }
+}
+
+/// Return all known feed parsers, newest first.
+pub fn feed_parsers() -> Vec<Box<dyn FeedParser>> {
+    vec![Box::new(RssV1)]
+}
+
+/// Return all known flight list parsers, newest first.
+pub fn list_parsers() -> Vec<Box<dyn ListParser>> {
+    vec![Box::new(TableV1)]
+}
+
+/// Return all known detail page parsers, newest first.
+pub fn detail_parsers() -> Vec<Box<dyn DetailParser>> {
+    vec![Box::new(OgImageV1)]
+}
+
+/// The live payload did not match any known parser.
+#[derive(Debug)]
+pub struct NoMatchingParser {
+    /// The kind of payload (e.g. "feed" or "detail page")
+    pub kind: &'static str,
+}
+
+impl std::fmt::Display for NoMatchingParser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "XContest {} does not match any known parser, the format may have changed",
+            self.kind
+        )
+    }
+}
+
+impl std::error::Error for NoMatchingParser {}
+
+/// Run all parsers against the bundled sample payloads.
+///
+/// Return the versions of the matching feed and detail parsers, or an error
+/// if no parser matches a sample.
+pub fn self_test() -> Result<(&'static str, &'static str)> {
+    let channel =
+        rss::Channel::read_from(SAMPLE_FEED.as_bytes()).context("Could not read sample feed")?;
+    let feed_parser = feed_parsers()
+        .into_iter()
+        .find(|parser| {
+            channel
+                .items()
+                .iter()
+                .all(|item| parser.parse_item(item).is_ok())
+        })
+        .ok_or(NoMatchingParser {
+            kind: "sample feed",
+        })?;
+    let detail_parser = detail_parsers()
+        .into_iter()
+        .find(|parser| parser.thumbnail_url(SAMPLE_DETAIL).is_some())
+        .ok_or(NoMatchingParser {
+            kind: "sample detail page",
+        })?;
+    Ok((feed_parser.version(), detail_parser.version()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_payloads() {
+        assert_eq!(self_test().unwrap(), ("rss-v1", "og-image-v1"));
+    }
+
+    #[test]
+    fn publication_date() {
+        let item = rss::Item {
+            title: Some("09.08.20 [21.98 km :: free_flight] Danilo Bargen".into()),
+            link: Some(
+                "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+                    .into(),
+            ),
+            pub_date: Some("Sun, 09 Aug 2020 16:20:00 +0200".into()),
+            ..Default::default()
+        };
+        let flight = RssV1.parse_item(&item).unwrap();
+        assert_eq!(
+            flight.published.map(|date| date.to_rfc3339()).as_deref(),
+            Some("2020-08-09T14:20:00+00:00")
+        );
+
+        let item = rss::Item {
+            pub_date: Some("yesterday".into()),
+            ..item
+        };
+        assert_eq!(RssV1.parse_item(&item).unwrap().published, None);
+    }
+
+    #[test]
+    fn configured_thumbnail() {
+        let parser = ConfiguredThumbnail(
+            Regex::new(r#"<meta\s*property="og:image"\s*content="(?P<url>[^"]*)""#).unwrap(),
+        );
+        assert_eq!(
+            parser.thumbnail_url(SAMPLE_DETAIL),
+            OgImageV1.thumbnail_url(SAMPLE_DETAIL)
+        );
+        assert_eq!(parser.thumbnail_url("<html></html>"), None);
+    }
+
+    #[test]
+    fn parse_sample_list() {
+        let base = Url::parse("https://www.xcontest.org/2020/switzerland/en/flights/").unwrap();
+        let flights: Vec<Flight> = TableV1
+            .rows(SAMPLE_LIST)
+            .iter()
+            .map(|row| TableV1.parse_row(row, &base).unwrap())
+            .collect();
+        assert_eq!(flights.len(), 2);
+        assert_eq!(
+            flights[0].title,
+            "09.08.20 [21.98 km :: free_flight] Danilo Bargen"
+        );
+        assert_eq!(
+            flights[0].url,
+            "https://www.xcontest.org/2020/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45"
+        );
+        assert_eq!(flights[0].pilot_username, "dbrgn");
+        assert_eq!(flights[0].contest.as_deref(), Some("switzerland"));
+        let parsed = flights[1].parsed_title.as_ref().unwrap();
+        assert_eq!(parsed.pilot_name, "Chrigel Maurer");
+        assert_eq!(parsed.distance_km, Some(187.25));
+        assert_eq!(parsed.flight_type, Some(crate::FlightType::FaiTriangle));
+
+        // Rows without a flight link cannot be parsed
+        assert!(TableV1
+            .parse_row(
+                r#"<td><a class="plt" href="/">Danilo Bargen</a></td>"#,
+                &base
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn sample_times() {
+        assert_eq!(
+            OgImageV1.times(SAMPLE_DETAIL),
+            FlightTimes {
+                start: NaiveTime::from_hms_opt(10, 45, 0),
+                airtime_minutes: Some(222),
+            }
+        );
+        assert!(OgImageV1.times("<html></html>").is_empty());
+    }
+
+    #[test]
+    fn sample_triangle() {
+        assert_eq!(
+            OgImageV1.triangle(SAMPLE_DETAIL),
+            TriangleStats {
+                closing_percent: Some(97.5),
+                speed_kmh: Some(24.3),
+            }
+        );
+        assert_eq!(
+            OgImageV1.triangle("<tr><th>avg. speed</th><td>18,2 km/h</td></tr>"),
+            TriangleStats {
+                closing_percent: None,
+                speed_kmh: Some(18.2),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_sample_feed() {
+        let channel = rss::Channel::read_from(SAMPLE_FEED.as_bytes()).unwrap();
+        let flights: Vec<Flight> = channel
+            .items()
+            .iter()
+            .map(|item| RssV1.parse_item(item).unwrap())
+            .collect();
+        assert_eq!(flights.len(), 2);
+        assert_eq!(flights[0].pilot_username, "dbrgn");
+        assert_eq!(
+            flights[0].guid.as_deref(),
+            Some("https://www.xcontest.org/switzerland/en/flights/detail:dbrgn/9.8.2020/10:45")
+        );
+    }
+}